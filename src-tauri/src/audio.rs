@@ -4,6 +4,9 @@
 
 #![allow(dead_code)]
 
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
 use std::sync::{Arc, Mutex, RwLock};
 
 use cpal::{
@@ -13,7 +16,11 @@ use cpal::{
 use lazy_static::lazy_static;
 use log::debug;
 
-use crate::tv::{BasicVisualizer, VisualizerTrait};
+use crate::eq::Curve;
+use crate::meter::{Meter, Meters, Readout};
+use crate::midi::ChannelMessage;
+use crate::sequencer::{Pattern, Sequencer};
+use crate::transport::{Position, Transport};
 
 lazy_static! {
     pub static ref HOST: Mutex<Option<cpal::Host>> = Mutex::new(None);
@@ -21,12 +28,35 @@ lazy_static! {
     pub static ref INPUT_DEVICE: Mutex<Option<cpal::Device>> = Mutex::new(None);
     pub static ref OUTPUT_CONFIG: Mutex<Option<cpal::StreamConfig>> = Mutex::new(None);
     pub static ref INPUT_CONFIG: Mutex<Option<cpal::StreamConfig>> = Mutex::new(None);
-    pub static ref STRIPS: RwLock<Vec<Strip>> = RwLock::new(Vec::new());
+    pub static ref CONTROL: Mutex<Control> = Mutex::new(Control::new());
+    pub static ref HEADLESS: Mutex<Option<Headless>> = Mutex::new(None);
     pub static ref RELOAD: RwLock<bool> = RwLock::new(false);
     pub static ref AUDIO_THREAD: Mutex<Option<std::thread::JoinHandle<Result<(), String>>>> =
         Mutex::new(None);
 }
 
+/// The number of commands that can wait for the audio thread before sending fails
+pub const COMMAND_QUEUE_SIZE: usize = 1024;
+
+/// The most strips the audio thread has room for; adding more is refused, so adding one never allocates
+pub const STRIP_CAPACITY: usize = 64;
+
+/// The number of frames strip and bus buffers have room for before a block has to allocate
+pub const BLOCK_CAPACITY: usize = 4096;
+
+/// The number of captured blocks that can wait for the output stream before input is dropped
+pub const CAPTURE_BLOCKS: usize = 8;
+
+/// The number of captured blocks the input stream has dropped because the output stream had not handed any back,
+/// or because they did not fit in one; the control side warns when it grows
+pub static CAPTURE_OVERRUNS: AtomicUsize = AtomicUsize::new(0);
+
+/// The highest sample rate effects allocate their delay lines and buffers for, so they never allocate on the audio thread
+pub const MAX_SAMPLE_RATE: f32 = 192000.0;
+
+/// The file the "file" host writes to when the output device is not a .wav path
+pub const HEADLESS_FILE: &str = "output.wav";
+
 /// ## `get_host(host_name: &str) -> Host`
 ///
 /// Gets either the desired hostname, or if it is unavailable, the default host.
@@ -225,7 +255,7 @@ pub fn get_input_device(device_name: &str, host: &Host) -> Option<Device> {
 
 /// ## `list_hosts() -> Vec<String>`
 ///
-/// Lists all available hosts.
+/// Lists all available hosts, including the headless "Null" and "File" hosts.
 ///
 /// ### Returns
 ///
@@ -237,6 +267,10 @@ pub fn list_hosts() -> Vec<String> {
         let host_id_name = host_id.name();
         hosts.push(host_id_name.to_owned());
     }
+
+    // the headless hosts are always available
+    hosts.push(Headless::Null.name().to_owned());
+    hosts.push(Headless::File(HEADLESS_FILE.to_owned()).name().to_owned());
     hosts
 }
 
@@ -310,12 +344,6 @@ pub fn list_input_devices(host: &Host) -> Vec<String> {
     devices
 }
 
-/*
-pub fn play_sound_file(path: String) -> Result<(), String> {
-    Ok(())
-}
-*/
-
 /// ## PreferenceAlt
 ///
 /// If the higher priority "Preference" is unavailable, this enum is used to
@@ -776,7 +804,7 @@ pub fn list_input_streams(device: &Device) -> Result<Vec<String>, String> {
 
 /// ## `reload() -> Result<(), String>`
 ///
-/// Reloads the audio thread.
+/// Asks the audio thread to rebuild its streams from the current device and stream config.
 ///
 /// ### Returns
 ///
@@ -795,438 +823,3935 @@ pub fn reload() -> Result<(), String> {
     Ok(())
 }
 
-/// ## `audio_thread() -> Result<(), String>`
+/// ## Capture
 ///
-/// Starts the audio thread.
+/// Holds audio captured by the input stream until the output stream reads it.
+/// The input and output streams can run at different sample rates and buffer sizes,
+/// so frames are resampled to the output sample rate (linear interpolation) as they are read.
 ///
-/// ### Returns
+/// ### Fields
 ///
-/// * `Result<(), String>` - An error message, or nothing if successful
-pub fn audio_thread() -> Result<(), String> {
-    let thread = std::thread::spawn(move || {
-        let config = {
-            match OUTPUT_CONFIG.try_lock() {
-                Ok(config) => match config.as_ref() {
-                    Some(config) => config.clone(),
-                    None => {
-                        debug!("OUTPUT_CONFIG is None");
-                        //return Err(format!("OUTPUT_CONFIG is None"));
-
-                        // specify type of Err to avoid type mismatch
-                        return Err("OUTPUT_CONFIG is None".to_owned());
-                    }
-                },
-                Err(e) => {
-                    debug!("Error locking OUTPUT_CONFIG: {}", e);
-                    return Err(format!("Error locking OUTPUT_CONFIG: {}", e));
-                }
-            }
-        };
+/// * `channels: usize` - The number of channels in the input stream
+/// * `ratio: f64` - The number of input frames consumed per output frame
+/// * `position: f64` - The fractional read position into the queue, in frames
+/// * `max_frames: usize` - The maximum number of frames held before the oldest are dropped
+/// * `queue: VecDeque<f32>` - The captured interleaved samples
+/// * `blocks: Receiver<Vec<f32>>` - Blocks sent by the input stream
+/// * `recycle: SyncSender<Vec<f32>>` - Hands emptied blocks back to the input stream so they can be reused
+///
+/// ### Functions
+///
+/// * `new(channels: usize, input_rate: u32, output_rate: u32, max_frames: usize) -> (Self, CaptureSender)` - Creates a new capture, and the sender the input stream writes to
+/// * `receive(&mut self)` - Moves every block sent by the input stream into the queue
+/// * `channels(&self) -> usize` - Returns the number of input channels
+/// * `frames(&self) -> usize` - Returns the number of frames waiting to be read
+/// * `push(&mut self, data: &[f32])` - Adds interleaved samples from the input stream
+/// * `read(&mut self, buffer: &mut Buffer)` - Reads a block of frames at the output sample rate
+pub struct Capture {
+    channels: usize,
+    ratio: f64,
+    position: f64,
+    max_frames: usize,
+    queue: VecDeque<f32>,
+    blocks: Receiver<Vec<f32>>,
+    recycle: SyncSender<Vec<f32>>,
+}
 
-        let output_stream_opt: Option<Result<cpal::Stream, cpal::BuildStreamError>>;
+impl Capture {
+    /// ## `new(channels: usize, input_rate: u32, output_rate: u32, max_frames: usize) -> (Self, CaptureSender)`
+    ///
+    /// Creates a new capture, and the sender the input stream writes to.
+    /// The two ends only talk through channels, so neither stream ever waits on the other.
+    ///
+    /// ### Arguments
+    ///
+    /// * `channels: usize` - The number of channels in the input stream
+    /// * `input_rate: u32` - The sample rate of the input stream
+    /// * `output_rate: u32` - The sample rate of the output stream
+    /// * `max_frames: usize` - The maximum number of frames to hold before dropping the oldest
+    ///
+    /// ### Returns
+    ///
+    /// * `(Self, CaptureSender)` - The new capture and its sender
+    pub fn new(
+        channels: usize,
+        input_rate: u32,
+        output_rate: u32,
+        max_frames: usize,
+    ) -> (Self, CaptureSender) {
+        let channels = channels.max(1);
+        let (block_sender, blocks) = sync_channel(CAPTURE_BLOCKS);
+        let (recycle, recycled) = sync_channel(CAPTURE_BLOCKS);
 
-        {
-            let output_device = OUTPUT_DEVICE.try_lock();
-            let output_device = match output_device {
-                Ok(output_device) => output_device,
-                Err(e) => {
-                    debug!("Error locking OUTPUT_DEVICE: {}", e);
-                    return Err(format!("Error locking OUTPUT_DEVICE: {}", e));
-                }
-            };
+        // hand the input stream every block up front, so it never has to allocate while running.
+        // There are only as many blocks as either channel holds, so sending one back or on never fails
+        for _ in 0..CAPTURE_BLOCKS {
+            let _ = recycle.try_send(Vec::with_capacity(max_frames.max(2) * channels));
+        }
 
-            let output_device = match output_device.as_ref() {
-                Some(output_device) => output_device,
-                None => {
-                    debug!("OUTPUT_DEVICE is None");
-                    return Err("OUTPUT_DEVICE is None".to_owned());
-                }
-            };
+        (
+            Self {
+                channels,
+                ratio: input_rate as f64 / output_rate.max(1) as f64,
+                position: 0.0,
+                max_frames: max_frames.max(2),
+                queue: VecDeque::with_capacity(max_frames.max(2) * channels * 2),
+                blocks,
+                recycle,
+            },
+            CaptureSender {
+                blocks: block_sender,
+                recycled,
+            },
+        )
+    }
 
-            // Produce a sinusoid of maximum amplitude.
-            let mut sample_clock = 0f32;
+    /// ## `receive(&mut self)`
+    ///
+    /// Moves every block sent by the input stream into the queue, and hands the emptied blocks back.
+    pub fn receive(&mut self) {
+        while let Ok(mut block) = self.blocks.try_recv() {
+            self.push(&block);
+            block.clear();
+            let _ = self.recycle.try_send(block);
+        }
+    }
 
-            let n_channels = config.channels as u32;
+    /// ## `channels(&self) -> usize`
+    ///
+    /// Returns the number of input channels.
+    ///
+    /// ### Returns
+    ///
+    /// * `usize` - The number of input channels
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
 
-            let data_callback = move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-                let buffer_size = data.len();
-                let mut strips = match STRIPS.try_write() {
-                    Ok(strips) => strips,
-                    Err(e) => {
-                        debug!("Error locking STRIPS: {}", e);
-                        return;
-                    }
-                };
+    /// ## `frames(&self) -> usize`
+    ///
+    /// Returns the number of frames waiting to be read.
+    ///
+    /// ### Returns
+    ///
+    /// * `usize` - The number of frames in the queue
+    pub fn frames(&self) -> usize {
+        self.queue.len() / self.channels
+    }
 
-                let mut channel = 0;
-
-                // cpal audio is interleaved, meaning that every sample is followed by another sample for the next channel
-                // example: in a stereo stream, the first sample is for the left channel, the second sample is for the right channel, the third sample is for the left channel, etc.
-                // So every other sample is for the same channel
-                //
-                // So there is a simple formula for determining what channel a sample is for:
-                // channel = sample_index % n_channels
-                let mut data_vec = Vec::new();
-                for sample in data.iter_mut() {
-                    if channel % n_channels == 0 {
-                        sample_clock += 1.0;
-                    }
+    /// ## `push(&mut self, data: &[f32])`
+    ///
+    /// Adds interleaved samples from the input stream.
+    /// If the output stream falls behind, the oldest frames are dropped so latency stays bounded.
+    ///
+    /// ### Arguments
+    ///
+    /// * `data: &[f32]` - The interleaved samples
+    pub fn push(&mut self, data: &[f32]) {
+        self.queue.extend(data.iter());
 
-                    for strip in strips.iter_mut() {
-                        match strip.output {
-                            Output::Mono(strip_channel) => {
-                                if strip_channel == channel % n_channels {
-                                    *sample = strip
-                                        .process(State {
-                                            sample_rate: config.sample_rate.0 as u32,
-                                            sample_clock: sample_clock as u64,
-                                            buffer_size,
-                                        })
-                                        .mono();
-                                }
-                            }
-                            Output::Stereo(left_channel, right_channel) => {
-                                if left_channel == channel % n_channels {
-                                    *sample = strip
-                                        .process(State {
-                                            sample_rate: config.sample_rate.0 as u32,
-                                            sample_clock: sample_clock as u64,
-                                            buffer_size,
-                                        })
-                                        .left();
-                                } else if right_channel == channel % n_channels {
-                                    *sample = strip
-                                        .process(State {
-                                            sample_rate: config.sample_rate.0 as u32,
-                                            sample_clock: sample_clock as u64,
-                                            buffer_size,
-                                        })
-                                        .right();
-                                }
-                            }
-                            _ => {}
-                        }
-                    }
+        let frames = self.frames();
+        if frames > self.max_frames {
+            let drop_frames = frames - self.max_frames / 2;
+            self.queue.drain(0..drop_frames * self.channels);
+            self.position = 0.0;
+        }
+    }
 
-                    if channel % n_channels == 0 {
-                        data_vec.push(*sample);
-                    }
-                    channel += 1;
-                }
+    /// ## `read(&mut self, buffer: &mut Buffer)`
+    ///
+    /// Reads a block of frames at the output sample rate, deinterleaving them into the buffer.
+    /// If there is not enough captured audio, the rest of the block is filled with silence.
+    ///
+    /// ### Arguments
+    ///
+    /// * `buffer: &mut Buffer` - The buffer to write into. Resized to the number of input channels, keeping its length.
+    pub fn read(&mut self, buffer: &mut Buffer) {
+        self.receive();
+        buffer.resize(self.channels, buffer.len());
 
-                let tv_window = crate::TV_WINDOW.lock();
-                match tv_window {
-                    Ok(tv_window) => match tv_window.as_ref() {
-                        Some(tv_window) => {
-                            let visualizer = <BasicVisualizer as VisualizerTrait>::new();
-                            let _ = visualizer.render(tv_window, &data_vec);
-                        }
-                        None => {
-                            debug!("TV_WINDOW is None");
-                        }
-                    },
-                    Err(e) => {
-                        debug!("Error locking TV_WINDOW: {}", e);
-                    }
+        for frame in 0..buffer.len() {
+            let index = self.position.floor() as usize;
+            if index + 1 >= self.frames() {
+                // underrun, wait for the input stream to catch up
+                for channel in 0..self.channels {
+                    buffer.channel_mut(channel)[frame] = 0.0;
                 }
-            };
-
-            let err_fn = |err| eprintln!("an error occurred on stream: {}", err);
-            let output_stream =
-                output_device.build_output_stream(&config, data_callback, err_fn, None);
-            output_stream_opt = Some(output_stream);
-        }
-
-        let output_stream = match output_stream_opt {
-            Some(output_stream) => output_stream,
-            None => {
-                return Err("Error building output stream".to_owned());
+                continue;
             }
-        };
 
-        let output_stream = match output_stream {
-            Ok(stream) => stream,
-            Err(err) => {
-                return Err(format!("Error building output stream: {}", err));
+            let fraction = (self.position - index as f64) as f32;
+            for channel in 0..self.channels {
+                let current = self.queue[index * self.channels + channel];
+                let next = self.queue[(index + 1) * self.channels + channel];
+                buffer.channel_mut(channel)[frame] = current + (next - current) * fraction;
             }
-        };
-
-        let _ = output_stream.play();
 
-        loop {
-            std::thread::sleep(std::time::Duration::from_millis(1000));
-            //debug!("Reloading audio thread...");
+            self.position += self.ratio;
         }
-    });
 
-    match AUDIO_THREAD.lock() {
-        Ok(mut audio_thread) => {
-            *audio_thread = Some(thread);
-        }
-        Err(e) => {
-            debug!("Error locking AUDIO_THREAD: {}", e);
-            return Err(format!("Error locking AUDIO_THREAD: {}", e));
+        // drop frames that have been read completely
+        let consumed = (self.position.floor() as usize).min(self.frames());
+        if consumed > 0 {
+            self.queue.drain(0..consumed * self.channels);
+            self.position -= consumed as f64;
         }
     }
-
-    Ok(())
 }
 
-/// ## Sample
+/// ## CaptureSender
 ///
-/// Represents a sample of audio data. Can be either mono or stereo.
+/// The input stream's end of a capture.
 ///
-/// ### Variants
+/// ### Fields
 ///
-/// * `Mono(f32)` - A mono sample
-/// * `Stereo(f32, f32)` - A stereo sample
+/// * `blocks: SyncSender<Vec<f32>>` - Sends captured blocks to the output stream
+/// * `recycled: Receiver<Vec<f32>>` - Emptied blocks handed back by the output stream
 ///
 /// ### Functions
 ///
-/// * `mono(&self) -> f32` - Returns the mono version of the sample
-/// * `stereo(&self) -> (f32, f32)` - Returns the stereo version of the sample
-/// * `left(&self) -> f32` - Returns the left channel of the sample
-/// * `right(&self) -> f32` - Returns the right channel of the sample
-#[derive(Clone, Debug)]
-pub enum Sample {
-    Mono(f32),
-    Stereo(f32, f32),
+/// * `send(&self, data: &[f32])` - Sends interleaved samples to the capture
+pub struct CaptureSender {
+    blocks: SyncSender<Vec<f32>>,
+    recycled: Receiver<Vec<f32>>,
 }
 
-impl Sample {
-    /// ## `mono(&self) -> f32`
+impl CaptureSender {
+    /// ## `send(&self, data: &[f32])`
     ///
-    /// Returns the mono version of the sample.
+    /// Sends interleaved samples to the capture, in a block handed back by the output stream.
+    /// If the output stream is not keeping up, no block is free and the samples are dropped; samples that don't fit
+    /// in a block are dropped too. Either way the overrun is counted in `CAPTURE_OVERRUNS`, and nothing is allocated.
     ///
-    /// ### Returns
+    /// ### Arguments
     ///
-    /// * `f32` - The mono version of the sample
-    pub fn mono(&self) -> f32 {
-        match self {
-            Sample::Mono(sample) => *sample,
-            Sample::Stereo(left, right) => (*left + *right) / 2.0,
+    /// * `data: &[f32]` - The interleaved samples
+    pub fn send(&self, data: &[f32]) {
+        let mut block = match self.recycled.try_recv() {
+            Ok(block) => block,
+            Err(_e) => {
+                CAPTURE_OVERRUNS.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+        };
+        let length = data.len().min(block.capacity());
+        if length < data.len() {
+            CAPTURE_OVERRUNS.fetch_add(1, Ordering::Relaxed);
         }
+        block.extend_from_slice(&data[..length]);
+        let _ = self.blocks.try_send(block);
     }
+}
 
-    /// ## `stereo(&self) -> (f32, f32)`
-    ///
-    /// Returns the stereo version of the sample.
-    ///
-    /// ### Returns
-    ///
-    /// * `(f32, f32)` - The stereo version of the sample
-    pub fn stereo(&self) -> (f32, f32) {
-        match self {
-            Sample::Mono(sample) => (*sample, *sample),
-            Sample::Stereo(left, right) => (*left, *right),
-        }
+/// ## `build_input_stream(input_device: &Device, input_config: &cpal::StreamConfig, output_config: &cpal::StreamConfig) -> Result<(cpal::Stream, Capture), String>`
+///
+/// Builds a capture stream on the input device, along with the capture the output stream
+/// reads from, resampled from the input sample rate to the output sample rate.
+///
+/// ### Arguments
+///
+/// * `input_device: &Device` - The device to capture from
+/// * `input_config: &cpal::StreamConfig` - The config of the input stream
+/// * `output_config: &cpal::StreamConfig` - The config of the output stream the captured audio will be played on
+///
+/// ### Returns
+///
+/// * `Result<(cpal::Stream, Capture), String>` - The input stream (not yet playing) and its capture, or an error message
+pub fn build_input_stream(
+    input_device: &Device,
+    input_config: &cpal::StreamConfig,
+    output_config: &cpal::StreamConfig,
+) -> Result<(cpal::Stream, Capture), String> {
+    let buffer_frames = |config: &cpal::StreamConfig| match config.buffer_size {
+        BufferSize::Fixed(buffer_size) => buffer_size as usize,
+        BufferSize::Default => 1024,
+    };
+
+    // allow a few buffers of either stream to queue up before dropping audio
+    let ratio = input_config.sample_rate.0 as f64 / output_config.sample_rate.0.max(1) as f64;
+    let max_frames = 4 * buffer_frames(input_config)
+        .max((buffer_frames(output_config) as f64 * ratio).ceil() as usize);
+
+    debug!(
+        "Building input stream: {} channels at {} Hz, resampled to {} Hz",
+        input_config.channels, input_config.sample_rate.0, output_config.sample_rate.0
+    );
+
+    let (capture, capture_sender) = Capture::new(
+        input_config.channels as usize,
+        input_config.sample_rate.0,
+        output_config.sample_rate.0,
+        max_frames,
+    );
+
+    let data_callback = move |data: &[f32], _: &cpal::InputCallbackInfo| capture_sender.send(data);
+
+    let err_fn = |err| eprintln!("an error occurred on input stream: {}", err);
+    match input_device.build_input_stream(input_config, data_callback, err_fn, None) {
+        Ok(stream) => Ok((stream, capture)),
+        Err(err) => Err(format!("Error building input stream: {}", err)),
     }
+}
 
-    /// ## `left(&self) -> f32`
-    ///
-    /// Returns the left channel of the sample.
+/// ## Headless
+///
+/// A built-in host that needs no sound card, selected by setting `audio.host` to "null" or "file".
+/// A timer thread drives the same callback a device stream would, at the configured sample rate and buffer size.
+///
+/// ### Variants
+///
+/// * `Null` - Discards the output
+/// * `File(String)` - Streams the output to a WAV file, whose path is the output device name
+///
+/// ### Functions
+///
+/// * `from_host(host_name: &str, device_name: &str) -> Option<Self>` - Gets the headless host with the given name
+/// * `name(&self) -> &'static str` - Returns the name of the host
+/// * `device(&self) -> String` - Returns the name of the output device
+#[derive(Clone, Debug)]
+pub enum Headless {
+    Null,
+    File(String),
+}
+
+impl Headless {
+    /// ## `from_host(host_name: &str, device_name: &str) -> Option<Self>`
     ///
-    /// ### Returns
+    /// Gets the headless host with the given name. Host name is not case-sensitive.
     ///
-    /// * `f32` - The left channel of the sample
-    pub fn left(&self) -> f32 {
-        match self {
-            Sample::Mono(sample) => *sample,
-            Sample::Stereo(left, _right) => *left,
-        }
-    }
-
-    /// ## `right(&self) -> f32`
+    /// ### Arguments
     ///
-    /// Returns the right channel of the sample.
+    /// * `host_name: &str` - The name of the host
+    /// * `device_name: &str` - The output device. For the file host, this is the path of a .wav file; anything else writes to HEADLESS_FILE.
     ///
     /// ### Returns
     ///
-    /// * `f32` - The right channel of the sample
-    pub fn right(&self) -> f32 {
-        match self {
-            Sample::Mono(sample) => *sample,
-            Sample::Stereo(_left, right) => *right,
+    /// * `Option<Self>` - The headless host, or None if the name is not a headless host
+    pub fn from_host(host_name: &str, device_name: &str) -> Option<Self> {
+        match host_name.to_lowercase().as_str() {
+            "null" => Some(Headless::Null),
+            // device names left over from another host (e.g. "default") are not file paths
+            "file" => match device_name.to_lowercase().ends_with(".wav") {
+                true => Some(Headless::File(device_name.to_owned())),
+                false => Some(Headless::File(HEADLESS_FILE.to_owned())),
+            },
+            _ => None,
         }
     }
 
-    /// ## `as_mono(&self) -> Sample`
+    /// ## `name(&self) -> &'static str`
     ///
-    /// Returns the mono version of the sample.
+    /// Returns the name of the host.
     ///
     /// ### Returns
     ///
-    /// * `Sample` - The mono version of the sample
-    pub fn as_mono(&self) -> Sample {
-        Sample::Mono(self.mono())
+    /// * `&'static str` - The name of the host
+    pub fn name(&self) -> &'static str {
+        match self {
+            Headless::Null => "Null",
+            Headless::File(_path) => "File",
+        }
     }
 
-    /// ## `as_stereo(&self) -> Sample`
+    /// ## `device(&self) -> String`
     ///
-    /// Returns the stereo version of the sample.
+    /// Returns the name of the output device.
     ///
     /// ### Returns
     ///
-    /// * `Sample` - The stereo version of the sample
-    pub fn as_stereo(&self) -> Sample {
-        Sample::Stereo(self.left(), self.right())
+    /// * `String` - "null", or the path of the file being written
+    pub fn device(&self) -> String {
+        match self {
+            Headless::Null => "null".to_owned(),
+            Headless::File(path) => path.clone(),
+        }
     }
 }
 
-/// ## State
-///
-/// Represents the current state of the audio engine. Primarily configuration settings needed by the effects,
-/// and details on what the sample clock is currently at.
-///
-/// ### Fields
-///
-/// * `sample_rate: u32` - The sample rate of the audio engine
-/// * `sample_clock: u64` - The current sample clock of the audio engine
-/// * `buffer_size: usize` - The buffer size of the audio engine
-pub struct State {
-    pub sample_rate: u32,
-    pub sample_clock: u64,
-    pub buffer_size: usize,
-}
-
-/// ## Output
-///
-/// Represents an output channel.
+/// ## `headless_config(channels: u32, sample_rate: u32, buffer_size: u32) -> cpal::StreamConfig`
 ///
-/// ### Variants
+/// Creates a stream config for a headless host. Headless hosts accept any config.
 ///
-/// * `Mono(u32)` - A mono output channel. The u32 represents the output channel number, tied to the interface.
-/// * `Stereo(u32, u32)` - A stereo output channel. The u32s represent the left and right output channel numbers, tied to the interface.
-/// * `Bus(Box<Input>)` - A bus output channel
-pub enum Output {
-    Mono(u32),
-    Stereo(u32, u32),
-    Bus(Arc<Input>),
-}
-
-/// ## Input
+/// ### Arguments
 ///
-/// Represents an input channel.
+/// * `channels: u32` - The number of channels
+/// * `sample_rate: u32` - The sample rate
+/// * `buffer_size: u32` - The buffer size
 ///
-/// ### Variants
+/// ### Returns
 ///
-/// * `Generator(Box<dyn Generator>)` - A generator input channel
-/// * `Bus(Box<Output>)` - A bus input channel
-pub enum Input {
-    Generator(Arc<Mutex<dyn plugin::Generator>>),
-    Bus(Arc<Output>),
+/// * `cpal::StreamConfig` - The config
+pub fn headless_config(channels: u32, sample_rate: u32, buffer_size: u32) -> cpal::StreamConfig {
+    cpal::StreamConfig {
+        channels: channels.max(1) as u16,
+        sample_rate: cpal::SampleRate(sample_rate.max(1)),
+        buffer_size: BufferSize::Fixed(buffer_size.max(1)),
+    }
 }
 
-/// ## Strip
+/// ## HeadlessStream
 ///
-/// Represents a strip of audio effects.
+/// The headless counterpart of a cpal output stream. A timer thread calls the data callback once per buffer,
+/// and the thread is stopped when the stream is dropped.
 ///
 /// ### Fields
 ///
-/// * `input: Input` - The input channel
-/// * `chain: Vec<Box<dyn Effect>>` - The chain of effects
-/// * `output: Output` - The output channel
+/// * `stop: Arc<AtomicBool>` - Tells the timer thread to stop
+/// * `thread: Option<std::thread::JoinHandle<()>>` - The timer thread
 ///
 /// ### Functions
 ///
-/// * `new(input: Input, output: Output) -> Self` - Creates a new strip
-/// * `add_effect(&mut self, effect: Box<dyn Effect>)` - Adds an effect to the end of the chain
-/// * `insert_effect(&mut self, effect: Box<dyn Effect>, index: usize)` - Inserts an effect into the chain at the given index
-/// * `remove_effect(&mut self, index: usize)` - Removes an effect from the chain at the given index
-/// * `process(&mut self, state: State) -> Sample` - Processes a sample
-pub struct Strip {
-    input: Input,
-    chain: Vec<Box<dyn plugin::Effect>>,
-    output: Output,
+/// * `new(headless: Headless, config: &cpal::StreamConfig, callback: impl FnMut(&mut [f32]) + Send + 'static) -> Result<Self, String>` - Starts the timer thread
+pub struct HeadlessStream {
+    stop: Arc<AtomicBool>,
+    thread: Option<std::thread::JoinHandle<()>>,
 }
 
-impl Strip {
-    /// ## `new(input: Input, output: Output) -> Self`
+impl HeadlessStream {
+    /// ## `new(headless: Headless, config: &cpal::StreamConfig, callback: impl FnMut(&mut [f32]) + Send + 'static) -> Result<Self, String>`
     ///
-    /// Creates a new strip.
+    /// Starts the timer thread.
     ///
     /// ### Arguments
     ///
-    /// * `input: Input` - The input channel
-    /// * `output: Output` - The output channel
+    /// * `headless: Headless` - The headless host
+    /// * `config: &cpal::StreamConfig` - The stream config
+    /// * `callback: impl FnMut(&mut [f32]) + Send + 'static` - The data callback, given interleaved buffers to fill
+    ///
+    /// ### Returns
+    ///
+    /// * `Result<Self, String>` - The stream, or an error message if the output file could not be created
+    pub fn new(
+        headless: Headless,
+        config: &cpal::StreamConfig,
+        mut callback: impl FnMut(&mut [f32]) + Send + 'static,
+    ) -> Result<Self, String> {
+        let channels = config.channels as usize;
+        let sample_rate = config.sample_rate.0;
+        let frames = match config.buffer_size {
+            BufferSize::Fixed(buffer_size) => buffer_size as usize,
+            BufferSize::Default => 1024,
+        };
+
+        let mut writer = match &headless {
+            Headless::Null => None,
+            Headless::File(path) => {
+                let spec = hound::WavSpec {
+                    channels: channels as u16,
+                    sample_rate,
+                    bits_per_sample: 32,
+                    sample_format: hound::SampleFormat::Float,
+                };
+                match hound::WavWriter::create(path, spec) {
+                    Ok(writer) => Some(writer),
+                    Err(e) => {
+                        return Err(format!("Error creating {}: {}", path, e));
+                    }
+                }
+            }
+        };
+
+        debug!(
+            "Starting {} host: {} channels at {} Hz, {} frames per buffer",
+            headless.name(),
+            channels,
+            sample_rate,
+            frames
+        );
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread = std::thread::spawn(move || {
+            let period = std::time::Duration::from_secs_f64(frames as f64 / sample_rate as f64);
+            let mut data = vec![0.0f32; frames * channels];
+            let mut next = std::time::Instant::now();
+            let mut blocks_since_flush = 0;
+
+            while !thread_stop.load(Ordering::Relaxed) {
+                callback(&mut data);
+
+                match writer.as_mut() {
+                    Some(writer) => {
+                        for sample in data.iter() {
+                            match writer.write_sample(*sample) {
+                                Ok(_) => {}
+                                Err(e) => {
+                                    debug!("Error writing headless output: {}", e);
+                                    break;
+                                }
+                            }
+                        }
+
+                        // keep the header up to date about once a second, so the file is readable while it grows
+                        blocks_since_flush += frames;
+                        if blocks_since_flush >= sample_rate as usize {
+                            blocks_since_flush = 0;
+                            let _ = writer.flush();
+                        }
+                    }
+                    None => {}
+                }
+
+                next += period;
+                let now = std::time::Instant::now();
+                if next > now {
+                    std::thread::sleep(next - now);
+                } else if now - next > std::time::Duration::from_secs(1) {
+                    // fell far behind (e.g. the machine was suspended), don't try to catch up
+                    next = now;
+                }
+            }
+
+            match writer {
+                Some(writer) => match writer.finalize() {
+                    Ok(_) => {}
+                    Err(e) => {
+                        debug!("Error finishing headless output: {}", e);
+                    }
+                },
+                None => {}
+            }
+        });
+
+        Ok(Self {
+            stop,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for HeadlessStream {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        match self.thread.take() {
+            Some(thread) => {
+                let _ = thread.join();
+            }
+            None => {}
+        }
+    }
+}
+
+/// ## OutputStream
+///
+/// A running output stream, either on a device or on a headless host.
+///
+/// ### Variants
+///
+/// * `Device(cpal::Stream)` - A cpal output stream
+/// * `Headless(HeadlessStream)` - A headless timer thread
+pub enum OutputStream {
+    Device(cpal::Stream),
+    Headless(HeadlessStream),
+}
+
+/// ## `build_callback(config: &cpal::StreamConfig, capture: Option<Capture>) -> Result<impl FnMut(&mut [f32]) + Send + 'static, String>`
+///
+/// Builds the data callback shared by device and headless streams. The callback takes ownership of the engine
+/// until it is dropped, and fills interleaved buffers of `config.channels` channels.
+///
+/// ### Arguments
+///
+/// * `config: &cpal::StreamConfig` - The output stream config
+/// * `capture: Option<Capture>` - The captured input, if an input stream is running
+///
+/// ### Returns
+///
+/// * `Result<impl FnMut(&mut [f32]) + Send + 'static, String>` - The callback, or an error message if the engine is already owned by another stream
+pub fn build_callback(
+    config: &cpal::StreamConfig,
+    capture: Option<Capture>,
+) -> Result<impl FnMut(&mut [f32]) + Send + 'static, String> {
+    let mut sample_clock = 0u64;
+
+    let n_channels = config.channels as usize;
+    let sample_rate = config.sample_rate.0;
+
+    // blocks handed to the graph, allocated up front and only grown if cpal asks for a bigger buffer
+    let block_size = match config.buffer_size {
+        BufferSize::Fixed(buffer_size) => buffer_size as usize,
+        BufferSize::Default => 1024,
+    };
+    let mut input_buffer = Buffer::new(0, block_size);
+    let mut output_buffer = Buffer::new(n_channels, block_size);
+
+    // the callback owns the engine while the stream exists, control code talks to it through CONTROL
+    let mut engine = EngineHandle::take()?;
+    let mut capture = capture;
+
+    Ok(move |data: &mut [f32]| {
+        let frames = data.len() / n_channels;
+
+        // read the captured input for this block, resampled to the output rate
+        input_buffer.resize(input_buffer.channels(), frames);
+        match capture.as_mut() {
+            Some(capture) => capture.read(&mut input_buffer),
+            None => input_buffer.resize(0, frames),
+        }
+
+        let state = State::advance(sample_rate, &mut sample_clock, frames);
+        output_buffer.resize(n_channels, frames);
+        engine.process(&state, &input_buffer, &mut output_buffer);
+
+        // cpal audio is interleaved, meaning that every sample is followed by another sample for the next channel
+        // example: in a stereo stream, the first sample is for the left channel, the second sample is for the right channel, the third sample is for the left channel, etc.
+        //
+        // The graph works on one buffer per channel, so interleave it into cpal's buffer once here
+        for (index, frame) in data.chunks_mut(n_channels).enumerate() {
+            for (channel, sample) in frame.iter_mut().enumerate() {
+                *sample = output_buffer.channel(channel)[index];
+            }
+        }
+
+        // the TV window renders the scope from the control side
+        crate::tv::SCOPE.write(output_buffer.channel(0));
+    })
+}
+
+/// ## StreamSettings
+///
+/// A snapshot of the globals the streams are built from, so a working setup can be restored
+/// if a rebuild fails.
+///
+/// ### Fields
+///
+/// * `headless: Option<Headless>` - The headless host, if one is selected
+/// * `output_device: Option<Device>` - The output device
+/// * `output_config: Option<cpal::StreamConfig>` - The output stream config
+/// * `input_device: Option<Device>` - The input device
+/// * `input_config: Option<cpal::StreamConfig>` - The input stream config
+///
+/// ### Functions
+///
+/// * `current() -> Result<Self, String>` - Reads the settings from the globals
+/// * `restore(&self) -> Result<(), String>` - Writes the settings back to the globals
+#[derive(Clone)]
+pub struct StreamSettings {
+    headless: Option<Headless>,
+    output_device: Option<Device>,
+    output_config: Option<cpal::StreamConfig>,
+    input_device: Option<Device>,
+    input_config: Option<cpal::StreamConfig>,
+}
+
+impl StreamSettings {
+    /// ## `current() -> Result<Self, String>`
+    ///
+    /// Reads the settings from HEADLESS, OUTPUT_DEVICE, OUTPUT_CONFIG, INPUT_DEVICE and INPUT_CONFIG.
+    ///
+    /// ### Returns
+    ///
+    /// * `Result<Self, String>` - The settings, or an error message if a global could not be locked
+    pub fn current() -> Result<Self, String> {
+        let headless = match HEADLESS.lock() {
+            Ok(headless) => headless.clone(),
+            Err(e) => return Err(format!("Error locking HEADLESS: {}", e)),
+        };
+        let output_device = match OUTPUT_DEVICE.lock() {
+            Ok(output_device) => output_device.clone(),
+            Err(e) => return Err(format!("Error locking OUTPUT_DEVICE: {}", e)),
+        };
+        let output_config = match OUTPUT_CONFIG.lock() {
+            Ok(output_config) => output_config.clone(),
+            Err(e) => return Err(format!("Error locking OUTPUT_CONFIG: {}", e)),
+        };
+        let input_device = match INPUT_DEVICE.lock() {
+            Ok(input_device) => input_device.clone(),
+            Err(e) => return Err(format!("Error locking INPUT_DEVICE: {}", e)),
+        };
+        let input_config = match INPUT_CONFIG.lock() {
+            Ok(input_config) => input_config.clone(),
+            Err(e) => return Err(format!("Error locking INPUT_CONFIG: {}", e)),
+        };
+
+        Ok(Self {
+            headless,
+            output_device,
+            output_config,
+            input_device,
+            input_config,
+        })
+    }
+
+    /// ## `restore(&self) -> Result<(), String>`
+    ///
+    /// Writes the settings back to HEADLESS, OUTPUT_DEVICE, OUTPUT_CONFIG, INPUT_DEVICE and INPUT_CONFIG.
+    ///
+    /// ### Returns
+    ///
+    /// * `Result<(), String>` - An error message, or nothing if successful
+    pub fn restore(&self) -> Result<(), String> {
+        match HEADLESS.lock() {
+            Ok(mut headless) => *headless = self.headless.clone(),
+            Err(e) => return Err(format!("Error locking HEADLESS: {}", e)),
+        }
+        match OUTPUT_DEVICE.lock() {
+            Ok(mut output_device) => *output_device = self.output_device.clone(),
+            Err(e) => return Err(format!("Error locking OUTPUT_DEVICE: {}", e)),
+        }
+        match OUTPUT_CONFIG.lock() {
+            Ok(mut output_config) => *output_config = self.output_config.clone(),
+            Err(e) => return Err(format!("Error locking OUTPUT_CONFIG: {}", e)),
+        }
+        match INPUT_DEVICE.lock() {
+            Ok(mut input_device) => *input_device = self.input_device.clone(),
+            Err(e) => return Err(format!("Error locking INPUT_DEVICE: {}", e)),
+        }
+        match INPUT_CONFIG.lock() {
+            Ok(mut input_config) => *input_config = self.input_config.clone(),
+            Err(e) => return Err(format!("Error locking INPUT_CONFIG: {}", e)),
+        }
+
+        Ok(())
+    }
+}
+
+/// ## Streams
+///
+/// The running streams of the audio thread. Dropping them stops playback and hands the engine,
+/// with all of its strips and effect state, back to CONTROL for the next streams.
+///
+/// ### Fields
+///
+/// * `output: OutputStream` - The output stream
+/// * `input: Option<cpal::Stream>` - The input stream, if input is being captured
+pub struct Streams {
+    output: OutputStream,
+    input: Option<cpal::Stream>,
+}
+
+/// ## `build_streams(settings: &StreamSettings) -> Result<Streams, String>`
+///
+/// Builds and starts the output stream, and the input stream if an input device is configured.
+///
+/// ### Arguments
+///
+/// * `settings: &StreamSettings` - The settings to build the streams from
+///
+/// ### Returns
+///
+/// * `Result<Streams, String>` - The running streams, or an error message
+pub fn build_streams(settings: &StreamSettings) -> Result<Streams, String> {
+    let config = match settings.output_config.as_ref() {
+        Some(config) => config.clone(),
+        None => {
+            return Err("OUTPUT_CONFIG is None".to_owned());
+        }
+    };
+
+    // the input stream is optional, output keeps running without it
+    let (input, capture) = match (
+        &settings.headless,
+        settings.input_device.as_ref(),
+        settings.input_config.as_ref(),
+    ) {
+        (None, Some(input_device), Some(input_config)) => {
+            match build_input_stream(input_device, input_config, &config) {
+                Ok((input, capture)) => (Some(input), Some(capture)),
+                Err(e) => {
+                    debug!("Not capturing input: {}", e);
+                    (None, None)
+                }
+            }
+        }
+        _ => (None, None),
+    };
+
+    let mut callback = build_callback(&config, capture)?;
+
+    let output = match settings.headless.as_ref() {
+        Some(headless) => {
+            OutputStream::Headless(HeadlessStream::new(headless.clone(), &config, callback)?)
+        }
+        None => {
+            let output_device = match settings.output_device.as_ref() {
+                Some(output_device) => output_device,
+                None => {
+                    return Err("OUTPUT_DEVICE is None".to_owned());
+                }
+            };
+
+            let data_callback =
+                move |data: &mut [f32], _: &cpal::OutputCallbackInfo| callback(data);
+            let err_fn = |err| eprintln!("an error occurred on stream: {}", err);
+            let output_stream =
+                match output_device.build_output_stream(&config, data_callback, err_fn, None) {
+                    Ok(stream) => stream,
+                    Err(err) => {
+                        return Err(format!("Error building output stream: {}", err));
+                    }
+                };
+
+            match output_stream.play() {
+                Ok(()) => {}
+                Err(err) => {
+                    return Err(format!("Error playing output stream: {}", err));
+                }
+            }
+            OutputStream::Device(output_stream)
+        }
+    };
+
+    match input.as_ref() {
+        Some(input) => {
+            let _ = input.play();
+        }
+        None => {}
+    }
+
+    Ok(Streams { output, input })
+}
+
+/// ## `take_reload() -> bool`
+///
+/// Reads and clears RELOAD.
+///
+/// ### Returns
+///
+/// * `bool` - Whether a reload was requested
+fn take_reload() -> bool {
+    match RELOAD.write() {
+        Ok(mut reload) => std::mem::replace(&mut *reload, false),
+        Err(e) => {
+            debug!("Error locking RELOAD: {}", e);
+            false
+        }
+    }
+}
+
+/// ## `audio_thread() -> Result<(), String>`
+///
+/// Starts the audio thread. The thread rebuilds its streams whenever RELOAD is set, keeping the strips
+/// and their effect state, and falls back to the last working settings if the new ones fail.
+/// If the audio thread is already running, this only requests a reload.
+///
+/// ### Returns
+///
+/// * `Result<(), String>` - An error message, or nothing if successful
+pub fn audio_thread() -> Result<(), String> {
+    let mut audio_thread = match AUDIO_THREAD.lock() {
+        Ok(audio_thread) => audio_thread,
+        Err(e) => {
+            debug!("Error locking AUDIO_THREAD: {}", e);
+            return Err(format!("Error locking AUDIO_THREAD: {}", e));
+        }
+    };
+
+    match audio_thread.as_ref() {
+        Some(thread) if !thread.is_finished() => {
+            debug!("Audio thread is already running, reloading");
+            return reload();
+        }
+        _ => {}
+    }
+
+    let thread = std::thread::spawn(move || {
+        let mut settings = StreamSettings::current()?;
+        let mut streams = match build_streams(&settings) {
+            Ok(streams) => Some(streams),
+            Err(e) => {
+                debug!("Error building streams: {}", e);
+                None
+            }
+        };
+
+        loop {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            if !take_reload() {
+                continue;
+            }
+
+            let next = match StreamSettings::current() {
+                Ok(next) => next,
+                Err(e) => {
+                    debug!("Error reading stream settings: {}", e);
+                    continue;
+                }
+            };
+
+            // the old streams have to let go of the device and the engine before new ones can be built
+            debug!("Reloading audio streams");
+            drop(streams.take());
+
+            match build_streams(&next) {
+                Ok(next_streams) => {
+                    settings = next;
+                    streams = Some(next_streams);
+                }
+                Err(e) => {
+                    debug!(
+                        "Error rebuilding streams: {}, falling back to the previous settings",
+                        e
+                    );
+                    match settings.restore() {
+                        Ok(()) => {}
+                        Err(e) => debug!("Error restoring stream settings: {}", e),
+                    }
+                    streams = match build_streams(&settings) {
+                        Ok(streams) => Some(streams),
+                        Err(e) => {
+                            debug!("Error building streams: {}", e);
+                            None
+                        }
+                    };
+                }
+            }
+        }
+    });
+
+    *audio_thread = Some(thread);
+
+    Ok(())
+}
+
+/// ## `sample_rate() -> u32`
+///
+/// Returns the sample rate the engine runs at: that of OUTPUT_CONFIG, or 48000 Hz (what renders use) if none is set.
+///
+/// ### Returns
+///
+/// * `u32` - The sample rate
+pub fn sample_rate() -> u32 {
+    match OUTPUT_CONFIG.lock() {
+        Ok(config) => match config.as_ref() {
+            Some(config) => config.sample_rate.0,
+            None => 48000,
+        },
+        Err(e) => {
+            debug!("Error locking OUTPUT_CONFIG: {}", e);
+            48000
+        }
+    }
+}
+
+/// ## `render(seconds: f32, path: &str) -> Result<String, String>`
+///
+/// Renders the strip graph to a 32 bit float WAV file without opening any device.
+/// Uses the sample rate, channels and buffer size of OUTPUT_CONFIG if one is set, otherwise 48000 Hz stereo in blocks of 1024.
+/// If a stream is playing, it is silent while the render runs.
+///
+/// ### Arguments
+///
+/// * `seconds: f32` - The length of the render
+/// * `path: &str` - The file to write
+///
+/// ### Returns
+///
+/// * `Result<String, String>` - A description of what was rendered, or an error message
+pub fn render(seconds: f32, path: &str) -> Result<String, String> {
+    if !(seconds > 0.0) {
+        return Err(format!("Cannot render {} seconds", seconds));
+    }
+
+    let (sample_rate, channels, block_size) = match OUTPUT_CONFIG.lock() {
+        Ok(config) => match config.as_ref() {
+            Some(config) => (
+                config.sample_rate.0,
+                config.channels as usize,
+                match config.buffer_size {
+                    BufferSize::Fixed(buffer_size) => buffer_size as usize,
+                    BufferSize::Default => 1024,
+                },
+            ),
+            None => (48000, 2, 1024),
+        },
+        Err(e) => {
+            return Err(format!("Error locking OUTPUT_CONFIG: {}", e));
+        }
+    };
+
+    let spec = hound::WavSpec {
+        channels: channels as u16,
+        sample_rate,
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Float,
+    };
+    let mut writer = match hound::WavWriter::create(path, spec) {
+        Ok(writer) => writer,
+        Err(e) => {
+            return Err(format!("Error creating {}: {}", path, e));
+        }
+    };
+
+    let mut engine = match CONTROL.lock() {
+        Ok(mut control) => control.borrow_engine()?,
+        Err(e) => {
+            return Err(format!("Error locking CONTROL: {}", e));
+        }
+    };
+
+    let total_frames = (seconds as f64 * sample_rate as f64).round() as u64;
+    let mut input_buffer = Buffer::new(0, block_size);
+    let mut output_buffer = Buffer::new(channels, block_size);
+    let mut sample_clock = 0u64;
+    let mut result = Ok(());
+    while sample_clock < total_frames && result.is_ok() {
+        let frames = (total_frames - sample_clock).min(block_size as u64) as usize;
+        let state = State::advance(sample_rate, &mut sample_clock, frames);
+        input_buffer.resize(0, frames);
+        output_buffer.resize(channels, frames);
+        engine.process(&state, &input_buffer, &mut output_buffer);
+
+        'frames: for index in 0..frames {
+            for channel in 0..channels {
+                result = writer.write_sample(output_buffer.channel(channel)[index]);
+                if result.is_err() {
+                    break 'frames;
+                }
+            }
+        }
+    }
+
+    match CONTROL.lock() {
+        Ok(mut control) => control.return_engine(engine),
+        Err(e) => {
+            return Err(format!("Error locking CONTROL: {}", e));
+        }
+    }
+
+    match result.and_then(|()| writer.finalize()) {
+        Ok(()) => Ok(format!(
+            "Rendered {} seconds ({} frames, {} channels at {} Hz) to {}",
+            seconds, total_frames, channels, sample_rate, path
+        )),
+        Err(e) => Err(format!("Error writing {}: {}", path, e)),
+    }
+}
+
+/// ## Sample
+///
+/// Represents a sample of audio data. Can be either mono or stereo.
+///
+/// ### Variants
+///
+/// * `Mono(f32)` - A mono sample
+/// * `Stereo(f32, f32)` - A stereo sample
+///
+/// ### Functions
+///
+/// * `mono(&self) -> f32` - Returns the mono version of the sample
+/// * `stereo(&self) -> (f32, f32)` - Returns the stereo version of the sample
+/// * `left(&self) -> f32` - Returns the left channel of the sample
+/// * `right(&self) -> f32` - Returns the right channel of the sample
+#[derive(Clone, Debug)]
+pub enum Sample {
+    Mono(f32),
+    Stereo(f32, f32),
+}
+
+impl Sample {
+    /// ## `mono(&self) -> f32`
+    ///
+    /// Returns the mono version of the sample.
+    ///
+    /// ### Returns
+    ///
+    /// * `f32` - The mono version of the sample
+    pub fn mono(&self) -> f32 {
+        match self {
+            Sample::Mono(sample) => *sample,
+            Sample::Stereo(left, right) => (*left + *right) / 2.0,
+        }
+    }
+
+    /// ## `stereo(&self) -> (f32, f32)`
+    ///
+    /// Returns the stereo version of the sample.
+    ///
+    /// ### Returns
+    ///
+    /// * `(f32, f32)` - The stereo version of the sample
+    pub fn stereo(&self) -> (f32, f32) {
+        match self {
+            Sample::Mono(sample) => (*sample, *sample),
+            Sample::Stereo(left, right) => (*left, *right),
+        }
+    }
+
+    /// ## `left(&self) -> f32`
+    ///
+    /// Returns the left channel of the sample.
+    ///
+    /// ### Returns
+    ///
+    /// * `f32` - The left channel of the sample
+    pub fn left(&self) -> f32 {
+        match self {
+            Sample::Mono(sample) => *sample,
+            Sample::Stereo(left, _right) => *left,
+        }
+    }
+
+    /// ## `right(&self) -> f32`
+    ///
+    /// Returns the right channel of the sample.
+    ///
+    /// ### Returns
+    ///
+    /// * `f32` - The right channel of the sample
+    pub fn right(&self) -> f32 {
+        match self {
+            Sample::Mono(sample) => *sample,
+            Sample::Stereo(_left, right) => *right,
+        }
+    }
+
+    /// ## `as_mono(&self) -> Sample`
+    ///
+    /// Returns the mono version of the sample.
+    ///
+    /// ### Returns
+    ///
+    /// * `Sample` - The mono version of the sample
+    pub fn as_mono(&self) -> Sample {
+        Sample::Mono(self.mono())
+    }
+
+    /// ## `as_stereo(&self) -> Sample`
+    ///
+    /// Returns the stereo version of the sample.
+    ///
+    /// ### Returns
+    ///
+    /// * `Sample` - The stereo version of the sample
+    pub fn as_stereo(&self) -> Sample {
+        Sample::Stereo(self.left(), self.right())
+    }
+}
+
+/// ## Buffer
+///
+/// A block of non-interleaved audio, one list of samples per channel.
+/// Effects and generators process a whole buffer at a time.
+///
+/// ### Fields
+///
+/// * `channels: Vec<Vec<f32>>` - The samples of each channel
+/// * `len: usize` - The number of frames in the buffer
+///
+/// ### Functions
+///
+/// * `new(channels: usize, len: usize) -> Self` - Creates a silent buffer
+/// * `with_capacity(channels: usize, capacity: usize) -> Self` - Creates an empty buffer with room to grow
+/// * `channels(&self) -> usize` - Returns the number of channels
+/// * `len(&self) -> usize` - Returns the number of frames
+/// * `resize(&mut self, channels: usize, len: usize)` - Changes the number of channels and frames
+/// * `clear(&mut self)` - Fills the buffer with silence
+/// * `channel(&self, channel: usize) -> &[f32]` - Returns the samples of a channel
+/// * `channel_mut(&mut self, channel: usize) -> &mut [f32]` - Returns the samples of a channel, mutably
+/// * `stereo_mut(&mut self) -> (&mut [f32], &mut [f32])` - Returns the first two channels, mutably
+/// * `frame(&self, index: usize) -> Sample` - Returns one frame as a sample
+/// * `set_frame(&mut self, index: usize, sample: Sample)` - Writes one frame from a sample
+/// * `copy_from(&mut self, other: &Buffer)` - Copies another buffer into this one
+/// * `mix_from(&mut self, other: &Buffer)` - Adds another buffer onto this one
+#[derive(Clone, Debug)]
+pub struct Buffer {
+    channels: Vec<Vec<f32>>,
+    len: usize,
+}
+
+impl Buffer {
+    /// ## `new(channels: usize, len: usize) -> Self`
+    ///
+    /// Creates a silent buffer.
+    ///
+    /// ### Arguments
+    ///
+    /// * `channels: usize` - The number of channels
+    /// * `len: usize` - The number of frames
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The new buffer
+    pub fn new(channels: usize, len: usize) -> Self {
+        Self {
+            channels: vec![vec![0.0; len]; channels],
+            len,
+        }
+    }
+
+    /// ## `with_capacity(channels: usize, capacity: usize) -> Self`
+    ///
+    /// Creates an empty buffer with room for `capacity` frames before resizing has to allocate.
+    ///
+    /// ### Arguments
+    ///
+    /// * `channels: usize` - The number of channels
+    /// * `capacity: usize` - The number of frames to make room for
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The new buffer
+    pub fn with_capacity(channels: usize, capacity: usize) -> Self {
+        Self {
+            channels: vec![Vec::with_capacity(capacity); channels],
+            len: 0,
+        }
+    }
+
+    /// ## `channels(&self) -> usize`
+    ///
+    /// Returns the number of channels.
+    ///
+    /// ### Returns
+    ///
+    /// * `usize` - The number of channels
+    pub fn channels(&self) -> usize {
+        self.channels.len()
+    }
+
+    /// ## `len(&self) -> usize`
+    ///
+    /// Returns the number of frames.
+    ///
+    /// ### Returns
+    ///
+    /// * `usize` - The number of frames
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// ## `resize(&mut self, channels: usize, len: usize)`
+    ///
+    /// Changes the number of channels and frames. New samples are silent.
+    /// Only allocates when the buffer grows past what it has held before.
+    ///
+    /// ### Arguments
+    ///
+    /// * `channels: usize` - The number of channels
+    /// * `len: usize` - The number of frames
+    pub fn resize(&mut self, channels: usize, len: usize) {
+        self.channels.resize(channels, Vec::new());
+        for channel in self.channels.iter_mut() {
+            channel.resize(len, 0.0);
+        }
+        self.len = len;
+    }
+
+    /// ## `clear(&mut self)`
+    ///
+    /// Fills the buffer with silence.
+    pub fn clear(&mut self) {
+        for channel in self.channels.iter_mut() {
+            for sample in channel.iter_mut() {
+                *sample = 0.0;
+            }
+        }
+    }
+
+    /// ## `channel(&self, channel: usize) -> &[f32]`
+    ///
+    /// Returns the samples of a channel.
+    ///
+    /// ### Arguments
+    ///
+    /// * `channel: usize` - The channel
+    ///
+    /// ### Returns
+    ///
+    /// * `&[f32]` - The samples
+    pub fn channel(&self, channel: usize) -> &[f32] {
+        &self.channels[channel][..self.len]
+    }
+
+    /// ## `channel_mut(&mut self, channel: usize) -> &mut [f32]`
+    ///
+    /// Returns the samples of a channel, mutably.
+    ///
+    /// ### Arguments
+    ///
+    /// * `channel: usize` - The channel
+    ///
+    /// ### Returns
+    ///
+    /// * `&mut [f32]` - The samples
+    pub fn channel_mut(&mut self, channel: usize) -> &mut [f32] {
+        let len = self.len;
+        &mut self.channels[channel][..len]
+    }
+
+    /// ## `stereo_mut(&mut self) -> (&mut [f32], &mut [f32])`
+    ///
+    /// Returns the left and right channels, mutably. The buffer must have at least two channels.
+    ///
+    /// ### Returns
+    ///
+    /// * `(&mut [f32], &mut [f32])` - The left and right samples
+    pub fn stereo_mut(&mut self) -> (&mut [f32], &mut [f32]) {
+        let len = self.len;
+        let (left, right) = self.channels.split_at_mut(1);
+        (&mut left[0][..len], &mut right[0][..len])
+    }
+
+    /// ## `frame(&self, index: usize) -> Sample`
+    ///
+    /// Returns one frame as a sample. Mono buffers give a mono sample, anything else gives the first two channels.
+    ///
+    /// ### Arguments
+    ///
+    /// * `index: usize` - The frame
+    ///
+    /// ### Returns
+    ///
+    /// * `Sample` - The frame
+    pub fn frame(&self, index: usize) -> Sample {
+        match self.channels.len() {
+            0 => Sample::Mono(0.0),
+            1 => Sample::Mono(self.channels[0][index]),
+            _ => Sample::Stereo(self.channels[0][index], self.channels[1][index]),
+        }
+    }
+
+    /// ## `set_frame(&mut self, index: usize, sample: Sample)`
+    ///
+    /// Writes one frame from a sample. Mono buffers get the mono version of the sample,
+    /// anything else gets the left and right channels in its first two channels.
+    ///
+    /// ### Arguments
+    ///
+    /// * `index: usize` - The frame
+    /// * `sample: Sample` - The sample to write
+    pub fn set_frame(&mut self, index: usize, sample: Sample) {
+        match self.channels.len() {
+            0 => {}
+            1 => self.channels[0][index] = sample.mono(),
+            _ => {
+                self.channels[0][index] = sample.left();
+                self.channels[1][index] = sample.right();
+            }
+        }
+    }
+
+    /// ## `copy_from(&mut self, other: &Buffer)`
+    ///
+    /// Copies another buffer into this one, keeping this buffer's channel count and length.
+    /// A mono buffer is copied into every channel; missing channels and frames are silent.
+    ///
+    /// ### Arguments
+    ///
+    /// * `other: &Buffer` - The buffer to copy
+    pub fn copy_from(&mut self, other: &Buffer) {
+        let len = self.len.min(other.len());
+        for channel in 0..self.channels() {
+            let source = match other.channels() {
+                0 => None,
+                1 => Some(0),
+                n if channel < n => Some(channel),
+                _ => None,
+            };
+            let samples = self.channel_mut(channel);
+            match source {
+                Some(source) => samples[..len].copy_from_slice(&other.channel(source)[..len]),
+                None => {
+                    for sample in samples[..len].iter_mut() {
+                        *sample = 0.0;
+                    }
+                }
+            }
+            for sample in samples[len..].iter_mut() {
+                *sample = 0.0;
+            }
+        }
+    }
+
+    /// ## `mix_from(&mut self, other: &Buffer)`
+    ///
+    /// Adds another buffer onto this one, channel by channel. A mono buffer is added to every channel.
+    ///
+    /// ### Arguments
+    ///
+    /// * `other: &Buffer` - The buffer to add
+    pub fn mix_from(&mut self, other: &Buffer) {
+        let len = self.len.min(other.len());
+        for channel in 0..self.channels() {
+            let source = match other.channels() {
+                0 => continue,
+                1 => 0,
+                n if channel < n => channel,
+                _ => continue,
+            };
+            for (sample, other) in self.channels[channel][..len]
+                .iter_mut()
+                .zip(other.channel(source)[..len].iter())
+            {
+                *sample += *other;
+            }
+        }
+    }
+
+    /// ## `copy_frames_from(&mut self, other: &Buffer, start: usize, len: usize)`
+    ///
+    /// Resizes this buffer to `len` frames of another buffer's channels, and copies those frames from `start` onwards.
+    /// Frames past the end of the other buffer are silent.
+    ///
+    /// ### Arguments
+    ///
+    /// * `other: &Buffer` - The buffer to copy from
+    /// * `start: usize` - The first frame to copy
+    /// * `len: usize` - The number of frames to copy
+    pub fn copy_frames_from(&mut self, other: &Buffer, start: usize, len: usize) {
+        self.resize(other.channels(), len);
+        let available = len.min(other.len().saturating_sub(start));
+        for channel in 0..self.channels() {
+            let samples = &mut self.channels[channel][..len];
+            match available {
+                0 => {}
+                _ => samples[..available]
+                    .copy_from_slice(&other.channel(channel)[start..start + available]),
+            }
+            for sample in samples[available..].iter_mut() {
+                *sample = 0.0;
+            }
+        }
+    }
+
+    /// ## `copy_frames_to(&self, other: &mut Buffer, start: usize)`
+    ///
+    /// Copies this buffer into another one, from frame `start` onwards. Channels the other buffer lacks are left out.
+    ///
+    /// ### Arguments
+    ///
+    /// * `other: &mut Buffer` - The buffer to copy into
+    /// * `start: usize` - The frame of the other buffer to start at
+    pub fn copy_frames_to(&self, other: &mut Buffer, start: usize) {
+        let len = self.len.min(other.len().saturating_sub(start));
+        for channel in 0..self.channels().min(other.channels()) {
+            other.channel_mut(channel)[start..start + len]
+                .copy_from_slice(&self.channel(channel)[..len]);
+        }
+    }
+}
+
+/// ## State
+///
+/// Represents the current state of the audio engine. Primarily configuration settings needed by the effects,
+/// and details on what the sample clock and the transport are currently at.
+///
+/// ### Fields
+///
+/// * `sample_rate: u32` - The sample rate of the audio engine
+/// * `sample_clock: u64` - The sample clock at the first frame of the block being processed
+/// * `buffer_size: usize` - The number of frames in the block being processed
+/// * `transport: Transport` - The tempo, time signature and musical position at the first frame of the block
+#[derive(Clone, Debug)]
+pub struct State {
+    pub sample_rate: u32,
+    pub sample_clock: u64,
+    pub buffer_size: usize,
+    pub transport: Transport,
+}
+
+impl State {
+    /// ## `advance(sample_rate: u32, sample_clock: &mut u64, frames: usize) -> State`
+    ///
+    /// Returns the state of the next block and moves the sample clock past it.
+    /// The live callback and offline renders both go through here, so they see the same progression.
+    /// The transport is filled in by the engine, which owns it.
+    ///
+    /// ### Arguments
+    ///
+    /// * `sample_rate: u32` - The sample rate being processed at
+    /// * `sample_clock: &mut u64` - The sample clock at the start of the block, advanced to the start of the next one
+    /// * `frames: usize` - The number of frames in the block
+    ///
+    /// ### Returns
+    ///
+    /// * `State` - The state of the block
+    pub fn advance(sample_rate: u32, sample_clock: &mut u64, frames: usize) -> State {
+        let state = State {
+            sample_rate,
+            sample_clock: *sample_clock,
+            buffer_size: frames,
+            transport: Transport::default(),
+        };
+        *sample_clock += frames as u64;
+        state
+    }
+
+    /// ## `at(&self, frame: usize) -> State`
+    ///
+    /// Returns the state at a frame within the block, for code that still works one frame at a time.
+    ///
+    /// ### Arguments
+    ///
+    /// * `frame: usize` - The index of the frame in the block
+    ///
+    /// ### Returns
+    ///
+    /// * `State` - The state with the sample clock and transport advanced to the frame
+    pub fn at(&self, frame: usize) -> State {
+        State {
+            sample_rate: self.sample_rate,
+            sample_clock: self.sample_clock + frame as u64,
+            buffer_size: self.buffer_size,
+            transport: self.transport.advanced(frame, self.sample_rate),
+        }
+    }
+
+    /// ## `part(&self, start: usize, frames: usize) -> State`
+    ///
+    /// Returns the state of part of the block, for when the block is split (e.g. at sequencer notes).
+    ///
+    /// ### Arguments
+    ///
+    /// * `start: usize` - The first frame of the part
+    /// * `frames: usize` - The number of frames in the part
+    ///
+    /// ### Returns
+    ///
+    /// * `State` - The state of the part
+    pub fn part(&self, start: usize, frames: usize) -> State {
+        State {
+            sample_rate: self.sample_rate,
+            sample_clock: self.sample_clock + start as u64,
+            buffer_size: frames,
+            transport: self.transport.advanced(start, self.sample_rate),
+        }
+    }
+}
+
+/// ## Output
+///
+/// Represents an output channel.
+///
+/// ### Variants
+///
+/// * `Mono(u32)` - A mono output channel. The u32 represents the output channel number, tied to the interface.
+/// * `Stereo(u32, u32)` - A stereo output channel. The u32s represent the left and right output channel numbers, tied to the interface.
+/// * `Bus(String)` - A named bus. Every strip outputting to the same bus is summed into it.
+pub enum Output {
+    Mono(u32),
+    Stereo(u32, u32),
+    Bus(String),
+}
+
+impl std::fmt::Display for Output {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Output::Mono(channel) => write!(f, "{}", channel),
+            Output::Stereo(left_channel, right_channel) => {
+                write!(f, "{},{}", left_channel, right_channel)
+            }
+            Output::Bus(name) => write!(f, "bus:{}", name),
+        }
+    }
+}
+
+/// ## Input
+///
+/// Represents an input channel.
+///
+/// ### Variants
+///
+/// * `Mono(u32)` - A mono input channel. The u32 represents the input channel number, tied to the interface.
+/// * `Stereo(u32, u32)` - A stereo input channel. The u32s represent the left and right input channel numbers, tied to the interface.
+/// * `Generator(Box<dyn Generator>)` - A generator input channel, owned by the strip (control code reaches it through `Command::NoteOn` and friends)
+/// * `Bus(String)` - A named bus. Reads the sum of every strip outputting to the bus.
+pub enum Input {
+    Mono(u32),
+    Stereo(u32, u32),
+    Generator(Box<dyn plugin::Generator>),
+    Bus(String),
+}
+
+impl std::fmt::Display for Input {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Input::Mono(channel) => write!(f, "{}", channel),
+            Input::Stereo(left_channel, right_channel) => {
+                write!(f, "{},{}", left_channel, right_channel)
+            }
+            Input::Generator(_generator) => write!(f, "generator"),
+            Input::Bus(name) => write!(f, "bus:{}", name),
+        }
+    }
+}
+
+/// ## `db_to_gain(db: f32) -> f32`
+///
+/// Converts a level in decibels to a linear gain.
+///
+/// ### Arguments
+///
+/// * `db: f32` - The level in dB
+///
+/// ### Returns
+///
+/// * `f32` - The linear gain
+pub fn db_to_gain(db: f32) -> f32 {
+    10.0f32.powf(db / 20.0)
+}
+
+/// ## `gain_to_db(gain: f32) -> f32`
+///
+/// Converts a linear gain to decibels. Silence is negative infinity.
+///
+/// ### Arguments
+///
+/// * `gain: f32` - The linear gain
+///
+/// ### Returns
+///
+/// * `f32` - The level in dB
+pub fn gain_to_db(gain: f32) -> f32 {
+    20.0 * gain.abs().log10()
+}
+
+/// ## `hermite(y0: f32, y1: f32, y2: f32, y3: f32, t: f32) -> f32`
+///
+/// Interpolates between two samples with a 4-point, third order Hermite spline, using the samples either side of them.
+///
+/// ### Arguments
+///
+/// * `y0: f32` - The sample before `y1`
+/// * `y1: f32` - The sample to interpolate from
+/// * `y2: f32` - The sample to interpolate to
+/// * `y3: f32` - The sample after `y2`
+/// * `t: f32` - How far from `y1` to `y2` (0.0-1.0)
+///
+/// ### Returns
+///
+/// * `f32` - The interpolated sample
+pub fn hermite(y0: f32, y1: f32, y2: f32, y3: f32, t: f32) -> f32 {
+    let c1 = 0.5 * (y2 - y0);
+    let c2 = y0 - 2.5 * y1 + 2.0 * y2 - 0.5 * y3;
+    let c3 = 0.5 * (y3 - y0) + 1.5 * (y1 - y2);
+    ((c3 * t + c2) * t + c1) * t + y1
+}
+
+/// ## PanLaw
+///
+/// How much each side is attenuated as a strip is panned, named after the level of a centered signal.
+///
+/// ### Variants
+///
+/// * `Balance` - 0 dB in the center; the far side is turned down, the near side stays at unity. Suits stereo sources.
+/// * `ConstantPower` - -3 dB in the center (sine/cosine), constant loudness across the field
+/// * `Compromise` - -4.5 dB in the center, halfway between constant power and linear
+/// * `Linear` - -6 dB in the center, constant amplitude across the field
+///
+/// ### Functions
+///
+/// * `from_name(name: &str) -> Option<Self>` - Gets a pan law by name ("balance", "constant", "compromise", "linear") or center level ("0", "-3", "-4.5", "-6")
+/// * `name(&self) -> &'static str` - Returns the name of the pan law
+/// * `gains(&self, pan: f32) -> (f32, f32)` - Returns the left and right gain for a pan position
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PanLaw {
+    Balance,
+    ConstantPower,
+    Compromise,
+    Linear,
+}
+
+impl PanLaw {
+    /// ## `from_name(name: &str) -> Option<Self>`
+    ///
+    /// Gets a pan law by name or center level. Not case-sensitive.
+    ///
+    /// ### Arguments
+    ///
+    /// * `name: &str` - "balance", "constant", "compromise" or "linear", or "0", "-3", "-4.5" or "-6"
+    ///
+    /// ### Returns
+    ///
+    /// * `Option<Self>` - The pan law, or None if the name is unknown
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "balance" | "0" => Some(PanLaw::Balance),
+            "constant" | "constantpower" | "-3" => Some(PanLaw::ConstantPower),
+            "compromise" | "-4.5" => Some(PanLaw::Compromise),
+            "linear" | "-6" => Some(PanLaw::Linear),
+            _ => None,
+        }
+    }
+
+    /// ## `name(&self) -> &'static str`
+    ///
+    /// Returns the name of the pan law.
+    ///
+    /// ### Returns
+    ///
+    /// * `&'static str` - The name
+    pub fn name(&self) -> &'static str {
+        match self {
+            PanLaw::Balance => "balance",
+            PanLaw::ConstantPower => "constant",
+            PanLaw::Compromise => "compromise",
+            PanLaw::Linear => "linear",
+        }
+    }
+
+    /// ## `gains(&self, pan: f32) -> (f32, f32)`
+    ///
+    /// Returns the left and right gain for a pan position.
+    ///
+    /// ### Arguments
+    ///
+    /// * `pan: f32` - The pan position, from -1.0 (left) to 1.0 (right)
+    ///
+    /// ### Returns
+    ///
+    /// * `(f32, f32)` - The left and right gain
+    pub fn gains(&self, pan: f32) -> (f32, f32) {
+        let pan = pan.clamp(-1.0, 1.0);
+        // position from 0.0 (left) to 1.0 (right)
+        let position = (pan + 1.0) / 2.0;
+        let angle = position * std::f32::consts::FRAC_PI_2;
+
+        match self {
+            PanLaw::Balance => ((1.0 - pan).min(1.0), (1.0 + pan).min(1.0)),
+            PanLaw::ConstantPower => (angle.cos(), angle.sin()),
+            PanLaw::Compromise => (
+                ((1.0 - position) * angle.cos()).sqrt(),
+                (position * angle.sin()).sqrt(),
+            ),
+            PanLaw::Linear => (1.0 - position, position),
+        }
+    }
+}
+
+/// ## Mixer
+///
+/// The mixer controls of a strip, applied after its effect chain.
+///
+/// ### Fields
+///
+/// * `gain: f32` - The gain in dB
+/// * `pan: f32` - The pan position, from -1.0 (left) to 1.0 (right)
+/// * `pan_law: PanLaw` - How the sides are attenuated as the strip is panned
+/// * `mute: bool` - Whether the strip is muted
+/// * `solo: bool` - Whether the strip is soloed. While any strip is soloed, strips that are not soloed and output to the device are silent.
+///
+/// ### Functions
+///
+/// * `gains(&self, silenced: bool) -> (f32, f32)` - Returns the left and right gain
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mixer {
+    pub gain: f32,
+    pub pan: f32,
+    pub pan_law: PanLaw,
+    pub mute: bool,
+    pub solo: bool,
+}
+
+impl Default for Mixer {
+    fn default() -> Self {
+        // balance keeps a centered strip at unity, so strips sound the same until they are panned
+        Self {
+            gain: 0.0,
+            pan: 0.0,
+            pan_law: PanLaw::Balance,
+            mute: false,
+            solo: false,
+        }
+    }
+}
+
+impl Mixer {
+    /// ## `gains(&self, silenced: bool) -> (f32, f32)`
+    ///
+    /// Returns the left and right gain, combining gain, pan and mute.
+    ///
+    /// ### Arguments
+    ///
+    /// * `silenced: bool` - Whether another strip's solo silences this one
+    ///
+    /// ### Returns
+    ///
+    /// * `(f32, f32)` - The left and right gain
+    pub fn gains(&self, silenced: bool) -> (f32, f32) {
+        if self.mute || silenced {
+            return (0.0, 0.0);
+        }
+
+        let gain = db_to_gain(self.gain);
+        let (left, right) = self.pan_law.gains(self.pan);
+        (left * gain, right * gain)
+    }
+}
+
+impl std::fmt::Display for Mixer {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{:+.1} dB, pan {:+.2} ({})",
+            self.gain,
+            self.pan,
+            self.pan_law.name()
+        )?;
+        if self.mute {
+            write!(f, ", muted")?;
+        }
+        if self.solo {
+            write!(f, ", solo")?;
+        }
+        Ok(())
+    }
+}
+
+/// ## `ramp(samples: &mut [f32], from: f32, to: f32)`
+///
+/// Multiplies a block by a gain that moves linearly from one value to another across the block,
+/// so gain changes don't click.
+///
+/// ### Arguments
+///
+/// * `samples: &mut [f32]` - The block
+/// * `from: f32` - The gain at the start of the block
+/// * `to: f32` - The gain at the end of the block
+pub fn ramp(samples: &mut [f32], from: f32, to: f32) {
+    if from == to {
+        match to == 1.0 {
+            true => {}
+            false => {
+                for sample in samples.iter_mut() {
+                    *sample *= to;
+                }
+            }
+        }
+        return;
+    }
+
+    let step = (to - from) / samples.len().max(1) as f32;
+    for (index, sample) in samples.iter_mut().enumerate() {
+        *sample *= from + step * (index + 1) as f32;
+    }
+}
+
+/// ## Master
+///
+/// The master bus: every strip that outputs to the device is summed into it, then it runs its own effect chain
+/// and mixer controls before the block is handed to the device. Even device channels are panned as left and odd
+/// ones as right; the master bus can't be soloed.
+///
+/// ### Fields
+///
+/// * `chain: Vec<Box<dyn Effect>>` - The chain of effects
+/// * `mixer: Mixer` - The gain, pan and mute
+/// * `applied: (f32, f32)` - The left and right linear gain applied at the end of the last block
+/// * `meter: Meter` - Measures the block handed to the device
+///
+/// ### Functions
+///
+/// * `new() -> Self` - Creates a master bus with an empty chain at unity gain
+/// * `process(&mut self, state: &State, buffer: &mut Buffer)` - Runs the chain and mixer controls over the summed block
+pub struct Master {
+    chain: Vec<Box<dyn plugin::Effect>>,
+    mixer: Mixer,
+    applied: (f32, f32),
+    meter: Meter,
+}
+
+impl Master {
+    /// ## `new() -> Self`
+    ///
+    /// Creates a master bus with an empty chain at unity gain.
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The new master bus
+    pub fn new() -> Self {
+        Self {
+            chain: Vec::new(),
+            mixer: Mixer::default(),
+            applied: (1.0, 1.0),
+            meter: Meter::new(),
+        }
+    }
+
+    /// ## `process(&mut self, state: &State, buffer: &mut Buffer, keys: &Keys)`
+    ///
+    /// Runs the effect chain, then the mixer controls, over the summed block.
+    ///
+    /// ### Arguments
+    ///
+    /// * `state: &State` - The current state of the audio engine
+    /// * `buffer: &mut Buffer` - The summed block, one channel per output channel
+    /// * `keys: &Keys` - The strips' blocks, for effects with a sidechain
+    pub fn process(&mut self, state: &State, buffer: &mut Buffer, keys: &Keys) {
+        for effect in self.chain.iter_mut() {
+            keys.key(effect.as_mut());
+            effect.process(state, buffer);
+        }
+
+        // a single channel isn't panned
+        let gains = match buffer.channels() {
+            1 => Mixer {
+                pan: 0.0,
+                pan_law: PanLaw::Balance,
+                ..self.mixer
+            }
+            .gains(false),
+            _ => self.mixer.gains(false),
+        };
+        for channel in 0..buffer.channels() {
+            match channel % 2 {
+                0 => ramp(buffer.channel_mut(channel), self.applied.0, gains.0),
+                _ => ramp(buffer.channel_mut(channel), self.applied.1, gains.1),
+            }
+        }
+        self.applied = gains;
+
+        self.meter.process(state, buffer);
+    }
+}
+
+/// ## Target
+///
+/// What a mixer or effect command applies to.
+///
+/// ### Variants
+///
+/// * `Strip(usize)` - The strip with the given index
+/// * `Master` - The master bus
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Target {
+    Strip(usize),
+    Master,
+}
+
+impl std::fmt::Display for Target {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Target::Strip(index) => write!(f, "strip {}", index),
+            Target::Master => write!(f, "master"),
+        }
+    }
+}
+
+/// ## Strip
+///
+/// Represents a strip of audio effects.
+///
+/// ### Fields
+///
+/// * `input: Input` - The input channel
+/// * `chain: Vec<Box<dyn Effect>>` - The chain of effects
+/// * `output: Output` - The output channel
+/// * `mixer: Mixer` - Gain, pan, mute and solo, applied after the chain
+/// * `gains: (f32, f32)` - The left and right gain applied at the end of the last block
+/// * `meter: Meter` - Measures the strip after the mixer
+/// * `buffer: Buffer` - The stereo block the strip processes
+/// * `processed: bool` - Whether `buffer` holds the part of the block being processed, so it can key other strips
+///
+/// ### Functions
+///
+/// * `new(input: Input, output: Output) -> Self` - Creates a new strip
+/// * `add_effect(&mut self, effect: Box<dyn Effect>)` - Adds an effect to the end of the chain
+/// * `insert_effect(&mut self, effect: Box<dyn Effect>, index: usize)` - Inserts an effect into the chain at the given index
+/// * `remove_effect(&mut self, index: usize)` - Removes an effect from the chain at the given index
+/// * `set_mixer(&mut self, mixer: Mixer)` - Sets the mixer controls
+/// * `mixer(&self) -> &Mixer` - Returns the mixer controls
+/// * `process(&mut self, state: &State, input: &Buffer, bus: &Buffer, silenced: bool)` - Processes a block into the strip's buffer
+/// * `buffer(&self) -> &Buffer` - Returns the last processed block
+pub struct Strip {
+    input: Input,
+    chain: Vec<Box<dyn plugin::Effect>>,
+    output: Output,
+    mixer: Mixer,
+    gains: (f32, f32),
+    meter: Meter,
+    buffer: Buffer,
+    processed: bool,
+}
+
+impl Strip {
+    /// ## `new(input: Input, output: Output) -> Self`
+    ///
+    /// Creates a new strip.
+    ///
+    /// ### Arguments
+    ///
+    /// * `input: Input` - The input channel
+    /// * `output: Output` - The output channel
     ///
     /// ### Returns
     ///
     /// * `Self` - The new strip
     pub fn new(input: Input, output: Output) -> Self {
         Self {
-            input,
-            chain: Vec::new(),
-            output,
+            input,
+            chain: Vec::new(),
+            output,
+            mixer: Mixer::default(),
+            gains: (1.0, 1.0),
+            meter: Meter::new(),
+            buffer: Buffer::with_capacity(2, BLOCK_CAPACITY),
+            processed: false,
+        }
+    }
+
+    /// ## `add_effect(&mut self, effect: Box<dyn Effect>)`
+    ///
+    /// Adds an effect to the end of the chain.
+    ///
+    /// ### Arguments
+    ///
+    /// * `effect: Box<dyn Effect>` - The effect to add
+    pub fn add_effect(&mut self, effect: Box<dyn plugin::Effect>) {
+        self.chain.push(effect);
+    }
+
+    /// ## `insert_effect(&mut self, effect: Box<dyn Effect>, index: usize)`
+    ///
+    /// Inserts an effect into the chain at the given index.
+    ///
+    /// ### Arguments
+    ///
+    /// * `effect: Box<dyn Effect>` - The effect to insert
+    /// * `index: usize` - The index to insert the effect at
+    pub fn insert_effect(&mut self, effect: Box<dyn plugin::Effect>, index: usize) {
+        self.chain.insert(index, effect);
+    }
+
+    /// ## `remove_effect(&mut self, index: usize)`
+    ///
+    /// Removes an effect from the chain at the given index.
+    ///
+    /// ### Arguments
+    ///
+    /// * `index: usize` - The index to remove the effect from
+    pub fn remove_effect(&mut self, index: usize) {
+        self.chain.remove(index);
+    }
+
+    /// ## `note(&mut self, note: u8, velocity: f32)`
+    ///
+    /// Starts or releases a note on the strip's generator, and passes it to the effects in the chain as a note on channel 0.
+    ///
+    /// ### Arguments
+    ///
+    /// * `note: u8` - The MIDI note number
+    /// * `velocity: f32` - The velocity (0.0-1.0); 0.0 releases the note
+    pub fn note(&mut self, note: u8, velocity: f32) {
+        let message = match velocity > 0.0 {
+            true => ChannelMessage::NoteOn {
+                note,
+                velocity: (velocity * 127.0).round().clamp(1.0, 127.0) as u8,
+            },
+            false => ChannelMessage::NoteOff { note, velocity: 0 },
+        };
+
+        match &mut self.input {
+            Input::Generator(generator) => match velocity > 0.0 {
+                true => generator.note_on(note, velocity),
+                false => generator.note_off(note),
+            },
+            _ => {}
+        }
+        for effect in self.chain.iter_mut() {
+            effect.midi(0, message);
+        }
+    }
+
+    /// ## `midi(&mut self, channel: u8, message: ChannelMessage)`
+    ///
+    /// Passes a MIDI channel message to the strip's generator and to every effect in the chain.
+    ///
+    /// ### Arguments
+    ///
+    /// * `channel: u8` - The MIDI channel (0-15)
+    /// * `message: ChannelMessage` - The message
+    pub fn midi(&mut self, channel: u8, message: ChannelMessage) {
+        match &mut self.input {
+            Input::Generator(generator) => generator.midi(channel, message),
+            _ => {}
+        }
+        for effect in self.chain.iter_mut() {
+            effect.midi(channel, message);
+        }
+    }
+
+    /// ## `input(&self) -> &Input`
+    ///
+    /// Returns the input of the strip.
+    ///
+    /// ### Returns
+    ///
+    /// * `&Input` - The input channel
+    pub fn input(&self) -> &Input {
+        &self.input
+    }
+
+    /// ## `output(&self) -> &Output`
+    ///
+    /// Returns the output of the strip.
+    ///
+    /// ### Returns
+    ///
+    /// * `&Output` - The output channel
+    pub fn output(&self) -> &Output {
+        &self.output
+    }
+
+    /// ## `set_mixer(&mut self, mixer: Mixer)`
+    ///
+    /// Sets the mixer controls. Strips that are already playing should be changed with `Control::set_mixer` instead.
+    ///
+    /// ### Arguments
+    ///
+    /// * `mixer: Mixer` - The mixer controls
+    pub fn set_mixer(&mut self, mixer: Mixer) {
+        self.mixer = mixer;
+    }
+
+    /// ## `mixer(&self) -> &Mixer`
+    ///
+    /// Returns the mixer controls.
+    ///
+    /// ### Returns
+    ///
+    /// * `&Mixer` - The mixer controls
+    pub fn mixer(&self) -> &Mixer {
+        &self.mixer
+    }
+
+    /// ## `buffer(&self) -> &Buffer`
+    ///
+    /// Returns the last processed block.
+    ///
+    /// ### Returns
+    ///
+    /// * `&Buffer` - The stereo block
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+
+    /// ## `process(&mut self, state: &State, input: &Buffer, bus: &Buffer, keys: &Keys, silenced: bool)`
+    ///
+    /// Processes a block into the strip's buffer. Strips always process in stereo; mono inputs are copied to both channels.
+    /// Muted and silenced strips still run their chain, so effects keep their state and timing.
+    ///
+    /// ### Arguments
+    ///
+    /// * `state: &State` - The current state of the audio engine
+    /// * `input: &Buffer` - The block captured from the input device, one channel per input channel
+    /// * `bus: &Buffer` - The block of the bus this strip reads from, if its input is a bus
+    /// * `keys: &Keys` - The other strips' blocks, for effects with a sidechain
+    /// * `silenced: bool` - Whether another strip's solo silences this one
+    pub fn process(
+        &mut self,
+        state: &State,
+        input: &Buffer,
+        bus: &Buffer,
+        keys: &Keys,
+        silenced: bool,
+    ) {
+        self.buffer.resize(2, state.buffer_size);
+
+        match &mut self.input {
+            Input::Mono(channel) => {
+                let (left, right) = self.buffer.stereo_mut();
+                for (index, (left, right)) in left.iter_mut().zip(right.iter_mut()).enumerate() {
+                    let sample = match input.channels() > *channel as usize {
+                        true => input.channel(*channel as usize)[index],
+                        false => 0.0,
+                    };
+                    *left = sample;
+                    *right = sample;
+                }
+            }
+            Input::Stereo(left_channel, right_channel) => {
+                let (left, right) = self.buffer.stereo_mut();
+                for (channel, samples) in [(*left_channel, left), (*right_channel, right)] {
+                    match input.channels() > channel as usize {
+                        true => samples.copy_from_slice(input.channel(channel as usize)),
+                        false => {
+                            for sample in samples.iter_mut() {
+                                *sample = 0.0;
+                            }
+                        }
+                    }
+                }
+            }
+            Input::Generator(generator) => generator.generate(state, &mut self.buffer),
+            Input::Bus(_bus) => self.buffer.copy_from(bus),
+        }
+
+        for effect in self.chain.iter_mut() {
+            keys.key(effect.as_mut());
+            effect.process(state, &mut self.buffer);
+        }
+
+        let gains = self.mixer.gains(silenced);
+        let (left, right) = self.buffer.stereo_mut();
+        ramp(left, self.gains.0, gains.0);
+        ramp(right, self.gains.1, gains.1);
+        self.gains = gains;
+
+        self.meter.process(state, &self.buffer);
+    }
+}
+
+/// ## Route
+///
+/// What the control side knows about a strip. The strips themselves are owned by the audio thread,
+/// so this is what routing is validated against and what gets listed in the console.
+///
+/// ### Fields
+///
+/// * `input: String` - The strip's input, as shown in the console
+/// * `output: String` - The strip's output, as shown in the console
+/// * `reads: Option<String>` - The bus the strip reads from
+/// * `writes: Option<String>` - The bus the strip writes to
+/// * `mixer: Mixer` - The strip's mixer controls
+/// * `meter: Arc<Readout>` - The strip's levels, published by the audio thread
+/// * `keys: Vec<Keying>` - How each effect in the strip's chain is keyed
+/// * `curves: Vec<Option<Arc<Curve>>>` - The settings of each effect in the strip's chain that is an EQ
+///
+/// ### Functions
+///
+/// * `new(input: &Input, output: &Output) -> Self` - Creates the route of a strip
+/// * `of(strip: &Strip) -> Self` - Creates the route of a strip, including its mixer, meter and chain
+#[derive(Clone, Debug)]
+pub struct Route {
+    pub input: String,
+    pub output: String,
+    pub reads: Option<String>,
+    pub writes: Option<String>,
+    pub mixer: Mixer,
+    pub meter: Arc<Readout>,
+    pub keys: Vec<Keying>,
+    pub curves: Vec<Option<Arc<Curve>>>,
+}
+
+impl Route {
+    /// ## `new(input: &Input, output: &Output) -> Self`
+    ///
+    /// Creates the route of a strip.
+    ///
+    /// ### Arguments
+    ///
+    /// * `input: &Input` - The strip's input
+    /// * `output: &Output` - The strip's output
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The new route
+    pub fn new(input: &Input, output: &Output) -> Self {
+        Self {
+            input: input.to_string(),
+            output: output.to_string(),
+            reads: match input {
+                Input::Bus(name) => Some(name.clone()),
+                _ => None,
+            },
+            writes: match output {
+                Output::Bus(name) => Some(name.clone()),
+                _ => None,
+            },
+            mixer: Mixer::default(),
+            meter: Arc::new(Readout::new()),
+            keys: Vec::new(),
+            curves: Vec::new(),
+        }
+    }
+
+    /// ## `of(strip: &Strip) -> Self`
+    ///
+    /// Creates the route of a strip that is about to be added, including its mixer, meter and the effects already in its chain.
+    ///
+    /// ### Arguments
+    ///
+    /// * `strip: &Strip` - The strip
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The new route
+    pub fn of(strip: &Strip) -> Self {
+        let mut route = Self::new(&strip.input, &strip.output);
+        route.mixer = strip.mixer;
+        route.meter = strip.meter.readout();
+        route.keys = strip
+            .chain
+            .iter()
+            .map(|effect| Keying::of(effect.as_ref()))
+            .collect();
+        route.curves = strip.chain.iter().map(|effect| effect.curve()).collect();
+        route
+    }
+
+    /// ## `key_strips(&self) -> impl Iterator<Item = usize> + '_`
+    ///
+    /// Private function which returns the strips keying effects on this strip.
+    fn key_strips(&self) -> impl Iterator<Item = usize> + '_ {
+        self.keys.iter().filter_map(|keying| match keying {
+            Keying::Strip(strip) => Some(*strip),
+            _ => None,
+        })
+    }
+}
+
+/// ## Keying
+///
+/// How an effect in a strip's chain is keyed, as far as the control side knows. Strips keying an effect are processed
+/// before the strip the effect is on, like strips writing to a bus it reads from.
+///
+/// ### Variants
+///
+/// * `Fixed` - The effect cannot be keyed
+/// * `Own` - The effect is keyed from its own input
+/// * `Strip(usize)` - The effect is keyed by another strip
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Keying {
+    Fixed,
+    Own,
+    Strip(usize),
+}
+
+impl Keying {
+    /// ## `of(effect: &dyn Effect) -> Self`
+    ///
+    /// Returns how an effect is keyed.
+    ///
+    /// ### Arguments
+    ///
+    /// * `effect: &dyn Effect` - The effect
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - How it is keyed
+    pub fn of(effect: &dyn plugin::Effect) -> Self {
+        match (effect.keyable(), effect.sidechain()) {
+            (false, _) => Keying::Fixed,
+            (true, None) => Keying::Own,
+            (true, Some(strip)) => Keying::Strip(strip),
+        }
+    }
+}
+
+/// ## `processing_order(routes: &[Route]) -> Result<Vec<usize>, String>`
+///
+/// Works out the order strips have to be processed in, so that every strip reading from a bus
+/// runs after all the strips writing to that bus, and every strip with a keyed effect runs after the strips keying it.
+/// Fails if the routing contains a cycle, i.e. a strip feeds (directly or through other buses) into the bus it reads from,
+/// or keys an effect on a strip it depends on.
+///
+/// ### Arguments
+///
+/// * `routes: &[Route]` - The routes of the strips to sort
+///
+/// ### Returns
+///
+/// * `Result<Vec<usize>, String>` - The indices of the strips in processing order, or an error message
+pub fn processing_order(routes: &[Route]) -> Result<Vec<usize>, String> {
+    // the strips each strip has to wait for: the writers to its bus, then the strips keying it
+    let depends_on: Vec<Vec<usize>> = routes
+        .iter()
+        .map(|route| {
+            let mut strips: Vec<usize> = match &route.reads {
+                Some(name) => (0..routes.len())
+                    .filter(|writer| routes[*writer].writes.as_ref() == Some(name))
+                    .collect(),
+                None => Vec::new(),
+            };
+            for key in route.key_strips() {
+                if key < routes.len() && !strips.contains(&key) {
+                    strips.push(key);
+                }
+            }
+            strips
+        })
+        .collect();
+
+    // how many strips each strip is still waiting on
+    let mut waiting_on: Vec<usize> = depends_on.iter().map(|strips| strips.len()).collect();
+
+    let mut ready: VecDeque<usize> = (0..routes.len())
+        .filter(|index| waiting_on[*index] == 0)
+        .collect();
+    let mut order = Vec::with_capacity(routes.len());
+
+    while let Some(index) = ready.pop_front() {
+        order.push(index);
+
+        for (waiter, strips) in depends_on.iter().enumerate() {
+            if strips.contains(&index) {
+                waiting_on[waiter] -= 1;
+                if waiting_on[waiter] == 0 {
+                    ready.push_back(waiter);
+                }
+            }
+        }
+    }
+
+    if order.len() < routes.len() {
+        let mut buses: Vec<String> = Vec::new();
+        let mut keyed: Vec<String> = Vec::new();
+        for (index, route) in routes.iter().enumerate() {
+            if order.contains(&index) {
+                continue;
+            }
+            match &route.reads {
+                Some(name) if !buses.contains(name) => buses.push(name.clone()),
+                _ => {}
+            }
+            if route.key_strips().next().is_some() {
+                keyed.push(index.to_string());
+            }
+        }
+        return Err(match keyed.is_empty() {
+            true => format!(
+                "Routing cycle through bus {}: a strip cannot feed back into a bus it reads from",
+                buses.join(", ")
+            ),
+            false => format!(
+                "Routing cycle through keyed strip {}: a strip cannot be keyed by itself or by a strip it feeds",
+                keyed.join(", ")
+            ),
+        });
+    }
+
+    Ok(order)
+}
+
+/// ## Routing
+///
+/// Everything the audio thread needs to process the strips in order. Built on the control side whenever
+/// the routing changes, so the audio thread only has to swap it in.
+///
+/// ### Fields
+///
+/// * `order: Vec<usize>` - The indices of the strips in processing order
+/// * `buses: Vec<(String, Buffer)>` - Every bus in use, and the sum written to it for the current block
+/// * `routes: Vec<(Option<usize>, Option<usize>)>` - For each strip, the index of the bus it reads from and the bus it writes to
+///
+/// ### Functions
+///
+/// * `new(routes: &[Route]) -> Result<Self, String>` - Works out the routing, refusing cycles
+pub struct Routing {
+    order: Vec<usize>,
+    buses: Vec<(String, Buffer)>,
+    routes: Vec<(Option<usize>, Option<usize>)>,
+}
+
+impl Routing {
+    /// ## `new(routes: &[Route]) -> Result<Self, String>`
+    ///
+    /// Works out the routing of a set of strips.
+    ///
+    /// ### Arguments
+    ///
+    /// * `routes: &[Route]` - The routes of every strip, in the order the strips were added
+    ///
+    /// ### Returns
+    ///
+    /// * `Result<Self, String>` - The routing, or an error message if it contains a cycle
+    pub fn new(routes: &[Route]) -> Result<Self, String> {
+        let order = processing_order(routes)?;
+
+        let mut buses: Vec<(String, Buffer)> = Vec::new();
+        let mut bus_index = |name: &String| match buses.iter().position(|(bus, _)| bus == name) {
+            Some(index) => index,
+            None => {
+                buses.push((name.clone(), Buffer::with_capacity(2, BLOCK_CAPACITY)));
+                buses.len() - 1
+            }
+        };
+
+        let mut bus_routes = Vec::with_capacity(routes.len());
+        for route in routes.iter() {
+            let input = route.reads.as_ref().map(|name| bus_index(name));
+            let output = route.writes.as_ref().map(|name| bus_index(name));
+            bus_routes.push((input, output));
+        }
+
+        Ok(Self {
+            order,
+            buses,
+            routes: bus_routes,
+        })
+    }
+}
+
+/// ## Keys
+///
+/// The blocks of the strips other than the one being processed, for effects keyed by another strip (see `Effect::sidechain`).
+/// Only strips already processed in the current part are handed out, so a key is never a stale or differently sized block;
+/// the processing order puts key strips first (see `processing_order`).
+///
+/// ### Fields
+///
+/// * `before: &[Box<Strip>]` - The strips with lower indices
+/// * `after: &[Box<Strip>]` - The strips with higher indices
+/// * `index: usize` - The index of the strip being processed, or the number of strips for the master bus
+pub struct Keys<'a> {
+    before: &'a [Box<Strip>],
+    after: &'a [Box<Strip>],
+    index: usize,
+}
+
+impl<'a> Keys<'a> {
+    /// ## `get(&self, strip: usize) -> Option<&Buffer>`
+    ///
+    /// Returns the block of a strip.
+    ///
+    /// ### Arguments
+    ///
+    /// * `strip: usize` - The index of the strip
+    ///
+    /// ### Returns
+    ///
+    /// * `Option<&Buffer>` - The block, or None if the strip does not exist, is the one being processed or has not been processed yet
+    pub fn get(&self, strip: usize) -> Option<&Buffer> {
+        let strip = match strip.cmp(&self.index) {
+            std::cmp::Ordering::Less => self.before.get(strip),
+            std::cmp::Ordering::Greater => self.after.get(strip - self.index - 1),
+            std::cmp::Ordering::Equal => None,
+        };
+        match strip {
+            Some(strip) if strip.processed => Some(&strip.buffer),
+            _ => None,
+        }
+    }
+
+    /// ## `key(&self, effect: &mut dyn Effect)`
+    ///
+    /// Hands an effect the block of the strip keying it, if it has a sidechain.
+    ///
+    /// ### Arguments
+    ///
+    /// * `effect: &mut dyn Effect` - The effect
+    pub fn key(&self, effect: &mut dyn plugin::Effect) {
+        match effect.sidechain().and_then(|strip| self.get(strip)) {
+            Some(key) => effect.key(key),
+            None => {}
+        }
+    }
+}
+
+/// ## Graph
+///
+/// Holds every strip along with the routing between them. Owned by the audio thread.
+///
+/// ### Fields
+///
+/// * `strips: Vec<Box<Strip>>` - The strips, in the order they were added
+/// * `routing: Box<Routing>` - The processing order and buses
+/// * `master: Master` - The master bus the strips are summed into
+///
+/// ### Functions
+///
+/// * `new() -> Self` - Creates an empty graph
+/// * `process(&mut self, state: &State, input: &Buffer, output: &mut Buffer)` - Processes one block of every strip
+pub struct Graph {
+    strips: Vec<Box<Strip>>,
+    routing: Box<Routing>,
+    master: Master,
+}
+
+impl Graph {
+    /// ## `new() -> Self`
+    ///
+    /// Creates an empty graph, with room for STRIP_CAPACITY strips, the most `Control` adds.
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The new graph
+    pub fn new() -> Self {
+        Self {
+            strips: Vec::with_capacity(STRIP_CAPACITY),
+            routing: Box::new(Routing {
+                order: Vec::new(),
+                buses: Vec::new(),
+                routes: Vec::new(),
+            }),
+            master: Master::new(),
+        }
+    }
+
+    /// ## `process(&mut self, state: &State, input: &Buffer, output: &mut Buffer)`
+    ///
+    /// Processes one block of every strip in processing order, summing the results into the master bus,
+    /// which is then processed into the output block.
+    ///
+    /// ### Arguments
+    ///
+    /// * `state: &State` - The current state of the audio engine
+    /// * `input: &Buffer` - The block captured from the input device
+    /// * `output: &mut Buffer` - The output block, one channel per output channel
+    pub fn process(&mut self, state: &State, input: &Buffer, output: &mut Buffer) {
+        output.clear();
+        let routing = &mut self.routing;
+        for (_name, buffer) in routing.buses.iter_mut() {
+            buffer.resize(2, state.buffer_size);
+            buffer.clear();
+        }
+
+        // solo silences every strip that goes to the device, strips feeding buses keep playing into the soloed strips
+        let soloing = self.strips.iter().any(|strip| strip.mixer.solo);
+        for strip in self.strips.iter_mut() {
+            strip.processed = false;
+        }
+
+        let silence = Buffer::new(0, 0);
+        for index in routing.order.iter() {
+            let (bus_in, bus_out) = routing.routes[*index];
+            let bus = match bus_in {
+                Some(bus_in) => &routing.buses[bus_in].1,
+                None => &silence,
+            };
+
+            let (before, rest) = self.strips.split_at_mut(*index);
+            let (strip, after) = match rest.split_first_mut() {
+                Some(split) => split,
+                None => continue,
+            };
+            let keys = Keys {
+                before,
+                after,
+                index: *index,
+            };
+            let silenced = soloing
+                && !strip.mixer.solo
+                && match strip.output {
+                    Output::Bus(_) => false,
+                    _ => true,
+                };
+            strip.process(state, input, bus, &keys, silenced);
+            strip.processed = true;
+            let buffer = &strip.buffer;
+
+            match &strip.output {
+                Output::Mono(channel) => {
+                    if (*channel as usize) < output.channels() {
+                        let samples = output.channel_mut(*channel as usize);
+                        for (index, sample) in samples.iter_mut().enumerate() {
+                            *sample += buffer.frame(index).mono();
+                        }
+                    }
+                }
+                Output::Stereo(left_channel, right_channel) => {
+                    for (channel, source) in [(*left_channel, 0), (*right_channel, 1)] {
+                        if (channel as usize) < output.channels() {
+                            let samples = output.channel_mut(channel as usize);
+                            for (sample, source) in samples.iter_mut().zip(buffer.channel(source)) {
+                                *sample += *source;
+                            }
+                        }
+                    }
+                }
+                Output::Bus(_name) => match bus_out {
+                    Some(bus_out) => routing.buses[bus_out].1.mix_from(buffer),
+                    None => {}
+                },
+            }
+        }
+
+        let keys = Keys {
+            before: &self.strips,
+            after: &[],
+            index: self.strips.len(),
+        };
+        self.master.process(state, output, &keys);
+    }
+
+    /// ## `note(&mut self, strip: usize, note: u8, velocity: f32)`
+    ///
+    /// Starts or releases a note on a strip's generator. Strips without a generator ignore it.
+    ///
+    /// ### Arguments
+    ///
+    /// * `strip: usize` - The index of the strip
+    /// * `note: u8` - The MIDI note number
+    /// * `velocity: f32` - The velocity (0.0-1.0); 0.0 releases the note
+    pub fn note(&mut self, strip: usize, note: u8, velocity: f32) {
+        match self.strips.get_mut(strip) {
+            Some(strip) => strip.note(note, velocity),
+            None => {}
+        }
+    }
+
+    /// ## `midi(&mut self, strip: usize, channel: u8, message: ChannelMessage)`
+    ///
+    /// Sends a MIDI channel message to a strip.
+    ///
+    /// ### Arguments
+    ///
+    /// * `strip: usize` - The index of the strip
+    /// * `channel: u8` - The MIDI channel (0-15)
+    /// * `message: ChannelMessage` - The message
+    pub fn midi(&mut self, strip: usize, channel: u8, message: ChannelMessage) {
+        match self.strips.get_mut(strip) {
+            Some(strip) => strip.midi(channel, message),
+            None => {}
+        }
+    }
+}
+
+/// ## Command
+///
+/// A message from control code (Tauri commands, MIDI callbacks, HID threads) to the audio thread.
+/// Commands are applied at the start of the next block.
+///
+/// ### Variants
+///
+/// * `AddStrip(Box<Strip>, Box<Routing>)` - Adds a strip, along with the routing that includes it
+/// * `SetOutput(usize, Output, Box<Routing>)` - Changes where a strip outputs to, along with the new routing
+/// * `SetRouting(Box<Routing>)` - Swaps the routing, when the strips keying effects change
+/// * `SetMixer(Target, Mixer)` - Sets the mixer controls of a strip or the master bus
+/// * `AddEffect(Target, Box<dyn Effect>)` - Adds an effect to the end of a strip's or the master bus' chain
+/// * `SetParameter { target, effect, name, value }` - Sets a parameter of an effect in a strip's or the master bus' chain
+/// * `SetGeneratorParameter { strip, name, value }` - Sets a parameter of a strip's generator
+/// * `NoteOn { strip, note, velocity }` - Starts a note on a strip's generator
+/// * `NoteOff { strip, note }` - Releases a note on a strip's generator
+/// * `Midi { strip, channel, message }` - Sends a MIDI channel message to a strip's generator and effects
+/// * `SetPattern(Box<Pattern>)` - Swaps the pattern played by the sequencer
+/// * `Play(bool)` - Starts or stops the transport
+/// * `Locate(f64)` - Moves the transport to a position, in beats
+/// * `SetTempo(f64)` - Sets the tempo of the transport, in beats per minute
+/// * `SetSignature(u32, u32)` - Sets the time signature of the transport
+/// * `Detach(SyncSender<Engine>)` - Hands the engine over to control code (e.g. for an offline render) after the current block
+pub enum Command {
+    AddStrip(Box<Strip>, Box<Routing>),
+    SetOutput(usize, Output, Box<Routing>),
+    SetRouting(Box<Routing>),
+    SetMixer(Target, Mixer),
+    AddEffect(Target, Box<dyn plugin::Effect>),
+    SetParameter {
+        target: Target,
+        effect: usize,
+        name: String,
+        value: f32,
+    },
+    SetGeneratorParameter {
+        strip: usize,
+        name: String,
+        value: f32,
+    },
+    NoteOn {
+        strip: usize,
+        note: u8,
+        velocity: f32,
+    },
+    NoteOff {
+        strip: usize,
+        note: u8,
+    },
+    Midi {
+        strip: usize,
+        channel: u8,
+        message: ChannelMessage,
+    },
+    SetPattern(Box<Pattern>),
+    Play(bool),
+    Locate(f64),
+    SetTempo(f64),
+    SetSignature(u32, u32),
+    Detach(SyncSender<Engine>),
+}
+
+/// ## Garbage
+///
+/// Whatever the audio thread replaced while applying a command. It is sent back to the control side
+/// so it gets freed there instead of on the audio thread.
+///
+/// ### Variants
+///
+/// * `Routing(Box<Routing>)` - The previous routing
+/// * `Rerouted(Output, Box<Routing>)` - The previous output of a strip, and the previous routing
+/// * `Name(String)` - The name of a parameter that was set
+/// * `Effect(Box<dyn Effect>)` - An effect that could not be added
+/// * `Pattern(Box<Pattern>)` - The previous pattern of the sequencer
+pub enum Garbage {
+    Routing(Box<Routing>),
+    Rerouted(Output, Box<Routing>),
+    Name(String),
+    Effect(Box<dyn plugin::Effect>),
+    Pattern(Box<Pattern>),
+}
+
+/// ## Engine
+///
+/// The strip graph along with the audio thread's end of the command queue.
+///
+/// ### Fields
+///
+/// * `graph: Graph` - The strips being played
+/// * `commands: Receiver<Command>` - Commands sent by control code
+/// * `garbage: SyncSender<Garbage>` - Hands replaced values back to the control side
+/// * `pending: Vec<Garbage>` - Replaced values the control side had no room for yet, sent again on the next block; allocated up front
+/// * `detach: Option<SyncSender<Engine>>` - Where to hand the engine over to once the current block is done
+/// * `transport: Transport` - The musical clock, at the start of the next block
+/// * `position: Arc<Position>` - Where the transport's position is published for the control side
+/// * `sequencer: Sequencer` - Plays notes on the strips at the frames they are due
+/// * `part_input: Buffer` - The input of part of a block, when the block is split at sequencer notes
+/// * `part_output: Buffer` - The output of part of a block
+///
+/// ### Functions
+///
+/// * `process(&mut self, state: &State, input: &Buffer, output: &mut Buffer)` - Applies pending commands, then processes a block
+pub struct Engine {
+    graph: Graph,
+    commands: Receiver<Command>,
+    garbage: SyncSender<Garbage>,
+    pending: Vec<Garbage>,
+    detach: Option<SyncSender<Engine>>,
+    transport: Transport,
+    position: Arc<Position>,
+    sequencer: Sequencer,
+    part_input: Buffer,
+    part_output: Buffer,
+}
+
+impl Engine {
+    /// ## `process(&mut self, state: &State, input: &Buffer, output: &mut Buffer)`
+    ///
+    /// Applies every pending command, then processes a block and moves the transport past it.
+    /// Whatever the commands replace is never freed here: if the control side has not collected in a while, it waits in `pending`,
+    /// and once that is full the commands wait in the queue.
+    /// The block is split wherever the sequencer starts or releases a note, so each note lands on its exact frame.
+    ///
+    /// ### Arguments
+    ///
+    /// * `state: &State` - The current state of the audio engine
+    /// * `input: &Buffer` - The block captured from the input device
+    /// * `output: &mut Buffer` - The output block, one channel per output channel
+    pub fn process(&mut self, state: &State, input: &Buffer, output: &mut Buffer) {
+        while let Some(garbage) = self.pending.pop() {
+            match self.garbage.try_send(garbage) {
+                Ok(()) => {}
+                Err(TrySendError::Full(garbage)) => {
+                    self.pending.push(garbage);
+                    break;
+                }
+                // the control side is gone, so there is nowhere else to free it
+                Err(TrySendError::Disconnected(_garbage)) => {}
+            }
+        }
+
+        while self.detach.is_none() && self.pending.len() < self.pending.capacity() {
+            let command = match self.commands.try_recv() {
+                Ok(command) => command,
+                Err(_e) => break,
+            };
+            match self.apply(command) {
+                Some(garbage) => match self.garbage.try_send(garbage) {
+                    Ok(()) => {}
+                    Err(TrySendError::Full(garbage)) => self.pending.push(garbage),
+                    Err(TrySendError::Disconnected(_garbage)) => {}
+                },
+                None => {}
+            }
+        }
+
+        let state = &State {
+            transport: self.transport,
+            ..state.clone()
+        };
+        self.transport = self
+            .transport
+            .advanced(state.buffer_size, state.sample_rate);
+        self.position.publish(&self.transport);
+
+        let events = self.sequencer.events(state);
+        if events.is_empty() {
+            self.graph.process(state, input, output);
+            return;
+        }
+
+        let mut start = 0;
+        for event in events.iter() {
+            let offset = event.offset.min(state.buffer_size);
+            if offset > start {
+                Self::process_part(
+                    &mut self.graph,
+                    &mut self.part_input,
+                    &mut self.part_output,
+                    &state.part(start, offset - start),
+                    start,
+                    input,
+                    output,
+                );
+                start = offset;
+            }
+            self.graph.note(event.strip, event.note, event.velocity);
+        }
+        if start < state.buffer_size {
+            Self::process_part(
+                &mut self.graph,
+                &mut self.part_input,
+                &mut self.part_output,
+                &state.part(start, state.buffer_size - start),
+                start,
+                input,
+                output,
+            );
+        }
+    }
+
+    /// ## `process_part(graph: &mut Graph, part_input: &mut Buffer, part_output: &mut Buffer, state: &State, start: usize, input: &Buffer, output: &mut Buffer)`
+    ///
+    /// Private function which processes part of a block, given the state of the part.
+    ///
+    /// ### Arguments
+    ///
+    /// * `graph: &mut Graph` - The strips
+    /// * `part_input: &mut Buffer` - Holds the input of the part
+    /// * `part_output: &mut Buffer` - Holds the output of the part
+    /// * `state: &State` - The state of the part, as returned by `State::part`
+    /// * `start: usize` - The first frame of the part within the block
+    /// * `input: &Buffer` - The input of the whole block
+    /// * `output: &mut Buffer` - The output of the whole block
+    fn process_part(
+        graph: &mut Graph,
+        part_input: &mut Buffer,
+        part_output: &mut Buffer,
+        state: &State,
+        start: usize,
+        input: &Buffer,
+        output: &mut Buffer,
+    ) {
+        part_input.copy_frames_from(input, start, state.buffer_size);
+        part_output.resize(output.channels(), state.buffer_size);
+        graph.process(state, part_input, part_output);
+        part_output.copy_frames_to(output, start);
+    }
+
+    /// ## `apply(&mut self, command: Command) -> Option<Garbage>`
+    ///
+    /// Private function which applies a command to the graph.
+    ///
+    /// ### Arguments
+    ///
+    /// * `command: Command` - The command to apply
+    ///
+    /// ### Returns
+    ///
+    /// * `Option<Garbage>` - Whatever the command replaced
+    fn apply(&mut self, command: Command) -> Option<Garbage> {
+        match command {
+            Command::AddStrip(strip, routing) => {
+                // Control refuses strips past STRIP_CAPACITY, so this never reallocates
+                self.graph.strips.push(strip);
+                Some(Garbage::Routing(std::mem::replace(
+                    &mut self.graph.routing,
+                    routing,
+                )))
+            }
+            Command::SetOutput(index, output, routing) => match self.graph.strips.get_mut(index) {
+                Some(strip) => {
+                    let previous = std::mem::replace(&mut strip.output, output);
+                    Some(Garbage::Rerouted(
+                        previous,
+                        std::mem::replace(&mut self.graph.routing, routing),
+                    ))
+                }
+                None => Some(Garbage::Rerouted(output, routing)),
+            },
+            Command::SetRouting(routing) => Some(Garbage::Routing(std::mem::replace(
+                &mut self.graph.routing,
+                routing,
+            ))),
+            Command::SetMixer(target, mixer) => {
+                match target {
+                    Target::Strip(index) => match self.graph.strips.get_mut(index) {
+                        Some(strip) => strip.mixer = mixer,
+                        None => {}
+                    },
+                    Target::Master => self.graph.master.mixer = mixer,
+                }
+                None
+            }
+            Command::AddEffect(target, effect) => {
+                match self.chain(target) {
+                    Some(chain) => chain.push(effect),
+                    None => return Some(Garbage::Effect(effect)),
+                }
+                None
+            }
+            Command::SetParameter {
+                target,
+                effect,
+                name,
+                value,
+            } => {
+                match self.chain(target) {
+                    Some(chain) => match chain.get_mut(effect) {
+                        Some(effect) => effect.set_parameter(&name, value),
+                        None => {}
+                    },
+                    None => {}
+                }
+                Some(Garbage::Name(name))
+            }
+            Command::SetGeneratorParameter { strip, name, value } => {
+                match self.graph.strips.get_mut(strip) {
+                    Some(strip) => match &mut strip.input {
+                        Input::Generator(generator) => generator.set_parameter(&name, value),
+                        _ => {}
+                    },
+                    None => {}
+                }
+                Some(Garbage::Name(name))
+            }
+            Command::NoteOn {
+                strip,
+                note,
+                velocity,
+            } => {
+                self.graph.note(strip, note, velocity);
+                None
+            }
+            Command::NoteOff { strip, note } => {
+                self.graph.note(strip, note, 0.0);
+                None
+            }
+            Command::Midi {
+                strip,
+                channel,
+                message,
+            } => {
+                self.graph.midi(strip, channel, message);
+                None
+            }
+            Command::SetPattern(pattern) => {
+                Some(Garbage::Pattern(self.sequencer.set_pattern(pattern)))
+            }
+            Command::Play(playing) => {
+                self.transport.playing = playing;
+                None
+            }
+            Command::Locate(beats) => {
+                self.transport = self.transport.located(beats);
+                None
+            }
+            Command::SetTempo(bpm) => {
+                self.transport.bpm = bpm;
+                None
+            }
+            Command::SetSignature(beats_per_bar, beat_unit) => {
+                self.transport.beats_per_bar = beats_per_bar;
+                self.transport.beat_unit = beat_unit;
+                self.transport = self.transport.located(self.transport.beats);
+                None
+            }
+            Command::Detach(reply) => {
+                self.detach = Some(reply);
+                None
+            }
+        }
+    }
+
+    /// ## `chain(&mut self, target: Target) -> Option<&mut Vec<Box<dyn Effect>>>`
+    ///
+    /// Private function which gets the effect chain of a strip or the master bus.
+    ///
+    /// ### Arguments
+    ///
+    /// * `target: Target` - The strip or the master bus
+    ///
+    /// ### Returns
+    ///
+    /// * `Option<&mut Vec<Box<dyn Effect>>>` - The chain, or None if the strip does not exist
+    fn chain(&mut self, target: Target) -> Option<&mut Vec<Box<dyn plugin::Effect>>> {
+        match target {
+            Target::Strip(index) => match self.graph.strips.get_mut(index) {
+                Some(strip) => Some(&mut strip.chain),
+                None => None,
+            },
+            Target::Master => Some(&mut self.graph.master.chain),
+        }
+    }
+}
+
+/// ## EngineHandle
+///
+/// Owns the engine inside the audio callback. When the stream (and with it the callback) is dropped,
+/// the engine is handed back to CONTROL, so the strips survive the stream being rebuilt.
+/// The engine can also be lent out while the stream keeps running (see `Control::borrow_engine`), in which case the callback plays silence until it comes back.
+///
+/// ### Fields
+///
+/// * `engine: Option<Engine>` - The engine, unless it is lent out
+/// * `attach: Receiver<Engine>` - Where a lent out engine comes back
+///
+/// ### Functions
+///
+/// * `take() -> Result<Self, String>` - Takes the engine out of CONTROL
+/// * `process(&mut self, state: &State, input: &Buffer, output: &mut Buffer)` - Processes a block
+pub struct EngineHandle {
+    engine: Option<Engine>,
+    attach: Receiver<Engine>,
+}
+
+impl EngineHandle {
+    /// ## `take() -> Result<Self, String>`
+    ///
+    /// Takes the engine out of CONTROL.
+    ///
+    /// ### Returns
+    ///
+    /// * `Result<Self, String>` - The handle, or an error message if the engine is already owned by a stream
+    pub fn take() -> Result<Self, String> {
+        match CONTROL.lock() {
+            Ok(mut control) => match control.engine.take() {
+                Some(engine) => {
+                    let (sender, attach) = sync_channel(1);
+                    control.attach = Some(sender);
+                    Ok(Self {
+                        engine: Some(engine),
+                        attach,
+                    })
+                }
+                None => Err("The audio engine is already running".to_owned()),
+            },
+            Err(e) => Err(format!("Error locking CONTROL: {}", e)),
+        }
+    }
+
+    /// ## `process(&mut self, state: &State, input: &Buffer, output: &mut Buffer)`
+    ///
+    /// Processes a block, then hands the engine over if it was asked to detach.
+    ///
+    /// ### Arguments
+    ///
+    /// * `state: &State` - The current state of the audio engine
+    /// * `input: &Buffer` - The block captured from the input device
+    /// * `output: &mut Buffer` - The output block, one channel per output channel
+    pub fn process(&mut self, state: &State, input: &Buffer, output: &mut Buffer) {
+        if self.engine.is_none() {
+            self.engine = self.attach.try_recv().ok();
+        }
+
+        let engine = match self.engine.as_mut() {
+            Some(engine) => engine,
+            None => {
+                output.clear();
+                return;
+            }
+        };
+        engine.process(state, input, output);
+
+        let reply = match engine.detach.take() {
+            Some(reply) => reply,
+            None => return,
+        };
+        match self.engine.take() {
+            Some(engine) => match reply.try_send(engine) {
+                Ok(()) => {}
+                Err(TrySendError::Full(engine)) | Err(TrySendError::Disconnected(engine)) => {
+                    // nobody is waiting for it anymore, keep playing
+                    self.engine = Some(engine);
+                }
+            },
+            None => {}
+        }
+    }
+}
+
+impl Drop for EngineHandle {
+    fn drop(&mut self) {
+        let engine = match self.engine.take() {
+            Some(engine) => Some(engine),
+            None => self.attach.try_recv().ok(),
+        };
+
+        match CONTROL.lock() {
+            Ok(mut control) => {
+                control.attach = None;
+                match engine {
+                    Some(engine) => control.engine = Some(engine),
+                    None => {}
+                }
+            }
+            Err(e) => debug!("Error locking CONTROL: {}", e),
+        }
+    }
+}
+
+/// ## Control
+///
+/// The control side of the audio engine. Control code sends commands through here instead of touching the strips,
+/// so the audio thread never has to wait on a lock or drop a buffer.
+///
+/// ### Fields
+///
+/// * `sender: SyncSender<Command>` - The control side of the command queue
+/// * `garbage: Receiver<Garbage>` - Values the audio thread replaced, freed whenever a command is sent
+/// * `routes: Vec<Route>` - The routing of every strip, mirrored from the audio thread
+/// * `master_mixer: Mixer` - The mixer controls of the master bus, mirrored from the audio thread
+/// * `master_meter: Arc<Readout>` - The levels of the master bus, published by the audio thread
+/// * `master_curves: Vec<Option<Arc<Curve>>>` - The settings of each effect in the master bus' chain that is an EQ
+/// * `engine: Option<Engine>` - The engine, while no stream owns it
+/// * `attach: Option<SyncSender<Engine>>` - Hands a lent out engine back to the stream that owns it
+///
+/// ### Functions
+///
+/// * `new() -> Self` - Creates the command queue and an empty engine
+/// * `routes(&self) -> &Vec<Route>` - Returns the routes of every strip
+/// * `buses(&self) -> Vec<String>` - Returns the names of every bus in use
+/// * `add_strip(&mut self, strip: Strip) -> Result<usize, String>` - Adds a strip, refusing it if it creates a cycle
+/// * `set_output(&mut self, index: usize, output: Output) -> Result<(), String>` - Reroutes a strip, refusing it if it creates a cycle
+/// * `mixer(&self, target: Target) -> Result<Mixer, String>` - Returns the mixer controls of a strip or the master bus
+/// * `set_mixer(&mut self, target: Target, mixer: Mixer) -> Result<(), String>` - Sets the mixer controls of a strip or the master bus
+/// * `add_effect(&mut self, target: Target, effect: Box<dyn Effect>) -> Result<usize, String>` - Adds an effect to a chain
+/// * `eq(&self, target: Target, effect: Option<usize>) -> Result<(usize, Arc<Curve>), String>` - Returns the settings of an EQ on a strip or the master bus
+/// * `meters(&self) -> Meters` - Returns the levels of every strip and the master bus
+/// * `reset_meters(&self)` - Resets integrated loudness and clip counts
+/// * `set_parameter(&mut self, target: Target, effect: usize, name: &str, value: f32) -> Result<(), String>` - Sets an effect parameter
+/// * `note_on(&mut self, strip: usize, note: u8, velocity: f32) -> Result<(), String>` - Starts a note on a strip's generator
+/// * `note_off(&mut self, strip: usize, note: u8) -> Result<(), String>` - Releases a note on a strip's generator
+/// * `midi(&mut self, strip: usize, channel: u8, message: ChannelMessage) -> Result<(), String>` - Sends a MIDI channel message to a strip's generator
+/// * `pattern(&self) -> &Pattern` - Returns the pattern played by the sequencer
+/// * `set_pattern(&mut self, pattern: Pattern) -> Result<(), String>` - Sets the pattern played by the sequencer
+/// * `transport(&self) -> Transport` - Returns the transport's settings and current position
+/// * `position(&self) -> Arc<Position>` - Returns where the audio thread publishes the transport
+/// * `play(&mut self, playing: bool) -> Result<(), String>` - Starts or stops the transport
+/// * `locate(&mut self, beats: f64) -> Result<(), String>` - Moves the transport to a position
+/// * `set_tempo(&mut self, bpm: f64) -> Result<(), String>` - Sets the tempo
+/// * `set_signature(&mut self, beats_per_bar: u32, beat_unit: u32) -> Result<(), String>` - Sets the time signature
+/// * `send(&mut self, command: Command) -> Result<(), String>` - Sends a command to the audio thread
+/// * `borrow_engine(&mut self) -> Result<Engine, String>` - Takes the engine, from the audio thread if a stream owns it
+/// * `return_engine(&mut self, engine: Engine)` - Gives a borrowed engine back
+pub struct Control {
+    sender: SyncSender<Command>,
+    garbage: Receiver<Garbage>,
+    routes: Vec<Route>,
+    master_mixer: Mixer,
+    master_meter: Arc<Readout>,
+    master_curves: Vec<Option<Arc<Curve>>>,
+    pattern: Pattern,
+    transport: Transport,
+    position: Arc<Position>,
+    engine: Option<Engine>,
+    attach: Option<SyncSender<Engine>>,
+}
+
+impl Control {
+    /// ## `new() -> Self`
+    ///
+    /// Creates the command queue and an empty engine.
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The new control
+    pub fn new() -> Self {
+        let (sender, commands) = sync_channel(COMMAND_QUEUE_SIZE);
+        let (garbage_sender, garbage) = sync_channel(COMMAND_QUEUE_SIZE);
+        let graph = Graph::new();
+        let position = Position::new();
+        Self {
+            sender,
+            garbage,
+            routes: Vec::new(),
+            master_mixer: Mixer::default(),
+            master_meter: graph.master.meter.readout(),
+            master_curves: Vec::new(),
+            pattern: Pattern::default(),
+            transport: Transport::default(),
+            position: position.clone(),
+            engine: Some(Engine {
+                graph,
+                commands,
+                garbage: garbage_sender,
+                pending: Vec::with_capacity(COMMAND_QUEUE_SIZE),
+                detach: None,
+                transport: Transport::default(),
+                position,
+                sequencer: Sequencer::new(),
+                part_input: Buffer::new(0, 0),
+                part_output: Buffer::new(0, 0),
+            }),
+            attach: None,
+        }
+    }
+
+    /// ## `routes(&self) -> &Vec<Route>`
+    ///
+    /// Returns the routes of every strip, in the order the strips were added.
+    ///
+    /// ### Returns
+    ///
+    /// * `&Vec<Route>` - The routes
+    pub fn routes(&self) -> &Vec<Route> {
+        &self.routes
+    }
+
+    /// ## `buses(&self) -> Vec<String>`
+    ///
+    /// Returns the names of every bus in use.
+    ///
+    /// ### Returns
+    ///
+    /// * `Vec<String>` - The bus names
+    pub fn buses(&self) -> Vec<String> {
+        let mut buses: Vec<String> = Vec::new();
+        for route in self.routes.iter() {
+            for name in [&route.reads, &route.writes].into_iter().flatten() {
+                if !buses.contains(name) {
+                    buses.push(name.clone());
+                }
+            }
+        }
+        buses
+    }
+
+    /// ## `add_strip(&mut self, strip: Strip) -> Result<usize, String>`
+    ///
+    /// Adds a strip. The strip is refused if it would create a routing cycle, or if there are already STRIP_CAPACITY strips.
+    ///
+    /// ### Arguments
+    ///
+    /// * `strip: Strip` - The strip to add
+    ///
+    /// ### Returns
+    ///
+    /// * `Result<usize, String>` - The index of the new strip, or an error message
+    pub fn add_strip(&mut self, strip: Strip) -> Result<usize, String> {
+        if self.routes.len() >= STRIP_CAPACITY {
+            return Err(format!(
+                "Cannot add a strip: there can be at most {} strips",
+                STRIP_CAPACITY
+            ));
+        }
+        let mut routes = self.routes.clone();
+        routes.push(Route::of(&strip));
+        let routing = Routing::new(&routes)?;
+
+        self.send(Command::AddStrip(Box::new(strip), Box::new(routing)))?;
+        self.routes = routes;
+        Ok(self.routes.len() - 1)
+    }
+
+    /// ## `add_strips(&mut self, strips: Vec<Strip>) -> Result<Vec<usize>, String>`
+    ///
+    /// Adds several strips. The routing of all of them is checked before any is added, so strips that would
+    /// create a routing cycle together, or go past STRIP_CAPACITY strips, add nothing. If the audio thread stops taking commands partway,
+    /// the strips already sent stay and the error says how many.
+    ///
+    /// ### Arguments
+    ///
+    /// * `strips: Vec<Strip>` - The strips to add
+    ///
+    /// ### Returns
+    ///
+    /// * `Result<Vec<usize>, String>` - The indices of the new strips, or an error message
+    pub fn add_strips(&mut self, strips: Vec<Strip>) -> Result<Vec<usize>, String> {
+        if self.routes.len() + strips.len() > STRIP_CAPACITY {
+            return Err(format!(
+                "Cannot add {} strips: there can be at most {} strips, and there are {}",
+                strips.len(),
+                STRIP_CAPACITY,
+                self.routes.len()
+            ));
+        }
+        let mut routes = self.routes.clone();
+        for strip in strips.iter() {
+            routes.push(Route::of(strip));
+        }
+        Routing::new(&routes)?;
+
+        let first = self.routes.len();
+        let mut indices = Vec::new();
+        for strip in strips {
+            let index = self.routes.len();
+            let routing = Routing::new(&routes[..=index])?;
+            match self.send(Command::AddStrip(Box::new(strip), Box::new(routing))) {
+                Ok(()) => {
+                    self.routes.push(routes[index].clone());
+                    indices.push(index);
+                }
+                Err(e) => {
+                    return Err(format!(
+                        "{} (added strips {}..{} before the error)",
+                        e, first, index
+                    ))
+                }
+            }
+        }
+        Ok(indices)
+    }
+
+    /// ## `set_output(&mut self, index: usize, output: Output) -> Result<(), String>`
+    ///
+    /// Changes where a strip outputs to. The change is refused if it would create a routing cycle.
+    ///
+    /// ### Arguments
+    ///
+    /// * `index: usize` - The index of the strip
+    /// * `output: Output` - The new output
+    ///
+    /// ### Returns
+    ///
+    /// * `Result<(), String>` - An error message, or nothing if successful
+    pub fn set_output(&mut self, index: usize, output: Output) -> Result<(), String> {
+        let mut routes = self.routes.clone();
+        let route = match routes.get_mut(index) {
+            Some(route) => route,
+            None => return Err(format!("No strip {}", index)),
+        };
+        route.output = output.to_string();
+        route.writes = match &output {
+            Output::Bus(name) => Some(name.clone()),
+            _ => None,
+        };
+        let routing = Routing::new(&routes)?;
+
+        self.send(Command::SetOutput(index, output, Box::new(routing)))?;
+        self.routes = routes;
+        Ok(())
+    }
+
+    /// ## `mixer(&self, target: Target) -> Result<Mixer, String>`
+    ///
+    /// Returns the mixer controls of a strip or the master bus.
+    ///
+    /// ### Arguments
+    ///
+    /// * `target: Target` - The strip or the master bus
+    ///
+    /// ### Returns
+    ///
+    /// * `Result<Mixer, String>` - The mixer controls, or an error message if the strip does not exist
+    pub fn mixer(&self, target: Target) -> Result<Mixer, String> {
+        match target {
+            Target::Strip(strip) => match self.routes.get(strip) {
+                Some(route) => Ok(route.mixer),
+                None => Err(format!("No strip {}", strip)),
+            },
+            Target::Master => Ok(self.master_mixer),
+        }
+    }
+
+    /// ## `set_mixer(&mut self, target: Target, mixer: Mixer) -> Result<(), String>`
+    ///
+    /// Sets the mixer controls of a strip or the master bus. Gain changes are ramped over the next block.
+    ///
+    /// ### Arguments
+    ///
+    /// * `target: Target` - The strip or the master bus
+    /// * `mixer: Mixer` - The mixer controls
+    ///
+    /// ### Returns
+    ///
+    /// * `Result<(), String>` - An error message, or nothing if successful
+    pub fn set_mixer(&mut self, target: Target, mixer: Mixer) -> Result<(), String> {
+        match target {
+            Target::Strip(strip) => self.check_strip(strip)?,
+            Target::Master => {
+                if mixer.solo {
+                    return Err("The master bus can't be soloed".to_string());
+                }
+            }
+        }
+        self.send(Command::SetMixer(target, mixer))?;
+        match target {
+            Target::Strip(strip) => self.routes[strip].mixer = mixer,
+            Target::Master => self.master_mixer = mixer,
+        }
+        Ok(())
+    }
+
+    /// ## `meters(&self) -> Meters`
+    ///
+    /// Returns the latest levels of every strip and the master bus.
+    ///
+    /// ### Returns
+    ///
+    /// * `Meters` - The levels
+    pub fn meters(&self) -> Meters {
+        Meters {
+            strips: self
+                .routes
+                .iter()
+                .enumerate()
+                .map(|(index, route)| route.meter.levels(&index.to_string()))
+                .collect(),
+            master: self.master_meter.levels("master"),
+        }
+    }
+
+    /// ## `reset_meters(&self)`
+    ///
+    /// Resets the integrated loudness and clip counts of every strip and the master bus.
+    pub fn reset_meters(&self) {
+        for route in self.routes.iter() {
+            route.meter.reset();
+        }
+        self.master_meter.reset();
+    }
+
+    /// ## `add_effect(&mut self, target: Target, effect: Box<dyn Effect>) -> Result<usize, String>`
+    ///
+    /// Adds an effect to the end of a strip's or the master bus' chain.
+    /// The settings of an EQ are kept here by the effect's index, for `eq` and `set_parameter`.
+    ///
+    /// ### Arguments
+    ///
+    /// * `target: Target` - The strip or the master bus
+    /// * `effect: Box<dyn Effect>` - The effect to add
+    ///
+    /// ### Returns
+    ///
+    /// * `Result<usize, String>` - The index of the effect in the chain, or an error message
+    pub fn add_effect(
+        &mut self,
+        target: Target,
+        effect: Box<dyn plugin::Effect>,
+    ) -> Result<usize, String> {
+        self.check_target(target)?;
+        let curve = effect.curve();
+        let keying = Keying::of(effect.as_ref());
+
+        match target {
+            Target::Strip(strip) => {
+                let mut routes = self.routes.clone();
+                routes[strip].keys.push(keying);
+                let routing = match keying {
+                    Keying::Strip(_) => Some(Routing::new(&routes)?),
+                    _ => None,
+                };
+                match routing {
+                    Some(routing) => self.send(Command::SetRouting(Box::new(routing)))?,
+                    None => {}
+                }
+                self.send(Command::AddEffect(target, effect))?;
+                routes[strip].curves.push(curve);
+                self.routes = routes;
+                Ok(self.routes[strip].curves.len() - 1)
+            }
+            Target::Master => {
+                self.send(Command::AddEffect(target, effect))?;
+                self.master_curves.push(curve);
+                Ok(self.master_curves.len() - 1)
+            }
+        }
+    }
+
+    /// ## `eq(&self, target: Target, effect: Option<usize>) -> Result<(usize, Arc<Curve>), String>`
+    ///
+    /// Returns the settings of an EQ on a strip or the master bus.
+    ///
+    /// ### Arguments
+    ///
+    /// * `target: Target` - The strip or the master bus
+    /// * `effect: Option<usize>` - The index of the EQ in the chain, or None for the only EQ in it
+    ///
+    /// ### Returns
+    ///
+    /// * `Result<(usize, Arc<Curve>), String>` - The index of the EQ in the chain and its settings, or an error message
+    ///   if the target does not exist, the effect is not an EQ, or no effect is given and the chain has no EQ or several
+    pub fn eq(&self, target: Target, effect: Option<usize>) -> Result<(usize, Arc<Curve>), String> {
+        self.check_target(target)?;
+        let curves = match target {
+            Target::Strip(strip) => &self.routes[strip].curves,
+            Target::Master => &self.master_curves,
+        };
+        match effect {
+            Some(effect) => match curves.get(effect) {
+                Some(Some(curve)) => Ok((effect, curve.clone())),
+                _ => Err(format!("Effect {} on {} is not an EQ", effect, target)),
+            },
+            None => {
+                let mut eqs = curves.iter().enumerate().filter_map(|(effect, curve)| {
+                    curve.as_ref().map(|curve| (effect, curve.clone()))
+                });
+                match (eqs.next(), eqs.next()) {
+                    (None, _) => Err(format!("{} has no EQ", target)),
+                    (Some(eq), None) => Ok(eq),
+                    (Some((first, _)), Some(_)) => Err(format!(
+                        "{} has more than one EQ: give the index of the one to use, e.g. effect {}",
+                        target, first
+                    )),
+                }
+            }
+        }
+    }
+
+    /// ## `set_parameter(&mut self, target: Target, effect: usize, name: &str, value: f32) -> Result<(), String>`
+    ///
+    /// Sets a parameter of an effect in a strip's or the master bus' chain.
+    /// The bands of an EQ are set on its curve here, so `eq` shows them and the EQ moves to them.
+    /// Setting the "key" of an effect on a strip that can be keyed reorders the strips, and is refused if it would create a routing cycle.
+    ///
+    /// ### Arguments
+    ///
+    /// * `target: Target` - The strip or the master bus
+    /// * `effect: usize` - The index of the effect in the chain
+    /// * `name: &str` - The name of the parameter
+    /// * `value: f32` - The new value
+    ///
+    /// ### Returns
+    ///
+    /// * `Result<(), String>` - An error message, or nothing if successful
+    pub fn set_parameter(
+        &mut self,
+        target: Target,
+        effect: usize,
+        name: &str,
+        value: f32,
+    ) -> Result<(), String> {
+        self.check_target(target)?;
+        // EQ bands live in the curve shared with the audio thread, which only the control side writes
+        match self.eq(target, Some(effect)) {
+            Ok((_, curve)) => {
+                return match curve.set_parameter(name, value) {
+                    true => Ok(()),
+                    false => Err(format!("EQ has no parameter {}", name)),
+                }
+            }
+            Err(_e) => {}
+        }
+        let keyed = match (target, name) {
+            (Target::Strip(strip), "key") => match self.routes[strip].keys.get(effect) {
+                Some(Keying::Fixed) | None => None,
+                Some(_) => {
+                    let keying = match value >= 0.0 {
+                        true => Keying::Strip(value as usize),
+                        false => Keying::Own,
+                    };
+                    let mut routes = self.routes.clone();
+                    routes[strip].keys[effect] = keying;
+                    let routing = Routing::new(&routes)?;
+                    Some((routes, routing))
+                }
+            },
+            _ => None,
+        };
+
+        match keyed {
+            Some((routes, routing)) => {
+                self.send(Command::SetRouting(Box::new(routing)))?;
+                self.routes = routes;
+            }
+            None => {}
+        }
+        self.send(Command::SetParameter {
+            target,
+            effect,
+            name: name.to_owned(),
+            value,
+        })
+    }
+
+    /// ## `set_generator_parameter(&mut self, strip: usize, name: &str, value: f32) -> Result<(), String>`
+    ///
+    /// Sets a parameter of a strip's generator. Strips without a generator ignore it.
+    ///
+    /// ### Arguments
+    ///
+    /// * `strip: usize` - The index of the strip
+    /// * `name: &str` - The name of the parameter
+    /// * `value: f32` - The new value
+    ///
+    /// ### Returns
+    ///
+    /// * `Result<(), String>` - An error message, or nothing if successful
+    pub fn set_generator_parameter(
+        &mut self,
+        strip: usize,
+        name: &str,
+        value: f32,
+    ) -> Result<(), String> {
+        self.check_strip(strip)?;
+        self.send(Command::SetGeneratorParameter {
+            strip,
+            name: name.to_owned(),
+            value,
+        })
+    }
+
+    /// ## `pattern(&self) -> &Pattern`
+    ///
+    /// Returns the pattern played by the sequencer.
+    ///
+    /// ### Returns
+    ///
+    /// * `&Pattern` - The pattern
+    pub fn pattern(&self) -> &Pattern {
+        &self.pattern
+    }
+
+    /// ## `set_pattern(&mut self, pattern: Pattern) -> Result<(), String>`
+    ///
+    /// Sets the pattern played by the sequencer. A playing sequencer carries on from the same position.
+    ///
+    /// ### Arguments
+    ///
+    /// * `pattern: Pattern` - The new pattern
+    ///
+    /// ### Returns
+    ///
+    /// * `Result<(), String>` - An error message, or nothing if successful
+    pub fn set_pattern(&mut self, pattern: Pattern) -> Result<(), String> {
+        self.send(Command::SetPattern(Box::new(pattern.clone())))?;
+        self.pattern = pattern;
+        Ok(())
+    }
+
+    /// ## `transport(&self) -> Transport`
+    ///
+    /// Returns the transport's settings and the position last published by the audio thread.
+    ///
+    /// ### Returns
+    ///
+    /// * `Transport` - The transport
+    pub fn transport(&self) -> Transport {
+        self.position.read(&self.transport)
+    }
+
+    /// ## `position(&self) -> Arc<Position>`
+    ///
+    /// Returns where the audio thread publishes the transport, for threads that follow it without locking CONTROL.
+    ///
+    /// ### Returns
+    ///
+    /// * `Arc<Position>` - The published position
+    pub fn position(&self) -> Arc<Position> {
+        self.position.clone()
+    }
+
+    /// ## `play(&mut self, playing: bool) -> Result<(), String>`
+    ///
+    /// Starts the transport from where it is, or stops it where it is.
+    ///
+    /// ### Arguments
+    ///
+    /// * `playing: bool` - Whether to play
+    ///
+    /// ### Returns
+    ///
+    /// * `Result<(), String>` - An error message, or nothing if successful
+    pub fn play(&mut self, playing: bool) -> Result<(), String> {
+        self.send(Command::Play(playing))?;
+        self.transport.playing = playing;
+        Ok(())
+    }
+
+    /// ## `locate(&mut self, beats: f64) -> Result<(), String>`
+    ///
+    /// Moves the transport to a position.
+    ///
+    /// ### Arguments
+    ///
+    /// * `beats: f64` - The position in beats from the start of the song
+    ///
+    /// ### Returns
+    ///
+    /// * `Result<(), String>` - An error message, or nothing if successful
+    pub fn locate(&mut self, beats: f64) -> Result<(), String> {
+        match beats >= 0.0 {
+            true => self.send(Command::Locate(beats)),
+            false => Err(format!("Invalid position {}", beats)),
         }
     }
 
-    /// ## `add_effect(&mut self, effect: Box<dyn Effect>)`
+    /// ## `set_tempo(&mut self, bpm: f64) -> Result<(), String>`
     ///
-    /// Adds an effect to the end of the chain.
+    /// Sets the tempo. The position carries on in beats, so everything following the transport speeds up or slows down with it.
     ///
     /// ### Arguments
     ///
-    /// * `effect: Box<dyn Effect>` - The effect to add
-    pub fn add_effect(&mut self, effect: Box<dyn plugin::Effect>) {
-        self.chain.push(effect);
+    /// * `bpm: f64` - The tempo in beats per minute
+    ///
+    /// ### Returns
+    ///
+    /// * `Result<(), String>` - An error message, or nothing if successful
+    pub fn set_tempo(&mut self, bpm: f64) -> Result<(), String> {
+        if !(1.0..=999.0).contains(&bpm) {
+            return Err(format!("Invalid tempo {}: expected 1 to 999 bpm", bpm));
+        }
+        self.send(Command::SetTempo(bpm))?;
+        self.transport.bpm = bpm;
+        Ok(())
     }
 
-    /// ## `insert_effect(&mut self, effect: Box<dyn Effect>, index: usize)`
+    /// ## `set_signature(&mut self, beats_per_bar: u32, beat_unit: u32) -> Result<(), String>`
     ///
-    /// Inserts an effect into the chain at the given index.
+    /// Sets the time signature.
     ///
     /// ### Arguments
     ///
-    /// * `effect: Box<dyn Effect>` - The effect to insert
-    /// * `index: usize` - The index to insert the effect at
-    pub fn insert_effect(&mut self, effect: Box<dyn plugin::Effect>, index: usize) {
-        self.chain.insert(index, effect);
+    /// * `beats_per_bar: u32` - The number of beats in a bar
+    /// * `beat_unit: u32` - The length of a beat (4 for quarter notes, 8 for eighth notes)
+    ///
+    /// ### Returns
+    ///
+    /// * `Result<(), String>` - An error message, or nothing if successful
+    pub fn set_signature(&mut self, beats_per_bar: u32, beat_unit: u32) -> Result<(), String> {
+        if beats_per_bar == 0
+            || beats_per_bar > 64
+            || !beat_unit.is_power_of_two()
+            || beat_unit > 64
+        {
+            return Err(format!(
+                "Invalid time signature {}/{}",
+                beats_per_bar, beat_unit
+            ));
+        }
+        self.send(Command::SetSignature(beats_per_bar, beat_unit))?;
+        self.transport.beats_per_bar = beats_per_bar;
+        self.transport.beat_unit = beat_unit;
+        Ok(())
     }
 
-    /// ## `remove_effect(&mut self, index: usize)`
+    /// ## `note_on(&mut self, strip: usize, note: u8, velocity: f32) -> Result<(), String>`
     ///
-    /// Removes an effect from the chain at the given index.
+    /// Starts a note on a strip's generator.
     ///
     /// ### Arguments
     ///
-    /// * `index: usize` - The index to remove the effect from
-    pub fn remove_effect(&mut self, index: usize) {
-        self.chain.remove(index);
+    /// * `strip: usize` - The index of the strip
+    /// * `note: u8` - The MIDI note number
+    /// * `velocity: f32` - The velocity (0.0-1.0)
+    ///
+    /// ### Returns
+    ///
+    /// * `Result<(), String>` - An error message, or nothing if successful
+    pub fn note_on(&mut self, strip: usize, note: u8, velocity: f32) -> Result<(), String> {
+        self.check_strip(strip)?;
+        self.send(Command::NoteOn {
+            strip,
+            note,
+            velocity,
+        })
     }
 
-    /// ## `process(&mut self, state: State) -> Sample`
+    /// ## `note_off(&mut self, strip: usize, note: u8) -> Result<(), String>`
     ///
-    /// Processes a sample.
+    /// Releases a note on a strip's generator.
     ///
     /// ### Arguments
     ///
-    /// * `state: State` - The current state of the audio engine
+    /// * `strip: usize` - The index of the strip
+    /// * `note: u8` - The MIDI note number
     ///
     /// ### Returns
     ///
-    /// * `Sample` - The resulting sample
-    pub fn process(&mut self, state: State) -> Sample {
-        let sample = match &self.input {
-            Input::Generator(generator) => {
-                let mut sample = match generator.try_lock() {
-                    Ok(mut generator) => generator.generate(&state),
-                    Err(error) => return Sample::Mono(0.0),
-                };
-                for effect in self.chain.iter_mut() {
-                    effect.process(&state, &mut sample);
-                }
-                sample
-            }
-            Input::Bus(_bus) => Sample::Mono(0.0),
-        };
+    /// * `Result<(), String>` - An error message, or nothing if successful
+    pub fn note_off(&mut self, strip: usize, note: u8) -> Result<(), String> {
+        self.check_strip(strip)?;
+        self.send(Command::NoteOff { strip, note })
+    }
+
+    /// ## `midi(&mut self, strip: usize, channel: u8, message: ChannelMessage) -> Result<(), String>`
+    ///
+    /// Sends a MIDI channel message to a strip's generator and effects.
+    ///
+    /// ### Arguments
+    ///
+    /// * `strip: usize` - The index of the strip
+    /// * `channel: u8` - The MIDI channel (0-15)
+    /// * `message: ChannelMessage` - The message
+    ///
+    /// ### Returns
+    ///
+    /// * `Result<(), String>` - An error message, or nothing if successful
+    pub fn midi(
+        &mut self,
+        strip: usize,
+        channel: u8,
+        message: ChannelMessage,
+    ) -> Result<(), String> {
+        self.check_strip(strip)?;
+        self.send(Command::Midi {
+            strip,
+            channel,
+            message,
+        })
+    }
+
+    /// ## `send(&mut self, command: Command) -> Result<(), String>`
+    ///
+    /// Sends a command to the audio thread without blocking. Also frees whatever the audio thread has replaced since the last command.
+    ///
+    /// ### Arguments
+    ///
+    /// * `command: Command` - The command to send
+    ///
+    /// ### Returns
+    ///
+    /// * `Result<(), String>` - An error message if the queue is full, or nothing if successful
+    pub fn send(&mut self, command: Command) -> Result<(), String> {
+        while let Ok(_garbage) = self.garbage.try_recv() {}
 
-        match &self.output {
-            Output::Mono(_channel) => Sample::Mono(sample.mono()),
-            Output::Stereo(_left_channel, _right_channel) => {
-                Sample::Stereo(sample.left(), sample.right())
+        match self.sender.try_send(command) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_command)) => Err("The audio command queue is full".to_owned()),
+            Err(TrySendError::Disconnected(_command)) => {
+                Err("The audio engine has stopped".to_owned())
             }
-            Output::Bus(_bus) => Sample::Stereo(sample.left(), sample.right()),
+        }
+    }
+
+    /// ## `borrow_engine(&mut self) -> Result<Engine, String>`
+    ///
+    /// Takes the engine. If a stream owns it, the audio thread is asked to hand it over after its current block,
+    /// and plays silence until it is returned with `return_engine`.
+    ///
+    /// ### Returns
+    ///
+    /// * `Result<Engine, String>` - The engine, or an error message if the audio thread did not hand it over
+    pub fn borrow_engine(&mut self) -> Result<Engine, String> {
+        match self.engine.take() {
+            Some(engine) => return Ok(engine),
+            None => {}
+        }
+
+        let (reply, engine) = sync_channel(1);
+        self.send(Command::Detach(reply))?;
+        match engine.recv_timeout(std::time::Duration::from_secs(2)) {
+            Ok(engine) => Ok(engine),
+            Err(e) => Err(format!(
+                "The audio thread did not hand over the engine: {}",
+                e
+            )),
+        }
+    }
+
+    /// ## `return_engine(&mut self, engine: Engine)`
+    ///
+    /// Gives a borrowed engine back, to the stream that owns it if there is one.
+    ///
+    /// ### Arguments
+    ///
+    /// * `engine: Engine` - The engine
+    pub fn return_engine(&mut self, engine: Engine) {
+        match self.attach.as_ref() {
+            Some(attach) => match attach.try_send(engine) {
+                Ok(()) => {}
+                Err(TrySendError::Full(engine)) | Err(TrySendError::Disconnected(engine)) => {
+                    self.engine = Some(engine)
+                }
+            },
+            None => self.engine = Some(engine),
+        }
+    }
+
+    /// ## `check_strip(&self, strip: usize) -> Result<(), String>`
+    ///
+    /// Private function which checks that a strip exists.
+    ///
+    /// ### Arguments
+    ///
+    /// * `strip: usize` - The index of the strip
+    ///
+    /// ### Returns
+    ///
+    /// * `Result<(), String>` - An error message, or nothing if the strip exists
+    fn check_strip(&self, strip: usize) -> Result<(), String> {
+        match strip < self.routes.len() {
+            true => Ok(()),
+            false => Err(format!("No strip {}", strip)),
+        }
+    }
+
+    /// ## `check_target(&self, target: Target) -> Result<(), String>`
+    ///
+    /// Private function which checks that a strip exists, if the target is a strip.
+    ///
+    /// ### Arguments
+    ///
+    /// * `target: Target` - The strip or the master bus
+    ///
+    /// ### Returns
+    ///
+    /// * `Result<(), String>` - An error message, or nothing if the target exists
+    fn check_target(&self, target: Target) -> Result<(), String> {
+        match target {
+            Target::Strip(strip) => self.check_strip(strip),
+            Target::Master => Ok(()),
         }
     }
 }
@@ -1234,8 +4759,20 @@ impl Strip {
 pub mod plugin {
     use log::debug;
 
+    use super::hermite;
+    use super::Buffer;
+    use super::ChannelMessage;
     use super::Sample;
     use super::State;
+    use std::sync::Arc;
+
+    use crate::dynamics::{Compressor, Gate, Limiter};
+    use crate::eq::{Curve, Equalizer};
+    use crate::filter::{Filter, FilterMode};
+    use crate::granulizer::Granulizer;
+    use crate::modulation::{Chorus, Flanger, Phaser};
+    use crate::pitch::{Harmonizer, PitchShift};
+    use crate::reverb::Reverb;
 
     /// ## Generator
     ///
@@ -1248,14 +4785,49 @@ pub mod plugin {
     ///
     /// ### Functions
     ///
-    /// * `generate(&self, sample_clock: &f32, sample_rate: &f32) -> f32` - Generates a sample
+    /// * `generate(&mut self, state: &State, buffer: &mut Buffer)` - Fills a block with generated samples
+    /// * `note_on(&mut self, note: u8, velocity: f32)` - Starts a note, if the generator plays notes
+    /// * `note_off(&mut self, note: u8)` - Releases a note, if the generator plays notes
+    /// * `midi(&mut self, channel: u8, message: ChannelMessage)` - Handles a MIDI channel message; by default plays its notes and ignores the rest
+    /// * `set_parameter(&mut self, name: &str, value: f32)` - Sets a parameter by name, if the generator has any
     pub trait Generator: Send + Sync {
-        fn generate(&mut self, state: &State) -> Sample;
+        fn generate(&mut self, state: &State, buffer: &mut Buffer);
+
+        fn note_on(&mut self, _note: u8, _velocity: f32) {}
+
+        fn note_off(&mut self, _note: u8) {}
+
+        fn midi(&mut self, _channel: u8, message: ChannelMessage) {
+            match message {
+                ChannelMessage::NoteOn { note, velocity } => {
+                    self.note_on(note, velocity as f32 / 127.0)
+                }
+                ChannelMessage::NoteOff { note, .. } => self.note_off(note),
+                _ => {}
+            }
+        }
+
+        fn set_parameter(&mut self, _name: &str, _value: f32) {}
+    }
+
+    /// ## `note_frequency(note: u8) -> f32`
+    ///
+    /// Returns the frequency of a MIDI note number, in equal temperament tuned to A4 = 440 Hz.
+    ///
+    /// ### Arguments
+    ///
+    /// * `note: u8` - The MIDI note number
+    ///
+    /// ### Returns
+    ///
+    /// * `f32` - The frequency in Hz
+    pub fn note_frequency(note: u8) -> f32 {
+        440.0 * 2.0f32.powf((note as f32 - 69.0) / 12.0)
     }
 
     /// ## ClosureGenerator
     ///
-    /// A generator that uses a closure to generate samples
+    /// A generator that uses a closure to generate samples, one frame at a time
     ///
     /// ### Fields
     ///
@@ -1279,81 +4851,152 @@ pub mod plugin {
     }
 
     impl Generator for ClosureGenerator {
-        fn generate(&mut self, state: &State) -> Sample {
-            (self.closure)(state)
+        fn generate(&mut self, state: &State, buffer: &mut Buffer) {
+            for index in 0..buffer.len() {
+                buffer.set_frame(index, (self.closure)(&state.at(index)));
+            }
         }
     }
 
-    static FALLOFF: f32 = 0.01;
-
-    pub struct SineGenerator {
-        // First value is frequency, second value is amplitude (0.0-1.0)
-        freqs: Vec<(f32, f32)>,
-    }
+    /// ## Effect
+    ///
+    /// Trait for audio effects
+    ///
+    /// ### Traits
+    ///
+    /// * `Send` - Can be sent between threads
+    /// * `Sync` - Is safe to share between threads
+    ///
+    /// ### Functions
+    ///
+    /// * `process(&mut self, state: &State, buffer: &mut Buffer)` - Processes a block in place
+    /// * `midi(&mut self, channel: u8, message: ChannelMessage)` - Handles a MIDI channel message sent to the strip, including its notes from HID keys and the sequencer; ignored by default
+    /// * `sidechain(&self) -> Option<usize>` - Returns the strip keying the effect, if any; None by default
+    /// * `key(&mut self, key: &Buffer)` - Receives the keying strip's block, just before `process`
+    /// * `keyable(&self) -> bool` - Returns whether the effect takes a "key" strip (see `sidechain`), so the strips can be ordered; false by default
+    /// * `curve(&self) -> Option<Arc<Curve>>` - Returns the settings an EQ shares with the control side; None by default
+    /// * `set_parameter(&mut self, name: &str, value: f32)` - Sets a parameter by name, ignoring names the effect does not have
+    pub trait Effect: Send + Sync {
+        fn process(&mut self, state: &State, buffer: &mut Buffer);
 
-    impl SineGenerator {
-        pub fn new() -> Self {
-            Self { freqs: Vec::new() }
-        }
+        fn midi(&mut self, _channel: u8, _message: ChannelMessage) {}
 
-        pub fn add_freq(&mut self, freq: f32, amp: f32) {
-            self.freqs.push((freq, amp));
+        fn sidechain(&self) -> Option<usize> {
+            None
         }
 
-        pub fn remove_freq(&mut self, freq: f32) {
-            let mut index = 0;
-            for (i, freq_amp) in self.freqs.iter().enumerate() {
-                if freq_amp.0 == freq {
-                    index = i;
-                    break;
-                }
-            }
-
-            if index >= self.freqs.len() {
-                return;
-            }
+        fn key(&mut self, _key: &Buffer) {}
 
-            self.freqs[index].1 = 1.0 - FALLOFF;
+        fn keyable(&self) -> bool {
+            false
         }
-    }
-
-    impl Generator for SineGenerator {
-        fn generate(&mut self, state: &State) -> Sample {
-            let mut sample = 0.0;
-            for mut freq_amp in self.freqs.iter_mut() {
-                if freq_amp.1 < 1.0 {
-                    freq_amp.1 = freq_amp.1 - FALLOFF;
-                    if freq_amp.1 < 0.0 {
-                        continue;
-                    }
-                }
-                sample += (state.sample_clock as f32 * freq_amp.0 * 2.0 * std::f32::consts::PI
-                    / state.sample_rate as f32)
-                    .sin()
-                    * freq_amp.1;
-            }
-
-            // remove freqs with amp 0.0
-            self.freqs.retain(|freq_amp| freq_amp.1 > 0.0);
 
-            Sample::Stereo(sample, sample)
+        fn curve(&self) -> Option<Arc<Curve>> {
+            None
         }
+
+        fn set_parameter(&mut self, _name: &str, _value: f32) {}
     }
 
-    /// ## Effect
+    /// ## `create_effect(name: &str, args: &[f32]) -> Result<Box<dyn Effect>, String>`
     ///
-    /// Trait for audio effects
+    /// Creates an effect by name, so effects can be added from the console. Missing arguments take their defaults.
     ///
-    /// ### Traits
+    /// ### Arguments
     ///
-    /// * `Send` - Can be sent between threads
-    /// * `Sync` - Is safe to share between threads
+    /// * `name: &str` - The name of the effect: "clip [threshold]", "bitcrusher [bits]", "delay [length] [feedback]", "lofidelay [length] [feedback] [detune]",
+    /// "filter [cutoff] [resonance] [mode]", "lowpass", "highpass", "bandpass" and "notch" with [cutoff] [resonance],
+    /// "compressor [threshold] [ratio] [attack] [release]", "limiter [ceiling] [lookahead] [release]", "gate [threshold] [ratio] [range]"
+    /// "reverb [size] [decay] [mix]", "chorus [voices] [rate] [depth] [mix]", "flanger [rate] [depth] [feedback]", "phaser [stages] [rate] [feedback]"
+    /// "granulizer [duration] [density] [pitch]", "pitchshift [semitones] [cents]", "harmonizer [interval...]" with up to four intervals in scale steps
+    /// or "eq" with every band off
+    /// * `args: &[f32]` - The arguments of the effect, in order
     ///
-    /// ### Functions
+    /// ### Returns
     ///
-    /// * `process(&mut self, sample: &mut Sample)` - Processes a sample
-    pub trait Effect: Send + Sync {
-        fn process(&mut self, state: &State, sample: &mut Sample);
+    /// * `Result<Box<dyn Effect>, String>` - The effect, or an error message if the name is unknown
+    pub fn create_effect(name: &str, args: &[f32]) -> Result<Box<dyn Effect>, String> {
+        let arg = |index: usize, default: f32| match args.get(index) {
+            Some(value) => *value,
+            None => default,
+        };
+
+        match name.to_lowercase().as_str() {
+            "clip" => Ok(Box::new(Clip::new(arg(0, 1.0).abs()))),
+            "bitcrusher" => Ok(Box::new(BitCrusher::new(arg(0, 8.0).max(1.0) as u32))),
+            "delay" => Ok(Box::new(Delay::new(
+                arg(0, 11025.0).max(1.0) as usize,
+                arg(1, 0.3),
+            ))),
+            "lofidelay" => Ok(Box::new(LofiDelay::new(
+                arg(0, 500.0).max(2.0) as usize,
+                arg(1, 0.5),
+                arg(2, 10.0).max(0.0) as u64,
+            ))),
+            "compressor" => Ok(Box::new(Compressor::new(
+                arg(0, -18.0),
+                arg(1, 4.0),
+                arg(2, 10.0),
+                arg(3, 100.0),
+            ))),
+            "limiter" => Ok(Box::new(Limiter::new(
+                arg(0, -0.3),
+                arg(1, 5.0),
+                arg(2, 100.0),
+            ))),
+            "gate" => Ok(Box::new(Gate::new(
+                arg(0, -50.0),
+                arg(1, 100.0),
+                arg(2, -80.0),
+            ))),
+            "reverb" => Ok(Box::new(Reverb::new(
+                arg(0, 0.7),
+                arg(1, 2.5),
+                arg(2, 0.3),
+            ))),
+            "chorus" => Ok(Box::new(Chorus::new(
+                arg(0, 2.0).max(1.0) as usize,
+                arg(1, 0.8),
+                arg(2, 4.0),
+                arg(3, 0.5),
+            ))),
+            "flanger" => Ok(Box::new(Flanger::new(
+                arg(0, 0.25),
+                arg(1, 2.0),
+                arg(2, 0.5),
+            ))),
+            "phaser" => Ok(Box::new(Phaser::new(
+                arg(0, 4.0).max(2.0) as usize,
+                arg(1, 0.5),
+                arg(2, 0.3),
+            ))),
+            "granulizer" => Ok(Box::new(Granulizer::new(
+                arg(0, 80.0),
+                arg(1, 20.0),
+                arg(2, 0.0),
+            ))),
+            "pitchshift" => Ok(Box::new(PitchShift::new(arg(0, 0.0), arg(1, 0.0)))),
+            "harmonizer" => {
+                let intervals = match args.is_empty() {
+                    true => vec![2],
+                    false => args.iter().map(|interval| interval.round() as i32).collect(),
+                };
+                Ok(Box::new(Harmonizer::new(&intervals)))
+            }
+            "eq" => Ok(Box::new(Equalizer::new(Arc::new(Curve::new())))),
+            "filter" => Ok(Box::new(Filter::new(
+                FilterMode::from_index(arg(2, 0.0)),
+                arg(0, 1000.0),
+                arg(1, 0.0),
+            ))),
+            name => match FilterMode::from_name(name) {
+                Some(mode) => Ok(Box::new(Filter::new(mode, arg(0, 1000.0), arg(1, 0.0)))),
+                None => Err(format!(
+                    "No effect named {} (available: clip, bitcrusher, delay, lofidelay, compressor, limiter, gate, reverb, chorus, flanger, phaser, granulizer, pitchshift, harmonizer, eq, filter, lowpass, highpass, bandpass, notch)",
+                    name
+                )),
+            },
+        }
     }
 
     /// ## Clip
@@ -1374,27 +5017,22 @@ pub mod plugin {
     }
 
     impl Effect for Clip {
-        fn process(&mut self, _state: &State, sample: &mut Sample) {
-            match sample {
-                Sample::Mono(sample) => {
+        fn process(&mut self, _state: &State, buffer: &mut Buffer) {
+            for channel in 0..buffer.channels() {
+                for sample in buffer.channel_mut(channel).iter_mut() {
                     if *sample > self.threshold {
                         *sample = self.threshold;
                     } else if *sample < -self.threshold {
                         *sample = -self.threshold;
                     }
                 }
-                Sample::Stereo(left, right) => {
-                    if *left > self.threshold {
-                        *left = self.threshold;
-                    } else if *left < -self.threshold {
-                        *left = -self.threshold;
-                    }
-                    if *right > self.threshold {
-                        *right = self.threshold;
-                    } else if *right < -self.threshold {
-                        *right = -self.threshold;
-                    }
-                }
+            }
+        }
+
+        fn set_parameter(&mut self, name: &str, value: f32) {
+            match name {
+                "threshold" => self.threshold = value.abs(),
+                _ => {}
             }
         }
     }
@@ -1417,105 +5055,327 @@ pub mod plugin {
     }
 
     impl Effect for BitCrusher {
-        fn process(&mut self, _state: &State, sample: &mut Sample) {
-            match sample {
-                Sample::Mono(sample) => {
-                    *sample = (*sample * 2.0f32.powf(self.bits as f32)).floor()
-                        / 2.0f32.powf(self.bits as f32);
-                }
-                Sample::Stereo(left, right) => {
-                    *left = (*left * 2.0f32.powf(self.bits as f32)).floor()
-                        / 2.0f32.powf(self.bits as f32);
-                    *right = (*right * 2.0f32.powf(self.bits as f32)).floor()
-                        / 2.0f32.powf(self.bits as f32);
+        fn process(&mut self, _state: &State, buffer: &mut Buffer) {
+            let steps = 2.0f32.powf(self.bits as f32);
+            for channel in 0..buffer.channels() {
+                for sample in buffer.channel_mut(channel).iter_mut() {
+                    *sample = (*sample * steps).floor() / steps;
                 }
             }
         }
+
+        fn set_parameter(&mut self, name: &str, value: f32) {
+            match name {
+                "bits" => self.bits = value.max(1.0) as u32,
+                _ => {}
+            }
+        }
     }
 
     /// ## Delay
     ///
-    /// An effect that delays samples
+    /// An effect that delays samples. Its buffer can also be used as a plain delay line through `write` and `read`,
+    /// which reads between samples for modulated delays.
     ///
     /// ### Fields
     ///
     /// * `length: usize` - The length of the delay buffer
     /// * `feedback: f32` - The amount of feedback to apply to the delay signal
-    /// * `buffer: Vec<Sample>` - The delay buffer
+    /// * `buffer: Vec<Sample>` - The delay buffer, used as a ring
+    /// * `position: usize` - The position of the oldest sample in the delay buffer
     pub struct Delay {
         length: usize,
         feedback: f32,
         buffer: Vec<Sample>,
+        position: usize,
     }
 
     impl Delay {
         pub fn new(length: usize, feedback: f32) -> Self {
+            let length = length.max(1);
             Self {
                 length,
                 feedback,
                 buffer: vec![Sample::Mono(0.0); length],
+                position: 0,
             }
         }
 
         pub fn resize(&mut self, length: usize) {
-            self.length = length;
-            self.buffer.resize(length, Sample::Mono(0.0));
+            self.length = length.max(1);
+            self.buffer.resize(self.length, Sample::Mono(0.0));
+            if self.position >= self.length {
+                self.position = 0;
+            }
+        }
+
+        /// ## `write(&mut self, sample: Sample)`
+        ///
+        /// Writes the next sample into the buffer, without feedback.
+        ///
+        /// ### Arguments
+        ///
+        /// * `sample: Sample` - The sample
+        pub fn write(&mut self, sample: Sample) {
+            self.buffer[self.position] = sample;
+            self.position = (self.position + 1) % self.length;
+        }
+
+        /// ## `read(&self, delay: f32) -> Sample`
+        ///
+        /// Returns the signal a fractional number of frames ago, with four point Hermite interpolation
+        /// so a moving read position doesn't zipper. The buffer needs at least 5 frames.
+        ///
+        /// ### Arguments
+        ///
+        /// * `delay: f32` - The delay in frames, from 2.0 up to 3 frames short of the length
+        ///
+        /// ### Returns
+        ///
+        /// * `Sample` - The interpolated sample, mono if the samples around it are
+        pub fn read(&self, delay: f32) -> Sample {
+            let length = self.length;
+            let delay = delay.clamp(2.0, length.saturating_sub(3).max(2) as f32);
+            let whole = delay.floor() as usize;
+            let fraction = delay - whole as f32;
+            // from one frame newer than `whole` ago to two frames older
+            let at = |offset: usize| {
+                &self.buffer[(self.position + length * 2 + 1 - whole - offset) % length]
+            };
+
+            match (at(0), at(1), at(2), at(3)) {
+                (Sample::Mono(y0), Sample::Mono(y1), Sample::Mono(y2), Sample::Mono(y3)) => {
+                    Sample::Mono(hermite(*y0, *y1, *y2, *y3, fraction))
+                }
+                (y0, y1, y2, y3) => Sample::Stereo(
+                    hermite(y0.left(), y1.left(), y2.left(), y3.left(), fraction),
+                    hermite(y0.right(), y1.right(), y2.right(), y3.right(), fraction),
+                ),
+            }
         }
     }
 
     impl Effect for Delay {
-        fn process(&mut self, _state: &State, sample: &mut Sample) {
-            match sample {
-                Sample::Mono(sample) => {
-                    let delay_signal = self.buffer.remove(0);
-                    self.buffer
-                        .push(Sample::Mono(*sample + delay_signal.mono() * self.feedback));
-                }
-                Sample::Stereo(left, right) => {
-                    let delay_signal = self.buffer.remove(0);
-                    self.buffer.push(Sample::Stereo(
-                        *left as f32 + delay_signal.left() * self.feedback,
-                        *right as f32 + delay_signal.right() * self.feedback,
-                    ));
-                    *left = (*left as f32 + delay_signal.left()) as f32;
-                    *right = (*right as f32 + delay_signal.right()) as f32;
-                }
+        fn process(&mut self, _state: &State, buffer: &mut Buffer) {
+            for index in 0..buffer.len() {
+                let sample = buffer.frame(index);
+                let delay_signal = self.buffer[self.position].clone();
+                self.buffer[self.position] = Sample::Stereo(
+                    sample.left() + delay_signal.left() * self.feedback,
+                    sample.right() + delay_signal.right() * self.feedback,
+                );
+                self.position = (self.position + 1) % self.length;
+
+                buffer.set_frame(
+                    index,
+                    Sample::Stereo(
+                        sample.left() + delay_signal.left(),
+                        sample.right() + delay_signal.right(),
+                    ),
+                );
+            }
+        }
+
+        fn set_parameter(&mut self, name: &str, value: f32) {
+            match name {
+                "feedback" => self.feedback = value,
+                _ => {}
             }
         }
     }
+
+    /// ## LofiDelay
+    ///
+    /// A mono delay that periodically smears its buffer, giving a detuned, lo-fi repeat
+    ///
+    /// ### Fields
+    ///
+    /// * `length: usize` - The length of the delay buffer
+    /// * `buffer: Vec<f32>` - The delay buffer, used as a ring
+    /// * `position: usize` - The position of the oldest sample in the delay buffer
+    /// * `feedback: f32` - The amount of feedback to apply to the delay signal
+    /// * `detune: u64` - How often the buffer is smeared, in milliseconds
     pub struct LofiDelay {
         length: usize,
         buffer: Vec<f32>,
+        position: usize,
         feedback: f32,
         detune: u64,
     }
     impl LofiDelay {
         pub fn new(length: usize, feedback: f32, detune: u64) -> Self {
+            let length = length.max(2);
             Self {
                 length,
                 buffer: vec![0.0; length],
+                position: 0,
                 feedback,
                 detune,
             }
         }
         pub fn resize(&mut self, length: usize) {
-            self.length = length;
-            self.buffer.resize(length, 0.0);
+            self.length = length.max(2);
+            self.buffer.resize(self.length, 0.0);
+            if self.position >= self.length {
+                self.position = 0;
+            }
         }
     }
     impl Effect for LofiDelay {
-        fn process(&mut self, state: &State, sample: &mut Sample) {
-            let sample_mono = sample.mono();
-            let main_signal = self.buffer[0];
-            let interp_signal = (self.buffer[0] + self.buffer[1]) / 2.0;
-            let detune = self.detune;
-            self.buffer.remove(0);
-            //add interp signal to buffer
-            if state.sample_clock % ((state.sample_rate as u64 * detune) / 1000) == 0 {
-                self.buffer.replace(0, interp_signal);
-            }
-            self.buffer.push(sample_mono + main_signal * self.feedback);
-            *sample = Sample::Mono((sample_mono + main_signal as f32));
+        fn process(&mut self, state: &State, buffer: &mut Buffer) {
+            let period = (state.sample_rate as u64 * self.detune) / 1000;
+            for index in 0..buffer.len() {
+                let sample_mono = buffer.frame(index).mono();
+                let next = (self.position + 1) % self.length;
+                let main_signal = self.buffer[self.position];
+                let interp_signal = (self.buffer[self.position] + self.buffer[next]) / 2.0;
+
+                //add interp signal to buffer
+                if period > 0 && (state.sample_clock + index as u64) % period == 0 {
+                    self.buffer[next] = interp_signal;
+                }
+                self.buffer[self.position] = sample_mono + main_signal * self.feedback;
+                self.position = next;
+
+                buffer.set_frame(index, Sample::Mono(sample_mono + main_signal));
+            }
+        }
+
+        fn set_parameter(&mut self, name: &str, value: f32) {
+            match name {
+                "feedback" => self.feedback = value,
+                "detune" => self.detune = value.max(0.0) as u64,
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(reads: Option<&str>, writes: Option<&str>, keys: &[usize]) -> Route {
+        let input = match reads {
+            Some(name) => Input::Bus(name.to_owned()),
+            None => Input::Mono(0),
+        };
+        let output = match writes {
+            Some(name) => Output::Bus(name.to_owned()),
+            None => Output::Stereo(0, 1),
+        };
+        let mut route = Route::new(&input, &output);
+        route.keys = keys.iter().map(|strip| Keying::Strip(*strip)).collect();
+        route
+    }
+
+    fn position(order: &[usize], strip: usize) -> usize {
+        order.iter().position(|index| *index == strip).unwrap()
+    }
+
+    #[test]
+    fn writers_before_readers() {
+        let routes = [
+            route(Some("drums"), None, &[]),
+            route(None, Some("drums"), &[]),
+            route(None, Some("drums"), &[]),
+        ];
+        let order = processing_order(&routes).unwrap();
+        assert_eq!(order.len(), 3);
+        assert!(position(&order, 1) < position(&order, 0));
+        assert!(position(&order, 2) < position(&order, 0));
+    }
+
+    #[test]
+    fn chained_buses_in_order() {
+        // two drums into a bus, its strip into a room bus, and a strip on no bus at all
+        let routes = [
+            route(Some("room"), None, &[]),
+            route(Some("drums"), Some("room"), &[]),
+            route(None, Some("drums"), &[]),
+            route(None, Some("drums"), &[]),
+            route(None, None, &[]),
+        ];
+        let order = processing_order(&routes).unwrap();
+        assert_eq!(order.len(), 5);
+        assert!(position(&order, 2) < position(&order, 1));
+        assert!(position(&order, 3) < position(&order, 1));
+        assert!(position(&order, 1) < position(&order, 0));
+    }
+
+    #[test]
+    fn refuses_bus_cycles() {
+        let routes = [
+            route(Some("a"), Some("b"), &[]),
+            route(Some("b"), Some("a"), &[]),
+        ];
+        assert!(processing_order(&routes).unwrap_err().contains("bus"));
+
+        // around three buses, and a strip reading its own bus
+        let routes = [
+            route(Some("a"), Some("b"), &[]),
+            route(Some("b"), Some("c"), &[]),
+            route(Some("c"), Some("a"), &[]),
+        ];
+        assert!(processing_order(&routes).unwrap_err().contains("bus"));
+        assert!(processing_order(&[route(Some("a"), Some("a"), &[])]).is_err());
+    }
+
+    #[test]
+    fn sidechain_sources_before_keyed_strips() {
+        let routes = [
+            route(None, None, &[2]),
+            route(Some("bass"), None, &[3]),
+            route(None, None, &[]),
+            route(None, Some("bass"), &[]),
+        ];
+        let order = processing_order(&routes).unwrap();
+        assert_eq!(order.len(), 4);
+        assert!(position(&order, 2) < position(&order, 0));
+        assert!(position(&order, 3) < position(&order, 1));
+    }
+
+    #[test]
+    fn refuses_key_cycles() {
+        assert!(processing_order(&[route(None, None, &[0])]).is_err());
+
+        // strip 1 feeds strip 0 through a bus, and strip 0 keys strip 1
+        let routes = [route(Some("a"), None, &[]), route(None, Some("a"), &[0])];
+        assert!(processing_order(&routes)
+            .unwrap_err()
+            .contains("keyed strip"));
+    }
+
+    #[test]
+    fn delay_reads_between_samples() {
+        let mut delay = plugin::Delay::new(64, 0.0);
+        for frame in 0..100 {
+            delay.write(Sample::Mono(frame as f32));
+        }
+        // the last sample written is a frame ago, and Hermite interpolation is exact on a ramp
+        for frames in [2.0, 2.5, 10.25, 33.75, 60.0] {
+            match delay.read(frames) {
+                Sample::Mono(sample) => assert!(
+                    (sample - (100.0 - frames)).abs() < 1e-4,
+                    "{} at {}",
+                    sample,
+                    frames
+                ),
+                sample => panic!("{:?} read from mono samples", sample),
+            }
+        }
+        // reads outside the line are clamped to it
+        assert_eq!(delay.read(0.0).mono(), 98.0);
+        assert_eq!(delay.read(1000.0).mono(), 39.0);
+
+        // a sine delayed by a fraction of a frame, in stereo
+        let mut delay = plugin::Delay::new(64, 0.0);
+        let sine = |frame: f32| (frame * 0.05).sin();
+        for frame in 0..100 {
+            delay.write(Sample::Stereo(sine(frame as f32), -sine(frame as f32)));
         }
+        let (left, right) = delay.read(12.3).stereo();
+        assert!((left - sine(100.0 - 12.3)).abs() < 1e-4, "{}", left);
+        assert!((right + sine(100.0 - 12.3)).abs() < 1e-4, "{}", right);
     }
 }
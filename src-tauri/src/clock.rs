@@ -0,0 +1,629 @@
+//! clock.rs
+//!
+//! Module for MIDI clock: following another device's clock and transport, and sending ours to another device
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use lazy_static::lazy_static;
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+use midir::{MidiOutput, MidiOutputConnection};
+
+use crate::audio;
+use crate::midi::{self, MidiEvent};
+use crate::transport::{Position, Transport};
+
+/// MIDI clock runs at 24 pulses per quarter note
+pub const CLOCK_PPQN: f64 = 24.0;
+
+/// Timing clock message
+pub const CLOCK: u8 = 0xF8;
+/// Start message: play from the start of the song
+pub const START: u8 = 0xFA;
+/// Continue message: play from where the song is
+pub const CONTINUE: u8 = 0xFB;
+/// Stop message
+pub const STOP: u8 = 0xFC;
+/// Song Position Pointer message, followed by the position in sixteenth notes (14 bits, LSB first)
+pub const SONG_POSITION: u8 = 0xF2;
+
+/// How much of each new pulse interval goes into the smoothed interval (0.0-1.0); lower is smoother but slower to follow tempo changes
+const SMOOTHING: f64 = 0.05;
+
+/// The largest tempo correction made to pull the transport back in phase, as a fraction of the tempo
+const MAX_CORRECTION: f64 = 0.05;
+
+/// How far the transport can drift from the clock, in beats, before it is located instead of nudged
+const MAX_DRIFT: f64 = 1.0;
+
+lazy_static! {
+    /// Follows incoming MIDI clock, fed by every MIDI input
+    pub static ref FOLLOWER: Mutex<ClockFollower> = Mutex::new(ClockFollower::new());
+    /// Sends MIDI clock out, if a port was chosen
+    pub static ref SENDER: Mutex<Option<ClockSender>> = Mutex::new(None);
+}
+
+/// ## `quarters_per_beat(transport: &Transport) -> f64`
+///
+/// Returns the number of quarter notes in one of the transport's beats. Clock counts quarter notes,
+/// while the transport counts beats of its time signature's unit (e.g. eighth notes in 7/8).
+///
+/// ### Arguments
+///
+/// * `transport: &Transport` - The transport
+///
+/// ### Returns
+///
+/// * `f64` - The number of quarter notes
+pub fn quarters_per_beat(transport: &Transport) -> f64 {
+    4.0 / transport.beat_unit.max(1) as f64
+}
+
+/// ## ClockFollower
+///
+/// Slaves the transport to incoming MIDI clock. Start, Stop, Continue and Song Position Pointer move
+/// the transport; the tempo is measured from the smoothed interval between pulses, and nudged up or down
+/// to keep the transport in phase with the pulses counted since the last start or locate.
+///
+/// ### Fields
+///
+/// * `following: bool` - Whether incoming clock moves the transport
+/// * `last: Option<u64>` - The timestamp of the previous pulse, in microseconds
+/// * `interval: f64` - The smoothed interval between pulses, in microseconds; 0.0 until two pulses arrive
+/// * `base: f64` - The position the pulses are counted from, in quarter notes
+/// * `pulses: u64` - The pulses counted since the last start or locate
+/// * `ticks: u64` - The pulses received since following started, playing or not, which time the tempo corrections
+/// * `running: bool` - Whether the clock source is playing
+/// * `starting: bool` - Whether the transport starts playing on the next pulse, as the MIDI spec asks
+/// * `tempo: f64` - The tempo last sent to the transport, in beats per minute
+///
+/// ### Functions
+///
+/// * `new() -> Self` - Creates a follower that is not following
+/// * `set_following(&mut self, following: bool)` - Starts or stops following
+/// * `following(&self) -> bool` - Returns whether incoming clock moves the transport
+/// * `bpm(&self) -> Option<f64>` - Returns the measured tempo of the clock source, in quarter notes per minute
+/// * `receive(&mut self, stamp: u64, event: &MidiEvent)` - Handles a clock or transport message
+pub struct ClockFollower {
+    following: bool,
+    last: Option<u64>,
+    interval: f64,
+    base: f64,
+    pulses: u64,
+    ticks: u64,
+    running: bool,
+    starting: bool,
+    tempo: f64,
+}
+
+impl ClockFollower {
+    /// ## `new() -> Self`
+    ///
+    /// Creates a follower that is not following.
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The new follower
+    pub fn new() -> Self {
+        Self {
+            following: false,
+            last: None,
+            interval: 0.0,
+            base: 0.0,
+            pulses: 0,
+            ticks: 0,
+            running: false,
+            starting: false,
+            tempo: 0.0,
+        }
+    }
+
+    /// ## `set_following(&mut self, following: bool)`
+    ///
+    /// Starts or stops following. The measured tempo starts over.
+    ///
+    /// ### Arguments
+    ///
+    /// * `following: bool` - Whether incoming clock moves the transport
+    pub fn set_following(&mut self, following: bool) {
+        *self = Self {
+            following,
+            ..Self::new()
+        };
+    }
+
+    /// ## `following(&self) -> bool`
+    ///
+    /// Returns whether incoming clock moves the transport.
+    ///
+    /// ### Returns
+    ///
+    /// * `bool` - Whether the follower is following
+    pub fn following(&self) -> bool {
+        self.following
+    }
+
+    /// ## `bpm(&self) -> Option<f64>`
+    ///
+    /// Returns the measured tempo of the clock source.
+    ///
+    /// ### Returns
+    ///
+    /// * `Option<f64>` - The tempo in quarter notes per minute, or None if no clock has arrived yet
+    pub fn bpm(&self) -> Option<f64> {
+        match self.interval > 0.0 {
+            true => Some(60_000_000.0 / (self.interval * CLOCK_PPQN)),
+            false => None,
+        }
+    }
+
+    /// ## `receive(&mut self, stamp: u64, event: &MidiEvent)`
+    ///
+    /// Handles a clock or transport message. Other messages are ignored, and so is everything while not following.
+    ///
+    /// ### Arguments
+    ///
+    /// * `stamp: u64` - The timestamp of the message, in microseconds
+    /// * `event: &MidiEvent` - The message
+    pub fn receive(&mut self, stamp: u64, event: &MidiEvent) {
+        if !self.following {
+            return;
+        }
+
+        match event {
+            MidiEvent::Clock => self.pulse(stamp),
+            MidiEvent::Start => {
+                self.base = 0.0;
+                self.pulses = 0;
+                self.starting = true;
+                self.transport(|control, _| control.locate(0.0));
+            }
+            MidiEvent::Continue => {
+                self.starting = true;
+            }
+            MidiEvent::Stop => {
+                self.running = false;
+                self.starting = false;
+                self.transport(|control, _| control.play(false));
+            }
+            MidiEvent::SongPosition(sixteenths) => {
+                self.base = *sixteenths as f64 / 4.0;
+                self.pulses = 0;
+                let quarters = self.base;
+                self.transport(|control, transport| {
+                    control.locate(quarters / quarters_per_beat(transport))
+                });
+            }
+            _ => {}
+        }
+    }
+
+    /// ## `pulse(&mut self, stamp: u64)`
+    ///
+    /// Private function which handles a clock pulse: measures the tempo, starts the transport if a start is pending,
+    /// and keeps the transport in phase with the pulses.
+    ///
+    /// ### Arguments
+    ///
+    /// * `stamp: u64` - The timestamp of the pulse, in microseconds
+    fn pulse(&mut self, stamp: u64) {
+        self.measure(stamp);
+        self.ticks += 1;
+
+        if self.starting {
+            self.starting = false;
+            self.running = true;
+            self.transport(|control, _| control.play(true));
+            return;
+        }
+        if self.running {
+            self.pulses += 1;
+        }
+
+        // correct the tempo every sixteenth note, rather than flooding the audio thread with commands;
+        // counted by every pulse, as the pulses since the last start stand still while the source is stopped
+        let bpm = match self.bpm() {
+            Some(bpm) => bpm,
+            None => return,
+        };
+        if self.ticks % 6 != 0 {
+            return;
+        }
+
+        let expected = self.base + self.pulses as f64 / CLOCK_PPQN;
+        let running = self.running;
+        let sent = self.tempo;
+        let mut tempo = sent;
+        self.transport(|control, transport| {
+            let (bpm, locate) = Self::correct(bpm, expected, running, transport);
+            match locate {
+                Some(beats) => control.locate(beats)?,
+                None => {}
+            }
+
+            match (bpm - sent).abs() > 0.01 {
+                true => {
+                    control.set_tempo(bpm.clamp(1.0, 999.0))?;
+                    tempo = bpm;
+                    Ok(())
+                }
+                false => Ok(()),
+            }
+        });
+        self.tempo = tempo;
+    }
+
+    /// ## `measure(&mut self, stamp: u64)`
+    ///
+    /// Private function which smooths the interval between pulses with the one ending at a pulse.
+    ///
+    /// ### Arguments
+    ///
+    /// * `stamp: u64` - The timestamp of the pulse, in microseconds
+    fn measure(&mut self, stamp: u64) {
+        match self.last {
+            Some(last) if stamp > last => {
+                let interval = (stamp - last) as f64;
+                match self.interval > 0.0 && interval < self.interval * 2.0 {
+                    true => self.interval += (interval - self.interval) * SMOOTHING,
+                    // the first interval, or the first after the source paused
+                    false => self.interval = interval,
+                }
+            }
+            _ => {}
+        }
+        self.last = Some(stamp);
+    }
+
+    /// ## `correct(bpm: f64, expected: f64, running: bool, transport: &Transport) -> (f64, Option<f64>)`
+    ///
+    /// Private function which works out the tempo that keeps the transport in phase with the clock: the measured tempo,
+    /// nudged by at most MAX_CORRECTION towards where the pulses say the transport should be,
+    /// or a locate there if it has drifted further than MAX_DRIFT.
+    ///
+    /// ### Arguments
+    ///
+    /// * `bpm: f64` - The measured tempo, in quarter notes per minute
+    /// * `expected: f64` - Where the pulses say the transport should be, in quarter notes
+    /// * `running: bool` - Whether the clock source is playing
+    /// * `transport: &Transport` - The transport
+    ///
+    /// ### Returns
+    ///
+    /// * `(f64, Option<f64>)` - The tempo in the transport's beats per minute, and where to locate to in beats, if anywhere
+    fn correct(
+        bpm: f64,
+        expected: f64,
+        running: bool,
+        transport: &Transport,
+    ) -> (f64, Option<f64>) {
+        let quarters = quarters_per_beat(transport);
+        let bpm = bpm / quarters;
+        if !running || !transport.playing {
+            return (bpm, None);
+        }
+
+        let drift = expected - transport.beats * quarters;
+        match drift.abs() > MAX_DRIFT {
+            true => (bpm, Some(expected / quarters)),
+            false => (
+                bpm * (1.0 + (drift * 0.5).clamp(-MAX_CORRECTION, MAX_CORRECTION)),
+                None,
+            ),
+        }
+    }
+
+    /// ## `transport(&self, change: impl FnOnce(&mut audio::Control, &Transport) -> Result<(), String>)`
+    ///
+    /// Private function which changes the transport, logging any error.
+    ///
+    /// ### Arguments
+    ///
+    /// * `change: impl FnOnce(&mut audio::Control, &Transport) -> Result<(), String>` - The change, given the control and the current transport
+    fn transport(
+        &self,
+        change: impl FnOnce(&mut audio::Control, &Transport) -> Result<(), String>,
+    ) {
+        let mut control = match audio::CONTROL.lock() {
+            Ok(control) => control,
+            Err(e) => {
+                debug!("Error locking CONTROL: {}", e);
+                return;
+            }
+        };
+
+        let transport = control.transport();
+        match change(&mut control, &transport) {
+            Ok(()) => {}
+            Err(e) => debug!("Error following MIDI clock: {}", e),
+        }
+    }
+}
+
+/// ## ClockSender
+///
+/// Sends MIDI clock out on a port from its own thread, following the transport: clock pulses at the transport's tempo,
+/// Start or Continue when it starts playing, Stop when it stops, and Song Position Pointer when it is located.
+/// Pulses are timed by the system clock and pulled towards the transport's position, so they stay in phase with the audio.
+///
+/// ### Fields
+///
+/// * `port: String` - The name of the port
+/// * `running: Arc<AtomicBool>` - Cleared to stop the thread
+/// * `thread: Option<JoinHandle<()>>` - The thread sending the clock
+///
+/// ### Functions
+///
+/// * `start(port: &str) -> Result<Self, String>` - Opens a port by index or name and starts sending clock to it
+/// * `port(&self) -> &str` - Returns the name of the port
+///
+/// Dropping the sender sends Stop if the transport is playing, and closes the port.
+pub struct ClockSender {
+    port: String,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ClockSender {
+    /// ## `start(port: &str) -> Result<Self, String>`
+    ///
+    /// Opens an output port and starts sending clock to it.
+    ///
+    /// ### Arguments
+    ///
+    /// * `port: &str` - The index of the port, or its name (exact or fuzzy, see `midi::find_port`)
+    ///
+    /// ### Returns
+    ///
+    /// * `Result<Self, String>` - The sender, or an error message
+    pub fn start(port: &str) -> Result<Self, String> {
+        let midi_out = MidiOutput::new("w4113 clock output")
+            .map_err(|e| format!("Error opening MIDI output: {}", e))?;
+        let ports = midi_out.ports();
+        let names: Vec<String> = ports
+            .iter()
+            .map(|port| midi_out.port_name(port).unwrap_or_default())
+            .collect();
+
+        let index = midi::find_port(&names, port)?;
+        let name = names[index].clone();
+        let connection = midi_out
+            .connect(&ports[index], "w4113 clock")
+            .map_err(|e| format!("Error connecting to {}: {}", name, e))?;
+
+        let position = match audio::CONTROL.lock() {
+            Ok(control) => control.position(),
+            Err(e) => return Err(format!("Error locking CONTROL: {}", e)),
+        };
+
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_running = running.clone();
+        let thread = std::thread::spawn(move || send_clock(connection, position, thread_running));
+
+        Ok(Self {
+            port: name,
+            running,
+            thread: Some(thread),
+        })
+    }
+
+    /// ## `port(&self) -> &str`
+    ///
+    /// Returns the name of the port.
+    ///
+    /// ### Returns
+    ///
+    /// * `&str` - The name
+    pub fn port(&self) -> &str {
+        &self.port
+    }
+}
+
+impl Drop for ClockSender {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        match self.thread.take() {
+            Some(thread) => match thread.join() {
+                Ok(()) => {}
+                Err(_e) => debug!("The MIDI clock thread panicked"),
+            },
+            None => {}
+        }
+    }
+}
+
+/// ## `song_position(quarters: f64) -> [u8; 3]`
+///
+/// Returns a Song Position Pointer message, rounded down to the sixteenth note.
+///
+/// ### Arguments
+///
+/// * `quarters: f64` - The position in quarter notes
+///
+/// ### Returns
+///
+/// * `[u8; 3]` - The message
+fn song_position(quarters: f64) -> [u8; 3] {
+    let sixteenths = ((quarters * 4.0).floor().max(0.0) as u32).min(0x3FFF);
+    [
+        SONG_POSITION,
+        (sixteenths & 0x7F) as u8,
+        (sixteenths >> 7 & 0x7F) as u8,
+    ]
+}
+
+/// ## `send_clock(connection: MidiOutputConnection, published: Arc<Position>, running: Arc<AtomicBool>)`
+///
+/// Private function run by the clock sender's thread until `running` is cleared. The transport is read from
+/// the position the audio thread publishes, so the clock never waits on CONTROL.
+///
+/// ### Arguments
+///
+/// * `connection: MidiOutputConnection` - The output port
+/// * `published: Arc<Position>` - Where the audio thread publishes the transport
+/// * `running: Arc<AtomicBool>` - Cleared to stop the thread
+fn send_clock(
+    mut connection: MidiOutputConnection,
+    published: Arc<Position>,
+    running: Arc<AtomicBool>,
+) {
+    let mut send = |message: &[u8]| match connection.send(message) {
+        Ok(()) => {}
+        Err(e) => debug!("Error sending MIDI clock: {}", e),
+    };
+
+    let quarters = |transport: &Transport| transport.beats * quarters_per_beat(transport);
+
+    let mut transport = published.transport();
+    let mut playing = false;
+    // the position of the last pulse sent while playing, in quarter notes
+    let mut sent = quarters(&transport);
+    let mut drift = 0.0;
+    let mut next = Instant::now();
+
+    send(&song_position(sent));
+    while running.load(Ordering::Relaxed) {
+        transport = published.transport();
+        let position = quarters(&transport);
+
+        match (playing, transport.playing) {
+            (false, true) => {
+                // the receiver starts on the next pulse
+                match position < 1.0 / CLOCK_PPQN {
+                    true => send(&[START]),
+                    false => {
+                        send(&song_position(position));
+                        send(&[CONTINUE]);
+                    }
+                }
+                sent = (position * 4.0).floor() / 4.0 - 1.0 / CLOCK_PPQN;
+                drift = 0.0;
+                next = Instant::now();
+            }
+            (true, false) => {
+                send(&[STOP]);
+                send(&song_position(position));
+                sent = position;
+            }
+            (false, false) if (position - sent).abs() > 1.0 / CLOCK_PPQN => {
+                send(&song_position(position));
+                sent = position;
+            }
+            (true, true) if (position - sent).abs() > MAX_DRIFT => {
+                // located while playing
+                send(&[STOP]);
+                send(&song_position(position));
+                send(&[CONTINUE]);
+                sent = (position * 4.0).floor() / 4.0 - 1.0 / CLOCK_PPQN;
+                drift = 0.0;
+            }
+            _ => {}
+        }
+        playing = transport.playing;
+
+        let now = Instant::now();
+        if now >= next {
+            send(&[CLOCK]);
+            let mut interval = 60.0 / (transport.bpm * quarters_per_beat(&transport)) / CLOCK_PPQN;
+            if playing {
+                sent += 1.0 / CLOCK_PPQN;
+                // the transport is only published once a block, so the drift is smoothed before correcting it
+                drift += (position - sent - drift) * 0.1;
+                interval *= 1.0 - (drift * 0.5).clamp(-MAX_CORRECTION, MAX_CORRECTION);
+            }
+            next += Duration::from_secs_f64(interval);
+            if next < now {
+                next = now;
+            }
+        }
+
+        let wait = next.saturating_duration_since(Instant::now());
+        std::thread::sleep(wait.min(Duration::from_millis(1)));
+    }
+
+    if playing {
+        send(&[STOP]);
+    }
+    connection.close();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The interval between pulses at 120 quarter notes per minute, in microseconds
+    const INTERVAL_120: f64 = 60_000_000.0 / (120.0 * CLOCK_PPQN);
+
+    #[test]
+    fn steady_clock_converges_to_its_tempo() {
+        let mut follower = ClockFollower::new();
+        let mut stamp = 0.0;
+        // 100 BPM for a bar, then 120 BPM with every pulse half a millisecond early or late
+        for _ in 0..96 {
+            stamp += 60_000_000.0 / (100.0 * CLOCK_PPQN);
+            follower.measure(stamp as u64);
+        }
+        let window = (4.0 / SMOOTHING) as usize;
+        for pulse in 0..window {
+            stamp += INTERVAL_120;
+            let jitter = match pulse % 2 {
+                0 => 500.0,
+                _ => -500.0,
+            };
+            follower.measure((stamp + jitter) as u64);
+        }
+        let bpm = follower.bpm().unwrap_or(0.0);
+        assert!((bpm - 120.0).abs() < 0.5, "measured {} BPM", bpm);
+    }
+
+    #[test]
+    fn jitter_spike_is_clamped() {
+        let mut follower = ClockFollower::new();
+        let mut stamp = 0.0;
+        for _ in 0..48 {
+            stamp += INTERVAL_120;
+            follower.measure(stamp as u64);
+        }
+        // one pulse 10 ms late moves the measured tempo a little
+        follower.measure((stamp + INTERVAL_120 + 10_000.0) as u64);
+        let bpm = follower.bpm().unwrap_or(0.0);
+        assert!(
+            (bpm - 120.0).abs() < 120.0 * MAX_CORRECTION,
+            "measured {} BPM",
+            bpm
+        );
+
+        // and however far the transport is out of phase, the tempo is pulled by at most MAX_CORRECTION
+        let transport = Transport {
+            playing: true,
+            ..Transport::default()
+        }
+        .located(8.0);
+        for drift in [-0.99, -0.5, -0.01, 0.01, 0.5, 0.99] {
+            let (tempo, locate) = ClockFollower::correct(bpm, 8.0 + drift, true, &transport);
+            assert_eq!(locate, None);
+            assert!(
+                (tempo / bpm - 1.0).abs() <= MAX_CORRECTION + 1e-9,
+                "{} BPM for a drift of {}",
+                tempo,
+                drift
+            );
+            assert_eq!(tempo > bpm, drift > 0.0);
+        }
+        let (tempo, locate) = ClockFollower::correct(bpm, 8.0 + MAX_DRIFT * 2.0, true, &transport);
+        assert_eq!(tempo, bpm);
+        assert_eq!(locate, Some(8.0 + MAX_DRIFT * 2.0));
+
+        // in 7/8, the transport counts eighth notes
+        let transport = Transport {
+            beats_per_bar: 7,
+            beat_unit: 8,
+            ..transport
+        };
+        let (tempo, locate) = ClockFollower::correct(120.0, 0.0, false, &transport);
+        assert_eq!((tempo, locate), (240.0, None));
+    }
+}
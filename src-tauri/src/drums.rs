@@ -0,0 +1,556 @@
+//! drums.rs
+//!
+//! Module for drum kits: pads of samples played by MIDI notes and HID keys
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::audio::{self, plugin::Generator, Buffer, PanLaw, State};
+use crate::sampler::{SampleData, SampleVoice};
+
+/// The most hits a drum sampler plays at once; the oldest hit is cut when another one starts
+pub const DRUM_VOICES: usize = 32;
+
+/// Where pads output to when neither the pad nor the kit says otherwise
+pub const DRUM_OUTPUT: &str = "0,1";
+
+/// ## KitFile
+///
+/// A kit as written in its JSON file. Sample paths are relative to the file.
+///
+/// ```json
+/// {
+///   "name": "house",
+///   "output": "0,1",
+///   "pads": [
+///     { "name": "kick", "note": 36, "key": "Z", "layers": [{ "velocity": 0, "samples": ["kick.wav"] }] },
+///     { "name": "closed hat", "note": 42, "key": "X", "choke": 1, "pan": 0.3, "output": "bus:hats",
+///       "layers": [{ "velocity": 0, "samples": ["hat soft.wav"] }, { "velocity": 96, "samples": ["hat 1.wav", "hat 2.wav"] }] },
+///     { "name": "open hat", "note": 46, "key": "C", "choke": 1, "tune": -1.0, "gain": -3.0, "output": "bus:hats",
+///       "layers": [{ "velocity": 0, "samples": ["open hat.wav"] }] }
+///   ]
+/// }
+/// ```
+#[derive(serde::Deserialize)]
+struct KitFile {
+    name: Option<String>,
+    output: Option<String>,
+    pads: Vec<PadFile>,
+}
+
+/// ## PadFile
+///
+/// A pad as written in a kit file.
+#[derive(serde::Deserialize)]
+struct PadFile {
+    name: String,
+    note: u8,
+    key: Option<String>,
+    #[serde(default)]
+    tune: f32,
+    #[serde(default)]
+    gain: f32,
+    #[serde(default)]
+    pan: f32,
+    choke: Option<u32>,
+    output: Option<String>,
+    layers: Vec<LayerFile>,
+}
+
+/// ## LayerFile
+///
+/// A velocity layer as written in a kit file.
+#[derive(serde::Deserialize)]
+struct LayerFile {
+    #[serde(default)]
+    velocity: u8,
+    samples: Vec<String>,
+}
+
+/// ## Layer
+///
+/// The samples a pad plays from a velocity upwards. Hits take turns through the samples (round-robin).
+///
+/// ### Fields
+///
+/// * `velocity: u8` - The lowest MIDI velocity (0-127) playing this layer
+/// * `samples: Vec<Arc<SampleData>>` - The samples, played in turn
+pub struct Layer {
+    pub velocity: u8,
+    pub samples: Vec<Arc<SampleData>>,
+}
+
+/// ## Pad
+///
+/// One drum of a kit.
+///
+/// ### Fields
+///
+/// * `name: String` - The name of the pad
+/// * `note: u8` - The MIDI note playing the pad
+/// * `key: Option<String>` - The HID key playing the pad, by name (e.g. "Z" or "Space")
+/// * `tune: f32` - The tuning in semitones
+/// * `gain: f32` - The gain in dB
+/// * `pan: f32` - The pan position, from -1.0 (left) to 1.0 (right)
+/// * `choke: Option<u32>` - The choke group; a hit on any pad of the group cuts the others (e.g. an open hi-hat by a closed one)
+/// * `output: String` - Where the pad outputs to, as channels ("0,1") or a bus ("bus:drums")
+/// * `layers: Vec<Layer>` - The velocity layers, from the lowest velocity up
+pub struct Pad {
+    pub name: String,
+    pub note: u8,
+    pub key: Option<String>,
+    pub tune: f32,
+    pub gain: f32,
+    pub pan: f32,
+    pub choke: Option<u32>,
+    pub output: String,
+    pub layers: Vec<Layer>,
+}
+
+impl Pad {
+    /// ## `layer(&self, velocity: f32) -> Option<usize>`
+    ///
+    /// Returns the velocity layer played at a velocity.
+    ///
+    /// ### Arguments
+    ///
+    /// * `velocity: f32` - The velocity (0.0-1.0)
+    ///
+    /// ### Returns
+    ///
+    /// * `Option<usize>` - The index of the layer, or None if no layer plays this softly
+    pub fn layer(&self, velocity: f32) -> Option<usize> {
+        let velocity = (velocity.clamp(0.0, 1.0) * 127.0).round() as u8;
+        self.layers
+            .iter()
+            .rposition(|layer| layer.velocity <= velocity)
+    }
+}
+
+/// ## DrumKit
+///
+/// A kit loaded from a JSON file, with every sample decoded into memory.
+///
+/// ### Fields
+///
+/// * `name: String` - The name of the kit
+/// * `pads: Vec<Pad>` - The pads
+///
+/// ### Functions
+///
+/// * `load(path: &str) -> Result<Self, String>` - Loads a kit and its samples
+/// * `outputs(&self) -> Vec<String>` - Returns every output the pads use
+pub struct DrumKit {
+    pub name: String,
+    pub pads: Vec<Pad>,
+}
+
+impl DrumKit {
+    /// ## `load(path: &str) -> Result<Self, String>`
+    ///
+    /// Loads a kit and decodes its samples. Samples used by several pads are only decoded once.
+    ///
+    /// ### Arguments
+    ///
+    /// * `path: &str` - The path of the kit file
+    ///
+    /// ### Returns
+    ///
+    /// * `Result<Self, String>` - The kit, or an error message
+    pub fn load(path: &str) -> Result<Self, String> {
+        let file =
+            std::fs::File::open(path).map_err(|e| format!("Error opening {}: {}", path, e))?;
+        let kit: KitFile = serde_json::from_reader(std::io::BufReader::new(file))
+            .map_err(|e| format!("Error reading {}: {}", path, e))?;
+
+        let root = Path::new(path).parent().unwrap_or(Path::new(""));
+        let name = match kit.name {
+            Some(name) => name,
+            None => match Path::new(path).file_stem() {
+                Some(stem) => stem.to_string_lossy().to_string(),
+                None => path.to_owned(),
+            },
+        };
+        let output = kit.output.unwrap_or(DRUM_OUTPUT.to_owned());
+
+        let mut decoded: HashMap<String, Arc<SampleData>> = HashMap::new();
+        let mut pads = Vec::new();
+        for pad in kit.pads {
+            let mut layers = Vec::new();
+            for layer in pad.layers {
+                let mut samples = Vec::new();
+                for sample in layer.samples {
+                    let sample_path = root.join(&sample).to_string_lossy().to_string();
+                    let data = match decoded.get(&sample_path) {
+                        Some(data) => data.clone(),
+                        None => {
+                            let data = Arc::new(SampleData::load(&sample_path)?);
+                            decoded.insert(sample_path, data.clone());
+                            data
+                        }
+                    };
+                    samples.push(data);
+                }
+
+                match samples.is_empty() {
+                    true => return Err(format!("Pad {} has a layer without samples", pad.name)),
+                    false => layers.push(Layer {
+                        velocity: layer.velocity.min(127),
+                        samples,
+                    }),
+                }
+            }
+
+            if layers.is_empty() {
+                return Err(format!("Pad {} has no layers", pad.name));
+            }
+            layers.sort_by_key(|layer| layer.velocity);
+
+            pads.push(Pad {
+                name: pad.name,
+                note: pad.note.min(127),
+                key: pad.key,
+                tune: pad.tune,
+                gain: pad.gain,
+                pan: pad.pan.clamp(-1.0, 1.0),
+                choke: pad.choke,
+                output: pad.output.unwrap_or(output.clone()),
+                layers,
+            });
+        }
+
+        Ok(Self { name, pads })
+    }
+
+    /// ## `outputs(&self) -> Vec<String>`
+    ///
+    /// Returns every output the pads use, in the order the pads first use them.
+    ///
+    /// ### Returns
+    ///
+    /// * `Vec<String>` - The outputs
+    pub fn outputs(&self) -> Vec<String> {
+        let mut outputs: Vec<String> = Vec::new();
+        for pad in self.pads.iter() {
+            if !outputs.contains(&pad.output) {
+                outputs.push(pad.output.clone());
+            }
+        }
+        outputs
+    }
+}
+
+/// ## DrumVoice
+///
+/// One playing hit of a pad.
+///
+/// ### Fields
+///
+/// * `pad: usize` - The index of the pad
+/// * `layer: usize` - The index of the velocity layer
+/// * `sample: usize` - The index of the sample in the layer
+/// * `voice: SampleVoice` - The playback position, pitch and gain
+struct DrumVoice {
+    pad: usize,
+    layer: usize,
+    sample: usize,
+    voice: SampleVoice,
+}
+
+/// ## DrumSampler
+///
+/// A generator playing the pads of a kit that go to one output. A kit whose pads go to several outputs
+/// is played by one drum sampler per output, each on its own strip. Every sampler of a kit is subscribed to MIDI,
+/// so MIDI notes choke across them; HID keys and sequencer tracks play a single strip, so their notes only choke
+/// pads on that strip.
+///
+/// ### Fields
+///
+/// * `kit: Arc<DrumKit>` - The kit
+/// * `plays: Vec<bool>` - Which pads this sampler plays
+/// * `tune: Vec<f32>` - The tuning of each pad, in semitones
+/// * `gain: Vec<f32>` - The gain of each pad, in dB
+/// * `pan: Vec<f32>` - The pan position of each pad
+/// * `round_robin: Vec<usize>` - The next sample of each pad
+/// * `voices: Vec<DrumVoice>` - The playing hits, up to DRUM_VOICES
+///
+/// ### Parameters
+///
+/// * "<pad>.tune", "<pad>.gain", "<pad>.pan" - The tuning, gain or pan of a pad, by name or index (e.g. "kick.tune" or "0.gain")
+pub struct DrumSampler {
+    kit: Arc<DrumKit>,
+    plays: Vec<bool>,
+    tune: Vec<f32>,
+    gain: Vec<f32>,
+    pan: Vec<f32>,
+    round_robin: Vec<usize>,
+    voices: Vec<DrumVoice>,
+}
+
+impl DrumSampler {
+    /// ## `new(kit: Arc<DrumKit>, output: &str) -> Self`
+    ///
+    /// Creates a sampler playing the pads of a kit that go to an output.
+    ///
+    /// ### Arguments
+    ///
+    /// * `kit: Arc<DrumKit>` - The kit
+    /// * `output: &str` - The output, as returned by `DrumKit::outputs`
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The new sampler
+    pub fn new(kit: Arc<DrumKit>, output: &str) -> Self {
+        Self {
+            plays: kit.pads.iter().map(|pad| pad.output == output).collect(),
+            tune: kit.pads.iter().map(|pad| pad.tune).collect(),
+            gain: kit.pads.iter().map(|pad| pad.gain).collect(),
+            pan: kit.pads.iter().map(|pad| pad.pan).collect(),
+            round_robin: vec![0; kit.pads.len()],
+            voices: Vec::with_capacity(DRUM_VOICES),
+            kit,
+        }
+    }
+
+    /// ## `pad(&self, name: &str) -> Option<usize>`
+    ///
+    /// Private function which finds a pad by name or index.
+    ///
+    /// ### Arguments
+    ///
+    /// * `name: &str` - The name or index of the pad
+    ///
+    /// ### Returns
+    ///
+    /// * `Option<usize>` - The index of the pad
+    fn pad(&self, name: &str) -> Option<usize> {
+        match name.parse::<usize>() {
+            Ok(index) if index < self.kit.pads.len() => Some(index),
+            _ => self
+                .kit
+                .pads
+                .iter()
+                .position(|pad| pad.name.eq_ignore_ascii_case(name)),
+        }
+    }
+
+    /// ## `choke(&mut self, group: u32)`
+    ///
+    /// Private function which releases every hit of the pads in a choke group.
+    ///
+    /// ### Arguments
+    ///
+    /// * `group: u32` - The choke group
+    fn choke(&mut self, group: u32) {
+        let pads = &self.kit.pads;
+        for voice in self.voices.iter_mut() {
+            if pads[voice.pad].choke == Some(group) {
+                voice.voice.released = true;
+            }
+        }
+    }
+}
+
+impl Generator for DrumSampler {
+    fn generate(&mut self, state: &State, buffer: &mut Buffer) {
+        buffer.clear();
+
+        let pads = &self.kit.pads;
+        self.voices.retain_mut(|voice| {
+            let sample = &pads[voice.pad].layers[voice.layer].samples[voice.sample];
+            let region = (0.0, sample.frames() as f64);
+            voice.voice.play(sample, region, false, state, buffer)
+        });
+    }
+
+    fn note_on(&mut self, note: u8, velocity: f32) {
+        let kit = self.kit.clone();
+        for (index, pad) in kit.pads.iter().enumerate() {
+            if pad.note != note {
+                continue;
+            }
+
+            // pads of other samplers still choke this one's
+            match pad.choke {
+                Some(group) => self.choke(group),
+                None => {}
+            }
+
+            if !self.plays[index] {
+                continue;
+            }
+
+            let layer = match pad.layer(velocity) {
+                Some(layer) => layer,
+                None => continue,
+            };
+            let samples = pad.layers[layer].samples.len();
+            let sample = self.round_robin[index] % samples;
+            self.round_robin[index] = (sample + 1) % samples;
+
+            if self.voices.len() >= DRUM_VOICES {
+                self.voices.remove(0);
+            }
+
+            let (left, right) = PanLaw::Balance.gains(self.pan[index]);
+            let gain = velocity * audio::db_to_gain(self.gain[index]);
+            self.voices.push(DrumVoice {
+                pad: index,
+                layer,
+                sample,
+                voice: SampleVoice {
+                    note,
+                    position: 0.0,
+                    pitch: 2.0f64.powf(self.tune[index] as f64 / 12.0),
+                    gain: (gain * left, gain * right),
+                    fade: 1.0,
+                    released: false,
+                },
+            });
+        }
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f32) {
+        let (pad, parameter) = match name.rsplit_once('.') {
+            Some(split) => split,
+            None => return,
+        };
+        let pad = match self.pad(pad) {
+            Some(pad) => pad,
+            None => return,
+        };
+
+        match parameter {
+            "tune" => self.tune[pad] = value,
+            "gain" => self.gain[pad] = value,
+            "pan" => self.pan[pad] = value.clamp(-1.0, 1.0),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// ## `sample(value: f32) -> Arc<SampleData>`
+    ///
+    /// Returns a tenth of a second of a constant mono sample at 48 kHz.
+    fn sample(value: f32) -> Arc<SampleData> {
+        Arc::new(SampleData::from_channels(vec![vec![value; 4800]], 48000))
+    }
+
+    /// ## `pad(name: &str, note: u8, choke: Option<u32>, layers: Vec<Layer>) -> Pad`
+    ///
+    /// Returns a centered pad at 0 dB going to DRUM_OUTPUT.
+    fn pad(name: &str, note: u8, choke: Option<u32>, layers: Vec<Layer>) -> Pad {
+        Pad {
+            name: name.to_owned(),
+            note,
+            key: None,
+            tune: 0.0,
+            gain: 0.0,
+            pan: 0.0,
+            choke,
+            output: DRUM_OUTPUT.to_owned(),
+            layers,
+        }
+    }
+
+    /// ## `layer(velocity: u8, samples: usize) -> Layer`
+    ///
+    /// Returns a layer of constant samples.
+    fn layer(velocity: u8, samples: usize) -> Layer {
+        Layer {
+            velocity,
+            samples: (0..samples).map(|_| sample(1.0)).collect(),
+        }
+    }
+
+    /// ## `run(sampler: &mut DrumSampler, frames: usize)`
+    ///
+    /// Plays a sampler at 48 kHz for some frames.
+    fn run(sampler: &mut DrumSampler, frames: usize) {
+        let mut clock = 0;
+        let state = State::advance(48000, &mut clock, frames);
+        let mut buffer = Buffer::new(2, frames);
+        sampler.generate(&state, &mut buffer);
+    }
+
+    #[test]
+    fn choke_group_cuts_the_open_hat() {
+        let kit = Arc::new(DrumKit {
+            name: "test".to_owned(),
+            pads: vec![
+                pad("kick", 36, None, vec![layer(0, 1)]),
+                pad("closed hat", 42, Some(1), vec![layer(0, 1)]),
+                pad("open hat", 46, Some(1), vec![layer(0, 1)]),
+            ],
+        });
+        let mut sampler = DrumSampler::new(kit, DRUM_OUTPUT);
+        sampler.note_on(36, 1.0);
+        sampler.note_on(46, 1.0);
+        run(&mut sampler, 64);
+        assert_eq!(sampler.voices.len(), 2);
+
+        // the closed hat releases the open one, which is gone once it has faded out
+        sampler.note_on(42, 1.0);
+        assert!(sampler.voices[1].voice.released);
+        assert!(!sampler.voices[2].voice.released);
+        run(&mut sampler, 480);
+        let pads: Vec<usize> = sampler.voices.iter().map(|voice| voice.pad).collect();
+        assert_eq!(pads, vec![0, 1]);
+
+        // a hit on the kick chokes nothing
+        sampler.note_on(36, 1.0);
+        assert!(sampler.voices.iter().all(|voice| !voice.voice.released));
+    }
+
+    #[test]
+    fn round_robin_takes_turns_through_the_samples() {
+        let kit = Arc::new(DrumKit {
+            name: "test".to_owned(),
+            pads: vec![
+                pad("snare", 38, None, vec![layer(0, 3)]),
+                pad("clap", 39, None, vec![layer(0, 2)]),
+            ],
+        });
+        let mut sampler = DrumSampler::new(kit, DRUM_OUTPUT);
+        for note in [38, 38, 39, 38, 38, 39, 39] {
+            sampler.note_on(note, 1.0);
+        }
+        let played: Vec<(usize, usize)> = sampler
+            .voices
+            .iter()
+            .map(|voice| (voice.pad, voice.sample))
+            .collect();
+        assert_eq!(
+            played,
+            vec![(0, 0), (0, 1), (1, 0), (0, 2), (0, 0), (1, 1), (1, 0)]
+        );
+    }
+
+    #[test]
+    fn velocity_selects_the_layer() {
+        let hat = pad("hat", 42, None, vec![layer(20, 1), layer(96, 2)]);
+        assert_eq!(hat.layer(0.0), None);
+        assert_eq!(hat.layer(19.0 / 127.0), None);
+        assert_eq!(hat.layer(20.0 / 127.0), Some(0));
+        assert_eq!(hat.layer(0.5), Some(0));
+        assert_eq!(hat.layer(96.0 / 127.0), Some(1));
+        assert_eq!(hat.layer(1.0), Some(1));
+        assert_eq!(hat.layer(2.0), Some(1));
+
+        // a hit too soft for any layer plays nothing
+        let kit = Arc::new(DrumKit {
+            name: "test".to_owned(),
+            pads: vec![hat],
+        });
+        let mut sampler = DrumSampler::new(kit, DRUM_OUTPUT);
+        sampler.note_on(42, 0.1);
+        assert!(sampler.voices.is_empty());
+        sampler.note_on(42, 0.9);
+        assert_eq!(sampler.voices[0].layer, 1);
+    }
+}
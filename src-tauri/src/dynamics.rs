@@ -0,0 +1,646 @@
+//! dynamics.rs
+//!
+//! Module for dynamics effects: a compressor, a lookahead brickwall limiter and a gate/expander,
+//! each of which can be keyed by another strip
+
+use crate::audio::{db_to_gain, gain_to_db, plugin::Effect, Buffer, State, MAX_SAMPLE_RATE};
+
+/// The longest lookahead of the limiter, in seconds
+pub const MAX_LOOKAHEAD: f32 = 0.02;
+
+/// The most channels the limiter delays; its rings are allocated for all of them up front
+pub const MAX_CHANNELS: usize = 32;
+
+/// The lowest level detected, in dB, so silence doesn't reach negative infinity
+const FLOOR: f32 = -120.0;
+
+/// ## `coefficient(milliseconds: f32, sample_rate: f32) -> f32`
+///
+/// Returns the coefficient of a one pole smoother that covers about two thirds of a step in the given time.
+///
+/// ### Arguments
+///
+/// * `milliseconds: f32` - The time, in milliseconds
+/// * `sample_rate: f32` - The sample rate
+///
+/// ### Returns
+///
+/// * `f32` - How far the smoother moves each frame (0.0-1.0)
+fn coefficient(milliseconds: f32, sample_rate: f32) -> f32 {
+    1.0 - (-1000.0 / (milliseconds.max(0.01) * sample_rate)).exp()
+}
+
+/// ## Sidechain
+///
+/// The key of a dynamics effect: the strip it listens to instead of its own input, and a copy of that strip's last block.
+///
+/// ### Fields
+///
+/// * `strip: Option<usize>` - The strip keying the effect, or None to key from the effect's own input
+/// * `buffer: Buffer` - The key strip's block, copied in before each block is processed
+/// * `keyed: bool` - Whether the key strip's block was copied in for the coming block
+pub struct Sidechain {
+    strip: Option<usize>,
+    buffer: Buffer,
+    keyed: bool,
+}
+
+impl Sidechain {
+    /// ## `new() -> Self`
+    ///
+    /// Creates a sidechain keyed from the effect's own input, with room for a block of 4096 frames.
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The new sidechain
+    pub fn new() -> Self {
+        Self {
+            strip: None,
+            buffer: Buffer::with_capacity(2, 4096),
+            keyed: false,
+        }
+    }
+
+    /// ## `set_strip(&mut self, strip: f32)`
+    ///
+    /// Sets the strip keying the effect, as set by the "key" parameter.
+    ///
+    /// ### Arguments
+    ///
+    /// * `strip: f32` - The index of the strip, or anything negative to key from the effect's own input
+    pub fn set_strip(&mut self, strip: f32) {
+        self.strip = match strip >= 0.0 {
+            true => Some(strip as usize),
+            false => None,
+        };
+    }
+
+    /// ## `key(&mut self, key: &Buffer)`
+    ///
+    /// Copies in the key strip's block.
+    ///
+    /// ### Arguments
+    ///
+    /// * `key: &Buffer` - The block
+    pub fn key(&mut self, key: &Buffer) {
+        self.buffer.resize(key.channels().clamp(1, 2), key.len());
+        self.buffer.copy_from(key);
+        self.keyed = true;
+    }
+
+    /// ## `level(&self, input: &Buffer, index: usize) -> f32`
+    ///
+    /// Returns the level a frame is detected at, in dB: the louder channel of the key if one was copied in, or of the input.
+    /// The key's block can be shorter than the input's when the engine renders in parts; missing frames are silent.
+    ///
+    /// ### Arguments
+    ///
+    /// * `input: &Buffer` - The effect's input
+    /// * `index: usize` - The frame
+    ///
+    /// ### Returns
+    ///
+    /// * `f32` - The level, in dB
+    pub fn level(&self, input: &Buffer, index: usize) -> f32 {
+        let source = match self.keyed {
+            true => &self.buffer,
+            false => input,
+        };
+        let mut peak = 0.0f32;
+        if index < source.len() {
+            for channel in 0..source.channels() {
+                peak = peak.max(source.channel(channel)[index].abs());
+            }
+        }
+        gain_to_db(peak).max(FLOOR)
+    }
+
+    /// ## `done(&mut self)`
+    ///
+    /// Forgets the key's block once it has been used, so a key strip that stops being processed doesn't hold the effect.
+    pub fn done(&mut self) {
+        self.keyed = false;
+    }
+}
+
+/// ## Compressor
+///
+/// A feed-forward compressor with a soft knee. The gain reduction is worked out in dB and smoothed with separate attack
+/// and release times; both channels get the same gain, so the stereo image doesn't shift.
+///
+/// ### Fields
+///
+/// * `threshold: f32` - The level compression starts at, in dB
+/// * `ratio: f32` - How many dB over the threshold the input has to go for the output to go 1 dB over
+/// * `knee: f32` - The width of the soft knee around the threshold, in dB
+/// * `attack: f32` - The attack time, in milliseconds
+/// * `release: f32` - The release time, in milliseconds
+/// * `makeup: f32` - The gain added after compression, in dB
+/// * `reduction: f32` - The current gain reduction, in dB (0.0 or less)
+/// * `sidechain: Sidechain` - The key
+///
+/// ### Parameters
+///
+/// * "threshold", "ratio", "knee", "attack", "release", "makeup" - As the fields
+/// * "key" - The strip keying the compressor; -1 keys it from its own input
+pub struct Compressor {
+    threshold: f32,
+    ratio: f32,
+    knee: f32,
+    attack: f32,
+    release: f32,
+    makeup: f32,
+    reduction: f32,
+    sidechain: Sidechain,
+}
+
+impl Compressor {
+    /// ## `new(threshold: f32, ratio: f32, attack: f32, release: f32) -> Self`
+    ///
+    /// Creates a compressor with a 6 dB knee and no makeup gain.
+    ///
+    /// ### Arguments
+    ///
+    /// * `threshold: f32` - The threshold, in dB
+    /// * `ratio: f32` - The ratio (1.0 and up)
+    /// * `attack: f32` - The attack time, in milliseconds
+    /// * `release: f32` - The release time, in milliseconds
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The new compressor
+    pub fn new(threshold: f32, ratio: f32, attack: f32, release: f32) -> Self {
+        Self {
+            threshold: threshold.min(0.0),
+            ratio: ratio.max(1.0),
+            knee: 6.0,
+            attack: attack.max(0.0),
+            release: release.max(0.0),
+            makeup: 0.0,
+            reduction: 0.0,
+            sidechain: Sidechain::new(),
+        }
+    }
+
+    /// ## `curve(&self, level: f32) -> f32`
+    ///
+    /// Private function which returns the gain reduction the compressor aims for at a level.
+    ///
+    /// ### Arguments
+    ///
+    /// * `level: f32` - The detected level, in dB
+    ///
+    /// ### Returns
+    ///
+    /// * `f32` - The gain reduction, in dB (0.0 or less)
+    fn curve(&self, level: f32) -> f32 {
+        let over = level - self.threshold;
+        let slope = 1.0 / self.ratio - 1.0;
+        if 2.0 * over <= -self.knee {
+            0.0
+        } else if 2.0 * over.abs() < self.knee {
+            slope * (over + self.knee / 2.0).powi(2) / (2.0 * self.knee)
+        } else {
+            slope * over
+        }
+    }
+}
+
+impl Effect for Compressor {
+    fn process(&mut self, state: &State, buffer: &mut Buffer) {
+        let sample_rate = state.sample_rate as f32;
+        let attack = coefficient(self.attack, sample_rate);
+        let release = coefficient(self.release, sample_rate);
+
+        for index in 0..buffer.len() {
+            let target = self.curve(self.sidechain.level(buffer, index));
+            let speed = match target < self.reduction {
+                true => attack,
+                false => release,
+            };
+            self.reduction += (target - self.reduction) * speed;
+
+            let gain = db_to_gain(self.reduction + self.makeup);
+            for channel in 0..buffer.channels() {
+                buffer.channel_mut(channel)[index] *= gain;
+            }
+        }
+        self.sidechain.done();
+    }
+
+    fn sidechain(&self) -> Option<usize> {
+        self.sidechain.strip
+    }
+
+    fn key(&mut self, key: &Buffer) {
+        self.sidechain.key(key);
+    }
+
+    fn keyable(&self) -> bool {
+        true
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f32) {
+        match name {
+            "threshold" => self.threshold = value.min(0.0),
+            "ratio" => self.ratio = value.max(1.0),
+            "knee" => self.knee = value.max(0.0),
+            "attack" => self.attack = value.max(0.0),
+            "release" => self.release = value.max(0.0),
+            "makeup" => self.makeup = value,
+            "key" => self.sidechain.set_strip(value),
+            _ => {}
+        }
+    }
+}
+
+/// ## Limiter
+///
+/// A lookahead brickwall limiter, meant for the end of the master chain. The audio is delayed by the lookahead, so the
+/// gain is already down when a peak comes out. The gain needed for every frame in the lookahead window is held (a running
+/// minimum, kept in a monotonic queue so it costs the same at any lookahead) and then averaged over the window, so the gain ramps down over the lookahead and never lets a peak over the ceiling.
+/// The gain then recovers with the release time. Every channel gets the same gain; channels past MAX_CHANNELS get it without the delay.
+///
+/// ### Fields
+///
+/// * `ceiling: f32` - The highest level let out, in dB
+/// * `lookahead: f32` - The lookahead, in milliseconds
+/// * `release: f32` - The release time, in milliseconds
+/// * `frames: usize` - The lookahead, in frames at the current sample rate
+/// * `delay: Vec<Vec<f32>>` - The delayed audio of each channel (up to MAX_CHANNELS), used as rings
+/// * `queue: Vec<(usize, f32)>` - The frames in the window that could still be the lowest gain needed, and the gain they need, rising from the front; used as a ring
+/// * `front: usize` - The index of the front of the queue
+/// * `queued: usize` - The number of frames in the queue
+/// * `frame: usize` - The number of frames limited since the rings were last emptied
+/// * `held: Vec<f32>` - The running minimum of each frame in the window, used as a ring, for the average
+/// * `sum: f64` - The sum of `held` over the window
+/// * `position: usize` - The position in the rings
+/// * `gain: f32` - The current gain
+/// * `sample_rate: u32` - The sample rate `frames` was worked out for
+/// * `sidechain: Sidechain` - The key
+///
+/// ### Parameters
+///
+/// * "ceiling" - The ceiling, in dB
+/// * "lookahead" - The lookahead, in milliseconds (up to 20)
+/// * "release" - The release time, in milliseconds
+/// * "key" - The strip keying the limiter; -1 keys it from its own input
+pub struct Limiter {
+    ceiling: f32,
+    lookahead: f32,
+    release: f32,
+    frames: usize,
+    delay: Vec<Vec<f32>>,
+    queue: Vec<(usize, f32)>,
+    front: usize,
+    queued: usize,
+    frame: usize,
+    held: Vec<f32>,
+    sum: f64,
+    position: usize,
+    gain: f32,
+    sample_rate: u32,
+    sidechain: Sidechain,
+}
+
+impl Limiter {
+    /// ## `new(ceiling: f32, lookahead: f32, release: f32) -> Self`
+    ///
+    /// Creates a limiter. Its rings are allocated for the longest lookahead up front, so the lookahead can change on the audio thread.
+    ///
+    /// ### Arguments
+    ///
+    /// * `ceiling: f32` - The ceiling, in dB
+    /// * `lookahead: f32` - The lookahead, in milliseconds
+    /// * `release: f32` - The release time, in milliseconds
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The new limiter
+    pub fn new(ceiling: f32, lookahead: f32, release: f32) -> Self {
+        let capacity = (MAX_LOOKAHEAD * MAX_SAMPLE_RATE) as usize + 1;
+        Self {
+            ceiling: ceiling.min(0.0),
+            lookahead: lookahead.clamp(0.0, MAX_LOOKAHEAD * 1000.0),
+            release: release.max(0.0),
+            frames: 0,
+            delay: vec![vec![0.0; capacity]; MAX_CHANNELS],
+            queue: vec![(0, 1.0); capacity],
+            front: 0,
+            queued: 0,
+            frame: 0,
+            held: vec![1.0; capacity],
+            sum: 0.0,
+            position: 0,
+            gain: 1.0,
+            sample_rate: 0,
+            sidechain: Sidechain::new(),
+        }
+    }
+
+    /// ## `resize(&mut self, sample_rate: u32)`
+    ///
+    /// Private function which works out the lookahead in frames and empties the rings, when the lookahead or the sample rate changes.
+    ///
+    /// ### Arguments
+    ///
+    /// * `sample_rate: u32` - The sample rate
+    fn resize(&mut self, sample_rate: u32) {
+        let frames =
+            ((self.lookahead / 1000.0 * sample_rate as f32) as usize).min(self.held.len() - 1);
+        if frames == self.frames && sample_rate == self.sample_rate {
+            return;
+        }
+
+        self.frames = frames;
+        self.sample_rate = sample_rate;
+        for channel in self.delay.iter_mut() {
+            channel.fill(0.0);
+        }
+        self.front = 0;
+        self.queued = 0;
+        self.frame = 0;
+        self.held.fill(1.0);
+        self.sum = (frames + 1) as f64;
+        self.position = 0;
+    }
+
+    /// ## `hold(&mut self, needed: f32, window: usize) -> f32`
+    ///
+    /// Private function which adds the gain needed by the next frame to the queue and returns the lowest gain needed in the window.
+    /// Every frame is queued and dropped at most once, so this takes constant time on average.
+    ///
+    /// ### Arguments
+    ///
+    /// * `needed: f32` - The gain needed by the frame
+    /// * `window: usize` - The number of frames in the window
+    ///
+    /// ### Returns
+    ///
+    /// * `f32` - The lowest gain needed in the window
+    fn hold(&mut self, needed: f32, window: usize) -> f32 {
+        let capacity = self.queue.len();
+
+        // frames that have left the window are gone
+        while self.queued > 0 && self.frame - self.queue[self.front].0 >= window {
+            self.front = (self.front + 1) % capacity;
+            self.queued -= 1;
+        }
+
+        // and frames needing no less than this one can never be the lowest again
+        while self.queued > 0 && self.queue[(self.front + self.queued - 1) % capacity].1 >= needed {
+            self.queued -= 1;
+        }
+        self.queue[(self.front + self.queued) % capacity] = (self.frame, needed);
+        self.queued += 1;
+        self.frame += 1;
+        self.queue[self.front].1
+    }
+}
+
+impl Effect for Limiter {
+    fn process(&mut self, state: &State, buffer: &mut Buffer) {
+        self.resize(state.sample_rate);
+        let window = self.frames + 1;
+        let ceiling = db_to_gain(self.ceiling);
+        let release = coefficient(self.release, state.sample_rate as f32);
+
+        for index in 0..buffer.len() {
+            let peak = db_to_gain(self.sidechain.level(buffer, index));
+            let needed = match peak > ceiling {
+                true => ceiling / peak,
+                false => 1.0,
+            };
+
+            // hold the lowest gain needed in the window, then average the holds so the gain ramps down over the lookahead
+            let held = self.hold(needed, window);
+            self.sum += (held - self.held[self.position]) as f64;
+            self.held[self.position] = held;
+            let average = (self.sum / window as f64) as f32;
+
+            self.gain = match average < self.gain {
+                true => average,
+                false => self.gain + (average - self.gain) * release,
+            };
+
+            // the ring is one frame longer than the lookahead, so the next slot holds the frame from exactly the lookahead ago
+            for channel in 0..buffer.channels() {
+                let sample = &mut buffer.channel_mut(channel)[index];
+                match self.delay.get_mut(channel) {
+                    Some(delay) => {
+                        delay[self.position] = *sample;
+                        *sample = delay[(self.position + 1) % window] * self.gain;
+                    }
+                    None => *sample *= self.gain,
+                }
+            }
+            self.position = (self.position + 1) % window;
+        }
+        self.sidechain.done();
+    }
+
+    fn sidechain(&self) -> Option<usize> {
+        self.sidechain.strip
+    }
+
+    fn key(&mut self, key: &Buffer) {
+        self.sidechain.key(key);
+    }
+
+    fn keyable(&self) -> bool {
+        true
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f32) {
+        match name {
+            "ceiling" => self.ceiling = value.min(0.0),
+            "lookahead" => self.lookahead = value.clamp(0.0, MAX_LOOKAHEAD * 1000.0),
+            "release" => self.release = value.max(0.0),
+            "key" => self.sidechain.set_strip(value),
+            _ => {}
+        }
+    }
+}
+
+/// ## Gate
+///
+/// A noise gate and downward expander. Under the threshold the level falls `ratio` dB for every dB the input falls,
+/// down to at most `range` dB; a high ratio makes it a gate. It opens with the attack time, stays open for the hold time
+/// once the input drops, then closes with the release time.
+///
+/// ### Fields
+///
+/// * `threshold: f32` - The level the gate opens at, in dB
+/// * `ratio: f32` - The expansion ratio under the threshold (1.0 and up)
+/// * `range: f32` - The most gain reduction, in dB (0.0 or less)
+/// * `attack: f32` - The attack time, in milliseconds
+/// * `hold: f32` - The hold time, in milliseconds
+/// * `release: f32` - The release time, in milliseconds
+/// * `level: f32` - The detected level, in dB, following peaks at once and falling over 10 ms
+/// * `reduction: f32` - The current gain reduction, in dB (0.0 or less)
+/// * `holding: usize` - The frames left to hold the gate open
+/// * `sidechain: Sidechain` - The key
+///
+/// ### Parameters
+///
+/// * "threshold", "ratio", "range", "attack", "hold", "release" - As the fields
+/// * "key" - The strip keying the gate; -1 keys it from its own input
+pub struct Gate {
+    threshold: f32,
+    ratio: f32,
+    range: f32,
+    attack: f32,
+    hold: f32,
+    release: f32,
+    level: f32,
+    reduction: f32,
+    holding: usize,
+    sidechain: Sidechain,
+}
+
+impl Gate {
+    /// ## `new(threshold: f32, ratio: f32, range: f32) -> Self`
+    ///
+    /// Creates a gate with a 1 ms attack, 50 ms hold and 100 ms release.
+    ///
+    /// ### Arguments
+    ///
+    /// * `threshold: f32` - The threshold, in dB
+    /// * `ratio: f32` - The expansion ratio (1.0 and up)
+    /// * `range: f32` - The most gain reduction, in dB
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The new gate
+    pub fn new(threshold: f32, ratio: f32, range: f32) -> Self {
+        Self {
+            threshold: threshold.min(0.0),
+            ratio: ratio.max(1.0),
+            range: range.min(0.0),
+            attack: 1.0,
+            hold: 50.0,
+            release: 100.0,
+            level: FLOOR,
+            reduction: range.min(0.0),
+            holding: 0,
+            sidechain: Sidechain::new(),
+        }
+    }
+}
+
+impl Effect for Gate {
+    fn process(&mut self, state: &State, buffer: &mut Buffer) {
+        let sample_rate = state.sample_rate as f32;
+        let attack = coefficient(self.attack, sample_rate);
+        let release = coefficient(self.release, sample_rate);
+        let fall = 1000.0 / (10.0 * sample_rate) * -FLOOR;
+        let hold = (self.hold / 1000.0 * sample_rate) as usize;
+
+        for index in 0..buffer.len() {
+            self.level = self.sidechain.level(buffer, index).max(self.level - fall);
+
+            let target = match self.level >= self.threshold {
+                true => {
+                    self.holding = hold;
+                    0.0
+                }
+                false => match self.holding > 0 {
+                    true => {
+                        self.holding -= 1;
+                        0.0
+                    }
+                    false => ((self.level - self.threshold) * (self.ratio - 1.0)).max(self.range),
+                },
+            };
+            let speed = match target > self.reduction {
+                true => attack,
+                false => release,
+            };
+            self.reduction += (target - self.reduction) * speed;
+
+            let gain = db_to_gain(self.reduction);
+            for channel in 0..buffer.channels() {
+                buffer.channel_mut(channel)[index] *= gain;
+            }
+        }
+        self.sidechain.done();
+    }
+
+    fn sidechain(&self) -> Option<usize> {
+        self.sidechain.strip
+    }
+
+    fn key(&mut self, key: &Buffer) {
+        self.sidechain.key(key);
+    }
+
+    fn keyable(&self) -> bool {
+        true
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f32) {
+        match name {
+            "threshold" => self.threshold = value.min(0.0),
+            "ratio" => self.ratio = value.max(1.0),
+            "range" => self.range = value.min(0.0),
+            "attack" => self.attack = value.max(0.0),
+            "hold" => self.hold = value.max(0.0),
+            "release" => self.release = value.max(0.0),
+            "key" => self.sidechain.set_strip(value),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oscillator::WhiteNoise;
+
+    fn limit(sample_rate: u32, ceiling: f32, lookahead: f32, block: usize) {
+        let mut limiter = Limiter::new(ceiling, lookahead, 50.0);
+        let mut noise = WhiteNoise::new();
+        let mut clock = 0;
+        let levels = [0.1, 4.0, 0.5, 16.0, 0.0, 1.0];
+        let mut loudest = 0.0f32;
+        for index in 0..sample_rate as usize / block {
+            let state = State::advance(sample_rate, &mut clock, block);
+            let level = levels[index / 8 % levels.len()];
+            let mut buffer = Buffer::new(2, block);
+            for channel in 0..2 {
+                for (frame, sample) in buffer.channel_mut(channel).iter_mut().enumerate() {
+                    *sample = match frame % 97 {
+                        // lone spikes, the hardest thing to catch
+                        0 => 20.0,
+                        _ => noise.sample() * level,
+                    };
+                }
+            }
+            limiter.process(&state, &mut buffer);
+            for channel in 0..2 {
+                for sample in buffer.channel(channel) {
+                    assert!(
+                        sample.abs() <= db_to_gain(ceiling) * 1.0001,
+                        "{} over a ceiling of {} dB",
+                        sample,
+                        ceiling
+                    );
+                    loudest = loudest.max(sample.abs());
+                }
+            }
+        }
+        assert!(loudest > db_to_gain(ceiling) * 0.9);
+    }
+
+    #[test]
+    fn limiter_stays_under_the_ceiling() {
+        limit(48000, -1.0, 5.0, 512);
+        limit(44100, -0.1, 1.0, 64);
+        limit(192000, -6.0, MAX_LOOKAHEAD * 1000.0, 1024);
+    }
+
+    #[test]
+    fn limiter_without_lookahead_stays_under_the_ceiling() {
+        limit(48000, -3.0, 0.0, 256);
+    }
+}
@@ -0,0 +1,1067 @@
+//! eq.rs
+//!
+//! Module for the parametric EQ effect: shelves, peaking bands and high and low pass filters made of biquads
+
+use std::sync::atomic::{fence, AtomicU32, Ordering};
+use std::sync::Arc;
+
+use crate::audio::{plugin::Effect, Buffer, State};
+
+/// The number of bands in an EQ
+pub const MAX_BANDS: usize = 8;
+
+/// The most biquads a high or low pass band is built from; each adds 12 dB per octave to the slope
+pub const MAX_SECTIONS: usize = 4;
+
+/// How many frames pass between coefficient updates while a band is moving to new settings
+const UPDATE_FRAMES: usize = 16;
+
+/// How long a band takes to move to new settings, in seconds
+const SMOOTHING: f32 = 0.02;
+
+/// ## BandKind
+///
+/// The shape of a band.
+///
+/// ### Variants
+///
+/// * `Off` - The band does nothing
+/// * `LowShelf` - Boosts or cuts everything under the frequency
+/// * `HighShelf` - Boosts or cuts everything over the frequency
+/// * `Peak` - Boosts or cuts a band around the frequency, as wide as the Q allows
+/// * `LowPass` - Removes everything over the frequency, at 12 dB per octave per section
+/// * `HighPass` - Removes everything under the frequency, at 12 dB per octave per section
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BandKind {
+    Off,
+    LowShelf,
+    HighShelf,
+    Peak,
+    LowPass,
+    HighPass,
+}
+
+impl BandKind {
+    /// ## `from_name(name: &str) -> Option<Self>`
+    ///
+    /// Returns the kind with a name, as typed in the console.
+    ///
+    /// ### Arguments
+    ///
+    /// * `name: &str` - "off", "lowshelf", "highshelf", "peak", "lowpass" or "highpass"
+    ///
+    /// ### Returns
+    ///
+    /// * `Option<Self>` - The kind, or None if the name is unknown
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "off" => Some(BandKind::Off),
+            "lowshelf" | "ls" => Some(BandKind::LowShelf),
+            "highshelf" | "hs" => Some(BandKind::HighShelf),
+            "peak" | "bell" => Some(BandKind::Peak),
+            "lowpass" | "lp" => Some(BandKind::LowPass),
+            "highpass" | "hp" => Some(BandKind::HighPass),
+            _ => None,
+        }
+    }
+
+    /// ## `from_index(index: u32) -> Self`
+    ///
+    /// Returns the kind with an index, in the order of the variants.
+    ///
+    /// ### Arguments
+    ///
+    /// * `index: u32` - The index (0 off, 1 low shelf, 2 high shelf, 3 peak, 4 low pass, 5 high pass)
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The kind; unknown indices are off
+    pub fn from_index(index: u32) -> Self {
+        match index {
+            1 => BandKind::LowShelf,
+            2 => BandKind::HighShelf,
+            3 => BandKind::Peak,
+            4 => BandKind::LowPass,
+            5 => BandKind::HighPass,
+            _ => BandKind::Off,
+        }
+    }
+
+    /// ## `index(&self) -> u32`
+    ///
+    /// Returns the index of the kind, as taken by `from_index`.
+    ///
+    /// ### Returns
+    ///
+    /// * `u32` - The index
+    pub fn index(&self) -> u32 {
+        match self {
+            BandKind::Off => 0,
+            BandKind::LowShelf => 1,
+            BandKind::HighShelf => 2,
+            BandKind::Peak => 3,
+            BandKind::LowPass => 4,
+            BandKind::HighPass => 5,
+        }
+    }
+
+    /// ## `name(&self) -> &'static str`
+    ///
+    /// Returns the name of the kind, as taken by `from_name`.
+    ///
+    /// ### Returns
+    ///
+    /// * `&'static str` - The name
+    pub fn name(&self) -> &'static str {
+        match self {
+            BandKind::Off => "off",
+            BandKind::LowShelf => "lowshelf",
+            BandKind::HighShelf => "highshelf",
+            BandKind::Peak => "peak",
+            BandKind::LowPass => "lowpass",
+            BandKind::HighPass => "highpass",
+        }
+    }
+}
+
+/// ## Band
+///
+/// The settings of one band.
+///
+/// ### Fields
+///
+/// * `kind: BandKind` - The shape of the band
+/// * `frequency: f32` - The center, corner or cutoff frequency, in Hz
+/// * `gain: f32` - The boost or cut of shelves and peaks, in dB
+/// * `q: f32` - The width of peaks, and the resonance of 12 dB per octave passes
+/// * `slope: usize` - The number of biquads in high and low passes (1-4, so 12-48 dB per octave)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Band {
+    pub kind: BandKind,
+    pub frequency: f32,
+    pub gain: f32,
+    pub q: f32,
+    pub slope: usize,
+}
+
+impl Default for Band {
+    fn default() -> Self {
+        Self {
+            kind: BandKind::Off,
+            frequency: 1000.0,
+            gain: 0.0,
+            q: std::f32::consts::FRAC_1_SQRT_2,
+            slope: 1,
+        }
+    }
+}
+
+impl std::fmt::Display for Band {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.kind {
+            BandKind::Off => write!(f, "off"),
+            BandKind::LowPass | BandKind::HighPass => write!(
+                f,
+                "{} {:.0} Hz, {} dB/oct",
+                self.kind.name(),
+                self.frequency,
+                self.slope * 12
+            ),
+            _ => write!(
+                f,
+                "{} {:.0} Hz, {:+.1} dB, Q {:.2}",
+                self.kind.name(),
+                self.frequency,
+                self.gain,
+                self.q
+            ),
+        }
+    }
+}
+
+impl Band {
+    /// ## `set(&mut self, name: &str, value: f32) -> bool`
+    ///
+    /// Sets one setting of the band by name, keeping it in range.
+    ///
+    /// ### Arguments
+    ///
+    /// * `name: &str` - "type", "freq", "gain", "q" or "slope"
+    /// * `value: f32` - The new value; types are given by index (see `BandKind::from_index`)
+    ///
+    /// ### Returns
+    ///
+    /// * `bool` - Whether the band has a setting with the name
+    pub fn set(&mut self, name: &str, value: f32) -> bool {
+        match name {
+            "type" => self.kind = BandKind::from_index(value.max(0.0) as u32),
+            "freq" => self.frequency = value.clamp(10.0, 22000.0),
+            "gain" => self.gain = value.clamp(-30.0, 30.0),
+            "q" => self.q = value.clamp(0.1, 30.0),
+            "slope" => self.slope = (value.max(1.0) as usize).min(MAX_SECTIONS),
+            _ => return false,
+        }
+        true
+    }
+
+    /// ## `sections(&self, sample_rate: f32, coefficients: &mut [Coefficients; MAX_SECTIONS]) -> usize`
+    ///
+    /// Works out the biquads that make up the band.
+    ///
+    /// ### Arguments
+    ///
+    /// * `sample_rate: f32` - The sample rate
+    /// * `coefficients: &mut [Coefficients; MAX_SECTIONS]` - Filled in with the coefficients of each biquad
+    ///
+    /// ### Returns
+    ///
+    /// * `usize` - The number of biquads used
+    pub fn sections(
+        &self,
+        sample_rate: f32,
+        coefficients: &mut [Coefficients; MAX_SECTIONS],
+    ) -> usize {
+        let frequency = self.frequency.clamp(10.0, sample_rate * 0.49);
+        match self.kind {
+            BandKind::Off => 0,
+            BandKind::LowShelf | BandKind::HighShelf | BandKind::Peak => {
+                coefficients[0] =
+                    Coefficients::new(self.kind, frequency, self.gain, self.q, sample_rate);
+                1
+            }
+            BandKind::LowPass | BandKind::HighPass => {
+                let sections = self.slope.clamp(1, MAX_SECTIONS);
+                for (section, coefficients) in coefficients.iter_mut().take(sections).enumerate() {
+                    // a single section uses the band's Q; cascades use the Qs of a Butterworth filter, so they stay flat
+                    let q = match sections {
+                        1 => self.q,
+                        _ => {
+                            let angle = (2 * section + 1) as f32 * std::f32::consts::PI
+                                / (4 * sections) as f32;
+                            1.0 / (2.0 * angle.sin())
+                        }
+                    };
+                    *coefficients = Coefficients::new(self.kind, frequency, 0.0, q, sample_rate);
+                }
+                sections
+            }
+        }
+    }
+}
+
+/// ## Coefficients
+///
+/// The coefficients of a biquad, normalized so a0 is 1.0, from Robert Bristow-Johnson's Audio EQ Cookbook.
+///
+/// ### Fields
+///
+/// * `b0`, `b1`, `b2` - The feedforward coefficients
+/// * `a1`, `a2` - The feedback coefficients
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Coefficients {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+}
+
+impl Coefficients {
+    /// A biquad that passes everything through unchanged
+    pub const FLAT: Coefficients = Coefficients {
+        b0: 1.0,
+        b1: 0.0,
+        b2: 0.0,
+        a1: 0.0,
+        a2: 0.0,
+    };
+
+    /// ## `new(kind: BandKind, frequency: f32, gain: f32, q: f32, sample_rate: f32) -> Self`
+    ///
+    /// Works out the coefficients of one biquad.
+    ///
+    /// ### Arguments
+    ///
+    /// * `kind: BandKind` - The shape
+    /// * `frequency: f32` - The frequency, in Hz
+    /// * `gain: f32` - The gain of shelves and peaks, in dB
+    /// * `q: f32` - The Q
+    /// * `sample_rate: f32` - The sample rate
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The coefficients
+    pub fn new(kind: BandKind, frequency: f32, gain: f32, q: f32, sample_rate: f32) -> Self {
+        let a = 10.0f32.powf(gain / 40.0);
+        let w0 = 2.0 * std::f32::consts::PI * frequency / sample_rate;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * q.max(0.01));
+
+        let (b0, b1, b2, a0, a1, a2) = match kind {
+            BandKind::Off => return Coefficients::FLAT,
+            BandKind::Peak => (
+                1.0 + alpha * a,
+                -2.0 * cos,
+                1.0 - alpha * a,
+                1.0 + alpha / a,
+                -2.0 * cos,
+                1.0 - alpha / a,
+            ),
+            BandKind::LowShelf => {
+                let root = 2.0 * a.sqrt() * alpha;
+                (
+                    a * ((a + 1.0) - (a - 1.0) * cos + root),
+                    2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                    a * ((a + 1.0) - (a - 1.0) * cos - root),
+                    (a + 1.0) + (a - 1.0) * cos + root,
+                    -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                    (a + 1.0) + (a - 1.0) * cos - root,
+                )
+            }
+            BandKind::HighShelf => {
+                let root = 2.0 * a.sqrt() * alpha;
+                (
+                    a * ((a + 1.0) + (a - 1.0) * cos + root),
+                    -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                    a * ((a + 1.0) + (a - 1.0) * cos - root),
+                    (a + 1.0) - (a - 1.0) * cos + root,
+                    2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                    (a + 1.0) - (a - 1.0) * cos - root,
+                )
+            }
+            BandKind::LowPass => (
+                (1.0 - cos) / 2.0,
+                1.0 - cos,
+                (1.0 - cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+            BandKind::HighPass => (
+                (1.0 + cos) / 2.0,
+                -(1.0 + cos),
+                (1.0 + cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+        };
+
+        Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        }
+    }
+
+    /// ## `magnitude(&self, frequency: f32, sample_rate: f32) -> f32`
+    ///
+    /// Returns how much the biquad amplifies a frequency.
+    ///
+    /// ### Arguments
+    ///
+    /// * `frequency: f32` - The frequency, in Hz
+    /// * `sample_rate: f32` - The sample rate
+    ///
+    /// ### Returns
+    ///
+    /// * `f32` - The linear gain
+    pub fn magnitude(&self, frequency: f32, sample_rate: f32) -> f32 {
+        let w = 2.0 * std::f64::consts::PI * frequency as f64 / sample_rate as f64;
+        let (sin1, cos1) = w.sin_cos();
+        let (sin2, cos2) = (2.0 * w).sin_cos();
+
+        // H(z) at z = e^jw, with z^-1 = cos w - j sin w
+        let numerator_re = self.b0 as f64 + self.b1 as f64 * cos1 + self.b2 as f64 * cos2;
+        let numerator_im = -(self.b1 as f64 * sin1 + self.b2 as f64 * sin2);
+        let denominator_re = 1.0 + self.a1 as f64 * cos1 + self.a2 as f64 * cos2;
+        let denominator_im = -(self.a1 as f64 * sin1 + self.a2 as f64 * sin2);
+
+        ((numerator_re * numerator_re + numerator_im * numerator_im)
+            / (denominator_re * denominator_re + denominator_im * denominator_im).max(1e-30))
+        .sqrt() as f32
+    }
+}
+
+/// ## Settings
+///
+/// The settings of one band, kept as the bits of f32s in atomics behind a sequence number,
+/// so a band is always read whole even while another thread is changing it.
+///
+/// ### Fields
+///
+/// * `sequence: AtomicU32` - Odd while the band is being written, bumped twice by every write
+/// * `values: [AtomicU32; 5]` - The type, frequency, gain, Q and slope
+#[derive(Debug)]
+struct Settings {
+    sequence: AtomicU32,
+    values: [AtomicU32; 5],
+}
+
+impl Settings {
+    /// ## `read(&self) -> Option<[f32; 5]>`
+    ///
+    /// Private function which reads the settings, or returns None if a write is in progress.
+    fn read(&self) -> Option<[f32; 5]> {
+        let before = self.sequence.load(Ordering::Acquire);
+        if before % 2 == 1 {
+            return None;
+        }
+        let values =
+            std::array::from_fn(|index| f32::from_bits(self.values[index].load(Ordering::Relaxed)));
+        fence(Ordering::Acquire);
+        match self.sequence.load(Ordering::Relaxed) == before {
+            true => Some(values),
+            false => None,
+        }
+    }
+
+    /// ## `write(&self, values: [f32; 5])`
+    ///
+    /// Private function which changes the settings, waiting for any other write to finish first.
+    fn write(&self, values: [f32; 5]) {
+        let mut sequence = self.sequence.load(Ordering::Relaxed);
+        loop {
+            if sequence % 2 == 1 {
+                std::hint::spin_loop();
+                sequence = self.sequence.load(Ordering::Relaxed);
+                continue;
+            }
+            match self.sequence.compare_exchange_weak(
+                sequence,
+                sequence.wrapping_add(1),
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(current) => sequence = current,
+            }
+        }
+        fence(Ordering::Release);
+        for (setting, value) in self.values.iter().zip(values) {
+            setting.store(value.to_bits(), Ordering::Relaxed);
+        }
+        self.sequence
+            .store(sequence.wrapping_add(2), Ordering::Release);
+    }
+}
+
+/// ## Curve
+///
+/// The settings of an EQ, shared between the EQ on the audio thread and the control side, which keeps it on the strip's `Route`.
+/// Only the control side changes it. Each band is published as a whole (see `Settings`), so the audio thread never sees half a change,
+/// and reads it with `try_band`, which never waits.
+///
+/// ### Fields
+///
+/// * `bands: Vec<Settings>` - The settings of each band
+/// * `sample_rate: AtomicU32` - The sample rate the EQ last ran at, for working out its response
+///
+/// ### Functions
+///
+/// * `new() -> Self` - Creates the settings of a flat EQ
+/// * `band(&self, index: usize) -> Band` - Returns the settings of a band
+/// * `try_band(&self, index: usize) -> Option<Band>` - Returns the settings of a band, unless they are being changed
+/// * `set_band(&self, index: usize, band: Band)` - Changes the settings of a band
+/// * `set_parameter(&self, name: &str, value: f32) -> bool` - Changes one setting of a band by name, e.g. "2.freq"
+/// * `response(&self, frequencies: &[f32]) -> Vec<f32>` - Returns the gain of the whole EQ at each frequency
+#[derive(Debug)]
+pub struct Curve {
+    bands: Vec<Settings>,
+    sample_rate: AtomicU32,
+}
+
+impl Curve {
+    /// ## `new() -> Self`
+    ///
+    /// Creates the settings of a flat EQ, with every band off.
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The new settings
+    pub fn new() -> Self {
+        let curve = Self {
+            bands: (0..MAX_BANDS)
+                .map(|_| Settings {
+                    sequence: AtomicU32::new(0),
+                    values: std::array::from_fn(|_| AtomicU32::new(0)),
+                })
+                .collect(),
+            sample_rate: AtomicU32::new(44100),
+        };
+        for index in 0..MAX_BANDS {
+            curve.set_band(index, Band::default());
+        }
+        curve
+    }
+
+    /// ## `band(&self, index: usize) -> Band`
+    ///
+    /// Returns the settings of a band, waiting out a change in progress (which only takes a few stores).
+    ///
+    /// ### Arguments
+    ///
+    /// * `index: usize` - The index of the band (0-7)
+    ///
+    /// ### Returns
+    ///
+    /// * `Band` - The settings; bands past the last are off
+    pub fn band(&self, index: usize) -> Band {
+        if index >= self.bands.len() {
+            return Band::default();
+        }
+        loop {
+            match self.try_band(index) {
+                Some(band) => return band,
+                None => std::hint::spin_loop(),
+            }
+        }
+    }
+
+    /// ## `try_band(&self, index: usize) -> Option<Band>`
+    ///
+    /// Returns the settings of a band, without waiting.
+    ///
+    /// ### Arguments
+    ///
+    /// * `index: usize` - The index of the band (0-7)
+    ///
+    /// ### Returns
+    ///
+    /// * `Option<Band>` - The settings, or None if they are being changed; bands past the last are off
+    pub fn try_band(&self, index: usize) -> Option<Band> {
+        match self.bands.get(index) {
+            Some(settings) => settings.read().map(|values| Band {
+                kind: BandKind::from_index(values[0] as u32),
+                frequency: values[1],
+                gain: values[2],
+                q: values[3],
+                slope: values[4] as usize,
+            }),
+            None => Some(Band::default()),
+        }
+    }
+
+    /// ## `set_band(&self, index: usize, band: Band)`
+    ///
+    /// Changes the settings of a band. The EQ moves to them smoothly.
+    ///
+    /// ### Arguments
+    ///
+    /// * `index: usize` - The index of the band (0-7); others are ignored
+    /// * `band: Band` - The new settings
+    pub fn set_band(&self, index: usize, band: Band) {
+        match self.bands.get(index) {
+            Some(settings) => settings.write([
+                band.kind.index() as f32,
+                band.frequency,
+                band.gain,
+                band.q,
+                band.slope as f32,
+            ]),
+            None => {}
+        }
+    }
+
+    /// ## `set_parameter(&self, name: &str, value: f32) -> bool`
+    ///
+    /// Changes one setting of a band by name, as "<band>.<setting>", publishing the whole band again.
+    ///
+    /// ### Arguments
+    ///
+    /// * `name: &str` - The band and the setting, e.g. "2.freq" (see `Band::set`)
+    /// * `value: f32` - The new value
+    ///
+    /// ### Returns
+    ///
+    /// * `bool` - Whether the curve has a band and setting with the name
+    pub fn set_parameter(&self, name: &str, value: f32) -> bool {
+        match name.split_once('.') {
+            Some((band, setting)) => match band.parse::<usize>() {
+                Ok(index) if index < MAX_BANDS => {
+                    let mut band = self.band(index);
+                    let known = band.set(setting, value);
+                    if known {
+                        self.set_band(index, band);
+                    }
+                    known
+                }
+                _ => false,
+            },
+            None => false,
+        }
+    }
+
+    /// ## `sample_rate(&self) -> f32`
+    ///
+    /// Returns the sample rate the EQ last ran at.
+    ///
+    /// ### Returns
+    ///
+    /// * `f32` - The sample rate
+    pub fn sample_rate(&self) -> f32 {
+        self.sample_rate.load(Ordering::Relaxed) as f32
+    }
+
+    /// ## `response(&self, frequencies: &[f32]) -> Vec<f32>`
+    ///
+    /// Returns the gain of the whole EQ at each frequency, from its settings (not from where the audio thread has smoothed to).
+    ///
+    /// ### Arguments
+    ///
+    /// * `frequencies: &[f32]` - The frequencies, in Hz
+    ///
+    /// ### Returns
+    ///
+    /// * `Vec<f32>` - The gain at each frequency, in dB
+    pub fn response(&self, frequencies: &[f32]) -> Vec<f32> {
+        let sample_rate = self.sample_rate();
+        let mut coefficients = [Coefficients::FLAT; MAX_SECTIONS];
+        let mut gains = vec![1.0f32; frequencies.len()];
+        for index in 0..MAX_BANDS {
+            let sections = self.band(index).sections(sample_rate, &mut coefficients);
+            for coefficients in coefficients.iter().take(sections) {
+                for (gain, frequency) in gains.iter_mut().zip(frequencies) {
+                    *gain *= coefficients.magnitude(*frequency, sample_rate);
+                }
+            }
+        }
+        gains
+            .iter()
+            .map(|gain| 20.0 * gain.max(1e-10).log10())
+            .collect()
+    }
+}
+
+/// ## EqResponse
+///
+/// The frequency response of the EQ on a strip or the master bus, as sent to the console and TV windows to be plotted.
+///
+/// ### Fields
+///
+/// * `name: String` - The strip index or "master", and the index of the EQ in the chain, e.g. "2:0"
+/// * `frequencies: Vec<f32>` - The frequencies, in Hz, spaced evenly on a log scale
+/// * `gains: Vec<f32>` - The gain at each frequency, in dB
+///
+/// ### Attributes
+///
+/// * `#[derive(ts_rs::TS, serde::Serialize, serde::Deserialize)]` - Serde and TS-RS are used to make this struct available to both Rust and TypeScript.
+/// * `#[ts(export, export_to = "../src/bindings/EqResponse.ts")]` - This struct is exported to TypeScript, and is used to plot EQ curves.
+#[derive(ts_rs::TS, serde::Serialize, serde::Deserialize, Clone, Debug)]
+#[ts(export, export_to = "../src/bindings/EqResponse.ts")]
+pub struct EqResponse {
+    pub name: String,
+    pub frequencies: Vec<f32>,
+    pub gains: Vec<f32>,
+}
+
+impl EqResponse {
+    /// ## `new(name: String, curve: &Curve, points: usize) -> Self`
+    ///
+    /// Works out the response of an EQ from 20 Hz to 20 kHz.
+    ///
+    /// ### Arguments
+    ///
+    /// * `name: String` - The strip index or "master", and the index of the EQ in the chain, e.g. "2:0"
+    /// * `curve: &Curve` - The settings of the EQ
+    /// * `points: usize` - The number of frequencies (at least 2)
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The response
+    pub fn new(name: String, curve: &Curve, points: usize) -> Self {
+        let points = points.max(2);
+        let frequencies: Vec<f32> = (0..points)
+            .map(|point| 20.0 * 1000.0f32.powf(point as f32 / (points - 1) as f32))
+            .collect();
+        let gains = curve.response(&frequencies);
+        Self {
+            name,
+            frequencies,
+            gains,
+        }
+    }
+}
+
+/// ## Section
+///
+/// One biquad of a band on the audio thread, in transposed direct form II, for up to two channels.
+///
+/// ### Fields
+///
+/// * `coefficients: Coefficients` - The coefficients
+/// * `state: [[f32; 2]; 2]` - The two state variables of each channel
+#[derive(Clone, Copy, Debug)]
+struct Section {
+    coefficients: Coefficients,
+    state: [[f32; 2]; 2],
+}
+
+impl Section {
+    /// ## `process(&mut self, channel: usize, input: f32) -> f32`
+    ///
+    /// Private function which filters one sample of a channel.
+    fn process(&mut self, channel: usize, input: f32) -> f32 {
+        let c = &self.coefficients;
+        let state = &mut self.state[channel];
+        let output = c.b0 * input + state[0];
+        state[0] = c.b1 * input - c.a1 * output + state[1];
+        state[1] = c.b2 * input - c.a2 * output;
+        output
+    }
+}
+
+/// ## Equalizer
+///
+/// A parametric EQ effect with MAX_BANDS bands. Mono buffers are filtered as one channel, anything else as a stereo pair.
+///
+/// Every UPDATE_FRAMES frames, each band moves its frequency, gain and Q a step towards its target
+/// and works out new coefficients, so turning a band doesn't click. Changing a band's type or slope takes effect at once.
+///
+/// The targets are the EQ's own: the audio thread only ever tries once to read the shared `Curve`, which the control side alone writes,
+/// and takes a band from it when the band has been published anew. Its parameters are set on the curve by the control side
+/// (see `Curve::set_parameter`), so the EQ has none of its own.
+///
+/// ### Fields
+///
+/// * `curve: Arc<Curve>` - The settings, written by the control side
+/// * `published: [Option<Band>; MAX_BANDS]` - The settings of each band last read from the curve
+/// * `targets: [Band; MAX_BANDS]` - Where each band is moving to
+/// * `current: [Band; MAX_BANDS]` - Where each band has smoothed to
+/// * `sections: [[Section; MAX_SECTIONS]; MAX_BANDS]` - The biquads of each band
+/// * `used: [usize; MAX_BANDS]` - The number of biquads each band uses
+/// * `sample_rate: f32` - The sample rate the coefficients were worked out for
+///
+/// ### Parameters (of the curve)
+///
+/// * "<band>.type" - 0 off, 1 low shelf, 2 high shelf, 3 peak, 4 low pass, 5 high pass
+/// * "<band>.freq" - The frequency, in Hz
+/// * "<band>.gain" - The gain, in dB
+/// * "<band>.q" - The Q
+/// * "<band>.slope" - The slope of passes, in biquads (1-4)
+pub struct Equalizer {
+    curve: Arc<Curve>,
+    published: [Option<Band>; MAX_BANDS],
+    targets: [Band; MAX_BANDS],
+    current: [Band; MAX_BANDS],
+    sections: [[Section; MAX_SECTIONS]; MAX_BANDS],
+    used: [usize; MAX_BANDS],
+    sample_rate: f32,
+}
+
+impl Equalizer {
+    /// ## `new(curve: Arc<Curve>) -> Self`
+    ///
+    /// Creates an EQ playing the settings in a curve.
+    ///
+    /// ### Arguments
+    ///
+    /// * `curve: Arc<Curve>` - The settings, which stay shared with the caller
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The new EQ
+    pub fn new(curve: Arc<Curve>) -> Self {
+        Self {
+            curve,
+            published: [None; MAX_BANDS],
+            targets: [Band::default(); MAX_BANDS],
+            current: [Band::default(); MAX_BANDS],
+            sections: [[Section {
+                coefficients: Coefficients::FLAT,
+                state: [[0.0; 2]; 2],
+            }; MAX_SECTIONS]; MAX_BANDS],
+            used: [0; MAX_BANDS],
+            sample_rate: 0.0,
+        }
+    }
+
+    /// ## `update(&mut self, sample_rate: f32, step: f32)`
+    ///
+    /// Private function which moves every band towards its settings and works out new coefficients for those that moved.
+    ///
+    /// ### Arguments
+    ///
+    /// * `sample_rate: f32` - The sample rate
+    /// * `step: f32` - How far to move, from 0.0 (not at all) to 1.0 (all the way)
+    fn update(&mut self, sample_rate: f32, step: f32) {
+        let rate_changed = sample_rate != self.sample_rate;
+        self.sample_rate = sample_rate;
+
+        for index in 0..MAX_BANDS {
+            // a band being changed right now keeps its target until the next update
+            match self.curve.try_band(index) {
+                Some(band) if self.published[index] != Some(band) => {
+                    self.published[index] = Some(band);
+                    self.targets[index] = band;
+                }
+                _ => {}
+            }
+            let target = self.targets[index];
+            let current = self.current[index];
+            let mut next = target;
+            if current.kind == target.kind && current.slope == target.slope {
+                let log_frequency = current.frequency.ln()
+                    + (target.frequency.ln() - current.frequency.ln()) * step;
+                next.frequency = log_frequency.exp();
+                next.gain = current.gain + (target.gain - current.gain) * step;
+                next.q = current.q + (target.q - current.q) * step;
+
+                // close enough is done, so settled bands stop being worked out
+                if (next.frequency - target.frequency).abs() < 0.01
+                    && (next.gain - target.gain).abs() < 0.001
+                    && (next.q - target.q).abs() < 0.0001
+                {
+                    next = target;
+                }
+            }
+
+            if next == current && !rate_changed {
+                continue;
+            }
+
+            let mut coefficients = [Coefficients::FLAT; MAX_SECTIONS];
+            let used = next.sections(sample_rate, &mut coefficients);
+            if next.kind != current.kind || used != self.used[index] {
+                for section in self.sections[index].iter_mut() {
+                    section.state = [[0.0; 2]; 2];
+                }
+            }
+            for (section, coefficients) in self.sections[index].iter_mut().zip(coefficients) {
+                section.coefficients = coefficients;
+            }
+            self.used[index] = used;
+            self.current[index] = next;
+        }
+    }
+}
+
+impl Effect for Equalizer {
+    fn process(&mut self, state: &State, buffer: &mut Buffer) {
+        let sample_rate = state.sample_rate as f32;
+        self.curve
+            .sample_rate
+            .store(state.sample_rate, Ordering::Relaxed);
+        let step = 1.0 - (-(UPDATE_FRAMES as f32) / (SMOOTHING * sample_rate)).exp();
+        let channels = buffer.channels().min(2);
+
+        let mut start = 0;
+        while start < buffer.len() {
+            self.update(sample_rate, step);
+            let end = (start + UPDATE_FRAMES).min(buffer.len());
+            for channel in 0..channels {
+                let samples = &mut buffer.channel_mut(channel)[start..end];
+                for (band, sections) in self.sections.iter_mut().enumerate() {
+                    for section in sections.iter_mut().take(self.used[band]) {
+                        for sample in samples.iter_mut() {
+                            *sample = section.process(channel, *sample);
+                        }
+                    }
+                }
+            }
+            start = end;
+        }
+    }
+
+    fn curve(&self) -> Option<Arc<Curve>> {
+        Some(self.curve.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// ## `sine(eq: &mut Equalizer, frequency: f32, sample_rate: u32, clock: &mut u64, start: usize, frames: usize) -> Vec<f32>`
+    ///
+    /// Runs a sine of amplitude 1.0 through an EQ in blocks of 64 frames, as the left channel of a stereo pair.
+    fn sine(
+        eq: &mut Equalizer,
+        frequency: f32,
+        sample_rate: u32,
+        clock: &mut u64,
+        start: usize,
+        frames: usize,
+    ) -> Vec<f32> {
+        let mut output = Vec::with_capacity(frames);
+        let mut frame = start;
+        while output.len() < frames {
+            let len = (frames - output.len()).min(64);
+            let state = State::advance(sample_rate, clock, len);
+            let mut buffer = Buffer::new(2, len);
+            for sample in buffer.channel_mut(0).iter_mut() {
+                *sample = (2.0 * std::f32::consts::PI * frequency * frame as f32
+                    / sample_rate as f32)
+                    .sin();
+                frame += 1;
+            }
+            eq.process(&state, &mut buffer);
+            output.extend_from_slice(buffer.channel(0));
+        }
+        output
+    }
+
+    fn band(kind: BandKind, frequency: f32, gain: f32, q: f32) -> Band {
+        Band {
+            kind,
+            frequency,
+            gain,
+            q,
+            slope: 1,
+        }
+    }
+
+    #[test]
+    fn coefficients_have_the_band_gain_at_its_frequency() {
+        for sample_rate in [44100.0, 48000.0, 96000.0] {
+            for frequency in [100.0, 1000.0, 10000.0] {
+                let gain_at = |kind, gain, q| {
+                    let coefficients = Coefficients::new(kind, frequency, gain, q, sample_rate);
+                    20.0 * coefficients.magnitude(frequency, sample_rate).log10()
+                };
+                // peaks reach their gain, shelves are half way there and passes are down by 1/Q
+                for gain in [-12.0, 6.0] {
+                    let peak = gain_at(BandKind::Peak, gain, 2.0);
+                    assert!((peak - gain).abs() < 0.05, "peak {} at {}", peak, frequency);
+                    let shelf = gain_at(BandKind::LowShelf, gain, 0.707);
+                    assert!(
+                        (shelf - gain / 2.0).abs() < 0.05,
+                        "shelf {} at {}",
+                        shelf,
+                        frequency
+                    );
+                    let shelf = gain_at(BandKind::HighShelf, gain, 0.707);
+                    assert!(
+                        (shelf - gain / 2.0).abs() < 0.05,
+                        "shelf {} at {}",
+                        shelf,
+                        frequency
+                    );
+                }
+                for kind in [BandKind::LowPass, BandKind::HighPass] {
+                    let pass = gain_at(kind, 0.0, 0.5);
+                    assert!(
+                        (pass - 20.0 * 0.5f32.log10()).abs() < 0.05,
+                        "pass {} at {}",
+                        pass,
+                        frequency
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn processed_signal_matches_the_response() {
+        let sample_rate = 48000;
+        let curve = Arc::new(Curve::new());
+        curve.set_band(0, band(BandKind::HighPass, 60.0, 0.0, 0.707));
+        curve.set_band(1, band(BandKind::LowShelf, 200.0, 4.0, 0.707));
+        curve.set_band(2, band(BandKind::Peak, 1000.0, -8.0, 1.5));
+        curve.set_band(3, band(BandKind::HighShelf, 8000.0, 5.0, 0.707));
+        let mut eq = Equalizer::new(curve.clone());
+        let mut clock = 0;
+
+        // let the bands move to their settings before measuring
+        sine(
+            &mut eq,
+            1000.0,
+            sample_rate,
+            &mut clock,
+            0,
+            sample_rate as usize / 4,
+        );
+        let frequencies = [40.0, 120.0, 500.0, 1000.0, 3000.0, 12000.0];
+        let response = curve.response(&frequencies);
+        for (frequency, expected) in frequencies.iter().zip(response) {
+            let output = sine(
+                &mut eq,
+                *frequency,
+                sample_rate,
+                &mut clock,
+                0,
+                sample_rate as usize,
+            );
+            // skip the transient of the new frequency, then measure the level over whole periods
+            let settled = &output[sample_rate as usize / 2..];
+            let power =
+                settled.iter().map(|sample| sample * sample).sum::<f32>() / settled.len() as f32;
+            let measured = 10.0 * (2.0 * power).log10();
+            assert!(
+                (measured - expected).abs() < 0.1,
+                "{} Hz: measured {} dB, expected {} dB",
+                frequency,
+                measured,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn band_changes_are_smooth() {
+        let sample_rate = 48000;
+        let frequency = 200.0;
+        let curve = Arc::new(Curve::new());
+        curve.set_band(0, band(BandKind::Peak, 200.0, 0.0, 1.0));
+        let mut eq = Equalizer::new(curve.clone());
+        let mut clock = 0;
+        let mut output = sine(&mut eq, frequency, sample_rate, &mut clock, 0, 4800);
+
+        // boost the band by 12 dB, then move it away, while the sine keeps playing
+        curve.set_parameter("0.gain", 12.0);
+        output.extend(sine(
+            &mut eq,
+            frequency,
+            sample_rate,
+            &mut clock,
+            4800,
+            9600,
+        ));
+        curve.set_parameter("0.freq", 5000.0);
+        output.extend(sine(
+            &mut eq,
+            frequency,
+            sample_rate,
+            &mut clock,
+            14400,
+            4800,
+        ));
+
+        // the second difference of a sine is at most its amplitude times (2 pi f / sample rate) squared,
+        // and the boost makes it at most 4 times louder, while a click shows up many times over that
+        let w = 2.0 * std::f32::consts::PI * frequency / sample_rate as f32;
+        for (frame, samples) in output.windows(3).enumerate() {
+            let bend = (samples[2] - 2.0 * samples[1] + samples[0]).abs();
+            assert!(
+                bend < 3.0 * 4.0 * w * w,
+                "bent by {} at frame {}",
+                bend,
+                frame + 1
+            );
+        }
+        // and the EQ did get there
+        let settled = &output[12960..14400];
+        let power =
+            settled.iter().map(|sample| sample * sample).sum::<f32>() / settled.len() as f32;
+        let boosted = 10.0 * (2.0 * power).log10();
+        assert!((boosted - 12.0).abs() < 0.1, "boosted by {} dB", boosted);
+    }
+
+    #[test]
+    fn parameters_are_published_to_the_curve() {
+        let curve = Curve::new();
+        assert!(curve.set_parameter("3.type", BandKind::Peak.index() as f32));
+        assert!(curve.set_parameter("3.freq", 2500.0));
+        assert!(curve.set_parameter("3.gain", -4.0));
+        assert!(!curve.set_parameter("3.width", 1.0));
+        assert!(!curve.set_parameter("8.gain", 1.0));
+        let band = curve.band(3);
+        assert_eq!(band.kind, BandKind::Peak);
+        assert_eq!(band.frequency, 2500.0);
+        assert_eq!(band.gain, -4.0);
+        let response = curve.response(&[2500.0]);
+        assert!((response[0] + 4.0).abs() < 0.05, "{}", response[0]);
+    }
+}
@@ -0,0 +1,400 @@
+//! filter.rs
+//!
+//! Module for the resonant multimode filter effect, with its cutoff moved by an envelope, an LFO, key tracking and a MIDI controller
+
+use crate::audio::{plugin::Effect, Buffer, State};
+use crate::midi::ChannelMessage;
+use crate::synth::{Adsr, Envelope};
+
+/// The lowest cutoff, in Hz
+pub const MIN_CUTOFF: f32 = 20.0;
+
+/// The highest cutoff, in Hz. Cutoffs are also kept under the Nyquist frequency.
+pub const MAX_CUTOFF: f32 = 20000.0;
+
+/// How long the cutoff and resonance take to follow a change, in seconds, so knobs and controllers don't zipper
+const SMOOTHING: f32 = 0.002;
+
+/// The note key tracking is measured from; it leaves the cutoff where it is
+const KEYTRACK_CENTER: f32 = 60.0;
+
+/// ## FilterMode
+///
+/// Which part of the spectrum a filter lets through.
+///
+/// ### Variants
+///
+/// * `LowPass` - Everything under the cutoff
+/// * `HighPass` - Everything over the cutoff
+/// * `BandPass` - A band around the cutoff
+/// * `Notch` - Everything but a band around the cutoff
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FilterMode {
+    LowPass,
+    HighPass,
+    BandPass,
+    Notch,
+}
+
+impl FilterMode {
+    /// ## `from_name(name: &str) -> Option<Self>`
+    ///
+    /// Returns the mode with a name, as used by `create_effect`.
+    ///
+    /// ### Arguments
+    ///
+    /// * `name: &str` - "lowpass", "highpass", "bandpass" or "notch"
+    ///
+    /// ### Returns
+    ///
+    /// * `Option<Self>` - The mode, or None if the name is unknown
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "lowpass" | "lp" => Some(FilterMode::LowPass),
+            "highpass" | "hp" => Some(FilterMode::HighPass),
+            "bandpass" | "bp" => Some(FilterMode::BandPass),
+            "notch" => Some(FilterMode::Notch),
+            _ => None,
+        }
+    }
+
+    /// ## `from_index(index: f32) -> Self`
+    ///
+    /// Returns the mode with an index, as set by the "mode" parameter.
+    ///
+    /// ### Arguments
+    ///
+    /// * `index: f32` - 0 for low pass, 1 for high pass, 2 for band pass, 3 for notch
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The mode; out of range indices are clamped
+    pub fn from_index(index: f32) -> Self {
+        match index.round().max(0.0) as u32 {
+            0 => FilterMode::LowPass,
+            1 => FilterMode::HighPass,
+            2 => FilterMode::BandPass,
+            _ => FilterMode::Notch,
+        }
+    }
+}
+
+/// ## Svf
+///
+/// A state variable filter for one channel, in the topology-preserving transform form (after Andrew Simper).
+/// Its state is kept as the charge of two integrators, so the cutoff can move every frame without the filter blowing up.
+///
+/// ### Fields
+///
+/// * `ic1: f32` - The state of the first integrator
+/// * `ic2: f32` - The state of the second integrator
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Svf {
+    ic1: f32,
+    ic2: f32,
+}
+
+impl Svf {
+    /// ## `process(&mut self, input: f32, g: f32, k: f32, mode: FilterMode) -> f32`
+    ///
+    /// Filters one sample.
+    ///
+    /// ### Arguments
+    ///
+    /// * `input: f32` - The sample
+    /// * `g: f32` - The cutoff coefficient, `tan(π * cutoff / sample_rate)`
+    /// * `k: f32` - The damping, `1 / Q`; lower values resonate more
+    /// * `mode: FilterMode` - The output to return
+    ///
+    /// ### Returns
+    ///
+    /// * `f32` - The filtered sample
+    pub fn process(&mut self, input: f32, g: f32, k: f32, mode: FilterMode) -> f32 {
+        let a1 = 1.0 / (1.0 + g * (g + k));
+        let a2 = g * a1;
+        let a3 = g * a2;
+
+        let v3 = input - self.ic2;
+        let band = a1 * self.ic1 + a2 * v3;
+        let low = self.ic2 + a2 * self.ic1 + a3 * v3;
+        self.ic1 = 2.0 * band - self.ic1;
+        self.ic2 = 2.0 * low - self.ic2;
+
+        match mode {
+            FilterMode::LowPass => low,
+            FilterMode::HighPass => input - k * band - low,
+            FilterMode::BandPass => band,
+            FilterMode::Notch => input - k * band,
+        }
+    }
+
+    /// ## `reset(&mut self)`
+    ///
+    /// Empties the filter.
+    pub fn reset(&mut self) {
+        self.ic1 = 0.0;
+        self.ic2 = 0.0;
+    }
+}
+
+/// ## Filter
+///
+/// A resonant multimode filter effect. Mono buffers are filtered as one channel, anything else as a stereo pair.
+///
+/// The cutoff is moved in octaves by an envelope, started by notes sent to the strip (from MIDI, HID keys and the sequencer),
+/// an LFO and key tracking. A MIDI controller can be mapped to turn the cutoff itself.
+///
+/// ### Fields
+///
+/// * `mode: FilterMode` - Which part of the spectrum is let through
+/// * `cutoff: f32` - The cutoff before modulation, in Hz
+/// * `resonance: f32` - How much the filter rings at the cutoff (0.0-1.0)
+/// * `channels: [Svf; 2]` - The filters for the left and right channels
+/// * `octaves: f32` - The smoothed cutoff, in octaves over MIN_CUTOFF
+/// * `damping: f32` - The smoothed damping
+/// * `adsr: Adsr` - The shape of the envelope
+/// * `envelope: Envelope` - The envelope
+/// * `env_amount: f32` - How far the envelope moves the cutoff at full level, in octaves (can be negative)
+/// * `held: usize` - The number of notes held, so the envelope is released with the last one
+/// * `lfo_depth: f32` - How far the LFO moves the cutoff either way, in octaves
+/// * `lfo_rate: f32` - The rate of the LFO, in Hz
+/// * `lfo: f64` - The phase of the LFO (0.0-1.0)
+/// * `keytrack: f32` - How far the cutoff follows the last note played (1.0 moves it an octave per octave)
+/// * `note: f32` - The last note played
+/// * `controller: Option<u8>` - The MIDI controller that turns the cutoff, if any
+///
+/// ### Parameters
+///
+/// * "mode" - 0 low pass, 1 high pass, 2 band pass, 3 notch
+/// * "cutoff" - The cutoff, in Hz
+/// * "resonance" - The resonance (0.0-1.0)
+/// * "env" - The envelope amount, in octaves
+/// * "attack", "decay", "release" - The envelope times, in seconds
+/// * "sustain" - The envelope sustain level (0.0-1.0)
+/// * "lfo" - The LFO depth, in octaves
+/// * "lfo_rate" - The LFO rate, in Hz
+/// * "keytrack" - The key tracking (0.0-1.0)
+/// * "cc" - The MIDI controller that turns the cutoff, from 20 Hz to 20 kHz; -1 unmaps it
+pub struct Filter {
+    mode: FilterMode,
+    cutoff: f32,
+    resonance: f32,
+    channels: [Svf; 2],
+    octaves: f32,
+    damping: f32,
+    adsr: Adsr,
+    envelope: Envelope,
+    env_amount: f32,
+    held: usize,
+    lfo_depth: f32,
+    lfo_rate: f32,
+    lfo: f64,
+    keytrack: f32,
+    note: f32,
+    controller: Option<u8>,
+}
+
+impl Filter {
+    /// ## `new(mode: FilterMode, cutoff: f32, resonance: f32) -> Self`
+    ///
+    /// Creates a filter with no modulation.
+    ///
+    /// ### Arguments
+    ///
+    /// * `mode: FilterMode` - Which part of the spectrum is let through
+    /// * `cutoff: f32` - The cutoff, in Hz
+    /// * `resonance: f32` - The resonance (0.0-1.0)
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The new filter
+    pub fn new(mode: FilterMode, cutoff: f32, resonance: f32) -> Self {
+        let cutoff = cutoff.clamp(MIN_CUTOFF, MAX_CUTOFF);
+        let resonance = resonance.clamp(0.0, 1.0);
+        Self {
+            mode,
+            cutoff,
+            resonance,
+            channels: [Svf::default(); 2],
+            octaves: (cutoff / MIN_CUTOFF).log2(),
+            damping: Self::damping(resonance),
+            adsr: Adsr::default(),
+            envelope: Envelope::new(),
+            env_amount: 0.0,
+            held: 0,
+            lfo_depth: 0.0,
+            lfo_rate: 1.0,
+            lfo: 0.0,
+            keytrack: 0.0,
+            note: KEYTRACK_CENTER,
+            controller: None,
+        }
+    }
+
+    /// ## `damping(resonance: f32) -> f32`
+    ///
+    /// Private function which turns a resonance into the damping of the state variable filter.
+    /// The damping never reaches 0.0, so the filter rings without running away.
+    ///
+    /// ### Arguments
+    ///
+    /// * `resonance: f32` - The resonance (0.0-1.0)
+    ///
+    /// ### Returns
+    ///
+    /// * `f32` - The damping (0.05-2.0)
+    fn damping(resonance: f32) -> f32 {
+        2.0 - 1.95 * resonance
+    }
+}
+
+impl Effect for Filter {
+    fn process(&mut self, state: &State, buffer: &mut Buffer) {
+        let sample_rate = state.sample_rate as f32;
+        let nyquist = (sample_rate * 0.49).min(MAX_CUTOFF);
+        let smoothing = 1.0 - (-1.0 / (SMOOTHING * sample_rate)).exp();
+        let target_octaves = (self.cutoff / MIN_CUTOFF).log2();
+        let target_damping = Self::damping(self.resonance);
+        let keytrack = self.keytrack * (self.note - KEYTRACK_CENTER) / 12.0;
+        let channels = buffer.channels().min(2);
+
+        for index in 0..buffer.len() {
+            self.octaves += (target_octaves - self.octaves) * smoothing;
+            self.damping += (target_damping - self.damping) * smoothing;
+
+            let mut octaves = self.octaves + keytrack;
+            if self.env_amount != 0.0 {
+                octaves += self.env_amount * self.envelope.next(&self.adsr, sample_rate);
+            }
+            if self.lfo_depth != 0.0 {
+                self.lfo = (self.lfo + self.lfo_rate as f64 / sample_rate as f64).fract();
+                octaves += self.lfo_depth * (self.lfo * std::f64::consts::TAU).sin() as f32;
+            }
+
+            let cutoff = (MIN_CUTOFF * 2.0f32.powf(octaves)).clamp(MIN_CUTOFF, nyquist);
+            let g = (std::f32::consts::PI * cutoff / sample_rate).tan();
+            for channel in 0..channels {
+                let samples = buffer.channel_mut(channel);
+                samples[index] =
+                    self.channels[channel].process(samples[index], g, self.damping, self.mode);
+            }
+        }
+    }
+
+    fn midi(&mut self, _channel: u8, message: ChannelMessage) {
+        match message {
+            ChannelMessage::NoteOn { note, .. } => {
+                self.note = note as f32;
+                self.held += 1;
+                self.envelope.start();
+            }
+            ChannelMessage::NoteOff { .. } => {
+                self.held = self.held.saturating_sub(1);
+                if self.held == 0 {
+                    self.envelope.release();
+                }
+            }
+            ChannelMessage::ControlChange { controller, value } => {
+                if self.controller == Some(controller) {
+                    self.cutoff = MIN_CUTOFF * (MAX_CUTOFF / MIN_CUTOFF).powf(value as f32 / 127.0);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f32) {
+        match name {
+            "mode" => {
+                self.mode = FilterMode::from_index(value);
+                for channel in self.channels.iter_mut() {
+                    channel.reset();
+                }
+            }
+            "cutoff" => self.cutoff = value.clamp(MIN_CUTOFF, MAX_CUTOFF),
+            "resonance" => self.resonance = value.clamp(0.0, 1.0),
+            "env" => self.env_amount = value.clamp(-10.0, 10.0),
+            "attack" => self.adsr.attack = value.max(0.0),
+            "decay" => self.adsr.decay = value.max(0.0),
+            "sustain" => self.adsr.sustain = value.clamp(0.0, 1.0),
+            "release" => self.adsr.release = value.max(0.0),
+            "lfo" => self.lfo_depth = value.clamp(-10.0, 10.0),
+            "lfo_rate" => self.lfo_rate = value.max(0.0),
+            "keytrack" => self.keytrack = value.clamp(0.0, 1.0),
+            "cc" => {
+                self.controller = match value >= 0.0 {
+                    true => Some(value.min(127.0) as u8),
+                    false => None,
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oscillator::WhiteNoise;
+
+    #[test]
+    fn svf_stays_finite_under_random_sweeps() {
+        let sample_rate = 48000.0;
+        let mut noise = WhiteNoise::new();
+        for mode in [
+            FilterMode::LowPass,
+            FilterMode::HighPass,
+            FilterMode::BandPass,
+            FilterMode::Notch,
+        ] {
+            let mut svf = Svf::default();
+            let mut loudest = 0.0f32;
+            for frame in 0..sample_rate as usize * 2 {
+                // a new cutoff and damping every frame, anywhere in their ranges
+                let cutoff =
+                    MIN_CUTOFF * (sample_rate * 0.49 / MIN_CUTOFF).powf(noise.sample() * 0.5 + 0.5);
+                let g = (std::f32::consts::PI * cutoff / sample_rate).tan();
+                let k = Filter::damping(noise.sample() * 0.5 + 0.5);
+                let input = match frame / 4800 % 2 {
+                    0 => noise.sample(),
+                    _ => 1.0,
+                };
+                let output = svf.process(input, g, k, mode);
+                assert!(output.is_finite(), "{:?} blew up at frame {}", mode, frame);
+                loudest = loudest.max(output.abs());
+            }
+            assert!(loudest < 100.0, "{:?} reached {}", mode, loudest);
+        }
+    }
+
+    #[test]
+    fn filter_stays_finite_under_per_sample_sweeps() {
+        let mut filter = Filter::new(FilterMode::LowPass, 1000.0, 0.5);
+        filter.set_parameter("lfo", 10.0);
+        filter.set_parameter("lfo_rate", 3000.0);
+        let mut clock = 0;
+        let mut noise = WhiteNoise::new();
+        for frame in 0..48000 {
+            if frame % 12000 == 0 {
+                filter.set_parameter("mode", (frame / 12000) as f32);
+            }
+            // a new cutoff and resonance every frame, on top of an LFO at audio rate
+            filter.set_parameter("cutoff", 20.0 + 19980.0 * (noise.sample() * 0.5 + 0.5));
+            filter.set_parameter("resonance", noise.sample() * 0.5 + 0.5);
+            let state = State::advance(48000, &mut clock, 1);
+            let mut buffer = Buffer::new(2, 1);
+            buffer.channel_mut(0)[0] = noise.sample();
+            buffer.channel_mut(1)[0] = 1.0;
+            filter.process(&state, &mut buffer);
+            for channel in 0..2 {
+                let output = buffer.channel(channel)[0];
+                assert!(
+                    output.is_finite() && output.abs() < 100.0,
+                    "{} at frame {}",
+                    output,
+                    frame
+                );
+            }
+        }
+    }
+}
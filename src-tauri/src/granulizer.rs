@@ -1,126 +1,648 @@
-// use crate::audio::plugin::Effect;
-// use crate::audio::plugin::Generator;
-
-// // take audio and break it down into grains
-// pub struct Granulate {
-//     grain_size: u32,
-//     grain_size_ms: u32,
-//     buffer: Vec<f32>,
-// }
-
-// impl Granulate {
-//     pub fn new(grain_size_ms: u32, buffer: Vec<f32>) -> Self {
-//         Self {
-//             grain_size: grain_size_ms,
-//             grain_size_ms,
-//             buffer: vec![0.0; grain_size_ms],
-//         }
-//     }
-
-//     pub fn resize_milliseconds(&mut self, milliseconds: u32, sample_rate: u32) {
-//         let grain_size = milliseconds * sample_rate / 1000;
-//         self.grain_size = grain_size;
-//         self.grain_size_ms = milliseconds;
-//         self.resize(grain_size);
-//     }
-
-//     fn resize(&mut self, grain_size: u32) {
-//         self.grain_size = grain_size;
-//         self.buffer.resize(grain_size, 0.0);
-//     }
-// }
-
-// // use state.
-// impl Effect for Granulate {
-//     fn process(&mut self, state: &crate::audio::State, sample: &mut crate::audio::Sample) {
-//         if state.sample_clock % state.sample_rate == 0 {
-//             self.resize_milliseconds(self.grain_size_ms, state.sample_rate);
-//         }
-//         if state.sample_clock % self.grain_size_ms == 0 {
-//             //write to buffer
-//         }
-//     }
-// }
-
-// //GRANULIZER
-// pub struct Granulizer {
-//     pub grain_start: f32,
-//     pub grain_end: f32,
-//     pub grain_duration: f32,
-//     pub grain_pitch: f32,
-//     pub grain_out: f32,
-//     pub grain_envelope: GrainEnvelope,
-// }
-
-// //break down sample input into grains
-// impl Granulize for Granulizer {
-//     fn granulize(&self) -> f32 {
-//         let grain_start = self.grain_start;
-//         let grain_end = self.grain_end;
-//         let grain_duration = self.grain_duration;
-//         let grain_pitch = self.grain_pitch;
-//         let grain_out = self.grain_out;
-//         let grain_envelope = self.grain_envelope;
-
-//         let grain = Grain {
-//             grain_start,
-//             grain_end,
-//             grain_duration,
-//             grain_pitch,
-//             grain_out,
-//             grain_envelope,
-//         };
-
-//         grain.granulize()
-//     }
-// }
-
-// //BEST ONE https://github.com/backtail/granulator-rs
-// //https://www.youtube.com/watch?v=Z4P5f6ZJ_nE
-// //https://github.com/PatrickWulfe/Granulizor/tree/master/src
-// //https://github.com/topics/granular-synthesis?l=rust
-
-// //grain start point high / low
-
-// // grain end point high / low
-
-// // grain duration ms
-
-// // grain pitch (interval ratio 12tones)
-
-// // grain out
-
-// // Envelope Formulas
-// //expr 1*(((sin(($i1)-255.5)*1/1))/(1*((1*$i1)-255.5)))
-// //expr 5*(sin((3.14*$i1)-255.5)/(1*((1*$i1)-255.5)))
-// //expr exp(-0.5*pow(($i1-((512-1)/2))/(0.4*((512-1)/2)),2))
-// //sinc
-// pub enum GrainEnvelope {
-//     Sine,
-//     Triangle,
-//     Gaussian,
-//     Sinc,
-// }
-
-// //https://www.youtube.com/watch?v=fJUmmcGKZMI
-// //frequency domain transform
-
-// //  pub struct FreqDom {}
-
-// //input waveform
-// //use granulizer rs
-// impl Granulize for FreqDom {}
-
-// //break the input waveform into small chuncks
-// //block based processing, tapering small chunks of audio to zero with a sign function so they start and end at the same place (sin(nt)).
-// //  imply Granulizer for FreqDom {
-
-// //imply Effect for FreqDom {}
-
-// //overlap and add
-// //Phase compensation for previous blocks - each one is shifted slightly more than previous.
-// //N = FFT length (samples), t = reference-time offset (samples), f = integer frequency index. Equation is [f] = e^(2*pi*i*f*t/N)
-
-// //output waveform
-
-// //controls - frequency
+//! granulizer.rs
+//!
+//! Module for granular synthesis: short enveloped grains drawn from a strip's live input or from a loaded sample
+
+use std::sync::Arc;
+
+use crate::audio::{
+    self,
+    plugin::{Effect, Generator},
+    Buffer, Sample, State, MAX_SAMPLE_RATE,
+};
+use crate::oscillator::WhiteNoise;
+use crate::sampler::SampleData;
+
+/// The most grains playing at once; a grain due while every one is playing is skipped
+pub const MAX_GRAINS: usize = 64;
+
+/// How much live input the granulizer keeps to draw grains from, in seconds
+pub const LIVE_SECONDS: f32 = 4.0;
+
+/// The number of zero crossings either side of the middle of the sinc envelope
+const SINC_LOBES: f32 = 2.0;
+
+/// How many frames a grain keeps clear of the ends of the live buffer, so the interpolation never reads across the write position
+const MARGIN: f64 = 4.0;
+
+/// ## GrainEnvelope
+///
+/// The shape that fades each grain in and out.
+///
+/// ### Functions
+///
+/// * `from_index(index: f32) -> Self` - Returns the envelope with an index, as set by the "envelope" parameter
+/// * `value(&self, position: f32) -> f32` - Returns the level at a position through the grain
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GrainEnvelope {
+    Sine,
+    Triangle,
+    Gaussian,
+    Sinc,
+}
+
+impl GrainEnvelope {
+    /// ## `from_index(index: f32) -> Self`
+    ///
+    /// Returns the envelope with an index, as set by the "envelope" parameter.
+    ///
+    /// ### Arguments
+    ///
+    /// * `index: f32` - 0 for sine, 1 for triangle, 2 for Gaussian, 3 for sinc
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The envelope; out of range indices are clamped
+    pub fn from_index(index: f32) -> Self {
+        match index.round().max(0.0) as u32 {
+            0 => GrainEnvelope::Sine,
+            1 => GrainEnvelope::Triangle,
+            2 => GrainEnvelope::Gaussian,
+            _ => GrainEnvelope::Sinc,
+        }
+    }
+
+    /// ## `value(&self, position: f32) -> f32`
+    ///
+    /// Returns the level at a position through the grain. The Gaussian is lowered by its value at the edges
+    /// and scaled back up to 1.0, and the sinc envelope is windowed by a sine, so both still start and end at zero.
+    ///
+    /// ### Arguments
+    ///
+    /// * `position: f32` - How far through the grain (0.0-1.0)
+    ///
+    /// ### Returns
+    ///
+    /// * `f32` - The level, 1.0 in the middle of the grain
+    pub fn value(&self, position: f32) -> f32 {
+        let centered = position * 2.0 - 1.0;
+        match self {
+            GrainEnvelope::Sine => (std::f32::consts::PI * position).sin(),
+            GrainEnvelope::Triangle => 1.0 - centered.abs(),
+            GrainEnvelope::Gaussian => {
+                let edge = (-0.5f32 / (0.4 * 0.4)).exp();
+                ((-0.5 * (centered / 0.4).powi(2)).exp() - edge) / (1.0 - edge)
+            }
+            GrainEnvelope::Sinc => {
+                let x = centered * SINC_LOBES * std::f32::consts::PI;
+                let sinc = match x == 0.0 {
+                    true => 1.0,
+                    false => x.sin() / x,
+                };
+                sinc * (std::f32::consts::PI * position).sin()
+            }
+        }
+    }
+}
+
+/// ## Source
+///
+/// Where grains are read from.
+///
+/// ### Variants
+///
+/// * `Live { channels, recorded }` - The most recent LIVE_SECONDS of the input, in a ring, and the number of frames ever written to it
+/// * `Sample(Arc<SampleData>)` - A decoded file
+enum Source {
+    Live {
+        channels: [Vec<f32>; 2],
+        recorded: u64,
+    },
+    Sample(Arc<SampleData>),
+}
+
+impl Source {
+    /// ## `live() -> Self`
+    ///
+    /// Private function which creates a silent live buffer long enough for LIVE_SECONDS at MAX_SAMPLE_RATE.
+    fn live() -> Self {
+        let length = (LIVE_SECONDS * MAX_SAMPLE_RATE) as usize;
+        Source::Live {
+            channels: [vec![0.0; length], vec![0.0; length]],
+            recorded: 0,
+        }
+    }
+
+    /// ## `frames(&self, sample_rate: u32) -> f64`
+    ///
+    /// Private function which returns how many frames of the source grains can be drawn from.
+    fn frames(&self, sample_rate: u32) -> f64 {
+        match self {
+            Source::Live { channels, .. } => {
+                (channels[0].len() as f64).min(LIVE_SECONDS as f64 * sample_rate as f64)
+            }
+            Source::Sample(sample) => sample.frames() as f64,
+        }
+    }
+
+    /// ## `rate(&self, sample_rate: u32) -> f64`
+    ///
+    /// Private function which returns how many source frames pass for every output frame at the original pitch.
+    fn rate(&self, sample_rate: u32) -> f64 {
+        match self {
+            Source::Live { .. } => 1.0,
+            Source::Sample(sample) => sample.sample_rate() as f64 / sample_rate.max(1) as f64,
+        }
+    }
+
+    /// ## `locate(&self, fraction: f64, sample_rate: u32) -> f64`
+    ///
+    /// Private function which turns a fraction of the source into a position. For live input the fraction counts
+    /// back from the newest frame, for a sample it counts on from the start.
+    fn locate(&self, fraction: f64, sample_rate: u32) -> f64 {
+        match self {
+            Source::Live { recorded, .. } => *recorded as f64 - fraction * self.frames(sample_rate),
+            Source::Sample(_) => fraction * self.frames(sample_rate),
+        }
+    }
+
+    /// ## `frame(&self, position: f64) -> (f32, f32)`
+    ///
+    /// Private function which returns the interpolated frame at a fractional position.
+    fn frame(&self, position: f64) -> (f32, f32) {
+        match self {
+            Source::Live { channels, .. } => {
+                let index = position.floor() as i64;
+                let fraction = (position - index as f64) as f32;
+
+                let mut sides = [0.0f32; 2];
+                for (side, value) in sides.iter_mut().enumerate() {
+                    let channel = &channels[side];
+                    let at = |offset: i64| {
+                        channel[(index + offset).rem_euclid(channel.len() as i64) as usize]
+                    };
+                    *value = audio::hermite(at(-1), at(0), at(1), at(2), fraction);
+                }
+
+                (sides[0], sides[1])
+            }
+            Source::Sample(sample) => sample.frame(position),
+        }
+    }
+
+    /// ## `record(&mut self, left: f32, right: f32)`
+    ///
+    /// Private function which writes the next frame of live input. Does nothing for a sample.
+    fn record(&mut self, left: f32, right: f32) {
+        match self {
+            Source::Live { channels, recorded } => {
+                let index = (*recorded % channels[0].len() as u64) as usize;
+                channels[0][index] = left;
+                channels[1][index] = right;
+                *recorded += 1;
+            }
+            Source::Sample(_) => {}
+        }
+    }
+}
+
+/// ## Grain
+///
+/// One playing grain.
+///
+/// ### Fields
+///
+/// * `active: bool` - Whether the grain is playing
+/// * `position: f64` - Where in the source the grain is reading
+/// * `step: f64` - How far the grain reads per frame; negative for a reversed grain
+/// * `age: usize` - How many frames the grain has played
+/// * `length: usize` - How many frames the grain plays for
+/// * `gains: (f32, f32)` - The gain of each channel, from the grain's level and pan
+#[derive(Clone, Copy, Debug, Default)]
+struct Grain {
+    active: bool,
+    position: f64,
+    step: f64,
+    age: usize,
+    length: usize,
+    gains: (f32, f32),
+}
+
+/// ## Granulizer
+///
+/// A granular engine. Grains are started at a steady density, each reading a short stretch of the source
+/// at a position around a playhead, which sweeps between the start and end of the source at the scan speed.
+/// Each grain is faded in and out by the envelope, pitched, maybe reversed, and panned at random within the width.
+///
+/// As an effect it draws grains from the strip's own input, which it keeps the last LIVE_SECONDS of; start and end
+/// then count back from the newest input, and freezing stops recording so the grains keep reading the same audio.
+/// As a generator it draws grains from a sample while a note is held, pitched relative to middle C.
+///
+/// ### Fields
+///
+/// * `source: Source` - Where grains are read from
+/// * `envelope: GrainEnvelope` - The shape of each grain
+/// * `start: f32` - Where the playhead's sweep starts, as a fraction of the source (0.0-1.0)
+/// * `end: f32` - Where the playhead's sweep ends, as a fraction of the source (0.0-1.0)
+/// * `scan: f32` - How fast the playhead sweeps, where 1.0 is the source's own speed and 0.0 stands still
+/// * `duration: f32` - The length of each grain, in milliseconds
+/// * `pitch: f32` - The pitch of each grain, in semitones
+/// * `density: f32` - How many grains start every second
+/// * `spray: f32` - How far a grain may start from the playhead either way, in milliseconds
+/// * `reverse: f32` - The chance of a grain playing backwards (0.0-1.0)
+/// * `width: f32` - How far grains are panned at random (0.0 centered, 1.0 anywhere)
+/// * `freeze: bool` - Whether the playhead and live recording are stopped
+/// * `mix: f32` - The level of the grains over the input, as an effect (0.0 dry, 1.0 only grains)
+/// * `gain: f32` - The gain of the grains, in dB
+/// * `grains: [Grain; MAX_GRAINS]` - The grain pool
+/// * `cursor: f64` - The playhead, as a fraction of the way from start to end
+/// * `countdown: f64` - The frames until the next grain
+/// * `random: WhiteNoise` - The random numbers for spray, reversing and panning
+/// * `held: usize` - The number of notes held, as a generator
+/// * `note: u8` - The last note played, as a generator
+/// * `velocity: f32` - The velocity of the last note played, as a generator
+///
+/// ### Parameters
+///
+/// * "start", "end", "scan", "duration", "pitch", "density", "spray", "reverse", "width", "mix", "gain" - As the fields
+/// * "envelope" - 0 for sine, 1 for triangle, 2 for Gaussian, 3 for sinc
+/// * "freeze" - 1 to freeze, 0 to thaw
+pub struct Granulizer {
+    source: Source,
+    envelope: GrainEnvelope,
+    start: f32,
+    end: f32,
+    scan: f32,
+    duration: f32,
+    pitch: f32,
+    density: f32,
+    spray: f32,
+    reverse: f32,
+    width: f32,
+    freeze: bool,
+    mix: f32,
+    gain: f32,
+    grains: [Grain; MAX_GRAINS],
+    cursor: f64,
+    countdown: f64,
+    random: WhiteNoise,
+    held: usize,
+    note: u8,
+    velocity: f32,
+}
+
+impl Granulizer {
+    /// ## `new(duration: f32, density: f32, pitch: f32) -> Self`
+    ///
+    /// Creates a granulizer effect on live input, drawing sine enveloped grains from the last second of input with
+    /// a little spray, at an even mix. The live buffer is allocated at its longest, so nothing allocates on the audio thread.
+    ///
+    /// ### Arguments
+    ///
+    /// * `duration: f32` - The length of each grain, in milliseconds
+    /// * `density: f32` - How many grains start every second
+    /// * `pitch: f32` - The pitch of each grain, in semitones
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The new granulizer
+    pub fn new(duration: f32, density: f32, pitch: f32) -> Self {
+        Self::with_source(
+            Source::live(),
+            0.0,
+            1.0 / LIVE_SECONDS,
+            0.0,
+            duration,
+            density,
+            pitch,
+        )
+    }
+
+    /// ## `from_sample(sample: Arc<SampleData>) -> Self`
+    ///
+    /// Creates a granulizer generator on a sample, sweeping through the whole file at its own speed with
+    /// 80 ms grains, 20 a second.
+    ///
+    /// ### Arguments
+    ///
+    /// * `sample: Arc<SampleData>` - The decoded file
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The new granulizer
+    pub fn from_sample(sample: Arc<SampleData>) -> Self {
+        Self::with_source(Source::Sample(sample), 0.0, 1.0, 1.0, 80.0, 20.0, 0.0)
+    }
+
+    /// ## `with_source(source: Source, start: f32, end: f32, scan: f32, duration: f32, density: f32, pitch: f32) -> Self`
+    ///
+    /// Private function which creates a granulizer on a source.
+    fn with_source(
+        source: Source,
+        start: f32,
+        end: f32,
+        scan: f32,
+        duration: f32,
+        density: f32,
+        pitch: f32,
+    ) -> Self {
+        Self {
+            source,
+            envelope: GrainEnvelope::Sine,
+            start,
+            end,
+            scan,
+            duration: duration.clamp(1.0, 1000.0),
+            pitch: pitch.clamp(-48.0, 48.0),
+            density: density.clamp(0.5, 1000.0),
+            spray: 10.0,
+            reverse: 0.0,
+            width: 0.5,
+            freeze: false,
+            mix: 0.5,
+            gain: 0.0,
+            grains: [Grain::default(); MAX_GRAINS],
+            cursor: 0.0,
+            countdown: 0.0,
+            random: WhiteNoise::new(),
+            held: 0,
+            note: 60,
+            velocity: 1.0,
+        }
+    }
+
+    /// ## `spawn(&mut self, sample_rate: u32, pitch: f32, level: f32)`
+    ///
+    /// Private function which starts a grain at the playhead, if one is free. A grain on live input is kept
+    /// within the recorded audio for its whole length, so it never reads across the write position.
+    fn spawn(&mut self, sample_rate: u32, pitch: f32, level: f32) {
+        let slot = match self.grains.iter().position(|grain| !grain.active) {
+            Some(slot) => slot,
+            None => return,
+        };
+
+        let rate = self.source.rate(sample_rate);
+        let length = (self.duration / 1000.0 * sample_rate as f32).max(1.0) as usize;
+        let ratio = 2.0f64.powf(pitch as f64 / 12.0) * rate;
+        let span = ratio * length as f64;
+
+        let fraction = self.start as f64 + (self.end - self.start) as f64 * self.cursor;
+        let spray = self.spray as f64 / 1000.0 * sample_rate as f64 * rate;
+        let mut low =
+            self.source.locate(fraction, sample_rate) + spray * self.random.sample() as f64;
+
+        match self.source {
+            Source::Live { recorded, .. } => {
+                let advance = match self.freeze {
+                    true => 0.0,
+                    false => length as f64,
+                };
+                let newest = recorded as f64 - MARGIN - span;
+                let oldest = recorded as f64 + advance - self.source.frames(sample_rate) + MARGIN;
+                if newest < oldest {
+                    return;
+                }
+                low = low.clamp(oldest, newest);
+            }
+            Source::Sample(_) => {}
+        }
+
+        let reversed = (self.random.sample() * 0.5 + 0.5) < self.reverse;
+        let pan = self.width * self.random.sample();
+        let angle = (pan + 1.0) * std::f32::consts::FRAC_PI_4;
+        let level = level * std::f32::consts::SQRT_2;
+
+        self.grains[slot] = Grain {
+            active: true,
+            position: match reversed {
+                true => low + span,
+                false => low,
+            },
+            step: match reversed {
+                true => -ratio,
+                false => ratio,
+            },
+            age: 0,
+            length,
+            gains: (angle.cos() * level, angle.sin() * level),
+        };
+    }
+
+    /// ## `advance(&mut self, state: &State, spawning: bool, pitch: f32, level: f32) -> (f32, f32)`
+    ///
+    /// Private function which starts any grain due, moves the playhead, and returns the next frame of every playing grain.
+    fn advance(&mut self, state: &State, spawning: bool, pitch: f32, level: f32) -> (f32, f32) {
+        if spawning {
+            self.countdown -= 1.0;
+            if self.countdown <= 0.0 {
+                self.spawn(state.sample_rate, pitch, level);
+                self.countdown =
+                    (self.countdown + state.sample_rate as f64 / self.density as f64).max(0.0);
+            }
+        }
+
+        if !self.freeze {
+            let region =
+                (self.end - self.start).abs() as f64 * self.source.frames(state.sample_rate);
+            if region >= 1.0 {
+                let step = self.scan as f64 * self.source.rate(state.sample_rate) / region;
+                self.cursor = (self.cursor + step).rem_euclid(1.0);
+            }
+        }
+
+        let mut output = (0.0, 0.0);
+        for grain in self.grains.iter_mut().filter(|grain| grain.active) {
+            let envelope = self.envelope.value(grain.age as f32 / grain.length as f32);
+            let (left, right) = self.source.frame(grain.position);
+            output.0 += left * envelope * grain.gains.0;
+            output.1 += right * envelope * grain.gains.1;
+
+            grain.position += grain.step;
+            grain.age += 1;
+            if grain.age >= grain.length {
+                grain.active = false;
+            }
+        }
+        output
+    }
+
+    /// ## `level(&self) -> f32`
+    ///
+    /// Private function which returns the output gain, lowered as grains overlap so the density doesn't change the loudness much.
+    fn level(&self) -> f32 {
+        let overlap = (self.density * self.duration / 1000.0).max(1.0);
+        audio::db_to_gain(self.gain) / overlap.sqrt()
+    }
+}
+
+impl Effect for Granulizer {
+    fn process(&mut self, state: &State, buffer: &mut Buffer) {
+        let level = self.level();
+        let pitch = self.pitch;
+
+        for index in 0..buffer.len() {
+            let dry = buffer.frame(index);
+            if !self.freeze {
+                self.source.record(dry.left(), dry.right());
+            }
+
+            let (left, right) = self.advance(state, true, pitch, 1.0);
+            buffer.set_frame(
+                index,
+                Sample::Stereo(
+                    dry.left() * (1.0 - self.mix) + left * level * self.mix,
+                    dry.right() * (1.0 - self.mix) + right * level * self.mix,
+                ),
+            );
+        }
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f32) {
+        Generator::set_parameter(self, name, value);
+    }
+}
+
+impl Generator for Granulizer {
+    fn generate(&mut self, state: &State, buffer: &mut Buffer) {
+        buffer.clear();
+
+        let level = self.level();
+        let pitch = self.pitch + self.note as f32 - 60.0;
+        let velocity = self.velocity;
+        let spawning = self.held > 0;
+
+        let (left, right) = buffer.stereo_mut();
+        for (left, right) in left.iter_mut().zip(right.iter_mut()) {
+            let frame = self.advance(state, spawning, pitch, velocity);
+            *left = frame.0 * level;
+            *right = frame.1 * level;
+        }
+    }
+
+    fn note_on(&mut self, note: u8, velocity: f32) {
+        if self.held == 0 {
+            self.countdown = 0.0;
+        }
+        self.held += 1;
+        self.note = note;
+        self.velocity = velocity;
+    }
+
+    fn note_off(&mut self, _note: u8) {
+        self.held = self.held.saturating_sub(1);
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f32) {
+        match name {
+            "start" => self.start = value.clamp(0.0, 1.0),
+            "end" => self.end = value.clamp(0.0, 1.0),
+            "scan" => self.scan = value.clamp(-4.0, 4.0),
+            "duration" => self.duration = value.clamp(1.0, 1000.0),
+            "pitch" => self.pitch = value.clamp(-48.0, 48.0),
+            "density" => self.density = value.clamp(0.5, 1000.0),
+            "spray" => self.spray = value.clamp(0.0, 1000.0),
+            "reverse" => self.reverse = value.clamp(0.0, 1.0),
+            "width" => self.width = value.clamp(0.0, 1.0),
+            "envelope" => self.envelope = GrainEnvelope::from_index(value),
+            "freeze" => self.freeze = value >= 0.5,
+            "mix" => self.mix = value.clamp(0.0, 1.0),
+            "gain" => self.gain = value.clamp(-60.0, 24.0),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// ## `run(granulizer: &mut Granulizer, input: impl Fn(usize) -> f32, frames: usize) -> Vec<f32>`
+    ///
+    /// Runs an input into both channels of a granulizer effect at 48 kHz in blocks of 64 frames,
+    /// and returns the left channel.
+    fn run(granulizer: &mut Granulizer, input: impl Fn(usize) -> f32, frames: usize) -> Vec<f32> {
+        let mut clock = 0;
+        let mut output = Vec::with_capacity(frames);
+        while output.len() < frames {
+            let len = (frames - output.len()).min(64);
+            let state = State::advance(48000, &mut clock, len);
+            let mut buffer = Buffer::new(2, len);
+            for channel in 0..2 {
+                for (index, sample) in buffer.channel_mut(channel).iter_mut().enumerate() {
+                    *sample = input(output.len() + index);
+                }
+            }
+            Effect::process(granulizer, &state, &mut buffer);
+            output.extend_from_slice(buffer.channel(0));
+        }
+        output
+    }
+
+    /// ## `recorded(granulizer: &Granulizer) -> u64`
+    ///
+    /// Returns the number of frames of live input a granulizer has recorded.
+    fn recorded(granulizer: &Granulizer) -> u64 {
+        match &granulizer.source {
+            Source::Live { recorded, .. } => *recorded,
+            Source::Sample(_) => 0,
+        }
+    }
+
+    #[test]
+    fn freezing_holds_the_live_buffer() {
+        let mut granulizer = Granulizer::new(50.0, 40.0, 0.0);
+        Generator::set_parameter(&mut granulizer, "mix", 1.0);
+        run(&mut granulizer, |_| 0.5, 48000);
+        assert_eq!(recorded(&granulizer), 48000);
+
+        // frozen, the silence coming in isn't recorded and the grains keep playing the held second
+        Generator::set_parameter(&mut granulizer, "freeze", 1.0);
+        let output = run(&mut granulizer, |_| 0.0, 96000);
+        assert_eq!(recorded(&granulizer), 48000);
+        match &granulizer.source {
+            Source::Live { channels, .. } => {
+                assert!(channels[0][..48000].iter().all(|sample| *sample == 0.5))
+            }
+            Source::Sample(_) => unreachable!(),
+        }
+        let level = output[48000..]
+            .iter()
+            .map(|sample| sample.abs())
+            .sum::<f32>()
+            / 48000.0;
+        assert!(level > 0.1, "{}", level);
+
+        // thawed, the silence is recorded over it and the grains die away
+        Generator::set_parameter(&mut granulizer, "freeze", 0.0);
+        let output = run(&mut granulizer, |_| 0.0, 96000);
+        assert_eq!(recorded(&granulizer), 144000);
+        assert!(output[72000..].iter().all(|sample| *sample == 0.0));
+    }
+
+    #[test]
+    fn reversed_grains_read_backwards() {
+        // half a second of a rising ramp of live input (long enough for one grain, which has finished),
+        // held still so both grains start at the same place
+        let mut granulizer = Granulizer::new(10.0, 1.0, 0.0);
+        Generator::set_parameter(&mut granulizer, "spray", 0.0);
+        Generator::set_parameter(&mut granulizer, "width", 0.0);
+        run(&mut granulizer, |frame| frame as f32 / 24000.0, 24000);
+        Generator::set_parameter(&mut granulizer, "freeze", 1.0);
+
+        let mut clock = 0;
+        let state = State::advance(48000, &mut clock, 1);
+        let mut grains = Vec::new();
+        for reverse in [0.0, 1.0] {
+            Generator::set_parameter(&mut granulizer, "reverse", reverse);
+            granulizer.spawn(48000, 0.0, 1.0);
+            let grain = granulizer.grains.iter().find(|grain| grain.active).unwrap();
+            assert_eq!(grain.step, 1.0 - reverse as f64 * 2.0);
+            let output: Vec<f32> = (0..480)
+                .map(|_| granulizer.advance(&state, false, 0.0, 1.0).0)
+                .collect();
+            grains.push(output);
+        }
+
+        // the envelope is symmetrical, so the reversed grain is the forward one backwards
+        let (forward, reversed) = (&grains[0], &grains[1]);
+        for frame in 1..480 {
+            assert!(
+                (reversed[frame] - forward[480 - frame]).abs() < 1e-5,
+                "frame {}: {} and {}",
+                frame,
+                reversed[frame],
+                forward[480 - frame]
+            );
+        }
+        assert!(reversed[120] > reversed[360] && forward[120] < forward[360]);
+    }
+}
@@ -2,16 +2,30 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 mod audio;
+mod clock;
 mod config;
+mod drums;
+mod dynamics;
+mod eq;
+mod filter;
 mod granulizer;
 mod interface;
+mod meter;
 mod midi;
+mod modulation;
+mod oscillator;
+mod pitch;
+mod reverb;
+mod sampler;
+mod sequencer;
+mod synth;
+mod transport;
 mod tv;
 
-use audio::{plugin::SineGenerator, Preference};
+use audio::Preference;
 use cpal::traits::DeviceTrait;
 use lazy_static::lazy_static;
-use log::{debug, error, LevelFilter};
+use log::{debug, error, warn, LevelFilter};
 use std::{
     path::{Path, PathBuf},
     sync::{Arc, Mutex},
@@ -20,24 +34,42 @@ use tauri::{api::path::BaseDirectory, LogicalPosition, Manager};
 use tauri_plugin_log::{fern::colors::ColoredLevelConfig, LogTarget};
 
 use crate::interface::Key;
+use crate::tv::{BasicVisualizer, VisualizerTrait};
 
 // Apply to Windows only
 #[cfg(target_os = "windows")]
 static CONFIG_FILE: &str = "public_win/config.json";
 #[cfg(target_os = "windows")]
 static CONFIG_ROOT: &str = "public_win/config/";
+#[cfg(target_os = "windows")]
+static KIT_ROOT: &str = "public_win/kits/";
+#[cfg(target_os = "windows")]
+static PATTERN_ROOT: &str = "public_win/config/patterns/";
 
 // Apply to any non-Windows platform
 #[cfg(not(target_os = "windows"))]
 static CONFIG_FILE: &str = "public/config.json";
 #[cfg(not(target_os = "windows"))]
 static CONFIG_ROOT: &str = "public/config/";
+#[cfg(not(target_os = "windows"))]
+static KIT_ROOT: &str = "public/kits/";
+#[cfg(not(target_os = "windows"))]
+static PATTERN_ROOT: &str = "public/config/patterns/";
+
+// Makes sure the meter thread is only started once, however often `run` is called
+static METER_THREAD: std::sync::Once = std::sync::Once::new();
 
 // The current configuration
 lazy_static! {
     static ref CONFIG: Mutex<config::Config> = Mutex::new(config::Config::empty());
     static ref CONSOLE_WINDOW: Mutex<Option<tauri::Window>> = Mutex::new(None);
     static ref TV_WINDOW: Mutex<Option<tauri::Window>> = Mutex::new(None);
+    // The strips playing notes from HID keys
+    static ref HID_STRIPS: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+    // The HID devices already given a strip and key handlers, by id
+    static ref HID_DEVICES: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+    // HID keys bound to a note on a strip (key name, strip, note), e.g. drum pads
+    static ref HID_KEYS: Mutex<Vec<(String, usize, u8)>> = Mutex::new(Vec::new());
 }
 
 /// ## MessageKind
@@ -165,10 +197,68 @@ async fn run(window: tauri::Window) -> String {
         }
     };
 
+    METER_THREAD.call_once(meter_thread);
+
     debug!("{}", result);
     result
 }
 
+/// ## `meter_thread()`
+///
+/// Starts a thread that emits the levels of every strip and the master bus to the console window
+/// as the "meters" event, and the latest output from `tv::SCOPE` to the TV window, `meter::METER_RATE` times per second.
+/// Rendering here keeps the window out of the audio callback. It also warns when captured input has been dropped.
+fn meter_thread() {
+    std::thread::spawn(|| {
+        let mut overruns = 0;
+        loop {
+            std::thread::sleep(std::time::Duration::from_millis(1000 / meter::METER_RATE));
+
+            let total = audio::CAPTURE_OVERRUNS.load(std::sync::atomic::Ordering::Relaxed);
+            if total > overruns {
+                warn!(
+                    "Dropped {} blocks of captured input; the output stream is not keeping up",
+                    total - overruns
+                );
+                overruns = total;
+            }
+
+            let meters = match audio::CONTROL.lock() {
+                Ok(control) => control.meters(),
+                Err(e) => {
+                    debug!("Error locking CONTROL: {}", e);
+                    continue;
+                }
+            };
+
+            match CONSOLE_WINDOW.lock() {
+                Ok(console_window) => match console_window.as_ref() {
+                    Some(console_window) => {
+                        let _ = console_window.emit("meters", meters);
+                    }
+                    None => {}
+                },
+                Err(e) => {
+                    debug!("Error locking CONSOLE_WINDOW: {}", e);
+                }
+            }
+
+            match TV_WINDOW.lock() {
+                Ok(tv_window) => match tv_window.as_ref() {
+                    Some(tv_window) => {
+                        let visualizer = BasicVisualizer::new();
+                        let _ = visualizer.render(tv_window, &tv::SCOPE.read());
+                    }
+                    None => {}
+                },
+                Err(e) => {
+                    debug!("Error locking TV_WINDOW: {}", e);
+                }
+            }
+        }
+    });
+}
+
 /// ## `init(_window: tauri::Window) -> Result<(), String>`
 ///
 /// Initializes the program.
@@ -183,8 +273,6 @@ async fn run(window: tauri::Window) -> String {
 fn init(window: tauri::Window) -> Result<(), String> {
     debug!("Initializing Tauri");
 
-    let strips = audio::STRIPS.try_write();
-
     // let new_strip = audio::Strip::new(
     // 	audio::Input::Generator(Box::new(|sample_clock: &f32, sample_rate: &f32| -> f32 {
     // 		(sample_clock * 440.0 * 2.0 * std::f32::consts::PI / sample_rate).sin()
@@ -201,9 +289,13 @@ fn init(window: tauri::Window) -> Result<(), String> {
     // 	}
     // }
 
-    let midi_generator = audio::plugin::ClosureGenerator::new(Box::new(midi::callback));
+    let midi_generator = synth::Synth::new(
+        oscillator::create_oscillator("sine")?,
+        8,
+        synth::VoiceMode::Poly,
+    );
     let mut midi_strip = audio::Strip::new(
-        audio::Input::Generator(Arc::new(Mutex::new(midi_generator))),
+        audio::Input::Generator(Box::new(midi_generator)),
         audio::Output::Stereo(0, 1),
     );
 
@@ -214,16 +306,22 @@ fn init(window: tauri::Window) -> Result<(), String> {
     // )));
     midi_strip.add_effect(Box::new(audio::plugin::LofiDelay::new(500, 0.5, 10)));
 
-    // let mut granulizer = granulizer::Granulizer::new();
-    // granulizer.resize_milliseconds(1000, state.sample_rate);
-    // midi_strip.add_effect(Box::new(granulizer));
+    // midi_strip.add_effect(Box::new(granulizer::Granulizer::new(80.0, 20.0, 0.0)));
 
-    match strips {
-        Ok(mut strips) => {
-            strips.push(midi_strip);
-        }
+    match audio::CONTROL.lock() {
+        Ok(mut control) => match control.add_strip(midi_strip) {
+            Ok(index) => match midi::subscribe(index, None) {
+                Ok(()) => {}
+                Err(e) => {
+                    debug!("Error subscribing midi strip: {}", e);
+                }
+            },
+            Err(e) => {
+                debug!("Error adding midi strip: {}", e);
+            }
+        },
         Err(e) => {
-            debug!("Error locking STRIPS: {}", e);
+            debug!("Error locking CONTROL: {}", e);
         }
     }
 
@@ -424,6 +522,18 @@ async fn host_list(_window: tauri::Window) -> ConsoleMessage {
 /// * `ConsoleMessage` - The result of the command
 #[tauri::command]
 async fn host_select(_window: tauri::Window, host: String) -> ConsoleMessage {
+    // headless hosts are set up from the config, in on_config_update
+    match audio::Headless::from_host(&host, "default") {
+        Some(headless) => {
+            let _ = set_global_config_value("audio.host", headless.name(), true);
+            return ConsoleMessage {
+                kind: MessageKind::Console,
+                message: vec![format!("Selected host {}", headless.name())],
+            };
+        }
+        None => {}
+    }
+
     let host = audio::get_host(&host);
     let host_name = &host.id().name();
 
@@ -458,6 +568,16 @@ async fn host_select(_window: tauri::Window, host: String) -> ConsoleMessage {
 /// * `ConsoleMessage` - The result of the command
 #[tauri::command]
 async fn output_list(_window: tauri::Window) -> ConsoleMessage {
+    match headless() {
+        Some(headless) => {
+            return ConsoleMessage {
+                kind: MessageKind::Console,
+                message: vec![headless.device()],
+            };
+        }
+        None => {}
+    }
+
     let host = audio::HOST.lock();
 
     let host = match host {
@@ -499,9 +619,38 @@ async fn output_list(_window: tauri::Window) -> ConsoleMessage {
 /// * `ConsoleMessage` - The result of the command
 #[tauri::command]
 async fn output_select(_window: tauri::Window, output: String) -> ConsoleMessage {
-    let host = audio::HOST.lock();
     let device_name = output.as_str();
 
+    // the file host writes to whatever .wav file is selected, the null host has nothing to select
+    match headless() {
+        Some(audio::Headless::File(_path)) => {
+            if !device_name.to_lowercase().ends_with(".wav") {
+                return ConsoleMessage {
+                    kind: MessageKind::Error,
+                    message: vec![format!(
+                        "The File host writes to .wav files, got {}",
+                        device_name
+                    )],
+                };
+            }
+
+            let _ = set_global_config_value("audio.output.device", device_name, true);
+            return ConsoleMessage {
+                kind: MessageKind::Console,
+                message: vec![format!("Writing output to {}", device_name)],
+            };
+        }
+        Some(audio::Headless::Null) => {
+            return ConsoleMessage {
+                kind: MessageKind::Console,
+                message: vec![format!("The Null host discards all output")],
+            };
+        }
+        None => {}
+    }
+
+    let host = audio::HOST.lock();
+
     let host = match host {
         Ok(host) => host,
         Err(e) => {
@@ -558,6 +707,19 @@ async fn output_select(_window: tauri::Window, output: String) -> ConsoleMessage
 /// * `ConsoleMessage` - The result of the command
 #[tauri::command]
 async fn output_stream_show(_window: tauri::Window) -> ConsoleMessage {
+    match headless() {
+        Some(headless) => {
+            return ConsoleMessage {
+                kind: MessageKind::Console,
+                message: vec![format!(
+                    "The {} host accepts any number of channels, sample rate and buffer size",
+                    headless.name()
+                )],
+            };
+        }
+        None => {}
+    }
+
     let output_device = audio::OUTPUT_DEVICE.lock();
     let output_device = match output_device {
         Ok(output_device) => output_device,
@@ -620,35 +782,40 @@ async fn output_stream_set(
     samples: u32,
     buffer_size: u32,
 ) -> ConsoleMessage {
-    let output_device = audio::OUTPUT_DEVICE.lock();
-    let output_device = match output_device {
-        Ok(output_device) => output_device,
-        Err(e) => {
-            debug!("Error locking OUTPUT_DEVICE: {}", e);
-            return ConsoleMessage {
-                kind: MessageKind::Error,
-                message: vec![format!("Error locking OUTPUT_DEVICE: {}", e)],
+    let config = match headless() {
+        Some(_headless) => Some(audio::headless_config(channels, samples, buffer_size)),
+        None => {
+            let output_device = audio::OUTPUT_DEVICE.lock();
+            let output_device = match output_device {
+                Ok(output_device) => output_device,
+                Err(e) => {
+                    debug!("Error locking OUTPUT_DEVICE: {}", e);
+                    return ConsoleMessage {
+                        kind: MessageKind::Error,
+                        message: vec![format!("Error locking OUTPUT_DEVICE: {}", e)],
+                    };
+                }
             };
-        }
-    };
 
-    let output_device = match output_device.as_ref() {
-        Some(output_device) => output_device,
-        None => {
-            return ConsoleMessage {
-                kind: MessageKind::Error,
-                message: vec![format!("No output device selected")],
+            let output_device = match output_device.as_ref() {
+                Some(output_device) => output_device,
+                None => {
+                    return ConsoleMessage {
+                        kind: MessageKind::Error,
+                        message: vec![format!("No output device selected")],
+                    };
+                }
             };
+
+            audio::get_output_config(
+                &output_device,
+                Preference::Exact(channels as u32, audio::PreferenceAlt::Higher),
+                Preference::Exact(samples as u32, audio::PreferenceAlt::Higher),
+                Preference::Exact(buffer_size as u32, audio::PreferenceAlt::Higher),
+            )
         }
     };
 
-    let config = audio::get_output_config(
-        &output_device,
-        Preference::Exact(channels as u32, audio::PreferenceAlt::Higher),
-        Preference::Exact(samples as u32, audio::PreferenceAlt::Higher),
-        Preference::Exact(buffer_size as u32, audio::PreferenceAlt::Higher),
-    );
-
     let result = match &config {
         Some(config) => (
             config.channels as i64,
@@ -714,6 +881,16 @@ async fn output_stream_set(
 /// * `ConsoleMessage` - The result of the command
 #[tauri::command]
 async fn input_list(_window: tauri::Window) -> ConsoleMessage {
+    match headless() {
+        Some(headless) => {
+            return ConsoleMessage {
+                kind: MessageKind::Error,
+                message: vec![format!("The {} host has no input devices", headless.name())],
+            };
+        }
+        None => {}
+    }
+
     let host = audio::HOST.lock();
 
     let host = match host {
@@ -755,6 +932,16 @@ async fn input_list(_window: tauri::Window) -> ConsoleMessage {
 /// * `ConsoleMessage` - The result of the command
 #[tauri::command]
 async fn input_select(_window: tauri::Window, input: String) -> ConsoleMessage {
+    match headless() {
+        Some(headless) => {
+            return ConsoleMessage {
+                kind: MessageKind::Error,
+                message: vec![format!("The {} host has no input devices", headless.name())],
+            };
+        }
+        None => {}
+    }
+
     let host = audio::HOST.lock();
     let device_name = input.as_str();
 
@@ -961,87 +1148,2233 @@ async fn input_stream_set(
     }
 }
 
-/// ## `set_global_config_value(key: &str, value: &str) -> Result<(), String>`
+/// ## `input_strip(_window: tauri::Window, input: String, output: String) -> ConsoleMessage`
 ///
-/// Sets a value in the global config.
+/// Adds a strip that plays live audio from the input device, or from a bus.
 ///
 /// ### Arguments
 ///
-/// * `key: &str` - The key to set
-/// * `value: &str` - The value to set
+/// * `input: String` - The input channel, a left and right input channel separated by a comma (e.g. "0" or "0,1"), or a bus (e.g. "bus:drums")
+/// * `output: String` - The output channel, a left and right output channel separated by a comma, or a bus
 ///
 /// ### Returns
 ///
-/// * `Ok(())` - The result of the command
-/// * `Err(String)` - The result of the command
-fn set_global_config_value(key: &str, value: &str, update: bool) -> Result<(), String> {
-    let config = CONFIG.lock();
-    let mut config = match config {
-        Ok(config) => config,
+/// * `ConsoleMessage` - The result of the command
+#[tauri::command]
+async fn input_strip(_window: tauri::Window, input: String, output: String) -> ConsoleMessage {
+    let strip_input = match parse_input(&input) {
+        Ok(strip_input) => strip_input,
         Err(e) => {
-            error!("Error locking CONFIG: {}", e);
-            return Err(format!("Error locking CONFIG: {}", e));
+            return ConsoleMessage {
+                kind: MessageKind::Error,
+                message: vec![format!("Invalid input {}: {}", input, e)],
+            };
         }
     };
 
-    if update {
-        config.set_str(key, value);
-    } else {
-        config.set_str_no_update(key, value);
+    let strip_output = match parse_output(&output) {
+        Ok(strip_output) => strip_output,
+        Err(e) => {
+            return ConsoleMessage {
+                kind: MessageKind::Error,
+                message: vec![format!("Invalid output {}: {}", output, e)],
+            };
+        }
+    };
+
+    match audio::CONTROL.lock() {
+        Ok(mut control) => match control.add_strip(audio::Strip::new(strip_input, strip_output)) {
+            Ok(index) => ConsoleMessage {
+                kind: MessageKind::Console,
+                message: vec![format!("Routed {} to {} (strip {})", input, output, index)],
+            },
+            Err(e) => ConsoleMessage {
+                kind: MessageKind::Error,
+                message: vec![e],
+            },
+        },
+        Err(e) => {
+            debug!("Error locking CONTROL: {}", e);
+            ConsoleMessage {
+                kind: MessageKind::Error,
+                message: vec![format!("Error locking CONTROL: {}", e)],
+            }
+        }
     }
+}
 
-    Ok(())
+/// ## `strip_list(_window: tauri::Window) -> ConsoleMessage`
+///
+/// Lists every strip along with where it reads from and outputs to.
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The result of the command
+#[tauri::command]
+async fn strip_list(_window: tauri::Window) -> ConsoleMessage {
+    match audio::CONTROL.lock() {
+        Ok(control) => {
+            let mut message = Vec::new();
+            for (index, route) in control.routes().iter().enumerate() {
+                message.push(format!(
+                    "{}: {} -> {} [{}]",
+                    index, route.input, route.output, route.mixer
+                ));
+            }
+            if message.is_empty() {
+                message.push("No strips".to_string());
+            }
+            match control.mixer(audio::Target::Master) {
+                Ok(mixer) => message.push(format!("master: [{}]", mixer)),
+                Err(e) => message.push(e),
+            }
+            ConsoleMessage {
+                kind: MessageKind::Console,
+                message,
+            }
+        }
+        Err(e) => ConsoleMessage {
+            kind: MessageKind::Error,
+            message: vec![format!("Error locking CONTROL: {}", e)],
+        },
+    }
 }
 
-fn global_config_force_update() {
-    let config = CONFIG.lock();
-    let mut config = match config {
-        Ok(config) => config,
+/// ## `strip_output(_window: tauri::Window, index: usize, output: String) -> ConsoleMessage`
+///
+/// Changes where a strip outputs to. Refused if the new routing contains a cycle.
+///
+/// ### Arguments
+///
+/// * `index: usize` - The index of the strip, as shown by `strip list`
+/// * `output: String` - The output channel(s) or bus
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The result of the command
+#[tauri::command]
+async fn strip_output(_window: tauri::Window, index: usize, output: String) -> ConsoleMessage {
+    let strip_output = match parse_output(&output) {
+        Ok(strip_output) => strip_output,
         Err(e) => {
-            debug!("Error locking CONFIG: {}", e);
-            return;
+            return ConsoleMessage {
+                kind: MessageKind::Error,
+                message: vec![format!("Invalid output {}: {}", output, e)],
+            };
         }
     };
 
-    config.force_update();
+    match audio::CONTROL.lock() {
+        Ok(mut control) => match control.set_output(index, strip_output) {
+            Ok(()) => ConsoleMessage {
+                kind: MessageKind::Console,
+                message: vec![format!("Strip {} now outputs to {}", index, output)],
+            },
+            Err(e) => ConsoleMessage {
+                kind: MessageKind::Error,
+                message: vec![e],
+            },
+        },
+        Err(e) => ConsoleMessage {
+            kind: MessageKind::Error,
+            message: vec![format!("Error locking CONTROL: {}", e)],
+        },
+    }
 }
 
+/// ## `strip_parameter(_window: tauri::Window, strip: String, effect: String, name: String, value: f32) -> ConsoleMessage`
+///
+/// Sets a parameter of an effect in a strip's or the master bus' chain, or of a strip's generator.
+/// The change is applied by the audio thread at the start of the next buffer.
+///
+/// ### Arguments
+///
+/// * `strip: String` - The index of the strip, as shown by `strip list`, or "master"
+/// * `effect: String` - The index of the effect in the strip's chain, or "gen" for the strip's generator
+/// * `name: String` - The name of the parameter (e.g. "feedback")
+/// * `value: f32` - The new value
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The result of the command
 #[tauri::command]
-async fn exit() -> ConsoleMessage {
-    // only exit if the config is saved
-    let config = CONFIG.lock();
-    let config = match config {
-        Ok(config) => config,
+async fn strip_parameter(
+    _window: tauri::Window,
+    strip: String,
+    effect: String,
+    name: String,
+    value: f32,
+) -> ConsoleMessage {
+    let target = match parse_target(&strip) {
+        Ok(target) => target,
         Err(e) => {
-            debug!("Error locking CONFIG: {}", e);
             return ConsoleMessage {
                 kind: MessageKind::Error,
-                message: vec![format!("Error locking CONFIG: {}", e)],
+                message: vec![e],
             };
         }
     };
 
-    if config.state() == &config::State::Saved {
-        std::process::exit(0);
-    }
+    let result = parse_effect(&effect).and_then(|effect| match audio::CONTROL.lock() {
+        Ok(mut control) => match (effect, target) {
+            (Some(effect), target) => {
+                control
+                    .set_parameter(target, effect, &name, value)
+                    .map(|()| {
+                        format!(
+                            "Set {} of effect {} on {} to {}",
+                            name, effect, target, value
+                        )
+                    })
+            }
+            (None, audio::Target::Strip(strip)) => control
+                .set_generator_parameter(strip, &name, value)
+                .map(|()| format!("Set {} of the generator on {} to {}", name, target, value)),
+            (None, audio::Target::Master) => Err("The master bus has no generator".to_owned()),
+        },
+        Err(e) => Err(format!("Error locking CONTROL: {}", e)),
+    });
 
-    // otherwise, prompt the user to save
-    return ConsoleMessage {
-        kind: MessageKind::Console,
-        message: vec![format!("Unsaved changes. Save before exiting?")],
-    };
+    match result {
+        Ok(message) => ConsoleMessage {
+            kind: MessageKind::Console,
+            message: vec![message],
+        },
+        Err(e) => ConsoleMessage {
+            kind: MessageKind::Error,
+            message: vec![e],
+        },
+    }
 }
 
+/// ## `strip_effect(_window: tauri::Window, strip: String, name: String, args: Vec<f32>) -> ConsoleMessage`
+///
+/// Adds an effect to the end of a strip's or the master bus' chain.
+///
+/// ### Arguments
+///
+/// * `strip: String` - The index of the strip, as shown by `strip list`, or "master"
+/// * `name: String` - The name of the effect (e.g. "delay")
+/// * `args: Vec<f32>` - The arguments of the effect
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The result of the command
 #[tauri::command]
-async fn confirm_exit() -> ConsoleMessage {
-    // exit program
-    std::process::exit(0);
+async fn strip_effect(
+    _window: tauri::Window,
+    strip: String,
+    name: String,
+    args: Vec<f32>,
+) -> ConsoleMessage {
+    let result = parse_target(&strip).and_then(|target| {
+        let effect = audio::plugin::create_effect(&name, &args)?;
+        match audio::CONTROL.lock() {
+            Ok(mut control) => control
+                .add_effect(target, effect)
+                .map(|index| (target, index)),
+            Err(e) => Err(format!("Error locking CONTROL: {}", e)),
+        }
+    });
+
+    match result {
+        Ok((target, index)) => ConsoleMessage {
+            kind: MessageKind::Console,
+            message: vec![format!("Added {} to {} as effect {}", name, target, index)],
+        },
+        Err(e) => ConsoleMessage {
+            kind: MessageKind::Error,
+            message: vec![e],
+        },
+    }
 }
 
-#[tauri::command]
-async fn midi_list(_window: tauri::Window) -> ConsoleMessage {
-    // call midi.rs function
-    debug!("Calling midi::midi_list()");
+/// ## `strip_gain(_window: tauri::Window, strip: String, gain: f32) -> ConsoleMessage`
+///
+/// Sets the gain of a strip or the master bus.
+///
+/// ### Arguments
+///
+/// * `strip: String` - The index of the strip, as shown by `strip list`, or "master"
+/// * `gain: f32` - The gain in dB
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The result of the command
+#[tauri::command]
+async fn strip_gain(_window: tauri::Window, strip: String, gain: f32) -> ConsoleMessage {
+    console_result(change_mixer(&strip, |mixer| {
+        mixer.gain = gain;
+        Ok(())
+    }))
+}
+
+/// ## `strip_pan(_window: tauri::Window, strip: String, pan: f32, law: Option<String>) -> ConsoleMessage`
+///
+/// Pans a strip or the master bus, optionally changing its pan law.
+///
+/// ### Arguments
+///
+/// * `strip: String` - The index of the strip, as shown by `strip list`, or "master"
+/// * `pan: f32` - The pan position, from -1.0 (left) to 1.0 (right)
+/// * `law: Option<String>` - The pan law: "balance", "constant", "compromise" or "linear" (or "0", "-3", "-4.5", "-6")
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The result of the command
+#[tauri::command]
+async fn strip_pan(
+    _window: tauri::Window,
+    strip: String,
+    pan: f32,
+    law: Option<String>,
+) -> ConsoleMessage {
+    console_result(change_mixer(&strip, |mixer| {
+        mixer.pan = pan.clamp(-1.0, 1.0);
+        match law.as_ref() {
+            Some(law) => match audio::PanLaw::from_name(law) {
+                Some(law) => mixer.pan_law = law,
+                None => {
+                    return Err(format!(
+                        "Unknown pan law {} (expected balance, constant, compromise or linear)",
+                        law
+                    ))
+                }
+            },
+            None => {}
+        }
+        Ok(())
+    }))
+}
+
+/// ## `strip_mute(_window: tauri::Window, strip: String, state: Option<String>) -> ConsoleMessage`
+///
+/// Mutes or unmutes a strip or the master bus.
+///
+/// ### Arguments
+///
+/// * `strip: String` - The index of the strip, as shown by `strip list`, or "master"
+/// * `state: Option<String>` - "on" or "off", toggles if not given
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The result of the command
+#[tauri::command]
+async fn strip_mute(
+    _window: tauri::Window,
+    strip: String,
+    state: Option<String>,
+) -> ConsoleMessage {
+    console_result(change_mixer(&strip, |mixer| {
+        mixer.mute = parse_switch(state.as_deref(), mixer.mute)?;
+        Ok(())
+    }))
+}
+
+/// ## `strip_solo(_window: tauri::Window, strip: String, state: Option<String>) -> ConsoleMessage`
+///
+/// Solos or unsolos a strip. While any strip is soloed, strips that are not soloed and output to the device are silent.
+/// The master bus can't be soloed.
+///
+/// ### Arguments
+///
+/// * `strip: String` - The index of the strip, as shown by `strip list`, or "master"
+/// * `state: Option<String>` - "on" or "off", toggles if not given
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The result of the command
+#[tauri::command]
+async fn strip_solo(
+    _window: tauri::Window,
+    strip: String,
+    state: Option<String>,
+) -> ConsoleMessage {
+    console_result(change_mixer(&strip, |mixer| {
+        mixer.solo = parse_switch(state.as_deref(), mixer.solo)?;
+        Ok(())
+    }))
+}
+
+/// ## `change_mixer(target: &str, change: impl FnOnce(&mut audio::Mixer) -> Result<(), String>) -> Result<String, String>`
+///
+/// Changes the mixer controls of a strip or the master bus and sends them to the audio thread.
+///
+/// ### Arguments
+///
+/// * `target: &str` - The index of the strip, or "master"
+/// * `change: impl FnOnce(&mut audio::Mixer) -> Result<(), String>` - Changes the controls
+///
+/// ### Returns
+///
+/// * `Result<String, String>` - The new mixer controls, or an error message
+fn change_mixer(
+    target: &str,
+    change: impl FnOnce(&mut audio::Mixer) -> Result<(), String>,
+) -> Result<String, String> {
+    let target = parse_target(target)?;
+    match audio::CONTROL.lock() {
+        Ok(mut control) => {
+            let mut mixer = control.mixer(target)?;
+            change(&mut mixer)?;
+            control.set_mixer(target, mixer)?;
+            Ok(format!("{}: {}", target, mixer))
+        }
+        Err(e) => Err(format!("Error locking CONTROL: {}", e)),
+    }
+}
+
+/// ## `meter_show(_window: tauri::Window) -> ConsoleMessage`
+///
+/// Shows the current levels of every strip and the master bus.
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The result of the command
+#[tauri::command]
+async fn meter_show(_window: tauri::Window) -> ConsoleMessage {
+    match audio::CONTROL.lock() {
+        Ok(control) => {
+            let meters = control.meters();
+            let mut message = Vec::new();
+            for levels in meters.strips.iter().chain(std::iter::once(&meters.master)) {
+                let decibels = |values: &Vec<f32>| {
+                    values
+                        .iter()
+                        .map(|value| format!("{:.1}", value))
+                        .collect::<Vec<String>>()
+                        .join("/")
+                };
+                message.push(format!(
+                    "{}: peak {} dB (hold {}), rms {} dB, M {:.1} S {:.1} I {:.1} LUFS, {} clips",
+                    levels.name,
+                    decibels(&levels.peak),
+                    decibels(&levels.hold),
+                    decibels(&levels.rms),
+                    levels.momentary,
+                    levels.short_term,
+                    levels.integrated,
+                    levels.clips
+                ));
+            }
+            ConsoleMessage {
+                kind: MessageKind::Console,
+                message,
+            }
+        }
+        Err(e) => ConsoleMessage {
+            kind: MessageKind::Error,
+            message: vec![format!("Error locking CONTROL: {}", e)],
+        },
+    }
+}
+
+/// ## `meter_reset(_window: tauri::Window) -> ConsoleMessage`
+///
+/// Resets the integrated loudness and clip counts of every strip and the master bus.
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The result of the command
+#[tauri::command]
+async fn meter_reset(_window: tauri::Window) -> ConsoleMessage {
+    match audio::CONTROL.lock() {
+        Ok(control) => {
+            control.reset_meters();
+            ConsoleMessage {
+                kind: MessageKind::Console,
+                message: vec!["Reset integrated loudness and clip counts".to_owned()],
+            }
+        }
+        Err(e) => ConsoleMessage {
+            kind: MessageKind::Error,
+            message: vec![format!("Error locking CONTROL: {}", e)],
+        },
+    }
+}
+
+/// ## `bus_list(_window: tauri::Window) -> ConsoleMessage`
+///
+/// Lists every bus along with the strips writing to and reading from it.
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The result of the command
+#[tauri::command]
+async fn bus_list(_window: tauri::Window) -> ConsoleMessage {
+    match audio::CONTROL.lock() {
+        Ok(control) => {
+            let mut message = Vec::new();
+            for bus in control.buses() {
+                let mut writers = Vec::new();
+                let mut readers = Vec::new();
+                for (index, route) in control.routes().iter().enumerate() {
+                    if route.writes.as_ref() == Some(&bus) {
+                        writers.push(index.to_string());
+                    }
+                    if route.reads.as_ref() == Some(&bus) {
+                        readers.push(index.to_string());
+                    }
+                }
+                message.push(format!(
+                    "{}: written by strips [{}], read by strips [{}]",
+                    bus,
+                    writers.join(", "),
+                    readers.join(", ")
+                ));
+            }
+            if message.is_empty() {
+                message.push("No buses".to_string());
+            }
+            ConsoleMessage {
+                kind: MessageKind::Console,
+                message,
+            }
+        }
+        Err(e) => ConsoleMessage {
+            kind: MessageKind::Error,
+            message: vec![format!("Error locking CONTROL: {}", e)],
+        },
+    }
+}
+
+/// ## `render(_window: tauri::Window, seconds: f32, path: String) -> ConsoleMessage`
+///
+/// Renders the strip graph to a WAV file without opening any device.
+///
+/// ### Arguments
+///
+/// * `seconds: f32` - The length of the render
+/// * `path: String` - The file to write
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The result of the command
+#[tauri::command]
+async fn render(_window: tauri::Window, seconds: f32, path: String) -> ConsoleMessage {
+    match audio::render(seconds, &path) {
+        Ok(message) => ConsoleMessage {
+            kind: MessageKind::Console,
+            message: vec![message],
+        },
+        Err(e) => ConsoleMessage {
+            kind: MessageKind::Error,
+            message: vec![e],
+        },
+    }
+}
+
+/// ## `sample_load(_window: tauri::Window, path: String, mode: Option<String>, root: Option<u8>) -> ConsoleMessage`
+///
+/// Loads an audio file (WAV, MP3, FLAC or OGG) into memory and adds a strip playing it.
+/// The strip plays notes from MIDI and HID keys, as well as `sample trigger`.
+///
+/// ### Arguments
+///
+/// * `path: String` - The file to load
+/// * `mode: Option<String>` - "oneshot" (default), "loop" or "gated"
+/// * `root: Option<u8>` - The note that plays the file at its original pitch; without one every note plays at the original pitch
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The result of the command
+#[tauri::command]
+async fn sample_load(
+    _window: tauri::Window,
+    path: String,
+    mode: Option<String>,
+    root: Option<u8>,
+) -> ConsoleMessage {
+    let mode = match mode {
+        Some(mode) => match sampler::PlayMode::from_name(&mode) {
+            Some(mode) => mode,
+            None => {
+                return ConsoleMessage {
+                    kind: MessageKind::Error,
+                    message: vec![format!(
+                        "Invalid mode {}: expected oneshot, loop or gated",
+                        mode
+                    )],
+                };
+            }
+        },
+        None => sampler::PlayMode::OneShot,
+    };
+
+    let sample = match sampler::SampleData::load(&path) {
+        Ok(sample) => Arc::new(sample),
+        Err(e) => {
+            return ConsoleMessage {
+                kind: MessageKind::Error,
+                message: vec![e],
+            };
+        }
+    };
+    let duration = sample.duration();
+
+    let mut player = sampler::SamplePlayer::new(sample, mode);
+    player.set_root(root);
+    let strip = audio::Strip::new(
+        audio::Input::Generator(Box::new(player)),
+        audio::Output::Stereo(0, 1),
+    );
+
+    let index = match audio::CONTROL.lock() {
+        Ok(mut control) => control.add_strip(strip),
+        Err(e) => Err(format!("Error locking CONTROL: {}", e)),
+    };
+
+    match index {
+        Ok(index) => {
+            listen(index);
+            ConsoleMessage {
+                kind: MessageKind::Console,
+                message: vec![format!(
+                    "Added strip {} playing {} ({:.2} s, {})",
+                    index,
+                    path,
+                    duration,
+                    mode.name()
+                )],
+            }
+        }
+        Err(e) => ConsoleMessage {
+            kind: MessageKind::Error,
+            message: vec![e],
+        },
+    }
+}
+
+/// ## `sample_grain(_window: tauri::Window, path: String) -> ConsoleMessage`
+///
+/// Loads an audio file (WAV, MP3, FLAC or OGG) into memory and adds a strip granulizing it.
+/// The strip plays grains while a note is held, pitched relative to middle C.
+///
+/// ### Arguments
+///
+/// * `path: String` - The file to load
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The result of the command
+#[tauri::command]
+async fn sample_grain(_window: tauri::Window, path: String) -> ConsoleMessage {
+    let sample = match sampler::SampleData::load(&path) {
+        Ok(sample) => Arc::new(sample),
+        Err(e) => {
+            return ConsoleMessage {
+                kind: MessageKind::Error,
+                message: vec![e],
+            };
+        }
+    };
+    let duration = sample.duration();
+
+    let strip = audio::Strip::new(
+        audio::Input::Generator(Box::new(granulizer::Granulizer::from_sample(sample))),
+        audio::Output::Stereo(0, 1),
+    );
+
+    let index = match audio::CONTROL.lock() {
+        Ok(mut control) => control.add_strip(strip),
+        Err(e) => Err(format!("Error locking CONTROL: {}", e)),
+    };
+
+    match index {
+        Ok(index) => {
+            listen(index);
+            ConsoleMessage {
+                kind: MessageKind::Console,
+                message: vec![format!(
+                    "Added strip {} granulizing {} ({:.2} s)",
+                    index, path, duration
+                )],
+            }
+        }
+        Err(e) => ConsoleMessage {
+            kind: MessageKind::Error,
+            message: vec![e],
+        },
+    }
+}
+
+/// ## `sample_trigger(_window: tauri::Window, strip: usize, note: Option<u8>, velocity: Option<f32>) -> ConsoleMessage`
+///
+/// Starts a note on a strip's generator from the console.
+///
+/// ### Arguments
+///
+/// * `strip: usize` - The index of the strip
+/// * `note: Option<u8>` - The MIDI note number, 60 if not given
+/// * `velocity: Option<f32>` - The velocity (0.0-1.0), 1.0 if not given
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The result of the command
+#[tauri::command]
+async fn sample_trigger(
+    _window: tauri::Window,
+    strip: usize,
+    note: Option<u8>,
+    velocity: Option<f32>,
+) -> ConsoleMessage {
+    let note = note.unwrap_or(60);
+    let velocity = velocity.unwrap_or(1.0).clamp(0.0, 1.0);
+    let result = match audio::CONTROL.lock() {
+        Ok(mut control) => control.note_on(strip, note, velocity),
+        Err(e) => Err(format!("Error locking CONTROL: {}", e)),
+    };
+
+    match result {
+        Ok(()) => ConsoleMessage {
+            kind: MessageKind::Console,
+            message: vec![format!("Started note {} on strip {}", note, strip)],
+        },
+        Err(e) => ConsoleMessage {
+            kind: MessageKind::Error,
+            message: vec![e],
+        },
+    }
+}
+
+/// ## `sample_release(_window: tauri::Window, strip: usize, note: Option<u8>) -> ConsoleMessage`
+///
+/// Releases a note on a strip's generator from the console.
+///
+/// ### Arguments
+///
+/// * `strip: usize` - The index of the strip
+/// * `note: Option<u8>` - The MIDI note number, 60 if not given
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The result of the command
+#[tauri::command]
+async fn sample_release(_window: tauri::Window, strip: usize, note: Option<u8>) -> ConsoleMessage {
+    let note = note.unwrap_or(60);
+    let result = match audio::CONTROL.lock() {
+        Ok(mut control) => control.note_off(strip, note),
+        Err(e) => Err(format!("Error locking CONTROL: {}", e)),
+    };
+
+    match result {
+        Ok(()) => ConsoleMessage {
+            kind: MessageKind::Console,
+            message: vec![format!("Released note {} on strip {}", note, strip)],
+        },
+        Err(e) => ConsoleMessage {
+            kind: MessageKind::Error,
+            message: vec![e],
+        },
+    }
+}
+
+/// ## `listen(strip: usize)`
+///
+/// Makes a strip play notes from MIDI and HID keys.
+///
+/// ### Arguments
+///
+/// * `strip: usize` - The index of the strip
+fn listen(strip: usize) {
+    match midi::subscribe(strip, None) {
+        Ok(()) => {}
+        Err(e) => {
+            debug!("Error subscribing strip {} to MIDI: {}", strip, e);
+        }
+    }
+
+    match HID_STRIPS.lock() {
+        Ok(mut strips) => strips.push(strip),
+        Err(e) => {
+            debug!("Error locking HID_STRIPS: {}", e);
+        }
+    }
+}
+
+/// ## `hid_key(key: &Key, velocity: f32)`
+///
+/// Sends the notes bound to an HID key (e.g. drum pads). A velocity of 0 releases them.
+///
+/// ### Arguments
+///
+/// * `key: &Key` - The key
+/// * `velocity: f32` - The velocity (0.0-1.0)
+fn hid_key(key: &Key, velocity: f32) {
+    let name = key.to_string().to_lowercase();
+    let bound: Vec<(usize, u8)> = match HID_KEYS.lock() {
+        Ok(keys) => keys
+            .iter()
+            .filter(|(key, _strip, _note)| *key == name)
+            .map(|(_key, strip, note)| (*strip, *note))
+            .collect(),
+        Err(e) => {
+            debug!("Error locking HID_KEYS: {}", e);
+            return;
+        }
+    };
+
+    match audio::CONTROL.lock() {
+        Ok(mut control) => {
+            for (strip, note) in bound {
+                let _ = match velocity > 0.0 {
+                    true => control.note_on(strip, note, velocity),
+                    false => control.note_off(strip, note),
+                };
+            }
+        }
+        Err(e) => {
+            debug!("Error locking CONTROL: {}", e);
+        }
+    }
+}
+
+/// ## `hid_note(note: u8, velocity: f32)`
+///
+/// Sends a note from an HID key to every strip listening to HID keys. A velocity of 0 releases the note.
+///
+/// ### Arguments
+///
+/// * `note: u8` - The MIDI note number
+/// * `velocity: f32` - The velocity (0.0-1.0)
+fn hid_note(note: u8, velocity: f32) {
+    let strips = match HID_STRIPS.lock() {
+        Ok(strips) => strips.clone(),
+        Err(e) => {
+            debug!("Error locking HID_STRIPS: {}", e);
+            return;
+        }
+    };
+
+    match audio::CONTROL.lock() {
+        Ok(mut control) => {
+            for strip in strips {
+                let _ = match velocity > 0.0 {
+                    true => control.note_on(strip, note, velocity),
+                    false => control.note_off(strip, note),
+                };
+            }
+        }
+        Err(e) => {
+            debug!("Error locking CONTROL: {}", e);
+        }
+    }
+}
+
+/// ## `synth_new(_window: tauri::Window, oscillator: Option<String>, voices: Option<usize>, mode: Option<String>) -> ConsoleMessage`
+///
+/// Adds a strip with a polyphonic synth. The strip plays notes from MIDI and HID keys, as well as `sample trigger`.
+/// The envelope, portamento, voice stealing and so on are set as generator parameters (see `synth::Synth`).
+///
+/// ### Arguments
+///
+/// * `oscillator: Option<String>` - The oscillator every voice plays, "sine" if not given
+/// * `voices: Option<usize>` - The number of voices (1-32), 8 if not given
+/// * `mode: Option<String>` - "poly" (default), "mono" or "legato"
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The result of the command
+#[tauri::command]
+async fn synth_new(
+    _window: tauri::Window,
+    oscillator: Option<String>,
+    voices: Option<usize>,
+    mode: Option<String>,
+) -> ConsoleMessage {
+    let name = oscillator.unwrap_or("sine".to_owned());
+    let mode = match mode {
+        Some(mode) => synth::VoiceMode::from_name(&mode).ok_or(format!(
+            "Invalid mode {}: expected poly, mono or legato",
+            mode
+        )),
+        None => Ok(synth::VoiceMode::Poly),
+    };
+
+    let result = mode.and_then(|mode| {
+        let oscillator = oscillator::create_oscillator(&name)?;
+        let voices = voices.unwrap_or(8).clamp(1, synth::MAX_VOICES);
+        let strip = audio::Strip::new(
+            audio::Input::Generator(Box::new(synth::Synth::new(oscillator, voices, mode))),
+            audio::Output::Stereo(0, 1),
+        );
+
+        let index = match audio::CONTROL.lock() {
+            Ok(mut control) => control.add_strip(strip),
+            Err(e) => Err(format!("Error locking CONTROL: {}", e)),
+        }?;
+        listen(index);
+        Ok(format!(
+            "Added strip {} playing a {} synth ({} voices, {})",
+            index,
+            name,
+            voices,
+            mode.name()
+        ))
+    });
+    console_result(result)
+}
+
+/// ## `kit_list(_window: tauri::Window) -> ConsoleMessage`
+///
+/// Lists the drum kits in the kit folder.
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The result of the command
+#[tauri::command]
+async fn kit_list(_window: tauri::Window) -> ConsoleMessage {
+    let entries = match std::fs::read_dir(KIT_ROOT) {
+        Ok(entries) => entries,
+        Err(e) => {
+            return ConsoleMessage {
+                kind: MessageKind::Error,
+                message: vec![format!("Error reading {}: {}", KIT_ROOT, e)],
+            };
+        }
+    };
+
+    let mut kits: Vec<String> = Vec::new();
+    for entry in entries {
+        let path = match entry {
+            Ok(entry) => entry.path(),
+            Err(e) => {
+                debug!("Error reading kit folder: {}", e);
+                continue;
+            }
+        };
+        match path.extension() {
+            Some(extension) if extension == "json" => match path.file_stem() {
+                Some(stem) => kits.push(stem.to_string_lossy().to_string()),
+                None => {}
+            },
+            _ => {}
+        }
+    }
+    kits.sort();
+
+    match kits.is_empty() {
+        true => ConsoleMessage {
+            kind: MessageKind::Console,
+            message: vec![format!("No kits in {}", KIT_ROOT)],
+        },
+        false => ConsoleMessage {
+            kind: MessageKind::Console,
+            message: kits,
+        },
+    }
+}
+
+/// ## `kit_load(_window: tauri::Window, name: String) -> ConsoleMessage`
+///
+/// Loads a drum kit from the kit folder and adds a strip for every output its pads use.
+/// The strips play notes from MIDI, and pads with a key are bound to that HID key.
+///
+/// ### Arguments
+///
+/// * `name: String` - The name of the kit file, with or without ".json"
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The result of the command
+#[tauri::command]
+async fn kit_load(_window: tauri::Window, name: String) -> ConsoleMessage {
+    let filename = match folder_file(KIT_ROOT, &name) {
+        Ok(filename) => filename,
+        Err(e) => {
+            return ConsoleMessage {
+                kind: MessageKind::Error,
+                message: vec![e],
+            };
+        }
+    };
+
+    let kit = match drums::DrumKit::load(&filename) {
+        Ok(kit) => Arc::new(kit),
+        Err(e) => {
+            return ConsoleMessage {
+                kind: MessageKind::Error,
+                message: vec![e],
+            };
+        }
+    };
+
+    // check every output before adding any strip, and add the strips together, so a bad kit adds nothing
+    let outputs = kit.outputs();
+    let mut strips = Vec::new();
+    for output in outputs.iter() {
+        match parse_output(output) {
+            Ok(parsed) => strips.push(audio::Strip::new(
+                audio::Input::Generator(Box::new(drums::DrumSampler::new(kit.clone(), output))),
+                parsed,
+            )),
+            Err(e) => {
+                return ConsoleMessage {
+                    kind: MessageKind::Error,
+                    message: vec![format!("Invalid output {}: {}", output, e)],
+                };
+            }
+        }
+    }
+
+    let indices = match audio::CONTROL.lock() {
+        Ok(mut control) => control.add_strips(strips),
+        Err(e) => Err(format!("Error locking CONTROL: {}", e)),
+    };
+    let indices = match indices {
+        Ok(indices) => indices,
+        Err(e) => {
+            return ConsoleMessage {
+                kind: MessageKind::Error,
+                message: vec![e],
+            };
+        }
+    };
+
+    let mut message = vec![format!("Loaded kit {} ({} pads)", kit.name, kit.pads.len())];
+    for (output, index) in outputs.into_iter().zip(indices) {
+        match midi::subscribe(index, None) {
+            Ok(()) => {}
+            Err(e) => {
+                debug!("Error subscribing strip {} to MIDI: {}", index, e);
+            }
+        }
+
+        let mut pads = Vec::new();
+        for pad in kit.pads.iter().filter(|pad| pad.output == output) {
+            match &pad.key {
+                Some(key) => {
+                    match HID_KEYS.lock() {
+                        Ok(mut keys) => keys.push((key.to_lowercase(), index, pad.note)),
+                        Err(e) => {
+                            debug!("Error locking HID_KEYS: {}", e);
+                        }
+                    }
+                    pads.push(format!("{} ({}, {})", pad.name, pad.note, key));
+                }
+                None => pads.push(format!("{} ({})", pad.name, pad.note)),
+            }
+        }
+        message.push(format!(
+            "Strip {} -> {}: {}",
+            index,
+            output,
+            pads.join(", ")
+        ));
+    }
+
+    ConsoleMessage {
+        kind: MessageKind::Console,
+        message,
+    }
+}
+
+/// ## `seq_show(_window: tauri::Window) -> ConsoleMessage`
+///
+/// Shows the sequencer's pattern, one grid per track.
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The result of the command
+#[tauri::command]
+async fn seq_show(_window: tauri::Window) -> ConsoleMessage {
+    match audio::CONTROL.lock() {
+        Ok(control) => {
+            let pattern = control.pattern();
+            let mut message = vec![format!(
+                "{} steps per bar, at {}",
+                pattern.steps_per_bar,
+                control.transport()
+            )];
+            for (index, track) in pattern.tracks.iter().enumerate() {
+                message.push(format!(
+                    "{}: {} (strip {}, note {}, gate {}){} {}",
+                    index,
+                    track.name,
+                    track.strip,
+                    track.note,
+                    track.gate,
+                    match track.mute {
+                        true => " muted",
+                        false => "",
+                    },
+                    track.grid(pattern.steps_per_bar)
+                ));
+            }
+            ConsoleMessage {
+                kind: MessageKind::Console,
+                message,
+            }
+        }
+        Err(e) => ConsoleMessage {
+            kind: MessageKind::Error,
+            message: vec![format!("Error locking CONTROL: {}", e)],
+        },
+    }
+}
+
+/// ## `seq_new(_window: tauri::Window, steps_per_bar: Option<usize>) -> ConsoleMessage`
+///
+/// Replaces the sequencer's pattern with an empty one.
+///
+/// ### Arguments
+///
+/// * `steps_per_bar: Option<usize>` - The number of steps in a bar, 16 if not given
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The result of the command
+#[tauri::command]
+async fn seq_new(_window: tauri::Window, steps_per_bar: Option<usize>) -> ConsoleMessage {
+    let pattern = sequencer::Pattern::new(steps_per_bar.unwrap_or(16));
+    console_result(change_pattern(|current| {
+        *current = pattern;
+        Ok("Created an empty pattern".to_owned())
+    }))
+}
+
+/// ## `seq_track(_window: tauri::Window, name: String, strip: usize, note: u8, grid: Option<String>) -> ConsoleMessage`
+///
+/// Adds a track to the pattern, or changes the strip and note of the track with that name.
+///
+/// ### Arguments
+///
+/// * `name: String` - The name of the track
+/// * `strip: usize` - The strip whose generator plays the track
+/// * `note: u8` - The MIDI note played (a drum pad, or a pitch for a synth)
+/// * `grid: Option<String>` - The steps, e.g. "x...o...x...o..."; a bar of empty steps if not given
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The result of the command
+#[tauri::command]
+async fn seq_track(
+    _window: tauri::Window,
+    name: String,
+    strip: usize,
+    note: u8,
+    grid: Option<String>,
+) -> ConsoleMessage {
+    let strips = match audio::CONTROL.lock() {
+        Ok(control) => control.routes().len(),
+        Err(e) => {
+            return ConsoleMessage {
+                kind: MessageKind::Error,
+                message: vec![format!("Error locking CONTROL: {}", e)],
+            };
+        }
+    };
+    if strip >= strips {
+        return ConsoleMessage {
+            kind: MessageKind::Error,
+            message: vec![format!("No strip {}", strip)],
+        };
+    }
+
+    console_result(change_pattern(|pattern| {
+        let index = match pattern.track(&name) {
+            Some(index) => index,
+            None => {
+                pattern.tracks.push(sequencer::Track::new(
+                    &name,
+                    strip,
+                    note,
+                    pattern.steps_per_bar,
+                ));
+                pattern.tracks.len() - 1
+            }
+        };
+        let steps_per_bar = pattern.steps_per_bar;
+        let track = &mut pattern.tracks[index];
+        track.strip = strip;
+        track.note = note.min(127);
+        match &grid {
+            Some(grid) => track.set_grid(grid)?,
+            None => {}
+        }
+        Ok(format!(
+            "{}: {} {}",
+            index,
+            track.name,
+            track.grid(steps_per_bar)
+        ))
+    }))
+}
+
+/// ## `seq_grid(_window: tauri::Window, track: String, grid: String) -> ConsoleMessage`
+///
+/// Programs a track's steps from a grid: "x" loud, "o" soft, "." empty. The track takes the length of the grid.
+///
+/// ### Arguments
+///
+/// * `track: String` - The name or index of the track
+/// * `grid: String` - The steps, e.g. "x...o...x...o..."
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The result of the command
+#[tauri::command]
+async fn seq_grid(_window: tauri::Window, track: String, grid: String) -> ConsoleMessage {
+    console_result(change_pattern(|pattern| {
+        let steps_per_bar = pattern.steps_per_bar;
+        let track = find_track(pattern, &track)?;
+        track.set_grid(&grid)?;
+        Ok(format!("{} {}", track.name, track.grid(steps_per_bar)))
+    }))
+}
+
+/// ## `seq_step(_window: tauri::Window, track: String, step: usize, velocity: f32, probability: Option<f32>, ratchet: Option<u8>, offset: Option<f32>) -> ConsoleMessage`
+///
+/// Sets one step of a track. Steps past the end of the track lengthen it, up to `sequencer::MAX_STEPS` steps.
+///
+/// ### Arguments
+///
+/// * `track: String` - The name or index of the track
+/// * `step: usize` - The index of the step (0 to `sequencer::MAX_STEPS` - 1)
+/// * `velocity: f32` - The velocity (0.0-1.0); 0.0 empties the step
+/// * `probability: Option<f32>` - The chance of the step playing (0.0-1.0), 1.0 if not given
+/// * `ratchet: Option<u8>` - How many times the step repeats within its length, 1 if not given
+/// * `offset: Option<f32>` - How early or late the step plays, as a fraction of a step (-0.5-0.5), 0.0 if not given
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The result of the command
+#[tauri::command]
+async fn seq_step(
+    _window: tauri::Window,
+    track: String,
+    step: usize,
+    velocity: f32,
+    probability: Option<f32>,
+    ratchet: Option<u8>,
+    offset: Option<f32>,
+) -> ConsoleMessage {
+    console_result(change_pattern(|pattern| {
+        if step >= sequencer::MAX_STEPS {
+            return Err(format!(
+                "Invalid step {}: expected 0-{}",
+                step,
+                sequencer::MAX_STEPS - 1
+            ));
+        }
+        let steps_per_bar = pattern.steps_per_bar;
+        let track = find_track(pattern, &track)?;
+        if step >= track.steps.len() {
+            track.steps.resize(step + 1, sequencer::Step::off());
+        }
+        track.steps[step] = sequencer::Step {
+            velocity: velocity.clamp(0.0, 1.0),
+            probability: probability.unwrap_or(1.0).clamp(0.0, 1.0),
+            ratchet: ratchet.unwrap_or(1).clamp(1, sequencer::MAX_RATCHET),
+            offset: offset.unwrap_or(0.0).clamp(-0.5, 0.5),
+        };
+        Ok(format!("{} {}", track.name, track.grid(steps_per_bar)))
+    }))
+}
+
+/// ## `seq_mute(_window: tauri::Window, track: String, state: Option<String>) -> ConsoleMessage`
+///
+/// Mutes or unmutes a track.
+///
+/// ### Arguments
+///
+/// * `track: String` - The name or index of the track
+/// * `state: Option<String>` - "on" or "off", toggles if not given
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The result of the command
+#[tauri::command]
+async fn seq_mute(_window: tauri::Window, track: String, state: Option<String>) -> ConsoleMessage {
+    console_result(change_pattern(|pattern| {
+        let track = find_track(pattern, &track)?;
+        track.mute = parse_switch(state.as_deref(), track.mute)?;
+        Ok(format!(
+            "{} {}",
+            track.name,
+            match track.mute {
+                true => "muted",
+                false => "unmuted",
+            }
+        ))
+    }))
+}
+
+/// ## `seq_remove(_window: tauri::Window, track: String) -> ConsoleMessage`
+///
+/// Removes a track from the pattern.
+///
+/// ### Arguments
+///
+/// * `track: String` - The name or index of the track
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The result of the command
+#[tauri::command]
+async fn seq_remove(_window: tauri::Window, track: String) -> ConsoleMessage {
+    console_result(change_pattern(|pattern| match pattern.track(&track) {
+        Some(index) => Ok(format!("Removed {}", pattern.tracks.remove(index).name)),
+        None => Err(format!("No track {}", track)),
+    }))
+}
+
+/// ## `transport_show(_window: tauri::Window) -> ConsoleMessage`
+///
+/// Shows the transport's position, tempo and time signature.
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The result of the command
+#[tauri::command]
+async fn transport_show(_window: tauri::Window) -> ConsoleMessage {
+    let result = match audio::CONTROL.lock() {
+        Ok(control) => Ok(format!("{}", control.transport())),
+        Err(e) => Err(format!("Error locking CONTROL: {}", e)),
+    };
+    console_result(result)
+}
+
+/// ## `transport_play(_window: tauri::Window, playing: bool) -> ConsoleMessage`
+///
+/// Starts the transport from where it is, or stops it where it is.
+///
+/// ### Arguments
+///
+/// * `playing: bool` - Whether to play
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The result of the command
+#[tauri::command]
+async fn transport_play(_window: tauri::Window, playing: bool) -> ConsoleMessage {
+    let result = match audio::CONTROL.lock() {
+        Ok(mut control) => control.play(playing).map(|()| match playing {
+            true => "Playing".to_owned(),
+            false => "Stopped".to_owned(),
+        }),
+        Err(e) => Err(format!("Error locking CONTROL: {}", e)),
+    };
+    console_result(result)
+}
+
+/// ## `transport_locate(_window: tauri::Window, bar: u32, beat: Option<f64>) -> ConsoleMessage`
+///
+/// Moves the transport to a bar and beat, both counting from one.
+///
+/// ### Arguments
+///
+/// * `bar: u32` - The bar
+/// * `beat: Option<f64>` - The beat within the bar, 1 if not given; fractions move between beats
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The result of the command
+#[tauri::command]
+async fn transport_locate(_window: tauri::Window, bar: u32, beat: Option<f64>) -> ConsoleMessage {
+    let beat = beat.unwrap_or(1.0);
+    if bar < 1 || beat < 1.0 {
+        return ConsoleMessage {
+            kind: MessageKind::Error,
+            message: vec!["Bars and beats count from 1".to_owned()],
+        };
+    }
+
+    let result = match audio::CONTROL.lock() {
+        Ok(mut control) => {
+            let beats_per_bar = control.transport().beats_per_bar();
+            control
+                .locate((bar - 1) as f64 * beats_per_bar + beat - 1.0)
+                .map(|()| format!("Moved to bar {}, beat {}", bar, beat))
+        }
+        Err(e) => Err(format!("Error locking CONTROL: {}", e)),
+    };
+    console_result(result)
+}
+
+/// ## `transport_tempo(_window: tauri::Window, bpm: f64) -> ConsoleMessage`
+///
+/// Sets the tempo of the transport.
+///
+/// ### Arguments
+///
+/// * `bpm: f64` - The tempo in beats per minute
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The result of the command
+#[tauri::command]
+async fn transport_tempo(_window: tauri::Window, bpm: f64) -> ConsoleMessage {
+    let result = match audio::CONTROL.lock() {
+        Ok(mut control) => control
+            .set_tempo(bpm)
+            .map(|()| format!("Tempo set to {} bpm", bpm)),
+        Err(e) => Err(format!("Error locking CONTROL: {}", e)),
+    };
+    console_result(result)
+}
+
+/// ## `transport_signature(_window: tauri::Window, signature: String) -> ConsoleMessage`
+///
+/// Sets the time signature of the transport.
+///
+/// ### Arguments
+///
+/// * `signature: String` - The time signature, e.g. "7/8"
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The result of the command
+#[tauri::command]
+async fn transport_signature(_window: tauri::Window, signature: String) -> ConsoleMessage {
+    let parsed = match signature.split_once('/') {
+        Some((beats, unit)) => match (beats.trim().parse::<u32>(), unit.trim().parse::<u32>()) {
+            (Ok(beats), Ok(unit)) => Ok((beats, unit)),
+            _ => Err(format!(
+                "Invalid time signature {}: expected e.g. 4/4",
+                signature
+            )),
+        },
+        None => Err(format!(
+            "Invalid time signature {}: expected e.g. 4/4",
+            signature
+        )),
+    };
+
+    let result = parsed.and_then(|(beats, unit)| match audio::CONTROL.lock() {
+        Ok(mut control) => control
+            .set_signature(beats, unit)
+            .map(|()| format!("Time signature set to {}/{}", beats, unit)),
+        Err(e) => Err(format!("Error locking CONTROL: {}", e)),
+    });
+    console_result(result)
+}
+
+/// ## `clock_show(_window: tauri::Window) -> ConsoleMessage`
+///
+/// Shows whether the transport follows incoming MIDI clock, and where MIDI clock is sent.
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The result of the command
+#[tauri::command]
+async fn clock_show(_window: tauri::Window) -> ConsoleMessage {
+    let following = match clock::FOLLOWER.lock() {
+        Ok(follower) => match (follower.following(), follower.bpm()) {
+            (true, Some(bpm)) => Ok(format!("Following MIDI clock at {:.2} bpm", bpm)),
+            (true, None) => Ok("Following MIDI clock, waiting for clock".to_owned()),
+            (false, _) => Ok("Not following MIDI clock".to_owned()),
+        },
+        Err(e) => Err(format!("Error locking clock::FOLLOWER: {}", e)),
+    };
+    let sending = match clock::SENDER.lock() {
+        Ok(sender) => match sender.as_ref() {
+            Some(sender) => Ok(format!("Sending MIDI clock to {}", sender.port())),
+            None => Ok("Not sending MIDI clock".to_owned()),
+        },
+        Err(e) => Err(format!("Error locking clock::SENDER: {}", e)),
+    };
+
+    match (following, sending) {
+        (Ok(following), Ok(sending)) => ConsoleMessage {
+            kind: MessageKind::Console,
+            message: vec![following, sending],
+        },
+        (Err(e), _) | (_, Err(e)) => ConsoleMessage {
+            kind: MessageKind::Error,
+            message: vec![e],
+        },
+    }
+}
+
+/// ## `clock_follow(_window: tauri::Window, state: Option<String>) -> ConsoleMessage`
+///
+/// Slaves the transport to incoming MIDI clock, Start, Stop, Continue and Song Position Pointer, or frees it.
+///
+/// ### Arguments
+///
+/// * `state: Option<String>` - "on" or "off"; toggles if not given
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The result of the command
+#[tauri::command]
+async fn clock_follow(_window: tauri::Window, state: Option<String>) -> ConsoleMessage {
+    let result = match clock::FOLLOWER.lock() {
+        Ok(mut follower) => parse_switch(state.as_deref(), follower.following()).map(|following| {
+            follower.set_following(following);
+            match following {
+                true => "Following MIDI clock".to_owned(),
+                false => "Stopped following MIDI clock".to_owned(),
+            }
+        }),
+        Err(e) => Err(format!("Error locking clock::FOLLOWER: {}", e)),
+    };
+    console_result(result)
+}
+
+/// ## `clock_send(_window: tauri::Window, port: String) -> ConsoleMessage`
+///
+/// Sends MIDI clock and transport messages to an output port, replacing any port it was sent to before.
+///
+/// ### Arguments
+///
+/// * `port: String` - The index of the port, or part of its name
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The result of the command
+#[tauri::command]
+async fn clock_send(_window: tauri::Window, port: String) -> ConsoleMessage {
+    let result = match clock::SENDER.lock() {
+        Ok(mut sender) => {
+            // close the previous port first, in case it is the same one
+            *sender = None;
+            clock::ClockSender::start(&port).map(|started| {
+                let message = format!("Sending MIDI clock to {}", started.port());
+                *sender = Some(started);
+                message
+            })
+        }
+        Err(e) => Err(format!("Error locking clock::SENDER: {}", e)),
+    };
+    console_result(result)
+}
+
+/// ## `clock_stop(_window: tauri::Window) -> ConsoleMessage`
+///
+/// Stops sending MIDI clock.
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The result of the command
+#[tauri::command]
+async fn clock_stop(_window: tauri::Window) -> ConsoleMessage {
+    let result = match clock::SENDER.lock() {
+        Ok(mut sender) => match sender.take() {
+            Some(stopped) => Ok(format!("Stopped sending MIDI clock to {}", stopped.port())),
+            None => Err("MIDI clock is not being sent".to_owned()),
+        },
+        Err(e) => Err(format!("Error locking clock::SENDER: {}", e)),
+    };
+    console_result(result)
+}
+
+/// ## `parse_eq(eq: &str) -> Result<(audio::Target, Option<usize>), String>`
+///
+/// Parses an EQ as "<strip|master>" or "<strip|master>:<effect>", where the effect is the index of the EQ in the chain,
+/// needed when the chain has more than one.
+///
+/// ### Arguments
+///
+/// * `eq: &str` - The EQ to parse
+///
+/// ### Returns
+///
+/// * `Result<(audio::Target, Option<usize>), String>` - The strip or the master bus and the index of the EQ, if given, or an error message
+fn parse_eq(eq: &str) -> Result<(audio::Target, Option<usize>), String> {
+    match eq.split_once(':') {
+        Some((target, effect)) => match effect.trim().parse::<usize>() {
+            Ok(effect) => Ok((parse_target(target)?, Some(effect))),
+            Err(_e) => Err(format!("Invalid effect {}: expected an index", effect)),
+        },
+        None => Ok((parse_target(eq)?, None)),
+    }
+}
+
+/// ## `eq_curve(eq: &str) -> Result<(audio::Target, usize, Arc<eq::Curve>), String>`
+///
+/// Returns the settings of an EQ on a strip or the master bus.
+///
+/// ### Arguments
+///
+/// * `eq: &str` - The EQ, as "<strip|master>" or "<strip|master>:<effect>" (see `parse_eq`)
+///
+/// ### Returns
+///
+/// * `Result<(audio::Target, usize, Arc<eq::Curve>), String>` - The strip or the master bus, the index of the EQ in its chain
+///   and its settings, or an error message if there is no such EQ
+fn eq_curve(eq: &str) -> Result<(audio::Target, usize, Arc<eq::Curve>), String> {
+    let (target, effect) = parse_eq(eq)?;
+    match audio::CONTROL.lock() {
+        Ok(control) => control
+            .eq(target, effect)
+            .map(|(effect, curve)| (target, effect, curve)),
+        Err(e) => Err(format!("Error locking CONTROL: {}", e)),
+    }
+}
+
+/// ## `eq_add(_window: tauri::Window, strip: String) -> ConsoleMessage`
+///
+/// Adds a parametric EQ to the end of a strip's or the master bus' chain, with every band off, to be set with `eq_band`.
+/// A chain can have several EQs, told apart by their index in it.
+///
+/// ### Arguments
+///
+/// * `strip: String` - The index of the strip, as shown by `strip list`, or "master"
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The result of the command
+#[tauri::command]
+async fn eq_add(_window: tauri::Window, strip: String) -> ConsoleMessage {
+    let result = parse_target(&strip).and_then(|target| {
+        let effect = audio::plugin::create_effect("eq", &[])?;
+        match audio::CONTROL.lock() {
+            Ok(mut control) => control.add_effect(target, effect),
+            Err(e) => Err(format!("Error locking CONTROL: {}", e)),
+        }
+        .map(|index| {
+            format!(
+                "Added an EQ with {} bands to {} as effect {}",
+                eq::MAX_BANDS,
+                target,
+                index
+            )
+        })
+    });
+    console_result(result)
+}
+
+/// ## `eq_band(_window: tauri::Window, strip: String, band: usize, kind: String, frequency: Option<f32>, amount: Option<f32>, q: Option<f32>) -> ConsoleMessage`
+///
+/// Sets a band of an EQ on a strip or the master bus. The EQ moves to the new settings smoothly.
+/// Settings that are not given are kept.
+///
+/// ### Arguments
+///
+/// * `strip: String` - The index of the strip, or "master", followed by ":<effect>" if the chain has more than one EQ
+/// * `band: usize` - The index of the band (0-7)
+/// * `kind: String` - "off", "lowshelf", "highshelf", "peak", "lowpass" or "highpass"
+/// * `frequency: Option<f32>` - The frequency, in Hz
+/// * `amount: Option<f32>` - The gain of shelves and peaks in dB, or the slope of passes in dB per octave (12, 24, 36 or 48)
+/// * `q: Option<f32>` - The Q
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The result of the command
+#[tauri::command]
+async fn eq_band(
+    _window: tauri::Window,
+    strip: String,
+    band: usize,
+    kind: String,
+    frequency: Option<f32>,
+    amount: Option<f32>,
+    q: Option<f32>,
+) -> ConsoleMessage {
+    let result = parse_eq(&strip).and_then(|(target, effect)| {
+        if band >= eq::MAX_BANDS {
+            return Err(format!(
+                "Invalid band {}: expected 0-{}",
+                band,
+                eq::MAX_BANDS - 1
+            ));
+        }
+        let kind = eq::BandKind::from_name(&kind).ok_or(format!(
+            "Invalid band type {}: expected off, lowshelf, highshelf, peak, lowpass or highpass",
+            kind
+        ))?;
+
+        // CONTROL stays locked while the band is written, as the curve must only have one writer at a time
+        let control = match audio::CONTROL.lock() {
+            Ok(control) => control,
+            Err(e) => return Err(format!("Error locking CONTROL: {}", e)),
+        };
+        let (effect, curve) = control.eq(target, effect)?;
+        let mut settings = curve.band(band);
+        settings.set("type", kind.index() as f32);
+        match frequency {
+            Some(frequency) => {
+                settings.set("freq", frequency);
+            }
+            None => {}
+        }
+        match (amount, kind) {
+            (Some(slope), eq::BandKind::LowPass | eq::BandKind::HighPass) => {
+                settings.set("slope", (slope / 12.0).round());
+            }
+            (Some(gain), _) => {
+                settings.set("gain", gain);
+            }
+            (None, _) => {}
+        }
+        match q {
+            Some(q) => {
+                settings.set("q", q);
+            }
+            None => {}
+        }
+        curve.set_band(band, settings);
+        Ok(format!(
+            "Set band {} of the EQ on {} (effect {}) to {}",
+            band, target, effect, settings
+        ))
+    });
+    console_result(result)
+}
+
+/// ## `eq_show(_window: tauri::Window, strip: String) -> ConsoleMessage`
+///
+/// Lists the bands of an EQ on a strip or the master bus.
+///
+/// ### Arguments
+///
+/// * `strip: String` - The index of the strip, or "master", followed by ":<effect>" if the chain has more than one EQ
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The bands, or an error message
+#[tauri::command]
+async fn eq_show(_window: tauri::Window, strip: String) -> ConsoleMessage {
+    let result = eq_curve(&strip);
+
+    match result {
+        Ok((_target, _effect, curve)) => ConsoleMessage {
+            kind: MessageKind::Console,
+            message: (0..eq::MAX_BANDS)
+                .map(|band| format!("{}: {}", band, curve.band(band)))
+                .collect(),
+        },
+        Err(e) => ConsoleMessage {
+            kind: MessageKind::Error,
+            message: vec![e],
+        },
+    }
+}
+
+/// ## `eq_response(window: tauri::Window, strip: String, points: Option<usize>) -> ConsoleMessage`
+///
+/// Works out the frequency response of an EQ on a strip or the master bus, from 20 Hz to 20 kHz.
+/// The response is emitted to the console and TV windows as the "eq" event, so it can be plotted,
+/// and printed to the console.
+///
+/// ### Arguments
+///
+/// * `window: tauri::Window` - The window the command came from
+/// * `strip: String` - The index of the strip, or "master", followed by ":<effect>" if the chain has more than one EQ
+/// * `points: Option<usize>` - The number of frequencies; 31 if not given
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The gain at each frequency, or an error message
+#[tauri::command]
+async fn eq_response(
+    window: tauri::Window,
+    strip: String,
+    points: Option<usize>,
+) -> ConsoleMessage {
+    let result = eq_curve(&strip).map(|(target, effect, curve)| {
+        let name = match target {
+            audio::Target::Strip(index) => format!("{}:{}", index, effect),
+            audio::Target::Master => format!("master:{}", effect),
+        };
+        eq::EqResponse::new(name, &curve, points.unwrap_or(31).clamp(2, 1024))
+    });
+
+    match result {
+        Ok(response) => {
+            let _ = window.emit("eq", &response);
+            match TV_WINDOW.lock() {
+                Ok(tv_window) => match tv_window.as_ref() {
+                    Some(tv_window) => {
+                        let _ = tv_window.emit("eq", &response);
+                    }
+                    None => {}
+                },
+                Err(e) => {
+                    debug!("Error locking TV_WINDOW: {}", e);
+                }
+            }
+
+            ConsoleMessage {
+                kind: MessageKind::Console,
+                message: response
+                    .frequencies
+                    .iter()
+                    .zip(response.gains.iter())
+                    .map(|(frequency, gain)| format!("{:>7.0} Hz {:>+7.1} dB", frequency, gain))
+                    .collect(),
+            }
+        }
+        Err(e) => ConsoleMessage {
+            kind: MessageKind::Error,
+            message: vec![e],
+        },
+    }
+}
+
+/// ## `seq_save(_window: tauri::Window, name: String) -> ConsoleMessage`
+///
+/// Saves the pattern to the pattern folder of the config directory.
+///
+/// ### Arguments
+///
+/// * `name: String` - The name of the pattern file, with or without ".json"
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The result of the command
+#[tauri::command]
+async fn seq_save(_window: tauri::Window, name: String) -> ConsoleMessage {
+    let filename = match folder_file(PATTERN_ROOT, &name) {
+        Ok(filename) => filename,
+        Err(e) => {
+            return ConsoleMessage {
+                kind: MessageKind::Error,
+                message: vec![e],
+            };
+        }
+    };
+    std::fs::create_dir_all(PATTERN_ROOT).unwrap_or_default();
+
+    let result = match audio::CONTROL.lock() {
+        Ok(control) => control.pattern().save(&filename),
+        Err(e) => Err(format!("Error locking CONTROL: {}", e)),
+    };
+
+    console_result(result.map(|()| format!("Saved the pattern to {}", filename)))
+}
+
+/// ## `seq_load(_window: tauri::Window, name: String) -> ConsoleMessage`
+///
+/// Loads a pattern from the pattern folder of the config directory. A playing sequencer carries on with the new pattern.
+///
+/// ### Arguments
+///
+/// * `name: String` - The name of the pattern file, with or without ".json"
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The result of the command
+#[tauri::command]
+async fn seq_load(_window: tauri::Window, name: String) -> ConsoleMessage {
+    let filename = match folder_file(PATTERN_ROOT, &name) {
+        Ok(filename) => filename,
+        Err(e) => {
+            return ConsoleMessage {
+                kind: MessageKind::Error,
+                message: vec![e],
+            };
+        }
+    };
+    let pattern = match sequencer::Pattern::load(&filename) {
+        Ok(pattern) => pattern,
+        Err(e) => {
+            return ConsoleMessage {
+                kind: MessageKind::Error,
+                message: vec![e],
+            };
+        }
+    };
+
+    let tracks = pattern.tracks.len();
+    console_result(match audio::CONTROL.lock() {
+        Ok(mut control) => {
+            // a track playing a strip that doesn't exist would send notes nowhere, as seq_track refuses
+            let strips = control.routes().len();
+            match pattern.tracks.iter().find(|track| track.strip >= strips) {
+                Some(track) => Err(format!(
+                    "Track {} of {} plays strip {}, but there are only {} strips",
+                    track.name, filename, track.strip, strips
+                )),
+                None => control
+                    .set_pattern(pattern)
+                    .map(|()| format!("Loaded {} ({} tracks)", filename, tracks)),
+            }
+        }
+        Err(e) => Err(format!("Error locking CONTROL: {}", e)),
+    })
+}
+
+/// ## `change_pattern(change: impl FnOnce(&mut sequencer::Pattern) -> Result<String, String>) -> Result<String, String>`
+///
+/// Changes a copy of the sequencer's pattern and sends it to the audio thread.
+///
+/// ### Arguments
+///
+/// * `change: impl FnOnce(&mut sequencer::Pattern) -> Result<String, String>` - Changes the pattern, returning a message
+///
+/// ### Returns
+///
+/// * `Result<String, String>` - The message, or an error message
+fn change_pattern(
+    change: impl FnOnce(&mut sequencer::Pattern) -> Result<String, String>,
+) -> Result<String, String> {
+    match audio::CONTROL.lock() {
+        Ok(mut control) => {
+            let mut pattern = control.pattern().clone();
+            let message = change(&mut pattern)?;
+            control.set_pattern(pattern)?;
+            Ok(message)
+        }
+        Err(e) => Err(format!("Error locking CONTROL: {}", e)),
+    }
+}
+
+/// ## `find_track<'a>(pattern: &'a mut sequencer::Pattern, track: &str) -> Result<&'a mut sequencer::Track, String>`
+///
+/// Finds a track of a pattern by name or index.
+///
+/// ### Arguments
+///
+/// * `pattern: &mut sequencer::Pattern` - The pattern
+/// * `track: &str` - The name or index of the track
+///
+/// ### Returns
+///
+/// * `Result<&mut sequencer::Track, String>` - The track, or an error message
+fn find_track<'a>(
+    pattern: &'a mut sequencer::Pattern,
+    track: &str,
+) -> Result<&'a mut sequencer::Track, String> {
+    match pattern.track(track) {
+        Some(index) => Ok(&mut pattern.tracks[index]),
+        None => Err(format!("No track {}", track)),
+    }
+}
+
+/// ## `folder_file(folder: &str, name: &str) -> Result<String, String>`
+///
+/// Returns the path of a JSON file in one of the program's folders (e.g. the kit or pattern folder).
+/// Names with path separators or ".." are refused, so a name can't reach outside the folder.
+///
+/// ### Arguments
+///
+/// * `folder: &str` - The folder, ending with a separator
+/// * `name: &str` - The name of the file, with or without ".json"
+///
+/// ### Returns
+///
+/// * `Result<String, String>` - The path, or an error message
+fn folder_file(folder: &str, name: &str) -> Result<String, String> {
+    let name = name.strip_suffix(".json").unwrap_or(name);
+    match name.is_empty() || name.contains(|c: char| c == '/' || c == '\\') || name.contains("..") {
+        true => Err(format!(
+            "Invalid name {}: expected a file name in {}",
+            name, folder
+        )),
+        false => Ok(format!("{}{}.json", folder, name)),
+    }
+}
+
+/// ## `console_result(result: Result<String, String>) -> ConsoleMessage`
+///
+/// Turns the result of a command into a console message.
+///
+/// ### Arguments
+///
+/// * `result: Result<String, String>` - A message, or an error message
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The console message
+fn console_result(result: Result<String, String>) -> ConsoleMessage {
+    match result {
+        Ok(message) => ConsoleMessage {
+            kind: MessageKind::Console,
+            message: vec![message],
+        },
+        Err(e) => ConsoleMessage {
+            kind: MessageKind::Error,
+            message: vec![e],
+        },
+    }
+}
+
+/// ## `parse_target(target: &str) -> Result<audio::Target, String>`
+///
+/// Parses a strip index, or "master" for the master bus.
+///
+/// ### Arguments
+///
+/// * `target: &str` - The target to parse
+///
+/// ### Returns
+///
+/// * `Result<audio::Target, String>` - The target, or an error message
+fn parse_target(target: &str) -> Result<audio::Target, String> {
+    match target.trim().to_lowercase().as_str() {
+        "master" => Ok(audio::Target::Master),
+        index => match index.parse::<usize>() {
+            Ok(index) => Ok(audio::Target::Strip(index)),
+            Err(_e) => Err(format!(
+                "Invalid strip {}: expected an index or master",
+                target
+            )),
+        },
+    }
+}
+
+/// ## `parse_effect(effect: &str) -> Result<Option<usize>, String>`
+///
+/// Parses the index of an effect in a chain, or "gen" for a strip's generator.
+///
+/// ### Arguments
+///
+/// * `effect: &str` - The effect to parse
+///
+/// ### Returns
+///
+/// * `Result<Option<usize>, String>` - The index of the effect, None for the generator, or an error message
+fn parse_effect(effect: &str) -> Result<Option<usize>, String> {
+    match effect.trim().to_lowercase().as_str() {
+        "gen" | "generator" => Ok(None),
+        index => match index.parse::<usize>() {
+            Ok(index) => Ok(Some(index)),
+            Err(_e) => Err(format!(
+                "Invalid effect {}: expected an index or gen",
+                effect
+            )),
+        },
+    }
+}
+
+/// ## `parse_switch(state: Option<&str>, current: bool) -> Result<bool, String>`
+///
+/// Parses "on" or "off", toggling the current value if no state is given.
+///
+/// ### Arguments
+///
+/// * `state: Option<&str>` - "on" or "off"
+/// * `current: bool` - The current value
+///
+/// ### Returns
+///
+/// * `Result<bool, String>` - The new value, or an error message
+fn parse_switch(state: Option<&str>, current: bool) -> Result<bool, String> {
+    match state {
+        Some(state) => match state.to_lowercase().as_str() {
+            "on" | "true" | "1" => Ok(true),
+            "off" | "false" | "0" => Ok(false),
+            _ => Err(format!("Invalid state {}: expected on or off", state)),
+        },
+        None => Ok(!current),
+    }
+}
+
+/// ## `parse_input(input: &str) -> Result<audio::Input, String>`
+///
+/// Parses a strip input, either one or two input channels (e.g. "0" or "0,1") or a bus (e.g. "bus:drums").
+///
+/// ### Arguments
+///
+/// * `input: &str` - The input to parse
+///
+/// ### Returns
+///
+/// * `Result<audio::Input, String>` - The input, or an error message
+fn parse_input(input: &str) -> Result<audio::Input, String> {
+    match parse_bus(input) {
+        Some(bus) => return Ok(audio::Input::Bus(bus?)),
+        None => {}
+    }
+
+    match parse_channels(input)?.as_slice() {
+        [channel] => Ok(audio::Input::Mono(*channel)),
+        [left, right] => Ok(audio::Input::Stereo(*left, *right)),
+        _ => Err("expected one or two input channels".to_string()),
+    }
+}
+
+/// ## `parse_output(output: &str) -> Result<audio::Output, String>`
+///
+/// Parses a strip output, either one or two output channels (e.g. "0" or "0,1") or a bus (e.g. "bus:drums").
+///
+/// ### Arguments
+///
+/// * `output: &str` - The output to parse
+///
+/// ### Returns
+///
+/// * `Result<audio::Output, String>` - The output, or an error message
+fn parse_output(output: &str) -> Result<audio::Output, String> {
+    match parse_bus(output) {
+        Some(bus) => return Ok(audio::Output::Bus(bus?)),
+        None => {}
+    }
+
+    match parse_channels(output)?.as_slice() {
+        [channel] => Ok(audio::Output::Mono(*channel)),
+        [left, right] => Ok(audio::Output::Stereo(*left, *right)),
+        _ => Err("expected one or two output channels".to_string()),
+    }
+}
+
+/// ## `parse_bus(spec: &str) -> Option<Result<String, String>>`
+///
+/// Parses a bus name in the form "bus:<name>".
+///
+/// ### Arguments
+///
+/// * `spec: &str` - The text to parse
+///
+/// ### Returns
+///
+/// * `Option<Result<String, String>>` - None if the text is not a bus, otherwise the bus name or an error message
+fn parse_bus(spec: &str) -> Option<Result<String, String>> {
+    match spec.trim().strip_prefix("bus:") {
+        Some(name) if name.trim().is_empty() => Some(Err("bus name is empty".to_string())),
+        Some(name) => Some(Ok(name.trim().to_string())),
+        None => None,
+    }
+}
+
+/// ## `parse_channels(channels: &str) -> Result<Vec<u32>, String>`
+///
+/// Parses a comma separated list of channel numbers, such as "0" or "0,1".
+///
+/// ### Arguments
+///
+/// * `channels: &str` - The list of channels
+///
+/// ### Returns
+///
+/// * `Result<Vec<u32>, String>` - The channel numbers, or an error message
+fn parse_channels(channels: &str) -> Result<Vec<u32>, String> {
+    let mut result = Vec::new();
+    for channel in channels.split(',') {
+        match channel.trim().parse::<u32>() {
+            Ok(channel) => result.push(channel),
+            Err(e) => return Err(format!("{} is not a channel number ({})", channel, e)),
+        }
+    }
+    Ok(result)
+}
+
+/// ## `key_note(key: &Key) -> Option<u8>`
+///
+/// Maps the computer keyboard to a piano keyboard, starting at middle C on A.
+///
+/// ### Arguments
+///
+/// * `key: &Key` - The key
+///
+/// ### Returns
+///
+/// * `Option<u8>` - The MIDI note number, or None if the key is not part of the keyboard
+fn key_note(key: &Key) -> Option<u8> {
+    match key {
+        Key::A => Some(60),
+        Key::W => Some(61),
+        Key::S => Some(62),
+        Key::E => Some(63),
+        Key::D => Some(64),
+        Key::F => Some(65),
+        Key::T => Some(66),
+        Key::G => Some(67),
+        Key::Y => Some(68),
+        Key::H => Some(69),
+        Key::U => Some(70),
+        Key::J => Some(71),
+        Key::K => Some(72),
+        Key::O => Some(73),
+        Key::L => Some(74),
+        Key::P => Some(75),
+        Key::Semicolon => Some(76),
+        Key::Apostrophe => Some(77),
+        _ => None,
+    }
+}
+
+/// ## `set_global_config_value(key: &str, value: &str) -> Result<(), String>`
+///
+/// Sets a value in the global config.
+///
+/// ### Arguments
+///
+/// * `key: &str` - The key to set
+/// * `value: &str` - The value to set
+///
+/// ### Returns
+///
+/// * `Ok(())` - The result of the command
+/// * `Err(String)` - The result of the command
+fn set_global_config_value(key: &str, value: &str, update: bool) -> Result<(), String> {
+    let config = CONFIG.lock();
+    let mut config = match config {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Error locking CONFIG: {}", e);
+            return Err(format!("Error locking CONFIG: {}", e));
+        }
+    };
+
+    if update {
+        config.set_str(key, value);
+    } else {
+        config.set_str_no_update(key, value);
+    }
+
+    Ok(())
+}
+
+/// ## `headless() -> Option<audio::Headless>`
+///
+/// Gets the selected headless host, if any.
+///
+/// ### Returns
+///
+/// * `Option<audio::Headless>` - The headless host, or None if a device host is selected
+fn headless() -> Option<audio::Headless> {
+    match audio::HEADLESS.lock() {
+        Ok(headless) => headless.clone(),
+        Err(e) => {
+            debug!("Error locking HEADLESS: {}", e);
+            None
+        }
+    }
+}
+
+fn global_config_force_update() {
+    let config = CONFIG.lock();
+    let mut config = match config {
+        Ok(config) => config,
+        Err(e) => {
+            debug!("Error locking CONFIG: {}", e);
+            return;
+        }
+    };
+
+    config.force_update();
+}
+
+#[tauri::command]
+async fn exit() -> ConsoleMessage {
+    // only exit if the config is saved
+    let config = CONFIG.lock();
+    let config = match config {
+        Ok(config) => config,
+        Err(e) => {
+            debug!("Error locking CONFIG: {}", e);
+            return ConsoleMessage {
+                kind: MessageKind::Error,
+                message: vec![format!("Error locking CONFIG: {}", e)],
+            };
+        }
+    };
+
+    if config.state() == &config::State::Saved {
+        std::process::exit(0);
+    }
+
+    // otherwise, prompt the user to save
+    return ConsoleMessage {
+        kind: MessageKind::Console,
+        message: vec![format!("Unsaved changes. Save before exiting?")],
+    };
+}
+
+#[tauri::command]
+async fn confirm_exit() -> ConsoleMessage {
+    // exit program
+    std::process::exit(0);
+}
+
+#[tauri::command]
+async fn midi_list(_window: tauri::Window) -> ConsoleMessage {
+    // call midi.rs function
+    debug!("Calling midi::midi_list()");
     let midi_devices = midi::midi_list();
     ConsoleMessage {
         kind: MessageKind::Console,
@@ -1049,32 +3382,119 @@ async fn midi_list(_window: tauri::Window) -> ConsoleMessage {
     }
 }
 
+/// ## `midi_start(_window: tauri::Window, device_name: String) -> ConsoleMessage`
+///
+/// Opens a MIDI input, alongside any already open.
+///
+/// ### Arguments
+///
+/// * `device_name: String` - The index or name of the device; names can be partial
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The result of the command
 #[tauri::command]
 async fn midi_start(_window: tauri::Window, device_name: String) -> ConsoleMessage {
-    // let device_name = "AKM320".to_owned();
-    // call midi.rs function
     debug!("Calling midi::midi_start()");
-    let device_name_clone = device_name.clone();
-    let _thread = std::thread::spawn(move || {
-        let _midi_devices = midi::midi_start(device_name_clone);
-    });
-    ConsoleMessage {
-        kind: MessageKind::Console,
-        message: vec![format!("MIDI device {} started", device_name)],
-    }
+    console_result(
+        midi::midi_start(device_name).map(|port| format!("MIDI device {} started", port)),
+    )
 }
 
+/// ## `midi_stop(_window: tauri::Window, device_name: String) -> ConsoleMessage`
+///
+/// Closes a MIDI input.
+///
+/// ### Arguments
+///
+/// * `device_name: String` - The name of the device, or "all"; names can be partial
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The result of the command
 #[tauri::command]
 async fn midi_stop(_window: tauri::Window, device_name: String) -> ConsoleMessage {
-    // call midi.rs function
     debug!("Calling midi::midi_stop()");
-    //let midi_devices = midi::midi_stop(device_name);
-    ConsoleMessage {
-        kind: MessageKind::Console,
-        message: vec![format!("MIDI device {} stopped", device_name)],
+    console_result(
+        midi::midi_stop(device_name).map(|ports| format!("MIDI device {} stopped", ports)),
+    )
+}
+
+/// ## `midi_connected(_window: tauri::Window) -> ConsoleMessage`
+///
+/// Lists the open MIDI inputs.
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The result of the command
+#[tauri::command]
+async fn midi_connected(_window: tauri::Window) -> ConsoleMessage {
+    let ports = midi::midi_connected();
+    match ports.is_empty() {
+        true => ConsoleMessage {
+            kind: MessageKind::Console,
+            message: vec!["No MIDI devices are started".to_owned()],
+        },
+        false => ConsoleMessage {
+            kind: MessageKind::Console,
+            message: ports,
+        },
     }
 }
 
+/// ## `midi_channel(_window: tauri::Window, strip: usize, channel: Option<String>) -> ConsoleMessage`
+///
+/// Makes a strip play incoming MIDI from one channel, or from every channel.
+///
+/// ### Arguments
+///
+/// * `strip: usize` - The index of the strip
+/// * `channel: Option<String>` - The channel (1-16), or "all"; every channel if not given
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The result of the command
+#[tauri::command]
+async fn midi_channel(
+    _window: tauri::Window,
+    strip: usize,
+    channel: Option<String>,
+) -> ConsoleMessage {
+    let channel = match channel.as_deref() {
+        None | Some("all") | Some("omni") => Ok(None),
+        Some(channel) => match channel.parse::<u8>() {
+            Ok(channel) if (1..=16).contains(&channel) => Ok(Some(channel - 1)),
+            _ => Err(format!(
+                "Invalid MIDI channel {}: expected 1 to 16 or all",
+                channel
+            )),
+        },
+    };
+
+    let exists = match audio::CONTROL.lock() {
+        Ok(control) => match strip < control.routes().len() {
+            true => Ok(()),
+            false => Err(format!("No strip {}", strip)),
+        },
+        Err(e) => Err(format!("Error locking CONTROL: {}", e)),
+    };
+
+    let result = exists.and(channel).and_then(|channel| {
+        midi::subscribe(strip, channel).map(|()| match channel {
+            Some(channel) => format!("Strip {} plays MIDI channel {}", strip, channel + 1),
+            None => format!("Strip {} plays every MIDI channel", strip),
+        })
+    });
+    console_result(result)
+}
+
+/// ## `hid_list(_window: tauri::Window) -> ConsoleMessage`
+///
+/// Lists the HID devices. The keyboard the app is played from gets a synth strip and key handlers the first time it is listed.
+///
+/// ### Returns
+///
+/// * `ConsoleMessage` - The devices
 #[tauri::command]
 async fn hid_list(_window: tauri::Window) -> ConsoleMessage {
     // call midi.rs function
@@ -1082,101 +3502,85 @@ async fn hid_list(_window: tauri::Window) -> ConsoleMessage {
     let mut interfaces = interface::get_interfaces();
     for interface in interfaces.iter_mut() {
         if interface.id() == 3294123376 {
-            let arc_generator = Arc::new(Mutex::new(SineGenerator::new()));
+            // listing again leaves a device that is already playing alone
+            match HID_DEVICES.lock() {
+                Ok(devices) => match devices.contains(&interface.id()) {
+                    true => continue,
+                    false => {}
+                },
+                Err(e) => {
+                    debug!("Error locking HID_DEVICES: {}", e);
+                    continue;
+                }
+            }
+
+            let oscillator = match oscillator::create_oscillator("sine") {
+                Ok(oscillator) => oscillator,
+                Err(e) => {
+                    debug!("Error creating hid oscillator: {}", e);
+                    continue;
+                }
+            };
             let mut new_strip = audio::Strip::new(
-                audio::Input::Generator(arc_generator.clone()),
+                audio::Input::Generator(Box::new(synth::Synth::new(
+                    oscillator,
+                    8,
+                    synth::VoiceMode::Poly,
+                ))),
                 audio::Output::Stereo(0, 1),
             );
             new_strip.add_effect(Box::new(audio::plugin::BitCrusher::new(16)));
+            // a quarter of a second of delay
             new_strip.add_effect(Box::new(audio::plugin::Delay::new(
-                (44100.0 / 4.0) as usize,
+                (audio::sample_rate() / 4) as usize,
                 0.1,
             )));
 
-            let arc_clone_keydown = arc_generator.clone();
-            let arc_clone_keyup = arc_generator.clone();
+            let strip = match audio::CONTROL.lock() {
+                Ok(mut control) => match control.add_strip(new_strip) {
+                    Ok(index) => index,
+                    Err(e) => {
+                        debug!("Error adding hid strip: {}", e);
+                        continue;
+                    }
+                },
+                Err(e) => {
+                    debug!("Error locking CONTROL: {}", e);
+                    continue;
+                }
+            };
+
+            match HID_STRIPS.lock() {
+                Ok(mut strips) => strips.push(strip),
+                Err(e) => {
+                    debug!("Error locking HID_STRIPS: {}", e);
+                }
+            }
+            match HID_DEVICES.lock() {
+                Ok(mut devices) => devices.push(interface.id()),
+                Err(e) => {
+                    debug!("Error locking HID_DEVICES: {}", e);
+                }
+            }
 
             interface.thread();
             interface.keydown(Box::new(move |key| {
                 debug!("Key down: {}", key);
-                let mut generator = match arc_clone_keydown.lock() {
-                    Ok(generator) => generator,
-                    Err(err) => {
-                        return;
-                    }
-                };
-
-                let freq: f32 = match key {
-                    Key::A => 261.626,
-                    Key::W => 277.183,
-                    Key::S => 293.665,
-                    Key::E => 311.127,
-                    Key::D => 329.628,
-                    Key::F => 349.228,
-                    Key::T => 369.994,
-                    Key::G => 391.995,
-                    Key::Y => 415.305,
-                    Key::H => 440.000,
-                    Key::U => 466.164,
-                    Key::J => 493.883,
-                    Key::K => 523.251,
-                    Key::O => 554.365,
-                    Key::L => 587.330,
-                    Key::P => 622.254,
-                    Key::Semicolon => 659.255,
-                    Key::Apostrophe => 698.456,
-                    _ => 0.0,
-                };
-
-                if freq > 0.0 {
-                    generator.add_freq(freq, 1.0);
+                hid_key(&key, 1.0);
+                match key_note(&key) {
+                    Some(note) => hid_note(note, 1.0),
+                    None => {}
                 }
             }));
 
             interface.keyup(Box::new(move |key| {
                 debug!("Key up: {}", key);
-                let mut generator = match arc_clone_keyup.lock() {
-                    Ok(generator) => generator,
-                    Err(err) => {
-                        return;
-                    }
-                };
-
-                let freq: f32 = match key {
-                    Key::A => 261.626,
-                    Key::W => 277.183,
-                    Key::S => 293.665,
-                    Key::E => 311.127,
-                    Key::D => 329.628,
-                    Key::F => 349.228,
-                    Key::T => 369.994,
-                    Key::G => 391.995,
-                    Key::Y => 415.305,
-                    Key::H => 440.000,
-                    Key::U => 466.164,
-                    Key::J => 493.883,
-                    Key::K => 523.251,
-                    Key::O => 554.365,
-                    Key::L => 587.330,
-                    Key::P => 622.254,
-                    Key::Semicolon => 659.255,
-                    Key::Apostrophe => 698.456,
-                    _ => 0.0,
-                };
-
-                if freq > 0.0 {
-                    generator.remove_freq(freq);
+                hid_key(&key, 0.0);
+                match key_note(&key) {
+                    Some(note) => hid_note(note, 0.0),
+                    None => {}
                 }
             }));
-
-            match audio::STRIPS.write() {
-                Ok(mut strips) => {
-                    strips.push(new_strip);
-                }
-                Err(e) => {
-                    debug!("Error locking STRIPS: {}", e);
-                }
-            }
         }
     }
     let mut hid_devices: Vec<String> = Vec::new();
@@ -1247,9 +3651,53 @@ fn main() {
             input_select,
             input_stream_show,
             input_stream_set,
+            input_strip,
+            strip_list,
+            strip_output,
+            strip_parameter,
+            strip_effect,
+            strip_gain,
+            strip_pan,
+            strip_mute,
+            strip_solo,
+            meter_show,
+            meter_reset,
+            bus_list,
+            render,
+            sample_load,
+            sample_grain,
+            sample_trigger,
+            sample_release,
+            synth_new,
+            kit_list,
+            kit_load,
+            seq_show,
+            seq_new,
+            seq_track,
+            seq_grid,
+            seq_step,
+            seq_mute,
+            seq_remove,
+            seq_save,
+            seq_load,
+            transport_show,
+            transport_play,
+            transport_locate,
+            transport_tempo,
+            transport_signature,
+            clock_show,
+            clock_follow,
+            clock_send,
+            clock_stop,
+            eq_add,
+            eq_band,
+            eq_show,
+            eq_response,
             midi_list,
             midi_start,
             midi_stop,
+            midi_connected,
+            midi_channel,
             hid_list
         ])
         .run(tauri::generate_context!())
@@ -1338,6 +3786,73 @@ fn on_config_update(config: &mut config::Config) {
         }
     };
 
+    // headless hosts have no devices, and take any output config
+    let headless = audio::Headless::from_host(&host_name, &output_name);
+    match audio::HEADLESS.try_lock() {
+        Ok(mut headless_mutex) => {
+            *headless_mutex = headless.clone();
+        }
+        Err(e) => {
+            debug!("Error locking HEADLESS: {}", e);
+        }
+    }
+
+    match headless {
+        Some(headless) => {
+            debug!("Using headless host {}", headless.name());
+            match audio::INPUT_DEVICE.try_lock() {
+                Ok(mut input_device_mutex) => {
+                    *input_device_mutex = None;
+                }
+                Err(e) => {
+                    debug!("Error locking INPUT_DEVICE: {}", e);
+                }
+            }
+
+            match audio::OUTPUT_DEVICE.try_lock() {
+                Ok(mut output_device_mutex) => {
+                    *output_device_mutex = None;
+                }
+                Err(e) => {
+                    debug!("Error locking OUTPUT_DEVICE: {}", e);
+                }
+            }
+
+            match audio::INPUT_CONFIG.try_lock() {
+                Ok(mut input_config_mutex) => {
+                    *input_config_mutex = None;
+                }
+                Err(e) => {
+                    debug!("Error locking INPUT_CONFIG: {}", e);
+                }
+            }
+
+            match audio::OUTPUT_CONFIG.try_lock() {
+                Ok(mut output_config_mutex) => {
+                    *output_config_mutex = Some(audio::headless_config(
+                        output_channels as u32,
+                        output_samples as u32,
+                        output_buffer_size as u32,
+                    ));
+                }
+                Err(e) => {
+                    debug!("Error locking OUTPUT_CONFIG: {}", e);
+                }
+            }
+
+            match audio::reload() {
+                Ok(()) => {
+                    debug!("Audio thread ran successfully");
+                }
+                Err(e) => {
+                    debug!("Error in audio thread: {}", e);
+                }
+            }
+            return;
+        }
+        None => {}
+    }
+
     let input_device = audio::get_input_device(&input_name, &host);
     let output_device = audio::get_output_device(&output_name, &host);
 
@@ -0,0 +1,616 @@
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+
+use crate::audio::{gain_to_db, Buffer, State};
+
+/// How many times per second levels are sent to the console window
+pub const METER_RATE: u64 = 20;
+
+/// The most channels a meter reads; the master bus can have more than the two channels of a strip
+pub const METER_CHANNELS: usize = 8;
+
+/// The lowest level reported, in dB. Anything quieter (including silence) is reported as this, since JSON has no infinity.
+pub const METER_FLOOR: f32 = -100.0;
+
+/// How long a peak is held before the hold falls back to the current peak, in seconds
+pub const PEAK_HOLD: f32 = 2.0;
+
+/// How fast the peak falls, in dB per second
+pub const PEAK_FALLOFF: f32 = 20.0;
+
+/// The time constant of the RMS average, in seconds
+pub const RMS_WINDOW: f32 = 0.3;
+
+/// The number of 100 ms blocks in the 3 second short-term loudness window
+const SHORT_TERM_BLOCKS: usize = 30;
+
+/// The number of 100 ms blocks in the 400 ms momentary loudness window, which is also the gating block for integrated loudness
+const MOMENTARY_BLOCKS: usize = 4;
+
+/// The lowest loudness kept in the integrated loudness histogram, which is also the absolute gate
+const HISTOGRAM_FLOOR: f32 = -70.0;
+
+/// The number of bins in the integrated loudness histogram, 0.1 LU each, from HISTOGRAM_FLOOR to +10 LUFS
+const HISTOGRAM_BINS: usize = 800;
+
+/// ## Levels
+///
+/// The levels of a strip or the master bus, as sent to the console window.
+///
+/// ### Fields
+///
+/// * `name: String` - The strip index, or "master"
+/// * `peak: Vec<f32>` - The peak of each channel in dBFS, falling at PEAK_FALLOFF
+/// * `hold: Vec<f32>` - The held peak of each channel in dBFS
+/// * `rms: Vec<f32>` - The RMS level of each channel in dBFS
+/// * `momentary: f32` - The momentary loudness (400 ms) in LUFS
+/// * `short_term: f32` - The short-term loudness (3 s) in LUFS
+/// * `integrated: f32` - The gated integrated loudness since the last reset, in LUFS
+/// * `clips: u32` - The number of samples at or over full scale since the last reset
+///
+/// ### Attributes
+///
+/// * `#[derive(ts_rs::TS, serde::Serialize, serde::Deserialize)]` - Serde and TS-RS are used to make this struct available to both Rust and TypeScript.
+/// * `#[ts(export, export_to = "../src/bindings/Levels.ts")]` - This struct is exported to TypeScript, and is used by the console meters.
+#[derive(ts_rs::TS, serde::Serialize, serde::Deserialize, Clone, Debug)]
+#[ts(export, export_to = "../src/bindings/Levels.ts")]
+pub struct Levels {
+    pub name: String,
+    pub peak: Vec<f32>,
+    pub hold: Vec<f32>,
+    pub rms: Vec<f32>,
+    pub momentary: f32,
+    pub short_term: f32,
+    pub integrated: f32,
+    pub clips: u32,
+}
+
+/// ## Meters
+///
+/// The levels of every strip and the master bus, emitted to the console window as the "meters" event.
+///
+/// ### Fields
+///
+/// * `strips: Vec<Levels>` - The levels of every strip, in the order they were added
+/// * `master: Levels` - The levels of the master bus
+///
+/// ### Attributes
+///
+/// * `#[derive(ts_rs::TS, serde::Serialize, serde::Deserialize)]` - Serde and TS-RS are used to make this struct available to both Rust and TypeScript.
+/// * `#[ts(export, export_to = "../src/bindings/Meters.ts")]` - This struct is exported to TypeScript, and is used by the console meters.
+#[derive(ts_rs::TS, serde::Serialize, serde::Deserialize, Clone, Debug)]
+#[ts(export, export_to = "../src/bindings/Meters.ts")]
+pub struct Meters {
+    pub strips: Vec<Levels>,
+    pub master: Levels,
+}
+
+/// ## Readout
+///
+/// The latest levels of a meter. Written by the audio thread after every block and read by the control side,
+/// through atomics so neither side ever waits on the other.
+///
+/// ### Fields
+///
+/// * `channels: AtomicU32` - The number of channels being metered
+/// * `peak: [AtomicU32; METER_CHANNELS]` - The peak of each channel in dB, as f32 bits
+/// * `hold: [AtomicU32; METER_CHANNELS]` - The held peak of each channel in dB, as f32 bits
+/// * `rms: [AtomicU32; METER_CHANNELS]` - The RMS level of each channel in dB, as f32 bits
+/// * `momentary: AtomicU32` - The momentary loudness in LUFS, as f32 bits
+/// * `short_term: AtomicU32` - The short-term loudness in LUFS, as f32 bits
+/// * `integrated: AtomicU32` - The integrated loudness in LUFS, as f32 bits
+/// * `clips: AtomicU32` - The number of clipped samples
+/// * `reset: AtomicBool` - Asks the audio thread to reset the integrated loudness and clip count
+///
+/// ### Functions
+///
+/// * `new() -> Self` - Creates a silent readout
+/// * `levels(&self, name: &str) -> Levels` - Reads the levels
+/// * `reset(&self)` - Resets the integrated loudness and clip count at the next block
+#[derive(Debug)]
+pub struct Readout {
+    channels: AtomicU32,
+    peak: [AtomicU32; METER_CHANNELS],
+    hold: [AtomicU32; METER_CHANNELS],
+    rms: [AtomicU32; METER_CHANNELS],
+    momentary: AtomicU32,
+    short_term: AtomicU32,
+    integrated: AtomicU32,
+    clips: AtomicU32,
+    reset: AtomicBool,
+}
+
+impl Readout {
+    /// ## `new() -> Self`
+    ///
+    /// Creates a silent readout.
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The new readout
+    pub fn new() -> Self {
+        let floor = || AtomicU32::new(METER_FLOOR.to_bits());
+        Self {
+            channels: AtomicU32::new(0),
+            peak: std::array::from_fn(|_| floor()),
+            hold: std::array::from_fn(|_| floor()),
+            rms: std::array::from_fn(|_| floor()),
+            momentary: floor(),
+            short_term: floor(),
+            integrated: floor(),
+            clips: AtomicU32::new(0),
+            reset: AtomicBool::new(false),
+        }
+    }
+
+    /// ## `levels(&self, name: &str) -> Levels`
+    ///
+    /// Reads the levels.
+    ///
+    /// ### Arguments
+    ///
+    /// * `name: &str` - The name to report the levels under
+    ///
+    /// ### Returns
+    ///
+    /// * `Levels` - The levels
+    pub fn levels(&self, name: &str) -> Levels {
+        let read = |value: &AtomicU32| f32::from_bits(value.load(Ordering::Relaxed));
+        let channels = (self.channels.load(Ordering::Relaxed) as usize).min(METER_CHANNELS);
+        Levels {
+            name: name.to_owned(),
+            peak: self.peak[..channels].iter().map(read).collect(),
+            hold: self.hold[..channels].iter().map(read).collect(),
+            rms: self.rms[..channels].iter().map(read).collect(),
+            momentary: read(&self.momentary),
+            short_term: read(&self.short_term),
+            integrated: read(&self.integrated),
+            clips: self.clips.load(Ordering::Relaxed),
+        }
+    }
+
+    /// ## `reset(&self)`
+    ///
+    /// Resets the integrated loudness and clip count at the next block.
+    pub fn reset(&self) {
+        self.reset.store(true, Ordering::Relaxed);
+    }
+}
+
+/// ## `level(value: f32) -> f32`
+///
+/// Clamps a level to METER_FLOOR.
+///
+/// ### Arguments
+///
+/// * `value: f32` - The level in dB, possibly negative infinity or NaN
+///
+/// ### Returns
+///
+/// * `f32` - The level, no lower than METER_FLOOR
+fn level(value: f32) -> f32 {
+    match value.is_nan() {
+        true => METER_FLOOR,
+        false => value.max(METER_FLOOR),
+    }
+}
+
+/// ## `loudness(energy: f64) -> f32`
+///
+/// Converts a mean square sum of K-weighted channels to LUFS (ITU-R BS.1770).
+///
+/// ### Arguments
+///
+/// * `energy: f64` - The sum of the mean squares of every channel
+///
+/// ### Returns
+///
+/// * `f32` - The loudness in LUFS
+fn loudness(energy: f64) -> f32 {
+    (-0.691 + 10.0 * energy.log10()) as f32
+}
+
+/// ## Biquad
+///
+/// One K-weighting filter stage, in transposed direct form II. Runs in f64, since the high-pass sits at 38 Hz.
+///
+/// ### Fields
+///
+/// * `b: [f64; 3]` - The feed-forward coefficients
+/// * `a: [f64; 2]` - The feedback coefficients, a1 and a2
+/// * `state: [[f64; 2]; METER_CHANNELS]` - The filter state of each channel
+#[derive(Clone)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    state: [[f64; 2]; METER_CHANNELS],
+}
+
+impl Biquad {
+    fn process(&mut self, channel: usize, input: f64) -> f64 {
+        let state = &mut self.state[channel];
+        let output = self.b[0] * input + state[0];
+        state[0] = self.b[1] * input - self.a[0] * output + state[1];
+        state[1] = self.b[2] * input - self.a[1] * output;
+        output
+    }
+}
+
+/// ## `k_weighting(sample_rate: u32) -> (Biquad, Biquad)`
+///
+/// Works out the two K-weighting stages of ITU-R BS.1770 (a high shelf for the head, then a high-pass) for any sample rate.
+///
+/// ### Arguments
+///
+/// * `sample_rate: u32` - The sample rate
+///
+/// ### Returns
+///
+/// * `(Biquad, Biquad)` - The shelf and the high-pass
+fn k_weighting(sample_rate: u32) -> (Biquad, Biquad) {
+    let rate = sample_rate.max(1) as f64;
+
+    let f0 = 1681.974450955533;
+    let gain = 3.999843853973347;
+    let q = 0.7071752369554196;
+    let k = (std::f64::consts::PI * f0 / rate).tan();
+    let vh = 10.0f64.powf(gain / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        state: [[0.0; 2]; METER_CHANNELS],
+    };
+
+    let f0 = 38.13547087602444;
+    let q = 0.5003270373238773;
+    let k = (std::f64::consts::PI * f0 / rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        state: [[0.0; 2]; METER_CHANNELS],
+    };
+
+    (shelf, high_pass)
+}
+
+/// ## Meter
+///
+/// Measures a strip or the master bus on the audio thread: peak with hold, RMS, momentary, short-term and integrated loudness
+/// (ITU-R BS.1770 / EBU R128, every channel weighted equally), and clipped samples.
+/// Everything is allocated up front so measuring never allocates on the audio thread.
+///
+/// ### Fields
+///
+/// * `readout: Arc<Readout>` - Where the levels are published
+/// * `sample_rate: u32` - The sample rate the filters and windows are set up for
+/// * `shelf: Biquad` - The first K-weighting stage
+/// * `high_pass: Biquad` - The second K-weighting stage
+/// * `peak: [f32; METER_CHANNELS]` - The falling peak of each channel, as a gain
+/// * `hold: [(f32, f32); METER_CHANNELS]` - The held peak of each channel as a gain, and the seconds left to hold it
+/// * `rms: [f32; METER_CHANNELS]` - The running mean square of each channel
+/// * `block_frames: usize` - The number of frames in a 100 ms block
+/// * `block_position: usize` - The number of frames in the current 100 ms block so far
+/// * `block_energy: [f64; METER_CHANNELS]` - The K-weighted sum of squares of each channel in the current 100 ms block
+/// * `blocks: [f64; SHORT_TERM_BLOCKS]` - The energy of the last 30 blocks, used as a ring
+/// * `block_count: usize` - The number of blocks measured since the last reset
+/// * `histogram: Box<[(u32, f64); HISTOGRAM_BINS]>` - The number and summed energy of 400 ms gating blocks at each loudness
+/// * `clips: u32` - The number of clipped samples since the last reset
+///
+/// ### Functions
+///
+/// * `new() -> Self` - Creates a meter
+/// * `readout(&self) -> Arc<Readout>` - Returns where the levels are published
+/// * `process(&mut self, state: &State, buffer: &Buffer)` - Measures a block
+pub struct Meter {
+    readout: Arc<Readout>,
+    sample_rate: u32,
+    shelf: Biquad,
+    high_pass: Biquad,
+    peak: [f32; METER_CHANNELS],
+    hold: [(f32, f32); METER_CHANNELS],
+    rms: [f32; METER_CHANNELS],
+    block_frames: usize,
+    block_position: usize,
+    block_energy: [f64; METER_CHANNELS],
+    blocks: [f64; SHORT_TERM_BLOCKS],
+    block_count: usize,
+    histogram: Box<[(u32, f64); HISTOGRAM_BINS]>,
+    clips: u32,
+}
+
+impl Meter {
+    /// ## `new() -> Self`
+    ///
+    /// Creates a meter. The filters are set up for the sample rate of the first block.
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The new meter
+    pub fn new() -> Self {
+        let (shelf, high_pass) = k_weighting(48000);
+        Self {
+            readout: Arc::new(Readout::new()),
+            sample_rate: 0,
+            shelf,
+            high_pass,
+            peak: [0.0; METER_CHANNELS],
+            hold: [(0.0, 0.0); METER_CHANNELS],
+            rms: [0.0; METER_CHANNELS],
+            block_frames: 4800,
+            block_position: 0,
+            block_energy: [0.0; METER_CHANNELS],
+            blocks: [0.0; SHORT_TERM_BLOCKS],
+            block_count: 0,
+            histogram: Box::new([(0, 0.0); HISTOGRAM_BINS]),
+            clips: 0,
+        }
+    }
+
+    /// ## `readout(&self) -> Arc<Readout>`
+    ///
+    /// Returns where the levels are published.
+    ///
+    /// ### Returns
+    ///
+    /// * `Arc<Readout>` - The readout
+    pub fn readout(&self) -> Arc<Readout> {
+        self.readout.clone()
+    }
+
+    /// ## `process(&mut self, state: &State, buffer: &Buffer)`
+    ///
+    /// Measures a block and publishes the levels.
+    ///
+    /// ### Arguments
+    ///
+    /// * `state: &State` - The current state of the audio engine
+    /// * `buffer: &Buffer` - The block to measure
+    pub fn process(&mut self, state: &State, buffer: &Buffer) {
+        if state.sample_rate != self.sample_rate {
+            self.configure(state.sample_rate);
+        }
+        if self.readout.reset.swap(false, Ordering::Relaxed) {
+            self.block_count = 0;
+            self.blocks = [0.0; SHORT_TERM_BLOCKS];
+            self.histogram.fill((0, 0.0));
+            self.clips = 0;
+        }
+
+        let channels = buffer.channels().min(METER_CHANNELS);
+        let rate = self.sample_rate as f32;
+        let seconds = buffer.len() as f32 / rate;
+        let falloff = 10.0f32.powf(-PEAK_FALLOFF * seconds / 20.0);
+        let rms_coefficient = (-1.0 / (RMS_WINDOW * rate)).exp();
+
+        for channel in 0..channels {
+            let mut block_peak = 0.0f32;
+            let mut mean_square = self.rms[channel];
+            for sample in buffer.channel(channel).iter() {
+                let magnitude = sample.abs();
+                block_peak = block_peak.max(magnitude);
+                if magnitude >= 1.0 {
+                    self.clips = self.clips.saturating_add(1);
+                }
+                mean_square =
+                    rms_coefficient * mean_square + (1.0 - rms_coefficient) * sample * sample;
+            }
+            self.rms[channel] = mean_square;
+
+            self.peak[channel] = block_peak.max(self.peak[channel] * falloff);
+            let (held, remaining) = &mut self.hold[channel];
+            if block_peak >= *held {
+                *held = block_peak;
+                *remaining = PEAK_HOLD;
+            } else {
+                *remaining -= seconds;
+                if *remaining <= 0.0 {
+                    *held = self.peak[channel];
+                }
+            }
+        }
+
+        for index in 0..buffer.len() {
+            for channel in 0..channels {
+                let sample = buffer.channel(channel)[index] as f64;
+                let weighted = self
+                    .high_pass
+                    .process(channel, self.shelf.process(channel, sample));
+                self.block_energy[channel] += weighted * weighted;
+            }
+
+            self.block_position += 1;
+            if self.block_position >= self.block_frames {
+                self.finish_block(channels);
+            }
+        }
+
+        self.publish(channels);
+    }
+
+    /// ## `configure(&mut self, sample_rate: u32)`
+    ///
+    /// Private function which sets the filters and windows up for a sample rate, starting the loudness measurement over.
+    ///
+    /// ### Arguments
+    ///
+    /// * `sample_rate: u32` - The sample rate
+    fn configure(&mut self, sample_rate: u32) {
+        let (shelf, high_pass) = k_weighting(sample_rate);
+        self.sample_rate = sample_rate.max(1);
+        self.shelf = shelf;
+        self.high_pass = high_pass;
+        self.block_frames = (self.sample_rate as usize / 10).max(1);
+        self.block_position = 0;
+        self.block_energy = [0.0; METER_CHANNELS];
+        self.blocks = [0.0; SHORT_TERM_BLOCKS];
+        self.block_count = 0;
+        self.histogram.fill((0, 0.0));
+    }
+
+    /// ## `finish_block(&mut self, channels: usize)`
+    ///
+    /// Private function which closes a 100 ms block, and adds the 400 ms block ending with it to the integrated loudness.
+    ///
+    /// ### Arguments
+    ///
+    /// * `channels: usize` - The number of channels being metered
+    fn finish_block(&mut self, channels: usize) {
+        let energy: f64 = self.block_energy[..channels]
+            .iter()
+            .map(|sum| sum / self.block_frames as f64)
+            .sum();
+        self.blocks[self.block_count % SHORT_TERM_BLOCKS] = energy;
+        self.block_count += 1;
+        self.block_position = 0;
+        self.block_energy = [0.0; METER_CHANNELS];
+
+        // gating blocks are 400 ms long and overlap by 75%
+        if self.block_count >= MOMENTARY_BLOCKS {
+            let gating = self.window(MOMENTARY_BLOCKS);
+            let loudness = loudness(gating);
+            if loudness > HISTOGRAM_FLOOR {
+                let bin = (((loudness - HISTOGRAM_FLOOR) * 10.0) as usize).min(HISTOGRAM_BINS - 1);
+                self.histogram[bin].0 += 1;
+                self.histogram[bin].1 += gating;
+            }
+        }
+    }
+
+    /// ## `window(&self, blocks: usize) -> f64`
+    ///
+    /// Private function which returns the mean energy of the most recent 100 ms blocks.
+    ///
+    /// ### Arguments
+    ///
+    /// * `blocks: usize` - The number of blocks to average
+    ///
+    /// ### Returns
+    ///
+    /// * `f64` - The mean energy, or 0.0 if nothing has been measured yet
+    fn window(&self, blocks: usize) -> f64 {
+        let blocks = blocks.min(self.block_count).min(SHORT_TERM_BLOCKS);
+        if blocks == 0 {
+            return 0.0;
+        }
+
+        let mut sum = 0.0;
+        for back in 1..=blocks {
+            sum += self.blocks[(self.block_count - back) % SHORT_TERM_BLOCKS];
+        }
+        sum / blocks as f64
+    }
+
+    /// ## `integrated(&self) -> f32`
+    ///
+    /// Private function which works out the integrated loudness: the mean of the gating blocks above the absolute gate,
+    /// then again over the blocks no more than 10 LU below that.
+    ///
+    /// ### Returns
+    ///
+    /// * `f32` - The integrated loudness in LUFS
+    fn integrated(&self) -> f32 {
+        let mean = |from: usize| {
+            let (count, energy) = self.histogram[from..]
+                .iter()
+                .fold((0u64, 0.0f64), |(count, energy), bin| {
+                    (count + bin.0 as u64, energy + bin.1)
+                });
+            match count {
+                0 => 0.0,
+                count => energy / count as f64,
+            }
+        };
+
+        let ungated = mean(0);
+        if ungated <= 0.0 {
+            return f32::NEG_INFINITY;
+        }
+
+        let threshold = loudness(ungated) - 10.0;
+        let from = ((threshold - HISTOGRAM_FLOOR) * 10.0).max(0.0) as usize;
+        loudness(mean(from.min(HISTOGRAM_BINS - 1)))
+    }
+
+    /// ## `publish(&self, channels: usize)`
+    ///
+    /// Private function which writes the levels to the readout.
+    ///
+    /// ### Arguments
+    ///
+    /// * `channels: usize` - The number of channels being metered
+    fn publish(&self, channels: usize) {
+        let write = |target: &AtomicU32, value: f32| {
+            target.store(level(value).to_bits(), Ordering::Relaxed)
+        };
+
+        self.readout
+            .channels
+            .store(channels as u32, Ordering::Relaxed);
+        for channel in 0..channels {
+            write(&self.readout.peak[channel], gain_to_db(self.peak[channel]));
+            write(
+                &self.readout.hold[channel],
+                gain_to_db(self.hold[channel].0),
+            );
+            write(
+                &self.readout.rms[channel],
+                gain_to_db(self.rms[channel].sqrt()),
+            );
+        }
+        write(
+            &self.readout.momentary,
+            loudness(self.window(MOMENTARY_BLOCKS)),
+        );
+        write(
+            &self.readout.short_term,
+            loudness(self.window(SHORT_TERM_BLOCKS)),
+        );
+        write(&self.readout.integrated, self.integrated());
+        self.readout.clips.store(self.clips, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn measure(sample_rate: u32) -> Levels {
+        let mut meter = Meter::new();
+        let amplitude = crate::audio::db_to_gain(-20.0);
+        let mut clock = 0;
+        for _ in 0..sample_rate as usize * 10 / 512 {
+            let state = State::advance(sample_rate, &mut clock, 512);
+            let mut buffer = Buffer::new(2, 512);
+            for channel in 0..2 {
+                for (index, sample) in buffer.channel_mut(channel).iter_mut().enumerate() {
+                    let time = (state.sample_clock + index as u64) as f64 / sample_rate as f64;
+                    *sample = amplitude * (std::f64::consts::TAU * 1000.0 * time).sin() as f32;
+                }
+            }
+            meter.process(&state, &buffer);
+        }
+        meter.readout().levels("test")
+    }
+
+    #[test]
+    fn stereo_sine_at_minus_20_reads_minus_20_lufs() {
+        for sample_rate in [44100, 48000, 96000] {
+            let levels = measure(sample_rate);
+            for loudness in [levels.momentary, levels.short_term, levels.integrated] {
+                assert!(
+                    (loudness + 20.0).abs() <= 0.1,
+                    "{} LUFS at {} Hz",
+                    loudness,
+                    sample_rate
+                );
+            }
+        }
+    }
+}
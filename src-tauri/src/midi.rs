@@ -2,198 +2,741 @@
 //!
 //! Module for handling midi devices
 
-use std::sync::RwLock;
+use std::sync::{Mutex, Once};
 
 #[allow(unused_imports)]
 use log::{debug, error, info, trace, warn};
 use midir;
 
-use midir::{Ignore, MidiInput, MidiOutput};
+use midir::{Ignore, MidiInput, MidiInputConnection, MidiInputPort, MidiOutput};
 
 use lazy_static::lazy_static;
 
 use crate::audio;
+use crate::clock;
 
+/// ## ChannelMessage
+///
+/// A channel voice message, without its channel. Small enough to be sent to the audio thread as is.
+///
+/// ### Variants
+///
+/// * `NoteOff { note, velocity }` - Releases a note; note ons with a velocity of 0 are read as note offs
+/// * `NoteOn { note, velocity }` - Starts a note (velocity 1-127)
+/// * `PolyPressure { note, pressure }` - Aftertouch on one note
+/// * `ControlChange { controller, value }` - Moves a controller, e.g. 1 for the mod wheel or 64 for the sustain pedal
+/// * `ProgramChange { program }` - Selects a program
+/// * `ChannelPressure { pressure }` - Aftertouch on the whole channel
+/// * `PitchBend { value }` - Bends the pitch, from -8192 (down) to 8191 (up); 0 is centered
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChannelMessage {
+    NoteOff { note: u8, velocity: u8 },
+    NoteOn { note: u8, velocity: u8 },
+    PolyPressure { note: u8, pressure: u8 },
+    ControlChange { controller: u8, value: u8 },
+    ProgramChange { program: u8 },
+    ChannelPressure { pressure: u8 },
+    PitchBend { value: i16 },
+}
 
-/// ## `midi_list() -> Vec<String>`
+/// ## MidiEvent
 ///
-/// Returns a list of midi devices
+/// A complete MIDI message, as put together by `MidiParser`.
 ///
-/// ### Returns
+/// ### Variants
 ///
-/// `Vec<String>` - A vector of strings containing the midi devices
-pub fn midi_list() -> Vec<String> {
-    //list midi devices
-    let mut midi_in = MidiInput::new("midir reading input").unwrap();
-    midi_in.ignore(Ignore::None);
-    let midi_out = MidiOutput::new("midir writing output").unwrap();
-    let _midi_out_ports = midi_out.ports();
-    let midi_in_ports = midi_in.ports();
-    let mut test = String::new();
-    for i in 0..midi_in_ports.len() {
-        test.push_str(&format!(
-            "{}: {:?}\n",
-            i,
-            midi_in.port_name(&midi_in_ports[i]).unwrap()
-        ));
-    }
-    return vec![test.to_string()];
+/// * `Channel { channel, message }` - A channel voice message, on a channel from 0 to 15
+/// * `SysEx(Vec<u8>)` - A system exclusive message, without its 0xF0 and 0xF7 bytes
+/// * `TimeCode(u8)` - A MIDI time code quarter frame
+/// * `SongPosition(u16)` - Song Position Pointer, in sixteenth notes from the start of the song
+/// * `SongSelect(u8)` - Selects a song
+/// * `TuneRequest` - Asks analog synths to tune themselves
+/// * `Clock` - A timing clock pulse, 24 per quarter note
+/// * `Start`, `Continue`, `Stop` - Transport messages
+/// * `ActiveSensing` - Sent by some devices every 300 ms to show they are connected
+/// * `Reset` - Asks every receiver to reset
+#[derive(Clone, Debug, PartialEq)]
+pub enum MidiEvent {
+    Channel {
+        channel: u8,
+        message: ChannelMessage,
+    },
+    SysEx(Vec<u8>),
+    TimeCode(u8),
+    SongPosition(u16),
+    SongSelect(u8),
+    TuneRequest,
+    Clock,
+    Start,
+    Continue,
+    Stop,
+    ActiveSensing,
+    Reset,
+}
+
+/// The longest SysEx message kept, in bytes. Longer ones (e.g. sample dumps) are dropped rather than grown without limit.
+pub const MAX_SYSEX: usize = 65536;
+
+/// ## MidiParser
+///
+/// Turns the bytes of a MIDI input into events. Handles running status (channel messages sent without their status byte
+/// when it repeats), system realtime bytes arriving in the middle of other messages, and SysEx split across several reads.
+/// Each input has its own parser.
+///
+/// ### Fields
+///
+/// * `status: Option<u8>` - The status of the message being read; channel statuses stay for running status
+/// * `data: [u8; 2]` - The data bytes read so far
+/// * `count: usize` - The number of data bytes read so far
+/// * `sysex: Option<Vec<u8>>` - The SysEx message being read, up to MAX_SYSEX bytes
+///
+/// ### Functions
+///
+/// * `new() -> Self` - Creates a parser
+/// * `parse(&mut self, bytes: &[u8], emit: impl FnMut(MidiEvent))` - Reads bytes, emitting every event they complete
+pub struct MidiParser {
+    status: Option<u8>,
+    data: [u8; 2],
+    count: usize,
+    sysex: Option<Vec<u8>>,
 }
 
+impl MidiParser {
+    /// ## `new() -> Self`
+    ///
+    /// Creates a parser.
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The new parser
+    pub fn new() -> Self {
+        Self {
+            status: None,
+            data: [0; 2],
+            count: 0,
+            sysex: None,
+        }
+    }
+
+    /// ## `parse(&mut self, bytes: &[u8], emit: impl FnMut(MidiEvent))`
+    ///
+    /// Reads bytes, emitting every event they complete. Incomplete messages carry over to the next call.
+    ///
+    /// ### Arguments
+    ///
+    /// * `bytes: &[u8]` - The bytes
+    /// * `emit: impl FnMut(MidiEvent)` - Called with each event
+    pub fn parse(&mut self, bytes: &[u8], mut emit: impl FnMut(MidiEvent)) {
+        for &byte in bytes {
+            match byte {
+                // system realtime can come at any time, even inside SysEx, and does not touch running status
+                0xF8 => emit(MidiEvent::Clock),
+                0xFA => emit(MidiEvent::Start),
+                0xFB => emit(MidiEvent::Continue),
+                0xFC => emit(MidiEvent::Stop),
+                0xFE => emit(MidiEvent::ActiveSensing),
+                0xFF => emit(MidiEvent::Reset),
+                0xF9 | 0xFD => {}
+                0xF0 => {
+                    self.status = None;
+                    self.sysex = Some(Vec::new());
+                }
+                0xF7 => match self.sysex.take() {
+                    Some(sysex) => emit(MidiEvent::SysEx(sysex)),
+                    None => {}
+                },
+                0x80..=0xF6 => {
+                    // any other status ends an unterminated SysEx, which is dropped
+                    self.sysex = None;
+                    self.status = Some(byte);
+                    self.count = 0;
+                    match byte {
+                        0xF6 => {
+                            self.status = None;
+                            emit(MidiEvent::TuneRequest);
+                        }
+                        0xF4 | 0xF5 => self.status = None,
+                        _ => {}
+                    }
+                }
+                _ => match (&mut self.sysex, self.status) {
+                    (Some(sysex), _) => match sysex.len() < MAX_SYSEX {
+                        true => sysex.push(byte),
+                        // the rest of the message is data without a status, so it is skipped up to the 0xF7
+                        false => self.sysex = None,
+                    },
+                    (None, Some(status)) => {
+                        self.data[self.count] = byte;
+                        self.count += 1;
+                        if self.count == Self::length(status) {
+                            self.count = 0;
+                            if status >= 0xF0 {
+                                self.status = None;
+                            }
+                            emit(Self::event(status, self.data));
+                        }
+                    }
+                    // data without a status, e.g. from a device started in the middle of a message
+                    (None, None) => {}
+                },
+            }
+        }
+    }
+
+    /// ## `length(status: u8) -> usize`
+    ///
+    /// Private function which returns the number of data bytes following a status.
+    ///
+    /// ### Arguments
+    ///
+    /// * `status: u8` - The status byte
+    ///
+    /// ### Returns
+    ///
+    /// * `usize` - The number of data bytes
+    fn length(status: u8) -> usize {
+        match status {
+            0xC0..=0xDF | 0xF1 | 0xF3 => 1,
+            _ => 2,
+        }
+    }
+
+    /// ## `event(status: u8, data: [u8; 2]) -> MidiEvent`
+    ///
+    /// Private function which builds the event of a complete message.
+    ///
+    /// ### Arguments
+    ///
+    /// * `status: u8` - The status byte
+    /// * `data: [u8; 2]` - The data bytes; only the first is used by one-byte messages
+    ///
+    /// ### Returns
+    ///
+    /// * `MidiEvent` - The event
+    fn event(status: u8, data: [u8; 2]) -> MidiEvent {
+        let [first, second] = data;
+        let message = match status & 0xF0 {
+            0x80 => ChannelMessage::NoteOff {
+                note: first,
+                velocity: second,
+            },
+            0x90 => match second {
+                0 => ChannelMessage::NoteOff {
+                    note: first,
+                    velocity: 0,
+                },
+                velocity => ChannelMessage::NoteOn {
+                    note: first,
+                    velocity,
+                },
+            },
+            0xA0 => ChannelMessage::PolyPressure {
+                note: first,
+                pressure: second,
+            },
+            0xB0 => ChannelMessage::ControlChange {
+                controller: first,
+                value: second,
+            },
+            0xC0 => ChannelMessage::ProgramChange { program: first },
+            0xD0 => ChannelMessage::ChannelPressure { pressure: first },
+            0xE0 => ChannelMessage::PitchBend {
+                value: ((second as i16) << 7 | first as i16) - 8192,
+            },
+            _ => {
+                return match status {
+                    0xF1 => MidiEvent::TimeCode(first),
+                    0xF2 => MidiEvent::SongPosition((second as u16) << 7 | first as u16),
+                    _ => MidiEvent::SongSelect(first),
+                }
+            }
+        };
 
-struct Note {
-	amp: f32,
-	freq: f32,
-	velocity: f32,
-	sample_clock: Option<u64>,
+        MidiEvent::Channel {
+            channel: status & 0x0F,
+            message,
+        }
+    }
 }
 
-impl Note {
-	fn key(&self) -> f32 {
-		self.freq
-	}
+/// ## Subscriber
+///
+/// A strip playing incoming MIDI.
+///
+/// ### Fields
+///
+/// * `strip: usize` - The index of the strip
+/// * `channel: Option<u8>` - The channel it listens to (0-15), or None for every channel
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Subscriber {
+    pub strip: usize,
+    pub channel: Option<u8>,
 }
 
 lazy_static! {
-    static ref NOTE: RwLock<Vec<Note>> = RwLock::new(Vec::new());
-}
-
-static NOTE_SPEED: f32 = 0.002;
-
-pub fn callback(state: &audio::State) -> audio::Sample {
-	
-    let mut notes = NOTE.write().unwrap();
-    let mut output = 0.0;
-    for note in notes.iter_mut() {
-		let sample_start = match note.sample_clock {
-			Some(x) => x,
-			None => {
-				note.sample_clock = Some(state.sample_clock);
-				state.sample_clock
-			}
-		};
-
-		let sample = (state.sample_clock as i128 - sample_start as i128) as f32 * note.freq * 2.0 * std::f32::consts::PI / state.sample_rate as f32;
-		let sample = sample.sin() * note.velocity * note.amp;
-
-		if note.amp > 1.0 {
-			note.amp = 1.0;
-		}
-		if note.amp < 1.0 && note.amp > 0.0 {
-			note.amp -= NOTE_SPEED;
-		}
-		
-		output += sample;
-    }
-	// remove notes where amp <= 0
-	for mut i in 0..notes.len() {
-		if i >= notes.len() {
-			break;
-		}
-		if notes[i].amp <= 0.0 {
-			notes.remove(i);
-		}
-	}
-
-
-	audio::Sample::Stereo(output, output)
-
-}
-
-fn midi_callback(stamp: u64, message: &[u8], _: &mut ()) {
-    let status = message[0];
-    let note = message[1];
-    let velocity = message[2];
-
-    let freq = 440.0 * 2.0f32.powf((note as f32 - 69.0) / 12.0);
-
-    match status {
-        144 => {
-			match velocity {
-				0 => {
-					debug!("Note off: {} {} {}", note, velocity, freq);
-					// subtract note amp by 0.1
-					let mut note = NOTE.write().unwrap();
-					for i in 0..note.len() {
-						if note[i].key() == freq {
-							note[i].amp -= 0.01;
-						}
-					}
-				}
-				_ => {
-					debug!("Note on: {} {} {}", note, velocity, freq);
-					NOTE.write().unwrap().push(Note {
-						amp: 1.0,
-						freq: freq,
-						velocity: velocity as f32 / 127.0,
-						sample_clock: None,
-					});
-				}
-			}
-        }
-        128 => {
-			debug!("Note off: {} {} {}", note, velocity, freq);
-			// subtract note amp by 0.1
-			let mut note = NOTE.write().unwrap();
-			for i in 0..note.len() {
-				if note[i].key() == freq {
-					note[i].amp -= 0.1;
-				}
-			}
+    /// The strips playing incoming midi, and the channel each listens to
+    pub static ref SUBSCRIBERS: Mutex<Vec<Subscriber>> = Mutex::new(Vec::new());
+}
+
+/// ## `subscribe(strip: usize, channel: Option<u8>) -> Result<(), String>`
+///
+/// Makes a strip play incoming MIDI from a channel, or changes the channel it listens to.
+///
+/// ### Arguments
+///
+/// * `strip: usize` - The index of the strip
+/// * `channel: Option<u8>` - The channel (0-15), or None for every channel
+///
+/// ### Returns
+///
+/// * `Result<(), String>` - An error message, or nothing if successful
+pub fn subscribe(strip: usize, channel: Option<u8>) -> Result<(), String> {
+    match channel {
+        Some(channel) if channel > 15 => {
+            return Err(format!("Invalid MIDI channel {}", channel + 1))
         }
         _ => {}
     }
 
-    debug!("{}: {:?} (len = {})", stamp, message, message.len());
+    let mut subscribers = SUBSCRIBERS
+        .lock()
+        .map_err(|e| format!("Error locking SUBSCRIBERS: {}", e))?;
+    match subscribers
+        .iter_mut()
+        .find(|subscriber| subscriber.strip == strip)
+    {
+        Some(subscriber) => subscriber.channel = channel,
+        None => subscribers.push(Subscriber { strip, channel }),
+    }
+    Ok(())
 }
 
-pub fn midi_start(device_name: String) -> Result<(), String> {
-    //start midi device
-    let mut midi_in = MidiInput::new("midir reading input").unwrap();
+/// How often the connected devices are checked for being unplugged or plugged back in
+const RECONNECT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// ## Connection
+///
+/// A MIDI input opened by `midi_start`.
+///
+/// ### Fields
+///
+/// * `device: String` - The device name asked for, matched again when reconnecting
+/// * `port: String` - The full name of the port
+/// * `connection: Option<MidiInputConnection<MidiParser>>` - The open connection, or None while the device is unplugged
+struct Connection {
+    device: String,
+    port: String,
+    connection: Option<MidiInputConnection<MidiParser>>,
+}
+
+lazy_static! {
+    /// The MIDI inputs opened by `midi_start`, including unplugged ones waiting to reconnect
+    static ref CONNECTIONS: Mutex<Vec<Connection>> = Mutex::new(Vec::new());
+}
+
+/// Starts the thread reconnecting unplugged devices, once
+static WATCHER: Once = Once::new();
+
+/// ## `normalize(name: &str) -> String`
+///
+/// Private function which lowercases a name and drops everything but letters and digits, for fuzzy matching.
+///
+/// ### Arguments
+///
+/// * `name: &str` - The name
+///
+/// ### Returns
+///
+/// * `String` - The normalized name
+fn normalize(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(|c| c.to_lowercase())
+        .collect()
+}
+
+/// ## `find_port(names: &[String], device: &str) -> Result<usize, String>`
+///
+/// Finds a port by index, by exact name (not case-sensitive), or by a fuzzy match:
+/// a port whose name contains the device name, ignoring case, spaces and punctuation.
+///
+/// ### Arguments
+///
+/// * `names: &[String]` - The names of the ports
+/// * `device: &str` - The index or name of the device
+///
+/// ### Returns
+///
+/// * `Result<usize, String>` - The index of the port, or an error message if none or several match
+pub fn find_port(names: &[String], device: &str) -> Result<usize, String> {
+    match device.parse::<usize>() {
+        Ok(index) if index < names.len() => return Ok(index),
+        _ => {}
+    }
+
+    match names
+        .iter()
+        .position(|name| name.eq_ignore_ascii_case(device))
+    {
+        Some(index) => return Ok(index),
+        None => {}
+    }
+
+    let wanted = normalize(device);
+    let matches: Vec<usize> = (0..names.len())
+        .filter(|index| !wanted.is_empty() && normalize(&names[*index]).contains(&wanted))
+        .collect();
+    match matches.len() {
+        1 => Ok(matches[0]),
+        0 => Err(format!(
+            "MIDI device {} not found. Devices: {}",
+            device,
+            names.join(", ")
+        )),
+        _ => Err(format!(
+            "MIDI device {} is ambiguous, it could be: {}",
+            device,
+            matches
+                .iter()
+                .map(|index| names[*index].clone())
+                .collect::<Vec<String>>()
+                .join(", ")
+        )),
+    }
+}
+
+/// ## `input_ports() -> Result<(MidiInput, Vec<MidiInputPort>, Vec<String>), String>`
+///
+/// Private function which lists the MIDI input ports.
+///
+/// ### Returns
+///
+/// * `Result<(MidiInput, Vec<MidiInputPort>, Vec<String>), String>` - A MIDI input to connect with, its ports and their names, or an error message
+fn input_ports() -> Result<(MidiInput, Vec<MidiInputPort>, Vec<String>), String> {
+    let mut midi_in =
+        MidiInput::new("w4113 input").map_err(|e| format!("Error opening MIDI input: {}", e))?;
     midi_in.ignore(Ignore::None);
-    let midi_out = MidiOutput::new("midir writing output").unwrap();
-    let _midi_out_ports = midi_out.ports();
-    let midi_in_ports = midi_in.ports();
-    let mut test = String::new();
-    for i in 0..midi_in_ports.len() {
-        test.push_str(&format!(
-            "{}: {:?}\n",
-            i,
-            midi_in.port_name(&midi_in_ports[i]).unwrap()
-        ));
-    }
-    debug!("{}", test);
-    let in_port = &midi_in_ports[0];
-    let out_port = &midi_out.ports()[0];
-    let in_port_name = midi_in.port_name(in_port).unwrap();
-    let out_port_name = midi_out.port_name(out_port).unwrap();
-    debug!("Opening connection");
-    let conn_in = midi_in.connect(in_port, "midir-read-input", midi_callback, ());
-
-    let conn_in = match conn_in {
-        Ok(conn_in) => conn_in,
-        Err(err) => {
-            debug!("Error: {}", err);
-            return Err(err.to_string());
+    let ports = midi_in.ports();
+    let names = ports
+        .iter()
+        .map(|port| midi_in.port_name(port).unwrap_or_default())
+        .collect();
+    Ok((midi_in, ports, names))
+}
+
+/// ## `connect(device: &str) -> Result<(String, MidiInputConnection<MidiParser>), String>`
+///
+/// Private function which opens a MIDI input, sending what it receives to `midi_callback`.
+///
+/// ### Arguments
+///
+/// * `device: &str` - The index or name of the device
+///
+/// ### Returns
+///
+/// * `Result<(String, MidiInputConnection<MidiParser>), String>` - The full name of the port and the connection, or an error message
+fn connect(device: &str) -> Result<(String, MidiInputConnection<MidiParser>), String> {
+    let (midi_in, ports, names) = input_ports()?;
+    let index = find_port(&names, device)?;
+    let port = names[index].clone();
+    match midi_in.connect(
+        &ports[index],
+        "w4113 input",
+        midi_callback,
+        MidiParser::new(),
+    ) {
+        Ok(connection) => Ok((port, connection)),
+        Err(e) => Err(format!("Error connecting to {}: {}", port, e)),
+    }
+}
+
+/// ## `midi_list() -> Vec<String>`
+///
+/// Returns a list of midi devices
+///
+/// ### Returns
+///
+/// `Vec<String>` - One line per input and output port, with connected inputs marked
+pub fn midi_list() -> Vec<String> {
+    let connected = midi_connected();
+    let mut lines = vec!["Inputs:".to_owned()];
+    match input_ports() {
+        Ok((_midi_in, _ports, names)) => {
+            for (index, name) in names.iter().enumerate() {
+                match connected.contains(name) {
+                    true => lines.push(format!("{}: {} (connected)", index, name)),
+                    false => lines.push(format!("{}: {}", index, name)),
+                }
+            }
+        }
+        Err(e) => lines.push(e),
+    }
+
+    lines.push("Outputs:".to_owned());
+    match MidiOutput::new("w4113 output") {
+        Ok(midi_out) => {
+            for (index, port) in midi_out.ports().iter().enumerate() {
+                lines.push(format!(
+                    "{}: {}",
+                    index,
+                    midi_out.port_name(port).unwrap_or_default()
+                ));
+            }
+        }
+        Err(e) => lines.push(format!("Error opening MIDI output: {}", e)),
+    }
+    lines
+}
+
+/// ## `midi_connected() -> Vec<String>`
+///
+/// Returns the inputs opened by `midi_start`, unplugged ones included.
+///
+/// ### Returns
+///
+/// `Vec<String>` - The full names of the ports
+pub fn midi_connected() -> Vec<String> {
+    match CONNECTIONS.lock() {
+        Ok(connections) => connections
+            .iter()
+            .map(|connection| connection.port.clone())
+            .collect(),
+        Err(e) => {
+            debug!("Error locking CONNECTIONS: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// ## `midi_start(device_name: String) -> Result<String, String>`
+///
+/// Opens a MIDI input by index or name, alongside any inputs already open. Notes go to the midi strips,
+/// clock and transport messages to the clock follower. The input is reopened whenever the device is plugged back in.
+///
+/// ### Arguments
+///
+/// * `device_name: String` - The index of the device, or its name (exact or fuzzy, see `find_port`)
+///
+/// ### Returns
+///
+/// * `Result<String, String>` - The full name of the port, or an error message
+pub fn midi_start(device_name: String) -> Result<String, String> {
+    let mut connections = CONNECTIONS
+        .lock()
+        .map_err(|e| format!("Error locking CONNECTIONS: {}", e))?;
+
+    let (port, connection) = connect(&device_name)?;
+    match connections.iter().any(|connection| connection.port == port) {
+        true => return Err(format!("MIDI device {} is already started", port)),
+        false => {}
+    }
+
+    debug!("Connected to MIDI input {}", port);
+    connections.push(Connection {
+        // an index could be another device once this one is unplugged
+        device: match device_name.parse::<usize>() {
+            Ok(_) => port.clone(),
+            Err(_) => device_name,
+        },
+        port: port.clone(),
+        connection: Some(connection),
+    });
+
+    WATCHER.call_once(|| {
+        std::thread::spawn(watch_connections);
+    });
+    Ok(port)
+}
+
+/// ## `midi_stop(device_name: String) -> Result<String, String>`
+///
+/// Closes a MIDI input opened by `midi_start`.
+///
+/// ### Arguments
+///
+/// * `device_name: String` - The name of the device (exact or fuzzy, see `find_port`), or "all"
+///
+/// ### Returns
+///
+/// * `Result<String, String>` - The full names of the closed ports, or an error message
+pub fn midi_stop(device_name: String) -> Result<String, String> {
+    let mut connections = CONNECTIONS
+        .lock()
+        .map_err(|e| format!("Error locking CONNECTIONS: {}", e))?;
+
+    let stopped: Vec<Connection> = match device_name.eq_ignore_ascii_case("all") {
+        true => connections.drain(..).collect(),
+        false => {
+            let ports: Vec<String> = connections
+                .iter()
+                .map(|connection| connection.port.clone())
+                .collect();
+            let index = match ports.is_empty() {
+                true => return Err("No MIDI devices are started".to_owned()),
+                false => find_port(&ports, &device_name)?,
+            };
+            vec![connections.remove(index)]
         }
     };
 
-    let conn_out = midi_out.connect(out_port, "midir-write-output").unwrap();
-    debug!(
-        "Connection open, reading input from '{}' (press enter to exit) ...",
-        in_port_name
-    );
-    let mut input = String::new();
+    let mut ports = Vec::new();
+    for connection in stopped {
+        match connection.connection {
+            Some(input) => {
+                input.close();
+            }
+            None => {}
+        }
+        debug!("Closed MIDI input {}", connection.port);
+        ports.push(connection.port);
+    }
+    Ok(ports.join(", "))
+}
+
+/// ## `watch_connections()`
+///
+/// Private function run by the reconnecting thread. midir has no hotplug events, so the ports are listed every
+/// `RECONNECT_INTERVAL`: connections whose port disappeared are closed, and reopened when a matching port shows up again.
+fn watch_connections() {
     loop {
-        // sleep for 1 second
-        std::thread::sleep(std::time::Duration::from_millis(1000));
+        std::thread::sleep(RECONNECT_INTERVAL);
+
+        let mut connections = match CONNECTIONS.lock() {
+            Ok(connections) => connections,
+            Err(e) => {
+                debug!("Error locking CONNECTIONS: {}", e);
+                continue;
+            }
+        };
+        if connections.is_empty() {
+            continue;
+        }
+
+        let names = match input_ports() {
+            Ok((_midi_in, _ports, names)) => names,
+            Err(e) => {
+                debug!("{}", e);
+                continue;
+            }
+        };
+
+        for connection in connections.iter_mut() {
+            if connection.connection.is_some() && !names.contains(&connection.port) {
+                warn!(
+                    "MIDI device {} was unplugged, waiting for it to come back",
+                    connection.port
+                );
+                connection.connection = None;
+            }
+        }
+
+        // a fuzzy name could match a port another saved connection already has, so connected ports are skipped
+        let mut connected: Vec<String> = connections
+            .iter()
+            .filter(|connection| connection.connection.is_some())
+            .map(|connection| connection.port.clone())
+            .collect();
+
+        for connection in connections
+            .iter_mut()
+            .filter(|connection| connection.connection.is_none())
+        {
+            // the port's name can change when it comes back (e.g. its ALSA client number), so fall back on the name asked for
+            let device = match names.contains(&connection.port) {
+                true => connection.port.clone(),
+                false => connection.device.clone(),
+            };
+            let port = match find_port(&names, &device) {
+                Ok(index) => names[index].clone(),
+                Err(_) => continue,
+            };
+            if connected.contains(&port) {
+                continue;
+            }
+
+            match connect(&port) {
+                Ok((port, input)) => {
+                    info!("MIDI device {} reconnected", port);
+                    connected.push(port.clone());
+                    connection.port = port;
+                    connection.connection = Some(input);
+                }
+                Err(e) => debug!(
+                    "Error reconnecting MIDI device {}: {}",
+                    connection.device, e
+                ),
+            }
+        }
     }
-    debug!("Closing connection");
-    conn_in.close();
-    conn_out.close();
-    debug!("Connection closed. Goodbye!");
+}
 
-    Ok(())
+/// ## `dispatch(stamp: u64, event: MidiEvent)`
+///
+/// Sends an event where it is needed: channel messages to the strips subscribed to their channel,
+/// clock and transport messages to the clock follower.
+///
+/// ### Arguments
+///
+/// * `stamp: u64` - The timestamp of the event, in microseconds
+/// * `event: MidiEvent` - The event
+fn dispatch(stamp: u64, event: MidiEvent) {
+    match event {
+        MidiEvent::Channel { channel, message } => {
+            match message {
+                ChannelMessage::NoteOn { note, velocity } => {
+                    debug!("Note on: {} {} (channel {})", note, velocity, channel + 1)
+                }
+                ChannelMessage::NoteOff { note, velocity } => {
+                    debug!("Note off: {} {} (channel {})", note, velocity, channel + 1)
+                }
+                _ => {}
+            }
+
+            // copied, so the lock isn't held while waiting for CONTROL
+            let subscribers = match SUBSCRIBERS.lock() {
+                Ok(subscribers) => subscribers.clone(),
+                Err(e) => {
+                    debug!("Error locking SUBSCRIBERS: {}", e);
+                    return;
+                }
+            };
+
+            let mut control = match audio::CONTROL.lock() {
+                Ok(control) => control,
+                Err(e) => {
+                    debug!("Error locking CONTROL: {}", e);
+                    return;
+                }
+            };
+
+            for subscriber in subscribers {
+                match subscriber.channel {
+                    Some(listening) if listening != channel => continue,
+                    _ => {}
+                }
+
+                match control.midi(subscriber.strip, channel, message) {
+                    Ok(()) => {}
+                    Err(e) => debug!("Error sending MIDI: {}", e),
+                }
+            }
+        }
+        MidiEvent::Clock
+        | MidiEvent::Start
+        | MidiEvent::Continue
+        | MidiEvent::Stop
+        | MidiEvent::SongPosition(_) => match clock::FOLLOWER.lock() {
+            Ok(mut follower) => follower.receive(stamp, &event),
+            Err(e) => debug!("Error locking clock::FOLLOWER: {}", e),
+        },
+        MidiEvent::SysEx(data) => debug!("{}: SysEx of {} bytes", stamp, data.len()),
+        MidiEvent::ActiveSensing => {}
+        event => debug!("{}: {:?}", stamp, event),
+    }
+}
+
+fn midi_callback(stamp: u64, message: &[u8], parser: &mut MidiParser) {
+    parser.parse(message, |event| dispatch(stamp, event));
 }
 
 // turns the computer keyboard into a midi keyboard
@@ -249,4 +792,95 @@ pub fn midi_keyboard_thread() {
     // listen for keyboard up/down events
 
 }
-*/
\ No newline at end of file
+*/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(parser: &mut MidiParser, bytes: &[u8]) -> Vec<MidiEvent> {
+        let mut events = Vec::new();
+        parser.parse(bytes, |event| events.push(event));
+        events
+    }
+
+    fn note_on(channel: u8, note: u8, velocity: u8) -> MidiEvent {
+        MidiEvent::Channel {
+            channel,
+            message: ChannelMessage::NoteOn { note, velocity },
+        }
+    }
+
+    #[test]
+    fn running_status() {
+        let mut parser = MidiParser::new();
+        let events = parse(&mut parser, &[0x91, 60, 100, 64, 90, 60, 0]);
+        assert_eq!(
+            events,
+            vec![
+                note_on(1, 60, 100),
+                note_on(1, 64, 90),
+                MidiEvent::Channel {
+                    channel: 1,
+                    message: ChannelMessage::NoteOff {
+                        note: 60,
+                        velocity: 0
+                    },
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn running_status_across_reads() {
+        let mut parser = MidiParser::new();
+        assert_eq!(parse(&mut parser, &[0x90, 60]), vec![]);
+        assert_eq!(parse(&mut parser, &[100, 62]), vec![note_on(0, 60, 100)]);
+        assert_eq!(parse(&mut parser, &[80]), vec![note_on(0, 62, 80)]);
+    }
+
+    #[test]
+    fn realtime_inside_messages() {
+        let mut parser = MidiParser::new();
+        let events = parse(&mut parser, &[0x90, 0xF8, 60, 0xFA, 100, 0xF8, 62, 80]);
+        assert_eq!(
+            events,
+            vec![
+                MidiEvent::Clock,
+                MidiEvent::Start,
+                note_on(0, 60, 100),
+                MidiEvent::Clock,
+                note_on(0, 62, 80),
+            ]
+        );
+    }
+
+    #[test]
+    fn realtime_inside_sysex() {
+        let mut parser = MidiParser::new();
+        let events = parse(&mut parser, &[0xF0, 0x7E, 0xF8, 0x01, 0xF7]);
+        assert_eq!(
+            events,
+            vec![MidiEvent::Clock, MidiEvent::SysEx(vec![0x7E, 0x01])]
+        );
+    }
+
+    #[test]
+    fn sysex_cancels_running_status() {
+        let mut parser = MidiParser::new();
+        let events = parse(&mut parser, &[0x90, 60, 100, 0xF0, 0x01, 0xF7, 62, 80]);
+        assert_eq!(
+            events,
+            vec![note_on(0, 60, 100), MidiEvent::SysEx(vec![0x01])]
+        );
+    }
+
+    #[test]
+    fn long_sysex_is_dropped() {
+        let mut parser = MidiParser::new();
+        let mut bytes = vec![0xF0];
+        bytes.extend(std::iter::repeat(0x01).take(MAX_SYSEX + 10));
+        bytes.extend([0xF7, 0x90, 60, 100]);
+        assert_eq!(parse(&mut parser, &bytes), vec![note_on(0, 60, 100)]);
+    }
+}
@@ -0,0 +1,510 @@
+//! modulation.rs
+//!
+//! Module for the modulated delay effects (chorus, flanger) and the phaser, driven by LFOs that can follow the transport
+
+use crate::audio::plugin::{Delay, Effect};
+use crate::audio::{Buffer, Sample, State, MAX_SAMPLE_RATE};
+
+/// The longest delay the chorus and flanger read, in seconds
+const MAX_DELAY: f32 = 0.05;
+
+/// The most voices in a chorus
+pub const MAX_VOICES: usize = 4;
+
+/// The most all-pass stages in a phaser
+pub const MAX_STAGES: usize = 12;
+
+/// ## Lfo
+///
+/// A sine LFO. It runs at a rate in Hz, or synced to the tempo at a length in beats. While the transport plays,
+/// a synced LFO takes its phase from the song position, so it lines up with the music.
+///
+/// ### Fields
+///
+/// * `rate: f32` - The rate when free running, in Hz
+/// * `sync: f32` - The length of a cycle in beats, or 0.0 to run free
+/// * `phase: f64` - The phase (0.0-1.0)
+pub struct Lfo {
+    rate: f32,
+    sync: f32,
+    phase: f64,
+}
+
+impl Lfo {
+    /// ## `new(rate: f32) -> Self`
+    ///
+    /// Creates a free running LFO.
+    ///
+    /// ### Arguments
+    ///
+    /// * `rate: f32` - The rate, in Hz
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The new LFO
+    pub fn new(rate: f32) -> Self {
+        Self {
+            rate: rate.max(0.0),
+            sync: 0.0,
+            phase: 0.0,
+        }
+    }
+
+    /// ## `start(&mut self, state: &State) -> f64`
+    ///
+    /// Lines a synced LFO up with the transport at the start of a block, and returns how far it moves per frame.
+    ///
+    /// ### Arguments
+    ///
+    /// * `state: &State` - The state of the block
+    ///
+    /// ### Returns
+    ///
+    /// * `f64` - The phase step per frame
+    pub fn start(&mut self, state: &State) -> f64 {
+        match self.sync > 0.0 {
+            true => {
+                if state.transport.playing {
+                    self.phase = (state.transport.beats / self.sync as f64).fract();
+                }
+                state.transport.beats_per_sample(state.sample_rate) / self.sync as f64
+            }
+            false => self.rate as f64 / state.sample_rate.max(1) as f64,
+        }
+    }
+
+    /// ## `value(&self, offset: f64) -> f32`
+    ///
+    /// Returns the LFO's value at an offset from its phase.
+    ///
+    /// ### Arguments
+    ///
+    /// * `offset: f64` - The offset, in cycles (e.g. 0.25 for 90°)
+    ///
+    /// ### Returns
+    ///
+    /// * `f32` - The value (-1.0-1.0)
+    pub fn value(&self, offset: f64) -> f32 {
+        ((self.phase + offset) * std::f64::consts::TAU).sin() as f32
+    }
+
+    /// ## `advance(&mut self, step: f64)`
+    ///
+    /// Moves the LFO one frame on.
+    ///
+    /// ### Arguments
+    ///
+    /// * `step: f64` - The phase step, as returned by `start`
+    pub fn advance(&mut self, step: f64) {
+        self.phase = (self.phase + step).fract();
+    }
+
+    /// ## `set_parameter(&mut self, name: &str, value: f32) -> bool`
+    ///
+    /// Sets the "rate" (Hz) or "sync" (beats per cycle, 0 to run free) of the LFO.
+    ///
+    /// ### Arguments
+    ///
+    /// * `name: &str` - The name of the parameter
+    /// * `value: f32` - The new value
+    ///
+    /// ### Returns
+    ///
+    /// * `bool` - Whether the LFO has a parameter with the name
+    pub fn set_parameter(&mut self, name: &str, value: f32) -> bool {
+        match name {
+            "rate" => self.rate = value.clamp(0.0, 20.0),
+            "sync" => self.sync = value.clamp(0.0, 64.0),
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// ## `line() -> Delay`
+///
+/// Creates a silent delay line for a chorus or flanger channel, long enough for MAX_DELAY at up to 192 kHz
+/// with room for the interpolation around it.
+///
+/// ### Returns
+///
+/// * `Delay` - The new delay line
+fn line() -> Delay {
+    Delay::new((MAX_DELAY * MAX_SAMPLE_RATE) as usize + 4, 0.0)
+}
+
+/// ## Chorus
+///
+/// A chorus: up to four copies of the signal, each delayed by a slowly moving amount, with the voices spread evenly
+/// around the LFO's cycle. The right channel's voices are offset by the stereo phase, which widens the sound.
+///
+/// ### Fields
+///
+/// * `voices: usize` - The number of voices (1-4)
+/// * `delay: f32` - The delay the voices move around, in milliseconds
+/// * `depth: f32` - How far the voices move either way, in milliseconds
+/// * `phase: f32` - The offset of the right channel's LFO, in degrees
+/// * `mix: f32` - The level of the voices (0.0 dry, 1.0 only voices)
+/// * `lfo: Lfo` - The LFO
+/// * `lines: [Delay; 2]` - The delay line of each channel
+///
+/// ### Parameters
+///
+/// * "voices", "delay", "depth", "phase", "mix" - As the fields
+/// * "rate" - The LFO rate, in Hz
+/// * "sync" - The LFO cycle in beats, or 0 to use the rate
+pub struct Chorus {
+    voices: usize,
+    delay: f32,
+    depth: f32,
+    phase: f32,
+    mix: f32,
+    lfo: Lfo,
+    lines: [Delay; 2],
+}
+
+impl Chorus {
+    /// ## `new(voices: usize, rate: f32, depth: f32, mix: f32) -> Self`
+    ///
+    /// Creates a chorus around a 20 ms delay, with the right channel 90° behind the left.
+    ///
+    /// ### Arguments
+    ///
+    /// * `voices: usize` - The number of voices (1-4)
+    /// * `rate: f32` - The LFO rate, in Hz
+    /// * `depth: f32` - The depth, in milliseconds
+    /// * `mix: f32` - The level of the voices (0.0-1.0)
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The new chorus
+    pub fn new(voices: usize, rate: f32, depth: f32, mix: f32) -> Self {
+        Self {
+            voices: voices.clamp(1, MAX_VOICES),
+            delay: 20.0,
+            depth: depth.clamp(0.0, 20.0),
+            phase: 90.0,
+            mix: mix.clamp(0.0, 1.0),
+            lfo: Lfo::new(rate),
+            lines: [line(), line()],
+        }
+    }
+}
+
+impl Effect for Chorus {
+    fn process(&mut self, state: &State, buffer: &mut Buffer) {
+        let step = self.lfo.start(state);
+        let frames_per_ms = state.sample_rate as f32 / 1000.0;
+        let gain = 1.0 / (self.voices as f32).sqrt();
+
+        for index in 0..buffer.len() {
+            for channel in 0..buffer.channels().min(2) {
+                let dry = buffer.channel(channel)[index];
+                self.lines[channel].write(Sample::Mono(dry));
+
+                let offset = channel as f64 * self.phase as f64 / 360.0;
+                let mut wet = 0.0;
+                for voice in 0..self.voices {
+                    let spread = voice as f64 / self.voices as f64;
+                    let delay = self.delay + self.depth * self.lfo.value(offset + spread);
+                    wet += self.lines[channel].read(delay * frames_per_ms).mono();
+                }
+                buffer.channel_mut(channel)[index] = dry * (1.0 - self.mix) + wet * gain * self.mix;
+            }
+            self.lfo.advance(step);
+        }
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f32) {
+        match name {
+            "voices" => self.voices = (value.max(1.0) as usize).min(MAX_VOICES),
+            "delay" => self.delay = value.clamp(self.depth, MAX_DELAY * 1000.0 - self.depth),
+            "depth" => {
+                self.depth = value.clamp(0.0, self.delay.min(MAX_DELAY * 1000.0 - self.delay))
+            }
+            "phase" => self.phase = value.rem_euclid(360.0),
+            "mix" => self.mix = value.clamp(0.0, 1.0),
+            _ => {
+                self.lfo.set_parameter(name, value);
+            }
+        }
+    }
+}
+
+/// ## Flanger
+///
+/// A flanger: the signal mixed with a copy delayed by a few milliseconds, with the delay swept by the LFO and fed back.
+/// Negative feedback moves the comb's peaks to where its notches were, for a hollower sound.
+///
+/// ### Fields
+///
+/// * `delay: f32` - The delay the sweep moves around, in milliseconds
+/// * `depth: f32` - How far the sweep moves either way, in milliseconds
+/// * `feedback: f32` - How much of the delayed signal is fed back (-0.95-0.95)
+/// * `phase: f32` - The offset of the right channel's LFO, in degrees
+/// * `mix: f32` - The level of the delayed signal (0.0 dry, 1.0 only delayed)
+/// * `lfo: Lfo` - The LFO
+/// * `lines: [Delay; 2]` - The delay line of each channel
+/// * `feedbacks: [f32; 2]` - The last delayed sample of each channel, fed back
+///
+/// ### Parameters
+///
+/// * "delay", "depth", "feedback", "phase", "mix" - As the fields
+/// * "rate" - The LFO rate, in Hz
+/// * "sync" - The LFO cycle in beats, or 0 to use the rate
+pub struct Flanger {
+    delay: f32,
+    depth: f32,
+    feedback: f32,
+    phase: f32,
+    mix: f32,
+    lfo: Lfo,
+    lines: [Delay; 2],
+    feedbacks: [f32; 2],
+}
+
+impl Flanger {
+    /// ## `new(rate: f32, depth: f32, feedback: f32) -> Self`
+    ///
+    /// Creates a flanger around a 2.5 ms delay at an even mix, with both channels in phase.
+    ///
+    /// ### Arguments
+    ///
+    /// * `rate: f32` - The LFO rate, in Hz
+    /// * `depth: f32` - The depth, in milliseconds
+    /// * `feedback: f32` - The feedback (-0.95-0.95)
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The new flanger
+    pub fn new(rate: f32, depth: f32, feedback: f32) -> Self {
+        Self {
+            delay: 2.5,
+            depth: depth.clamp(0.0, 2.5),
+            feedback: feedback.clamp(-0.95, 0.95),
+            phase: 0.0,
+            mix: 0.5,
+            lfo: Lfo::new(rate),
+            lines: [line(), line()],
+            feedbacks: [0.0; 2],
+        }
+    }
+}
+
+impl Effect for Flanger {
+    fn process(&mut self, state: &State, buffer: &mut Buffer) {
+        let step = self.lfo.start(state);
+        let frames_per_ms = state.sample_rate as f32 / 1000.0;
+
+        for index in 0..buffer.len() {
+            for channel in 0..buffer.channels().min(2) {
+                let dry = buffer.channel(channel)[index];
+                self.lines[channel]
+                    .write(Sample::Mono(dry + self.feedbacks[channel] * self.feedback));
+
+                let offset = channel as f64 * self.phase as f64 / 360.0;
+                let delay = self.delay + self.depth * self.lfo.value(offset);
+                let wet = self.lines[channel].read(delay * frames_per_ms).mono();
+                self.feedbacks[channel] = wet;
+                buffer.channel_mut(channel)[index] = dry * (1.0 - self.mix) + wet * self.mix;
+            }
+            self.lfo.advance(step);
+        }
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f32) {
+        match name {
+            "delay" => self.delay = value.clamp(self.depth.max(0.1), 20.0 - self.depth),
+            "depth" => {
+                self.depth = value.clamp(0.0, (self.delay - 0.1).min(20.0 - self.delay).max(0.0))
+            }
+            "feedback" => self.feedback = value.clamp(-0.95, 0.95),
+            "phase" => self.phase = value.rem_euclid(360.0),
+            "mix" => self.mix = value.clamp(0.0, 1.0),
+            _ => {
+                self.lfo.set_parameter(name, value);
+            }
+        }
+    }
+}
+
+/// ## Phaser
+///
+/// A phaser: the signal mixed with itself through a chain of first order all-pass filters, whose corner frequency is
+/// swept by the LFO between a low and a high frequency. Every two stages add a notch, and feedback sharpens them.
+///
+/// ### Fields
+///
+/// * `stages: usize` - The number of all-pass stages (2-12)
+/// * `low: f32` - The lowest corner frequency of the sweep, in Hz
+/// * `high: f32` - The highest corner frequency of the sweep, in Hz
+/// * `feedback: f32` - How much of the last stage is fed back into the first (-0.95-0.95)
+/// * `phase: f32` - The offset of the right channel's LFO, in degrees
+/// * `mix: f32` - The level of the filtered signal (0.0 dry, 1.0 only filtered)
+/// * `lfo: Lfo` - The LFO
+/// * `states: [[f32; MAX_STAGES]; 2]` - The state of each stage of each channel
+/// * `feedbacks: [f32; 2]` - The last output of each channel's last stage, fed back
+///
+/// ### Parameters
+///
+/// * "stages", "low", "high", "feedback", "phase", "mix" - As the fields
+/// * "rate" - The LFO rate, in Hz
+/// * "sync" - The LFO cycle in beats, or 0 to use the rate
+pub struct Phaser {
+    stages: usize,
+    low: f32,
+    high: f32,
+    feedback: f32,
+    phase: f32,
+    mix: f32,
+    lfo: Lfo,
+    states: [[f32; MAX_STAGES]; 2],
+    feedbacks: [f32; 2],
+}
+
+impl Phaser {
+    /// ## `new(stages: usize, rate: f32, feedback: f32) -> Self`
+    ///
+    /// Creates a phaser sweeping from 200 Hz to 4 kHz at an even mix, with the right channel 90° behind the left.
+    ///
+    /// ### Arguments
+    ///
+    /// * `stages: usize` - The number of all-pass stages (2-12)
+    /// * `rate: f32` - The LFO rate, in Hz
+    /// * `feedback: f32` - The feedback (-0.95-0.95)
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The new phaser
+    pub fn new(stages: usize, rate: f32, feedback: f32) -> Self {
+        Self {
+            stages: stages.clamp(2, MAX_STAGES),
+            low: 200.0,
+            high: 4000.0,
+            feedback: feedback.clamp(-0.95, 0.95),
+            phase: 90.0,
+            mix: 0.5,
+            lfo: Lfo::new(rate),
+            states: [[0.0; MAX_STAGES]; 2],
+            feedbacks: [0.0; 2],
+        }
+    }
+}
+
+impl Effect for Phaser {
+    fn process(&mut self, state: &State, buffer: &mut Buffer) {
+        let step = self.lfo.start(state);
+        let sample_rate = state.sample_rate as f32;
+        let low = self.low.min(sample_rate * 0.45);
+        let high = self.high.min(sample_rate * 0.45).max(low);
+        let range = (high / low).ln();
+
+        for index in 0..buffer.len() {
+            for channel in 0..buffer.channels().min(2) {
+                let dry = buffer.channel(channel)[index];
+
+                // sweep the corner on a log scale, so the sweep sounds even
+                let offset = channel as f64 * self.phase as f64 / 360.0;
+                let sweep = 0.5 + 0.5 * self.lfo.value(offset);
+                let corner = low * (range * sweep).exp();
+                let tan = (std::f32::consts::PI * corner / sample_rate).tan();
+                let coefficient = (tan - 1.0) / (tan + 1.0);
+
+                let mut sample = dry + self.feedbacks[channel] * self.feedback;
+                for state in self.states[channel].iter_mut().take(self.stages) {
+                    let output = coefficient * sample + *state;
+                    *state = sample - coefficient * output;
+                    sample = output;
+                }
+                self.feedbacks[channel] = sample;
+                buffer.channel_mut(channel)[index] = dry * (1.0 - self.mix) + sample * self.mix;
+            }
+            self.lfo.advance(step);
+        }
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f32) {
+        match name {
+            "stages" => self.stages = (value.max(2.0) as usize).min(MAX_STAGES),
+            "low" => self.low = value.clamp(20.0, self.high),
+            "high" => self.high = value.clamp(self.low, 20000.0),
+            "feedback" => self.feedback = value.clamp(-0.95, 0.95),
+            "phase" => self.phase = value.rem_euclid(360.0),
+            "mix" => self.mix = value.clamp(0.0, 1.0),
+            _ => {
+                self.lfo.set_parameter(name, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::Transport;
+
+    /// ## `state(beats: f64, bpm: f64) -> State`
+    ///
+    /// Returns the state of a 64 frame block at 48 kHz with the transport playing from a position.
+    fn state(beats: f64, bpm: f64) -> State {
+        State {
+            sample_rate: 48000,
+            sample_clock: 0,
+            buffer_size: 64,
+            transport: Transport {
+                playing: true,
+                bpm,
+                ..Transport::default()
+            }
+            .located(beats),
+        }
+    }
+
+    #[test]
+    fn synced_lfo_cycles_in_beats() {
+        let mut lfo = Lfo::new(5.0);
+        assert_eq!(lfo.start(&state(0.0, 120.0)), 5.0 / 48000.0);
+
+        // a beat at 120 BPM is 24000 frames
+        lfo.set_parameter("sync", 1.0);
+        let step = lfo.start(&state(0.0, 120.0));
+        assert_eq!(step, 1.0 / 24000.0);
+        for frame in 1..=24000 {
+            lfo.advance(step);
+            match frame {
+                6000 => assert!((lfo.value(0.0) - 1.0).abs() < 1e-4),
+                18000 => assert!((lfo.value(0.0) + 1.0).abs() < 1e-4),
+                _ => {}
+            }
+        }
+        assert!(lfo.value(0.0).abs() < 1e-3);
+
+        // two beats at 90 BPM is 64000 frames, lined up with the song position
+        lfo.set_parameter("sync", 2.0);
+        assert_eq!(lfo.start(&state(3.0, 90.0)), 1.0 / 64000.0);
+        assert!(lfo.value(0.0).abs() < 1e-4);
+        assert!((lfo.value(0.25) + 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn still_flanger_is_a_plain_delay() {
+        let mut flanger = Flanger::new(1.0, 0.0, 0.0);
+        flanger.set_parameter("mix", 1.0);
+        let mut clock = 0;
+        let mut output = Vec::new();
+        for block in 0..4 {
+            let state = State::advance(48000, &mut clock, 64);
+            let mut buffer = Buffer::new(2, 64);
+            if block == 0 {
+                buffer.channel_mut(0)[0] = 1.0;
+                buffer.channel_mut(1)[0] = 1.0;
+            }
+            flanger.process(&state, &mut buffer);
+            output.extend_from_slice(buffer.channel(1));
+        }
+        // 2.5 ms at 48 kHz, counting the frame just written as a frame ago
+        let peak = output.iter().position(|sample| *sample == 1.0);
+        assert_eq!(peak, Some(119));
+        assert_eq!(output.iter().filter(|sample| **sample != 0.0).count(), 1);
+    }
+}
@@ -0,0 +1,753 @@
+//! oscillator.rs
+//!
+//! Module for oscillators, the sound sources played by the synth's voices
+
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// The most copies of an oscillator a unison plays
+pub const MAX_UNISON: usize = 8;
+
+/// Gives every noise oscillator its own seed, so voices and unison copies don't play the same noise
+static NOISE_SEED: AtomicU32 = AtomicU32::new(0x2545_F491);
+
+/// ## Oscillator
+///
+/// Trait for oscillators. Every synth voice owns its own oscillator, so an oscillator keeps its phase from one frame to the next.
+///
+/// ### Traits
+///
+/// * `Send` - Can be sent between threads
+/// * `Sync` - Is safe to share between threads
+///
+/// ### Functions
+///
+/// * `next(&mut self, frequency: f32, sample_rate: f32) -> (f32, f32)` - Returns the next left and right sample at a frequency
+/// * `reset(&mut self, phase: f32)` - Starts the waveform over from a phase (0.0-1.0), for oscillators that restart with each note
+/// * `duplicate(&self) -> Box<dyn Oscillator>` - Returns a new oscillator with the same settings, for another voice
+/// * `set_parameter(&mut self, name: &str, value: f32)` - Sets a parameter by name, ignoring names the oscillator does not have
+pub trait Oscillator: Send + Sync {
+    fn next(&mut self, frequency: f32, sample_rate: f32) -> (f32, f32);
+
+    fn reset(&mut self, _phase: f32) {}
+
+    fn duplicate(&self) -> Box<dyn Oscillator>;
+
+    fn set_parameter(&mut self, _name: &str, _value: f32) {}
+}
+
+/// ## `create_oscillator(name: &str) -> Result<Box<dyn Oscillator>, String>`
+///
+/// Creates an oscillator by name, so synths can be set up from the console. The oscillator comes in a `Unison`
+/// of one, so it can be detuned and thickened with parameters later.
+///
+/// ### Arguments
+///
+/// * `name: &str` - The name of the oscillator: "sine", "saw", "square", "pulse", "triangle", "noise" or "pink"
+///
+/// ### Returns
+///
+/// * `Result<Box<dyn Oscillator>, String>` - The oscillator, or an error message if the name is unknown
+pub fn create_oscillator(name: &str) -> Result<Box<dyn Oscillator>, String> {
+    let oscillator: Box<dyn Oscillator> = match name.to_lowercase().as_str() {
+        "sine" => Box::new(Sine::new()),
+        "saw" | "sawtooth" => Box::new(Saw::new()),
+        "square" => Box::new(Pulse::new(0.5)),
+        "pulse" => Box::new(Pulse::new(0.25)),
+        "triangle" | "tri" => Box::new(Triangle::new()),
+        "noise" | "white" => Box::new(WhiteNoise::new()),
+        "pink" => Box::new(PinkNoise::new()),
+        _ => return Err(format!(
+            "No oscillator named {} (available: sine, saw, square, pulse, triangle, noise, pink)",
+            name
+        )),
+    };
+    Ok(Box::new(Unison::new(oscillator)))
+}
+
+/// ## `poly_blep(phase: f64, step: f64) -> f64`
+///
+/// Returns the PolyBLEP correction for a jump of 2.0 at phase 0.0, which takes the aliasing out of a naive waveform's edges.
+///
+/// ### Arguments
+///
+/// * `phase: f64` - The position in the cycle (0.0-1.0), relative to the jump
+/// * `step: f64` - How far the phase moves per frame
+///
+/// ### Returns
+///
+/// * `f64` - The correction to subtract
+fn poly_blep(phase: f64, step: f64) -> f64 {
+    if phase < step {
+        let t = phase / step;
+        2.0 * t - t * t - 1.0
+    } else if phase > 1.0 - step {
+        let t = (phase - 1.0) / step;
+        t * t + 2.0 * t + 1.0
+    } else {
+        0.0
+    }
+}
+
+/// ## `poly_blamp(phase: f64, step: f64) -> f64`
+///
+/// Returns the PolyBLAMP correction for a corner at phase 0.0, the integral of `poly_blep`, used for the triangle's corners.
+///
+/// ### Arguments
+///
+/// * `phase: f64` - The position in the cycle (0.0-1.0), relative to the corner
+/// * `step: f64` - How far the phase moves per frame
+///
+/// ### Returns
+///
+/// * `f64` - The correction, to be scaled by the change in slope
+fn poly_blamp(phase: f64, step: f64) -> f64 {
+    if phase < step {
+        let t = phase / step - 1.0;
+        -t * t * t / 3.0
+    } else if phase > 1.0 - step {
+        let t = (phase - 1.0) / step + 1.0;
+        t * t * t / 3.0
+    } else {
+        0.0
+    }
+}
+
+/// ## Phase
+///
+/// A phase accumulator. The phase is kept in f64 between 0.0 and 1.0, so it stays accurate however long a note plays.
+///
+/// ### Fields
+///
+/// * `phase: f64` - The position in the cycle (0.0-1.0)
+#[derive(Clone, Copy, Debug)]
+struct Phase {
+    phase: f64,
+}
+
+impl Phase {
+    /// ## `advance(&mut self, frequency: f32, sample_rate: f32) -> (f64, f64)`
+    ///
+    /// Private function which moves the phase one frame on.
+    ///
+    /// ### Arguments
+    ///
+    /// * `frequency: f32` - The frequency in Hz
+    /// * `sample_rate: f32` - The sample rate of the audio engine
+    ///
+    /// ### Returns
+    ///
+    /// * `(f64, f64)` - The phase before moving, and how far it moved
+    fn advance(&mut self, frequency: f32, sample_rate: f32) -> (f64, f64) {
+        let phase = self.phase;
+        let step = (frequency.abs() as f64 / sample_rate.max(1.0) as f64).min(0.5);
+        self.phase += step;
+        if self.phase >= 1.0 {
+            self.phase -= 1.0;
+        }
+        (phase, step)
+    }
+}
+
+/// ## Sine
+///
+/// A sine wave.
+///
+/// ### Fields
+///
+/// * `phase: Phase` - The phase accumulator
+pub struct Sine {
+    phase: Phase,
+}
+
+impl Sine {
+    /// ## `new() -> Self`
+    ///
+    /// Creates a sine wave starting at phase 0.0.
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The new oscillator
+    pub fn new() -> Self {
+        Self {
+            phase: Phase { phase: 0.0 },
+        }
+    }
+}
+
+impl Oscillator for Sine {
+    fn next(&mut self, frequency: f32, sample_rate: f32) -> (f32, f32) {
+        let (phase, _step) = self.phase.advance(frequency, sample_rate);
+        let sample = (phase * std::f64::consts::TAU).sin() as f32;
+        (sample, sample)
+    }
+
+    fn reset(&mut self, phase: f32) {
+        self.phase.phase = phase.rem_euclid(1.0) as f64;
+    }
+
+    fn duplicate(&self) -> Box<dyn Oscillator> {
+        Box::new(Sine::new())
+    }
+}
+
+/// ## Saw
+///
+/// A rising sawtooth wave, anti-aliased with PolyBLEP.
+///
+/// ### Fields
+///
+/// * `phase: Phase` - The phase accumulator
+pub struct Saw {
+    phase: Phase,
+}
+
+impl Saw {
+    /// ## `new() -> Self`
+    ///
+    /// Creates a sawtooth wave starting at phase 0.0.
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The new oscillator
+    pub fn new() -> Self {
+        Self {
+            phase: Phase { phase: 0.0 },
+        }
+    }
+}
+
+impl Oscillator for Saw {
+    fn next(&mut self, frequency: f32, sample_rate: f32) -> (f32, f32) {
+        let (phase, step) = self.phase.advance(frequency, sample_rate);
+        let sample = (2.0 * phase - 1.0 - poly_blep(phase, step)) as f32;
+        (sample, sample)
+    }
+
+    fn reset(&mut self, phase: f32) {
+        self.phase.phase = phase.rem_euclid(1.0) as f64;
+    }
+
+    fn duplicate(&self) -> Box<dyn Oscillator> {
+        Box::new(Saw::new())
+    }
+}
+
+/// ## Pulse
+///
+/// A pulse wave, anti-aliased with PolyBLEP, with its own LFO for pulse width modulation. A width of 0.5 is a square wave.
+///
+/// ### Fields
+///
+/// * `phase: Phase` - The phase accumulator
+/// * `width: f32` - The part of the cycle spent high (0.01-0.99)
+/// * `pwm: f32` - How far the LFO moves the width either way (0.0-0.49)
+/// * `pwm_rate: f32` - The rate of the LFO, in Hz
+/// * `lfo: f64` - The phase of the LFO (0.0-1.0)
+///
+/// ### Parameters
+///
+/// * "width" - The pulse width (0.01-0.99)
+/// * "pwm" - The depth of the pulse width modulation (0.0-0.49)
+/// * "pwm_rate" - The rate of the pulse width modulation, in Hz
+pub struct Pulse {
+    phase: Phase,
+    width: f32,
+    pwm: f32,
+    pwm_rate: f32,
+    lfo: f64,
+}
+
+impl Pulse {
+    /// ## `new(width: f32) -> Self`
+    ///
+    /// Creates a pulse wave starting at phase 0.0, without pulse width modulation.
+    ///
+    /// ### Arguments
+    ///
+    /// * `width: f32` - The part of the cycle spent high (0.01-0.99); 0.5 is a square wave
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The new oscillator
+    pub fn new(width: f32) -> Self {
+        Self {
+            phase: Phase { phase: 0.0 },
+            width: width.clamp(0.01, 0.99),
+            pwm: 0.0,
+            pwm_rate: 0.5,
+            lfo: 0.0,
+        }
+    }
+}
+
+impl Oscillator for Pulse {
+    fn next(&mut self, frequency: f32, sample_rate: f32) -> (f32, f32) {
+        let width = match self.pwm > 0.0 {
+            true => {
+                self.lfo = (self.lfo + self.pwm_rate as f64 / sample_rate.max(1.0) as f64).fract();
+                self.width + (self.lfo * std::f64::consts::TAU).sin() as f32 * self.pwm
+            }
+            false => self.width,
+        }
+        .clamp(0.01, 0.99) as f64;
+
+        let (phase, step) = self.phase.advance(frequency, sample_rate);
+        let naive = match phase < width {
+            true => 1.0,
+            false => -1.0,
+        };
+        let sample =
+            naive + poly_blep(phase, step) - poly_blep((phase + 1.0 - width).fract(), step);
+        (sample as f32, sample as f32)
+    }
+
+    fn reset(&mut self, phase: f32) {
+        self.phase.phase = phase.rem_euclid(1.0) as f64;
+    }
+
+    fn duplicate(&self) -> Box<dyn Oscillator> {
+        Box::new(Pulse {
+            phase: Phase { phase: 0.0 },
+            lfo: 0.0,
+            ..*self
+        })
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f32) {
+        match name {
+            "width" => self.width = value.clamp(0.01, 0.99),
+            "pwm" => self.pwm = value.clamp(0.0, 0.49),
+            "pwm_rate" => self.pwm_rate = value.max(0.0),
+            _ => {}
+        }
+    }
+}
+
+/// ## Triangle
+///
+/// A triangle wave, anti-aliased with PolyBLAMP at its corners.
+///
+/// ### Fields
+///
+/// * `phase: Phase` - The phase accumulator
+pub struct Triangle {
+    phase: Phase,
+}
+
+impl Triangle {
+    /// ## `new() -> Self`
+    ///
+    /// Creates a triangle wave starting at phase 0.0.
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The new oscillator
+    pub fn new() -> Self {
+        Self {
+            phase: Phase { phase: 0.0 },
+        }
+    }
+}
+
+impl Oscillator for Triangle {
+    fn next(&mut self, frequency: f32, sample_rate: f32) -> (f32, f32) {
+        let (phase, step) = self.phase.advance(frequency, sample_rate);
+
+        // rises from -1.0 at 0.75 to 1.0 at 0.25, so it starts from 0.0 like the sine
+        let mut naive = phase * 4.0;
+        if naive >= 3.0 {
+            naive -= 4.0;
+        } else if naive > 1.0 {
+            naive = 2.0 - naive;
+        }
+        let corners =
+            poly_blamp((phase + 0.25).fract(), step) - poly_blamp((phase + 0.75).fract(), step);
+        let sample = (naive + 4.0 * step * corners) as f32;
+        (sample, sample)
+    }
+
+    fn reset(&mut self, phase: f32) {
+        self.phase.phase = phase.rem_euclid(1.0) as f64;
+    }
+
+    fn duplicate(&self) -> Box<dyn Oscillator> {
+        Box::new(Triangle::new())
+    }
+}
+
+/// ## WhiteNoise
+///
+/// White noise, from a xorshift generator. Ignores the frequency.
+///
+/// ### Fields
+///
+/// * `state: u32` - The state of the generator
+pub struct WhiteNoise {
+    state: u32,
+}
+
+impl WhiteNoise {
+    /// ## `new() -> Self`
+    ///
+    /// Creates white noise with a seed of its own.
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The new oscillator
+    pub fn new() -> Self {
+        Self {
+            state: NOISE_SEED.fetch_add(0x9E37_79B9, Ordering::Relaxed) | 1,
+        }
+    }
+
+    /// ## `sample(&mut self) -> f32`
+    ///
+    /// Returns the next random sample.
+    ///
+    /// ### Returns
+    ///
+    /// * `f32` - The sample (-1.0-1.0)
+    pub fn sample(&mut self) -> f32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        self.state as f32 / u32::MAX as f32 * 2.0 - 1.0
+    }
+}
+
+impl Oscillator for WhiteNoise {
+    fn next(&mut self, _frequency: f32, _sample_rate: f32) -> (f32, f32) {
+        let sample = self.sample();
+        (sample, sample)
+    }
+
+    fn duplicate(&self) -> Box<dyn Oscillator> {
+        Box::new(WhiteNoise::new())
+    }
+}
+
+/// ## PinkNoise
+///
+/// Pink noise (-3 dB per octave), filtered from white noise with Paul Kellet's economy filter. Ignores the frequency.
+///
+/// ### Fields
+///
+/// * `white: WhiteNoise` - The white noise being filtered
+/// * `poles: [f32; 3]` - The state of the filter
+pub struct PinkNoise {
+    white: WhiteNoise,
+    poles: [f32; 3],
+}
+
+impl PinkNoise {
+    /// ## `new() -> Self`
+    ///
+    /// Creates pink noise with a seed of its own.
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The new oscillator
+    pub fn new() -> Self {
+        Self {
+            white: WhiteNoise::new(),
+            poles: [0.0; 3],
+        }
+    }
+}
+
+impl Oscillator for PinkNoise {
+    fn next(&mut self, _frequency: f32, _sample_rate: f32) -> (f32, f32) {
+        let white = self.white.sample();
+        self.poles[0] = 0.99765 * self.poles[0] + white * 0.0990460;
+        self.poles[1] = 0.96300 * self.poles[1] + white * 0.2965164;
+        self.poles[2] = 0.57000 * self.poles[2] + white * 1.0526913;
+        let sample = (self.poles[0] + self.poles[1] + self.poles[2] + white * 0.1848) * 0.25;
+        (sample, sample)
+    }
+
+    fn duplicate(&self) -> Box<dyn Oscillator> {
+        Box::new(PinkNoise::new())
+    }
+}
+
+/// ## Unison
+///
+/// Plays several copies of an oscillator at once, detuned from each other and spread across the stereo field.
+/// Every copy is made up front, so the number played can change on the audio thread.
+///
+/// ### Fields
+///
+/// * `oscillators: Vec<Box<dyn Oscillator>>` - MAX_UNISON copies of the oscillator
+/// * `count: usize` - The number of copies played (1-8)
+/// * `detune: f32` - The distance between the lowest and the highest copy, in cents
+/// * `spread: f32` - How far the copies are panned apart (0.0-1.0)
+///
+/// ### Parameters
+///
+/// * "unison" - The number of copies played (1-8)
+/// * "detune" - The detune, in cents
+/// * "spread" - The stereo spread (0.0-1.0)
+/// * Anything else is passed on to the copies (e.g. "width")
+pub struct Unison {
+    oscillators: Vec<Box<dyn Oscillator>>,
+    count: usize,
+    detune: f32,
+    spread: f32,
+}
+
+impl Unison {
+    /// ## `new(oscillator: Box<dyn Oscillator>) -> Self`
+    ///
+    /// Creates a unison of one copy, not detuned.
+    ///
+    /// ### Arguments
+    ///
+    /// * `oscillator: Box<dyn Oscillator>` - The oscillator
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The new unison
+    pub fn new(oscillator: Box<dyn Oscillator>) -> Self {
+        let mut oscillators = Vec::with_capacity(MAX_UNISON);
+        for _ in 1..MAX_UNISON {
+            oscillators.push(oscillator.duplicate());
+        }
+        oscillators.insert(0, oscillator);
+
+        Self {
+            oscillators,
+            count: 1,
+            detune: 0.0,
+            spread: 0.0,
+        }
+    }
+
+    /// ## `position(&self, index: usize) -> f32`
+    ///
+    /// Private function which returns where a copy sits among the copies played, from -1.0 to 1.0.
+    ///
+    /// ### Arguments
+    ///
+    /// * `index: usize` - The index of the copy
+    ///
+    /// ### Returns
+    ///
+    /// * `f32` - The position; 0.0 with a single copy
+    fn position(&self, index: usize) -> f32 {
+        match self.count > 1 {
+            true => index as f32 / (self.count - 1) as f32 * 2.0 - 1.0,
+            false => 0.0,
+        }
+    }
+}
+
+impl Oscillator for Unison {
+    fn next(&mut self, frequency: f32, sample_rate: f32) -> (f32, f32) {
+        if self.count == 1 {
+            return self.oscillators[0].next(frequency, sample_rate);
+        }
+
+        let (mut left, mut right) = (0.0, 0.0);
+        for index in 0..self.count {
+            let position = self.position(index);
+            let detuned = frequency * 2.0f32.powf(position * self.detune / 2.0 / 1200.0);
+            let (sample_left, sample_right) = self.oscillators[index].next(detuned, sample_rate);
+            let pan = position * self.spread;
+            left += sample_left * (1.0 - pan.max(0.0));
+            right += sample_right * (1.0 + pan.min(0.0));
+        }
+
+        let gain = 1.0 / (self.count as f32).sqrt();
+        (left * gain, right * gain)
+    }
+
+    fn reset(&mut self, phase: f32) {
+        // copies start at different phases, so they don't begin in unison and sweep apart
+        let count = self.count;
+        for (index, oscillator) in self.oscillators.iter_mut().enumerate() {
+            oscillator.reset(phase + index as f32 / count as f32);
+        }
+    }
+
+    fn duplicate(&self) -> Box<dyn Oscillator> {
+        Box::new(Unison {
+            oscillators: self
+                .oscillators
+                .iter()
+                .map(|oscillator| oscillator.duplicate())
+                .collect(),
+            count: self.count,
+            detune: self.detune,
+            spread: self.spread,
+        })
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f32) {
+        match name {
+            "unison" => self.count = (value.max(1.0) as usize).min(MAX_UNISON),
+            "detune" => self.detune = value.clamp(0.0, 1200.0),
+            "spread" => self.spread = value.clamp(0.0, 1.0),
+            _ => {
+                for oscillator in self.oscillators.iter_mut() {
+                    oscillator.set_parameter(name, value);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+
+    /// A frequency with a whole number of cycles in a second, whose harmonics never alias onto each other at 48 kHz
+    const FREQUENCY: f32 = 2637.0;
+
+    /// ## `render(oscillator: &mut dyn Oscillator, frames: usize) -> Vec<f32>`
+    ///
+    /// Plays an oscillator's left channel at FREQUENCY.
+    fn render(oscillator: &mut dyn Oscillator, frames: usize) -> Vec<f32> {
+        (0..frames)
+            .map(|_| oscillator.next(FREQUENCY, SAMPLE_RATE).0)
+            .collect()
+    }
+
+    /// ## `naive(shape: impl Fn(f64) -> f64) -> Vec<f32>`
+    ///
+    /// Plays a second of a waveform at FREQUENCY, straight from the phase with nothing taken out of its edges.
+    fn naive(shape: impl Fn(f64) -> f64) -> Vec<f32> {
+        let mut phase = Phase { phase: 0.0 };
+        (0..SAMPLE_RATE as usize)
+            .map(|_| shape(phase.advance(FREQUENCY, SAMPLE_RATE).0) as f32)
+            .collect()
+    }
+
+    /// ## `power(samples: &[f32], frequency: f32) -> f64`
+    ///
+    /// Returns the power of the DFT bin at a frequency (Goertzel); a second of samples puts every whole frequency on a bin.
+    fn power(samples: &[f32], frequency: f32) -> f64 {
+        let coefficient =
+            2.0 * (std::f64::consts::TAU * frequency as f64 / SAMPLE_RATE as f64).cos();
+        let (mut previous, mut before) = (0.0, 0.0);
+        for sample in samples {
+            let current = *sample as f64 + coefficient * previous - before;
+            before = previous;
+            previous = current;
+        }
+        (previous * previous + before * before - coefficient * previous * before)
+            / (samples.len() as f64 * samples.len() as f64)
+    }
+
+    /// ## `aliasing(samples: &[f32]) -> f64`
+    ///
+    /// Returns the power of the harmonics over Nyquist that fold back above Nyquist/2, in dB.
+    fn aliasing(samples: &[f32]) -> f64 {
+        let nyquist = SAMPLE_RATE / 2.0;
+        let mut total = 0.0;
+        for harmonic in 1..200 {
+            let frequency = (FREQUENCY * harmonic as f32) % SAMPLE_RATE;
+            let folded = match frequency > nyquist {
+                true => SAMPLE_RATE - frequency,
+                false => frequency,
+            };
+            if FREQUENCY * harmonic as f32 > nyquist && folded > nyquist / 2.0 {
+                total += power(samples, folded);
+            }
+        }
+        10.0 * total.log10()
+    }
+
+    #[test]
+    fn band_limited_waveforms_alias_less_than_naive_ones() {
+        let frames = SAMPLE_RATE as usize;
+        let cases: [(&str, Box<dyn Oscillator>, Vec<f32>); 3] = [
+            (
+                "saw",
+                Box::new(Saw::new()),
+                naive(|phase| 2.0 * phase - 1.0),
+            ),
+            (
+                "pulse",
+                Box::new(Pulse::new(0.3)),
+                naive(|phase| match phase < 0.3 {
+                    true => 1.0,
+                    false => -1.0,
+                }),
+            ),
+            (
+                "triangle",
+                Box::new(Triangle::new()),
+                naive(|phase| {
+                    let naive = phase * 4.0;
+                    match naive {
+                        naive if naive >= 3.0 => naive - 4.0,
+                        naive if naive > 1.0 => 2.0 - naive,
+                        naive => naive,
+                    }
+                }),
+            ),
+        ];
+        for (name, mut oscillator, naive) in cases {
+            let band_limited = render(oscillator.as_mut(), frames);
+            // the fundamental is the same either way
+            let fundamental = power(&band_limited, FREQUENCY) / power(&naive, FREQUENCY);
+            assert!(
+                (fundamental - 1.0).abs() < 0.05,
+                "{} fundamental off by {}",
+                name,
+                fundamental
+            );
+
+            let (band_limited, naive) = (aliasing(&band_limited), aliasing(&naive));
+            assert!(
+                band_limited < naive - 10.0,
+                "{} aliases at {:.1} dB, naive at {:.1} dB",
+                name,
+                band_limited,
+                naive
+            );
+        }
+    }
+
+    #[test]
+    fn pulse_width_moves_the_dc_offset() {
+        let mean = |samples: &[f32]| samples.iter().sum::<f32>() / samples.len() as f32;
+
+        // a pulse high a quarter of the time averages -0.5
+        let mut pulse = Pulse::new(0.25);
+        let samples = render(&mut pulse, 48000);
+        assert!((mean(&samples) + 0.5).abs() < 0.01, "{}", mean(&samples));
+
+        // modulating a square at 1 Hz moves the average up for half a second and down for the other half
+        let mut pulse = Pulse::new(0.5);
+        pulse.set_parameter("pwm", 0.3);
+        pulse.set_parameter("pwm_rate", 1.0);
+        let samples = render(&mut pulse, 48000);
+        let high = mean(&samples[9600..14400]);
+        let low = mean(&samples[33600..38400]);
+        assert!(high > 0.5, "{}", high);
+        assert!(low < -0.5, "{}", low);
+    }
+
+    #[test]
+    fn unison_of_one_plays_the_oscillator() {
+        let mut single = Saw::new();
+        let mut unison = Unison::new(Box::new(Saw::new()));
+        // detune and spread do nothing to a single copy
+        unison.set_parameter("detune", 50.0);
+        unison.set_parameter("spread", 1.0);
+        single.reset(0.3);
+        unison.reset(0.3);
+        for frame in 0..4800 {
+            let frequency = 110.0 + frame as f32 * 0.1;
+            assert_eq!(
+                single.next(frequency, SAMPLE_RATE),
+                unison.next(frequency, SAMPLE_RATE),
+                "frame {}",
+                frame
+            );
+        }
+    }
+}
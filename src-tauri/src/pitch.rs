@@ -0,0 +1,1191 @@
+//! pitch.rs
+//!
+//! Module for the pitch shifter and harmonizer, which move the pitch of their input with TD-PSOLA (tdpsola)
+
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::Mutex;
+
+use tdpsola::{AlternatingHann, Speed, TdpsolaAnalysis, TdpsolaSynthesis};
+
+use crate::audio::{self, plugin::Effect, Buffer, PanLaw, Sample, State, MAX_SAMPLE_RATE};
+
+/// The length of the segments the input is shifted in, in seconds. Segments overlap by half, and the output lags the input
+/// by two: a segment is shifted once half a segment more input is in, for the grains at its end, and the worker has
+/// another half a segment to send it back while the blocks after it are played.
+const SEGMENT: f32 = 0.03;
+
+/// The lowest pitch the tracker looks for, in Hz; its period has to fit in half a segment
+const MIN_FREQUENCY: f32 = 70.0;
+
+/// The highest pitch the tracker looks for, in Hz
+const MAX_FREQUENCY: f32 = 1000.0;
+
+/// The lowest rate the pitch is tracked at, in Hz. Faster input is averaged down to between this and twice it first,
+/// as the tracker's work grows with the square of the rate.
+const TRACK_RATE: f32 = 16000.0;
+
+/// How low the tracker's normalized difference has to dip for a period to count as the pitch
+const THRESHOLD: f32 = 0.15;
+
+/// Segments quieter than this (RMS) aren't tracked, and keep the last pitch
+const SILENCE: f32 = 0.001;
+
+/// The pitch assumed until the tracker finds one, in Hz
+const DEFAULT_FREQUENCY: f32 = 200.0;
+
+/// The length of the rings holding the input and output, in frames: a power of two with room for the latency at MAX_SAMPLE_RATE
+const RING: usize = ((3.0 * SEGMENT * MAX_SAMPLE_RATE) as usize + 2).next_power_of_two();
+
+/// The most segments handed to a worker at once. A segment that finds none free is left out of the output.
+const JOBS: usize = 4;
+
+/// The input of a job holding both channels mixed to mono, after the left and right channels
+const MONO: usize = 2;
+
+/// The furthest the pitch can be shifted either way, in semitones
+pub const MAX_SHIFT: f32 = 24.0;
+
+/// The most harmony voices in a harmonizer
+pub const MAX_VOICES: usize = 4;
+
+/// The intervals of harmony voices that were not given one, in scale steps: a third and a fifth above,
+/// a fourth below and an octave above in a major scale
+const DEFAULT_INTERVALS: [i32; MAX_VOICES] = [2, 4, -3, 7];
+
+/// The scales the harmonizer can keep its voices in, as semitones above the key: chromatic, major, natural minor,
+/// harmonic minor, dorian, major pentatonic and minor pentatonic
+const SCALES: [&[i32]; 7] = [
+    &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+    &[0, 2, 4, 5, 7, 9, 11],
+    &[0, 2, 3, 5, 7, 8, 10],
+    &[0, 2, 3, 5, 7, 8, 11],
+    &[0, 2, 3, 5, 7, 9, 10],
+    &[0, 2, 4, 7, 9],
+    &[0, 3, 5, 7, 10],
+];
+
+/// ## `segment_length(sample_rate: u32) -> usize`
+///
+/// Returns the length of a segment in frames at a sample rate, rounded to an even number so it splits into two hops.
+///
+/// ### Arguments
+///
+/// * `sample_rate: u32` - The sample rate
+///
+/// ### Returns
+///
+/// * `usize` - The length of a segment, in frames
+fn segment_length(sample_rate: u32) -> usize {
+    ((SEGMENT * sample_rate.min(MAX_SAMPLE_RATE as u32) as f32) as usize / 2).max(1) * 2
+}
+
+/// ## `harmonize(note: i32, key: i32, scale: &[i32], interval: i32) -> i32`
+///
+/// Works out how far to shift a note to move it a number of steps along a scale. A note outside the scale
+/// counts from the scale note below it, so the harmony stays in the key.
+///
+/// ### Arguments
+///
+/// * `note: i32` - The MIDI note being sung or played
+/// * `key: i32` - The key, as a pitch class (0 for C to 11 for B)
+/// * `scale: &[i32]` - The scale, as semitones above the key
+/// * `interval: i32` - How many scale steps to move; 2 is a third in a major scale, -3 a fourth below
+///
+/// ### Returns
+///
+/// * `i32` - The shift, in semitones
+fn harmonize(note: i32, key: i32, scale: &[i32], interval: i32) -> i32 {
+    let relative = note - key;
+    let octave = relative.div_euclid(12);
+    let class = relative.rem_euclid(12);
+    let degree = scale.iter().rposition(|step| *step <= class).unwrap_or(0) as i32;
+
+    let length = scale.len() as i32;
+    let target = degree + interval;
+    let target_note =
+        key + 12 * (octave + target.div_euclid(length)) + scale[target.rem_euclid(length) as usize];
+    target_note - note
+}
+
+/// ## Ring
+///
+/// The last RING frames of a signal, indexed by the time of each frame.
+///
+/// ### Fields
+///
+/// * `samples: Vec<f32>` - The frames, allocated up front
+struct Ring {
+    samples: Vec<f32>,
+}
+
+impl Ring {
+    /// ## `new() -> Self`
+    ///
+    /// Private function which creates a silent ring.
+    fn new() -> Self {
+        Self {
+            samples: vec![0.0; RING],
+        }
+    }
+
+    /// ## `clear(&mut self)`
+    ///
+    /// Private function which silences the ring.
+    fn clear(&mut self) {
+        self.samples.fill(0.0);
+    }
+
+    /// ## `write(&mut self, time: u64, sample: f32)`
+    ///
+    /// Private function which sets the frame at a time.
+    fn write(&mut self, time: u64, sample: f32) {
+        self.samples[time as usize % RING] = sample;
+    }
+
+    /// ## `read(&self, time: u64) -> f32`
+    ///
+    /// Private function which returns the frame at a time.
+    fn read(&self, time: u64) -> f32 {
+        self.samples[time as usize % RING]
+    }
+
+    /// ## `add(&mut self, time: u64, sample: f32)`
+    ///
+    /// Private function which adds to the frame at a time.
+    fn add(&mut self, time: u64, sample: f32) {
+        self.samples[time as usize % RING] += sample;
+    }
+
+    /// ## `take(&mut self, time: u64) -> f32`
+    ///
+    /// Private function which returns the frame at a time and silences it, so the slot can be added to again.
+    fn take(&mut self, time: u64) -> f32 {
+        std::mem::take(&mut self.samples[time as usize % RING])
+    }
+}
+
+/// ## Tracker
+///
+/// A pitch tracker, after the YIN algorithm: the period is the first lag at which the input's normalized
+/// difference with itself dips below THRESHOLD. While the input is unvoiced or silent it keeps the last period it found.
+///
+/// ### Fields
+///
+/// * `wavelength: f32` - The last period found, in frames
+/// * `differences: Vec<f32>` - The normalized difference at each lag, allocated for MIN_FREQUENCY at MAX_SAMPLE_RATE
+struct Tracker {
+    wavelength: f32,
+    differences: Vec<f32>,
+}
+
+impl Tracker {
+    /// ## `new() -> Self`
+    ///
+    /// Private function which creates a tracker assuming DEFAULT_FREQUENCY at 48 kHz.
+    fn new() -> Self {
+        Self {
+            wavelength: 48000.0 / DEFAULT_FREQUENCY,
+            differences: vec![0.0; (MAX_SAMPLE_RATE / MIN_FREQUENCY) as usize + 2],
+        }
+    }
+
+    /// ## `track(&mut self, samples: &[f32], sample_rate: u32, decimation: usize) -> Option<f32>`
+    ///
+    /// Private function which looks for the period of a stretch of input, decimated from the sample rate by a factor.
+    /// Returns it in frames at the sample rate if the input is voiced; either way the tracker's wavelength is the period to shift the input with.
+    fn track(&mut self, samples: &[f32], sample_rate: u32, decimation: usize) -> Option<f32> {
+        let sample_rate = sample_rate as f32 / decimation as f32;
+        let min_lag = ((sample_rate / MAX_FREQUENCY) as usize).max(2);
+        let max_lag = ((sample_rate / MIN_FREQUENCY) as usize).min(samples.len() / 2);
+        if max_lag <= min_lag + 1 {
+            return None;
+        }
+
+        let energy = samples.iter().map(|sample| sample * sample).sum::<f32>();
+        if (energy / samples.len() as f32).sqrt() < SILENCE {
+            return None;
+        }
+
+        // the cumulative mean normalized difference, which doesn't dip at lag 0 like plain autocorrelation peaks
+        let span = samples.len() - max_lag;
+        let mut running = 0.0;
+        for lag in 1..=max_lag {
+            let difference = samples[..span]
+                .iter()
+                .zip(&samples[lag..lag + span])
+                .map(|(a, b)| (a - b) * (a - b))
+                .sum::<f32>();
+            running += difference;
+            self.differences[lag] = match running > 0.0 {
+                true => difference * lag as f32 / running,
+                false => 1.0,
+            };
+        }
+
+        let mut lag = (min_lag..max_lag).find(|lag| self.differences[*lag] < THRESHOLD)?;
+        while lag + 1 < max_lag && self.differences[lag + 1] < self.differences[lag] {
+            lag += 1;
+        }
+
+        // fit a parabola through the dip to find the period between lags
+        let (a, b, c) = (
+            self.differences[lag - 1],
+            self.differences[lag],
+            self.differences[lag + 1],
+        );
+        let curve = a - 2.0 * b + c;
+        let offset = match curve.abs() > f32::EPSILON {
+            true => (0.5 * (a - c) / curve).clamp(-0.5, 0.5),
+            false => 0.0,
+        };
+
+        self.wavelength = (lag as f32 + offset) * decimation as f32;
+        Some(self.wavelength)
+    }
+}
+
+/// ## `next_mark(mark: f64, after: f64, spacing: f64) -> f64`
+///
+/// Moves a mark on by whole spacings to the first at or after a time, so marks carry on from segment to segment.
+///
+/// ### Arguments
+///
+/// * `mark: f64` - The mark
+/// * `after: f64` - The time
+/// * `spacing: f64` - The distance between marks
+///
+/// ### Returns
+///
+/// * `f64` - The first mark at or after the time
+fn next_mark(mark: f64, after: f64, spacing: f64) -> f64 {
+    match mark < after {
+        true => mark + ((after - mark) / spacing).ceil() * spacing,
+        false => mark,
+    }
+}
+
+/// ## Job
+///
+/// A stretch of input for a worker to shift, and the shifted segments it hands back. Jobs are allocated up front
+/// and passed back and forth between the audio thread and the worker, so the audio thread never allocates.
+///
+/// The input is the last three segments, and the output the segment starting a segment and a half in:
+/// the input before it gives the grains at its start something to be cut from, and the input after it the grains at its end.
+///
+/// ### Fields
+///
+/// * `time: u64` - The time of the input's first frame
+/// * `length: usize` - The length of a segment, in frames
+/// * `wavelength: f32` - The period of the input, in frames
+/// * `mark: f64` - The time of the first input pitch mark, where grains are cut from
+/// * `formant: bool` - Whether the formants are preserved
+/// * `inputs: [Vec<f32>; 3]` - The input's left and right channels and their mono mix, allocated for MAX_SAMPLE_RATE
+/// * `voices: usize` - How many shifted segments are wanted
+/// * `sources: [usize; MAX_VOICES]` - The input each shifted segment is made from
+/// * `ratios: [f32; MAX_VOICES]` - The ratio of each shifted segment's pitch to the input's
+/// * `grids: [f64; MAX_VOICES]` - The time of an output pitch mark of each shifted segment, where grains are added
+/// * `outputs: [Vec<f32>; MAX_VOICES]` - The shifted segments, tapered to be overlap-added, allocated for MAX_SAMPLE_RATE
+struct Job {
+    time: u64,
+    length: usize,
+    wavelength: f32,
+    mark: f64,
+    formant: bool,
+    inputs: [Vec<f32>; 3],
+    voices: usize,
+    sources: [usize; MAX_VOICES],
+    ratios: [f32; MAX_VOICES],
+    grids: [f64; MAX_VOICES],
+    outputs: [Vec<f32>; MAX_VOICES],
+}
+
+impl Job {
+    /// ## `new() -> Self`
+    ///
+    /// Private function which creates an empty job long enough for MAX_SAMPLE_RATE.
+    fn new() -> Self {
+        let length = segment_length(MAX_SAMPLE_RATE as u32);
+        Self {
+            time: 0,
+            length: 0,
+            wavelength: 0.0,
+            mark: 0.0,
+            formant: true,
+            inputs: std::array::from_fn(|_| vec![0.0; 3 * length]),
+            voices: 0,
+            sources: [0; MAX_VOICES],
+            ratios: [1.0; MAX_VOICES],
+            grids: [0.0; MAX_VOICES],
+            outputs: std::array::from_fn(|_| vec![0.0; length]),
+        }
+    }
+
+    /// ## `shift(&mut self, resampled: &mut Vec<f32>)`
+    ///
+    /// Private function which makes every shifted segment, on the worker.
+    ///
+    /// TD-PSOLA cuts grains two periods long at input pitch marks and adds them at output pitch marks, the shifted period apart.
+    /// Starting the analysis on an input mark and lining the output up with an output mark keeps consecutive segments
+    /// in phase, so they overlap-add without beating.
+    fn shift(&mut self, resampled: &mut Vec<f32>) {
+        let start = ((self.mark - self.time as f64).round().max(0.0) as usize).min(2 * self.length);
+        let first = 3 * self.length / 2;
+        for voice in 0..self.voices {
+            let spacing = self.wavelength as f64 / self.ratios[voice] as f64;
+            // how far the grains are moved later to land on the output marks
+            let delay = (self.grids[voice] - self.time as f64 - start as f64).rem_euclid(spacing);
+            shift(
+                &self.inputs[self.sources[voice]][start..3 * self.length],
+                &mut self.outputs[voice][..self.length],
+                first as f64 - start as f64 - delay,
+                self.wavelength,
+                self.ratios[voice],
+                self.formant,
+                resampled,
+            );
+        }
+    }
+}
+
+/// ## `shift(input: &[f32], output: &mut [f32], offset: f64, wavelength: f32, ratio: f32, formant: bool, resampled: &mut Vec<f32>)`
+///
+/// Shifts an input with TD-PSOLA, writing the part of the result from an offset on into an output under a squared sine window,
+/// which sums to one at half overlap. The input should start on a pitch mark; the first grain is added at the start of the result.
+///
+/// With formants preserved, the input's pitch periods are re-spaced by TD-PSOLA and its spectral envelope stays put.
+/// Otherwise the input is first resampled by the ratio, moving pitch and formants together, and TD-PSOLA stretches it
+/// back to length at the new pitch. tdpsola allocates as it goes, so this only ever runs on a worker.
+///
+/// ### Arguments
+///
+/// * `input: &[f32]` - The input
+/// * `output: &mut [f32]` - Where to write the shifted input
+/// * `offset: f64` - Where in the result the output starts, in frames; the output is silent before the result starts
+/// * `wavelength: f32` - The period of the input, in frames
+/// * `ratio: f32` - The ratio of the shifted pitch to the input's
+/// * `formant: bool` - Whether the formants are preserved
+/// * `resampled: &mut Vec<f32>` - Room for the resampled input, kept between calls
+fn shift(
+    input: &[f32],
+    output: &mut [f32],
+    offset: f64,
+    wavelength: f32,
+    ratio: f32,
+    formant: bool,
+    resampled: &mut Vec<f32>,
+) {
+    let length = input.len();
+    output.fill(0.0);
+
+    let (samples, source, speed) = match formant {
+        true => (input, wavelength, 1.0),
+        false => {
+            resampled.clear();
+            for index in 0..((length - 1) as f32 / ratio) as usize + 1 {
+                let position = index as f32 * ratio;
+                let whole = position as usize;
+                let fraction = position - whole as f32;
+                let next = input[(whole + 1).min(length - 1)];
+                resampled.push(input[whole] + (next - input[whole]) * fraction);
+            }
+            (&resampled[..], wavelength / ratio, 1.0 / ratio)
+        }
+    };
+
+    let mut window = AlternatingHann::new(source);
+    let mut analysis = TdpsolaAnalysis::new(&window);
+    for sample in samples {
+        analysis.push_sample(*sample, &mut window);
+    }
+
+    let offset = offset.round() as i64;
+    let (skip, lead) = match offset >= 0 {
+        true => (offset as usize, 0),
+        false => (0, (-offset as usize).min(output.len())),
+    };
+    let mut synthesis = TdpsolaSynthesis::new(Speed::from_f32(speed), wavelength / ratio);
+    let total = output.len();
+    for (index, sample) in synthesis
+        .iter(&analysis)
+        .skip(skip)
+        .take(total - lead)
+        .enumerate()
+    {
+        let index = index + lead;
+        let taper = (std::f32::consts::PI * (index as f32 + 0.5) / total as f32).sin();
+        output[index] = sample * taper * taper;
+    }
+}
+
+/// ## `work(jobs: Receiver<Job>, done: SyncSender<Job>)`
+///
+/// Shifts every job sent to a worker and sends it back, until the effect that sent them is dropped.
+///
+/// ### Arguments
+///
+/// * `jobs: Receiver<Job>` - The jobs to shift
+/// * `done: SyncSender<Job>` - Where to send them back
+fn work(jobs: Receiver<Job>, done: SyncSender<Job>) {
+    let mut resampled = Vec::new();
+    while let Ok(mut job) = jobs.recv() {
+        job.shift(&mut resampled);
+        match done.send(job) {
+            Ok(_) => {}
+            Err(_) => break,
+        }
+    }
+}
+
+/// ## Shifting
+///
+/// The audio thread's side of a worker: it collects the input into segments, tracks the pitch of each, and hands them
+/// to a worker thread to shift with tdpsola. The shifted segments come back within half a segment and are overlap-added into a ring
+/// of each voice, from which they are played two segments behind the input, along with the dry signal.
+/// The pitch marks carry on from segment to segment, so the segments stay in phase with each other.
+///
+/// ### Fields
+///
+/// * `jobs: SyncSender<Job>` - Sends segments to the worker, which stops when this is dropped
+/// * `done: Mutex<Receiver<Job>>` - Shifted segments sent back; in a mutex only so effects are Sync, as it's reached through `get_mut`
+/// * `idle: Vec<Job>` - The jobs not with the worker
+/// * `inputs: [Ring; 2]` - The input of each channel
+/// * `outputs: [Ring; MAX_VOICES]` - The shifted output of each voice
+/// * `tracker: Tracker` - The pitch tracker
+/// * `mark: f64` - The time of the latest input pitch mark
+/// * `grids: [f64; MAX_VOICES]` - The time of the latest output pitch mark of each voice
+/// * `window: Vec<f32>` - The latest segment mixed to mono and averaged down, for the tracker
+/// * `time: u64` - The time of the latest frame; it starts a ring in, so looking back never goes below zero
+/// * `length: usize` - The length of a segment at the current sample rate, in frames
+/// * `countdown: usize` - The frames left until the next segment is complete
+/// * `sample_rate: u32` - The sample rate the segments were worked out for
+struct Shifting {
+    jobs: SyncSender<Job>,
+    done: Mutex<Receiver<Job>>,
+    idle: Vec<Job>,
+    inputs: [Ring; 2],
+    outputs: [Ring; MAX_VOICES],
+    tracker: Tracker,
+    mark: f64,
+    grids: [f64; MAX_VOICES],
+    window: Vec<f32>,
+    time: u64,
+    length: usize,
+    countdown: usize,
+    sample_rate: u32,
+}
+
+impl Shifting {
+    /// ## `new() -> Self`
+    ///
+    /// Private function which starts a worker and allocates its jobs.
+    fn new() -> Self {
+        let (jobs, worker_jobs) = sync_channel(JOBS);
+        let (worker_done, done) = sync_channel(JOBS);
+        std::thread::spawn(move || work(worker_jobs, worker_done));
+
+        Self {
+            jobs,
+            done: Mutex::new(done),
+            idle: (0..JOBS).map(|_| Job::new()).collect(),
+            inputs: std::array::from_fn(|_| Ring::new()),
+            outputs: std::array::from_fn(|_| Ring::new()),
+            tracker: Tracker::new(),
+            mark: 0.0,
+            grids: [0.0; MAX_VOICES],
+            window: vec![0.0; (2.0 * SEGMENT * TRACK_RATE) as usize + 2],
+            time: RING as u64,
+            length: 0,
+            countdown: 0,
+            sample_rate: 0,
+        }
+    }
+
+    /// ## `resize(&mut self, sample_rate: u32)`
+    ///
+    /// Private function which starts over when the sample rate changes.
+    fn resize(&mut self, sample_rate: u32) {
+        if sample_rate == self.sample_rate {
+            return;
+        }
+        self.sample_rate = sample_rate;
+        self.length = segment_length(sample_rate);
+        self.countdown = self.length / 2;
+        self.tracker.wavelength = sample_rate as f32 / DEFAULT_FREQUENCY;
+        for ring in self.inputs.iter_mut().chain(self.outputs.iter_mut()) {
+            ring.clear();
+        }
+    }
+
+    /// ## `played(&self) -> u64`
+    ///
+    /// Private function which returns the time of the frame played now, two segments behind the latest.
+    fn played(&self) -> u64 {
+        self.time - 2 * self.length as u64
+    }
+
+    /// ## `push(&mut self, left: f32, right: f32) -> bool`
+    ///
+    /// Private function which adds a frame of input, and returns whether it completed a segment.
+    fn push(&mut self, left: f32, right: f32) -> bool {
+        self.time += 1;
+        self.inputs[0].write(self.time, left);
+        self.inputs[1].write(self.time, right);
+
+        self.countdown -= 1;
+        if self.countdown > 0 {
+            return false;
+        }
+        self.countdown = self.length / 2;
+        true
+    }
+
+    /// ## `track(&mut self) -> Option<f32>`
+    ///
+    /// Private function which tracks the pitch of the latest segment, and returns its period in frames if it is voiced.
+    fn track(&mut self) -> Option<f32> {
+        let decimation = ((self.sample_rate as f32 / TRACK_RATE) as usize).max(1);
+        let length = (self.length / decimation).min(self.window.len());
+        let start = self.time + 1 - (length * decimation) as u64;
+        for (offset, sample) in self.window[..length].iter_mut().enumerate() {
+            let first = start + (offset * decimation) as u64;
+            *sample = (first..first + decimation as u64)
+                .map(|time| self.inputs[0].read(time) + self.inputs[1].read(time))
+                .sum::<f32>()
+                / (2 * decimation) as f32;
+        }
+        self.tracker
+            .track(&self.window[..length], self.sample_rate, decimation)
+    }
+
+    /// ## `send(&mut self, voices: &[(usize, f32)], formant: bool)`
+    ///
+    /// Private function which hands the latest segment to the worker, at the last pitch tracked, to be shifted once for each voice:
+    /// from an input (0 left, 1 right or MONO) by a ratio. If the worker is still busy with every job, the segment is left out.
+    fn send(&mut self, voices: &[(usize, f32)], formant: bool) {
+        let mut job = match self.idle.pop() {
+            Some(job) => job,
+            None => return,
+        };
+
+        job.time = self.time + 1 - 3 * self.length as u64;
+        job.length = self.length;
+        job.wavelength = self.tracker.wavelength;
+        job.formant = formant;
+        let wavelength = job.wavelength as f64;
+        self.mark = next_mark(self.mark, job.time as f64, wavelength);
+        job.mark = self.mark;
+        for offset in 0..3 * self.length {
+            let left = self.inputs[0].read(job.time + offset as u64);
+            let right = self.inputs[1].read(job.time + offset as u64);
+            job.inputs[0][offset] = left;
+            job.inputs[1][offset] = right;
+            job.inputs[MONO][offset] = (left + right) / 2.0;
+        }
+        job.voices = voices.len().min(MAX_VOICES);
+        for (voice, (source, ratio)) in voices.iter().take(MAX_VOICES).enumerate() {
+            job.sources[voice] = *source;
+            job.ratios[voice] = *ratio;
+            let spacing = wavelength / *ratio as f64;
+            self.grids[voice] = next_mark(self.grids[voice], job.time as f64, spacing);
+            job.grids[voice] = self.grids[voice];
+        }
+
+        match self.jobs.try_send(job) {
+            Ok(_) => {}
+            Err(TrySendError::Full(job)) | Err(TrySendError::Disconnected(job)) => {
+                self.idle.push(job)
+            }
+        }
+    }
+
+    /// ## `receive(&mut self)`
+    ///
+    /// Private function which adds every segment the worker has sent back to the outputs.
+    fn receive(&mut self) {
+        while let Ok(job) = self.done().try_recv() {
+            self.finish(job);
+        }
+    }
+
+    /// ## `done(&mut self) -> &Receiver<Job>`
+    ///
+    /// Private function which returns the receiver of shifted segments, without locking it.
+    fn done(&mut self) -> &Receiver<Job> {
+        match self.done.get_mut() {
+            Ok(done) => done,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// ## `finish(&mut self, job: Job)`
+    ///
+    /// Private function which adds a shifted segment to the outputs and keeps its job for the next one.
+    /// Frames already played are left out, as their slots would come round again a ring later.
+    fn finish(&mut self, job: Job) {
+        let played = self.played();
+        let first = job.time + 3 * job.length as u64 / 2;
+        for voice in 0..job.voices {
+            for (offset, sample) in job.outputs[voice][..job.length].iter().enumerate() {
+                let time = first + offset as u64;
+                if time >= played {
+                    self.outputs[voice].add(time, *sample);
+                }
+            }
+        }
+        self.idle.push(job);
+    }
+
+    /// ## `dry(&self, channel: usize) -> f32`
+    ///
+    /// Private function which returns the input of a channel, delayed to play with the shifted output.
+    fn dry(&self, channel: usize) -> f32 {
+        self.inputs[channel].read(self.played())
+    }
+
+    /// ## `wet(&mut self, voice: usize) -> f32`
+    ///
+    /// Private function which plays the shifted output of a voice.
+    fn wet(&mut self, voice: usize) -> f32 {
+        let played = self.played();
+        self.outputs[voice].take(played)
+    }
+
+    /// ## `silence(&mut self, voice: usize)`
+    ///
+    /// Private function which clears the output of a voice, for when it starts sounding again.
+    fn silence(&mut self, voice: usize) {
+        self.outputs[voice].clear();
+    }
+
+    /// ## `settle(&mut self)`
+    ///
+    /// Private function which waits for the worker to send back every job, so tests don't depend on how fast it runs.
+    #[cfg(test)]
+    fn settle(&mut self) {
+        while self.idle.len() < JOBS {
+            match self.done().recv() {
+                Ok(job) => self.finish(job),
+                Err(_) => break,
+            }
+        }
+    }
+}
+
+/// ## PitchShift
+///
+/// A pitch shifter, moving its input by a number of semitones and cents with TD-PSOLA (tdpsola). The pitch of the input is
+/// tracked (on both channels mixed to mono) so the shifter can work period by period, which keeps voices and solo
+/// instruments clean; by default the formants stay where they are, so voices don't sound chipmunked.
+/// The output lags the input by two segments (60 ms), and the dry signal is delayed to match.
+///
+/// ### Fields
+///
+/// * `semitones: f32` - The shift, in semitones
+/// * `cents: f32` - The fine shift, in cents
+/// * `formant: bool` - Whether the formants are preserved
+/// * `mix: f32` - The level of the shifted signal (0.0 dry, 1.0 only shifted)
+/// * `shifting: Shifting` - The segments of the input and the worker shifting them, with each channel as a voice
+///
+/// ### Parameters
+///
+/// * "semitones", "cents", "mix" - As the fields
+/// * "formant" - 1 to preserve formants, 0 to move them with the pitch
+pub struct PitchShift {
+    semitones: f32,
+    cents: f32,
+    formant: bool,
+    mix: f32,
+    shifting: Shifting,
+}
+
+impl PitchShift {
+    /// ## `new(semitones: f32, cents: f32) -> Self`
+    ///
+    /// Creates a pitch shifter preserving formants, with only the shifted signal.
+    ///
+    /// ### Arguments
+    ///
+    /// * `semitones: f32` - The shift, in semitones
+    /// * `cents: f32` - The fine shift, in cents
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The new pitch shifter
+    pub fn new(semitones: f32, cents: f32) -> Self {
+        Self {
+            semitones: semitones.clamp(-MAX_SHIFT, MAX_SHIFT),
+            cents: cents.clamp(-100.0, 100.0),
+            formant: true,
+            mix: 1.0,
+            shifting: Shifting::new(),
+        }
+    }
+}
+
+impl Effect for PitchShift {
+    fn process(&mut self, state: &State, buffer: &mut Buffer) {
+        let channels = buffer.channels().min(2);
+        if channels == 0 {
+            return;
+        }
+        self.shifting.resize(state.sample_rate);
+
+        let shift = (self.semitones + self.cents / 100.0).clamp(-MAX_SHIFT, MAX_SHIFT);
+        let ratio = 2.0f32.powf(shift / 12.0);
+        let voices = [(0, ratio), (1, ratio)];
+
+        for index in 0..buffer.len() {
+            let left = buffer.channel(0)[index];
+            let right = buffer.channel(channels - 1)[index];
+            if self.shifting.push(left, right) {
+                self.shifting.track();
+                self.shifting.send(&voices[..channels], self.formant);
+            }
+            self.shifting.receive();
+
+            for channel in 0..channels {
+                let dry = self.shifting.dry(channel);
+                let wet = self.shifting.wet(channel);
+                buffer.channel_mut(channel)[index] = dry * (1.0 - self.mix) + wet * self.mix;
+            }
+        }
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f32) {
+        match name {
+            "semitones" => self.semitones = value.clamp(-MAX_SHIFT, MAX_SHIFT),
+            "cents" => self.cents = value.clamp(-100.0, 100.0),
+            "formant" => self.formant = value >= 0.5,
+            "mix" => self.mix = value.clamp(0.0, 1.0),
+            _ => {}
+        }
+    }
+}
+
+/// ## Harmonizer
+///
+/// A harmonizer for a vocal or other monophonic strip: it tracks the pitch of the input and adds up to four voices,
+/// each a number of steps away along a scale, so the harmonies follow the melody in key. The voices are shifted with
+/// TD-PSOLA (tdpsola) preserving formants. Like the pitch shifter, the output lags the input by 60 ms, and the dry signal is delayed to match.
+///
+/// ### Fields
+///
+/// * `voices: usize` - The number of voices sounding (0-4)
+/// * `intervals: [i32; MAX_VOICES]` - The interval of each voice, in scale steps
+/// * `gains: [f32; MAX_VOICES]` - The gain of each voice, in dB
+/// * `pans: [f32; MAX_VOICES]` - The pan of each voice (-1.0-1.0)
+/// * `shifts: [i32; MAX_VOICES]` - The current shift of each voice, in semitones
+/// * `note: i32` - The last note tracked in the input
+/// * `key: i32` - The key, as a pitch class (0 for C to 11 for B)
+/// * `scale: usize` - The index of the scale in SCALES
+/// * `dry: f32` - The gain of the input, in dB
+/// * `formant: bool` - Whether the voices preserve formants
+/// * `shifting: Shifting` - The segments of the input and the worker shifting them, with the voices made from its mono mix
+///
+/// ### Parameters
+///
+/// * "key", "dry" - As the fields
+/// * "voices" - The number of voices sounding; the sounding voices are spread across the stereo field again, as `new` does
+/// * "scale" - 0 chromatic, 1 major, 2 natural minor, 3 harmonic minor, 4 dorian, 5 major pentatonic, 6 minor pentatonic
+/// * "formant" - 1 to preserve formants, 0 to move them with the pitch
+/// * "<voice>.interval" - The interval of a voice, in scale steps (semitones in the chromatic scale)
+/// * "<voice>.gain" - The gain of a voice, in dB
+/// * "<voice>.pan" - The pan of a voice
+pub struct Harmonizer {
+    voices: usize,
+    intervals: [i32; MAX_VOICES],
+    gains: [f32; MAX_VOICES],
+    pans: [f32; MAX_VOICES],
+    shifts: [i32; MAX_VOICES],
+    note: i32,
+    key: i32,
+    scale: usize,
+    dry: f32,
+    formant: bool,
+    shifting: Shifting,
+}
+
+impl Harmonizer {
+    /// ## `new(intervals: &[i32]) -> Self`
+    ///
+    /// Creates a harmonizer in C major with a voice at each interval (up to four), spread across the stereo field at -6 dB.
+    /// Voices that are not sounding yet take the first of DEFAULT_INTERVALS not already used, for when "voices" turns them on.
+    ///
+    /// ### Arguments
+    ///
+    /// * `intervals: &[i32]` - The interval of each voice, in scale steps; e.g. [2, 4] adds a third and a fifth above
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The new harmonizer
+    pub fn new(intervals: &[i32]) -> Self {
+        let voices = intervals.len().min(MAX_VOICES);
+        let mut harmonizer = Self {
+            voices,
+            intervals: [0; MAX_VOICES],
+            gains: [-6.0; MAX_VOICES],
+            pans: [0.0; MAX_VOICES],
+            shifts: [0; MAX_VOICES],
+            note: 60,
+            key: 0,
+            scale: 1,
+            dry: 0.0,
+            formant: true,
+            shifting: Shifting::new(),
+        };
+
+        let mut defaults = DEFAULT_INTERVALS
+            .iter()
+            .filter(|interval| !intervals[..voices].contains(interval));
+        for voice in 0..MAX_VOICES {
+            harmonizer.intervals[voice] = match intervals[..voices].get(voice) {
+                Some(interval) => *interval,
+                None => defaults.next().copied().unwrap_or(0),
+            };
+        }
+        harmonizer.spread();
+        harmonizer.retune();
+        harmonizer
+    }
+
+    /// ## `spread(&mut self)`
+    ///
+    /// Private function which pans the sounding voices evenly from -0.6 to 0.6, or to the center if there is one.
+    fn spread(&mut self) {
+        for voice in 0..self.voices {
+            self.pans[voice] = match self.voices {
+                1 => 0.0,
+                voices => voice as f32 / (voices - 1) as f32 * 1.2 - 0.6,
+            };
+        }
+    }
+
+    /// ## `retune(&mut self)`
+    ///
+    /// Private function which works out the shift of every voice for the last note tracked.
+    fn retune(&mut self) {
+        let scale = SCALES[self.scale];
+        for voice in 0..MAX_VOICES {
+            self.shifts[voice] = harmonize(self.note, self.key, scale, self.intervals[voice])
+                .clamp(-MAX_SHIFT as i32, MAX_SHIFT as i32);
+        }
+    }
+}
+
+impl Effect for Harmonizer {
+    fn process(&mut self, state: &State, buffer: &mut Buffer) {
+        self.shifting.resize(state.sample_rate);
+
+        let dry = audio::db_to_gain(self.dry);
+        let mut gains = [(0.0f32, 0.0f32); MAX_VOICES];
+        for voice in 0..self.voices {
+            let gain = audio::db_to_gain(self.gains[voice]);
+            let (left, right) = PanLaw::Balance.gains(self.pans[voice]);
+            gains[voice] = (left * gain, right * gain);
+        }
+
+        for index in 0..buffer.len() {
+            let input = buffer.frame(index);
+            if self.shifting.push(input.left(), input.right()) {
+                match self.shifting.track() {
+                    Some(wavelength) => {
+                        let frequency = state.sample_rate as f32 / wavelength;
+                        let note = 69.0 + 12.0 * (frequency / 440.0).log2();
+                        self.note = note.round() as i32;
+                        self.retune();
+                    }
+                    None => {}
+                }
+                let voices = self
+                    .shifts
+                    .map(|shift| (MONO, 2.0f32.powf(shift as f32 / 12.0)));
+                self.shifting.send(&voices[..self.voices], self.formant);
+            }
+            self.shifting.receive();
+
+            let mut left = self.shifting.dry(0) * dry;
+            let mut right = self.shifting.dry(1) * dry;
+            for (voice, (left_gain, right_gain)) in gains.iter().enumerate().take(self.voices) {
+                let sample = self.shifting.wet(voice);
+                left += sample * left_gain;
+                right += sample * right_gain;
+            }
+            buffer.set_frame(index, Sample::Stereo(left, right));
+        }
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f32) {
+        match name {
+            "voices" => {
+                let voices = (value.max(0.0) as usize).min(MAX_VOICES);
+                // voices that were silent have stale segments from when they last sounded
+                for voice in self.voices..voices {
+                    self.shifting.silence(voice);
+                }
+                self.voices = voices;
+                self.spread();
+            }
+            "key" => self.key = (value.round() as i32).rem_euclid(12),
+            "scale" => self.scale = (value.max(0.0) as usize).min(SCALES.len() - 1),
+            "dry" => self.dry = value.clamp(-60.0, 12.0),
+            "formant" => self.formant = value >= 0.5,
+            name => match name.split_once('.') {
+                Some((voice, setting)) => match voice.parse::<usize>() {
+                    Ok(voice) if voice < MAX_VOICES => match setting {
+                        "interval" => self.intervals[voice] = value.round() as i32,
+                        "gain" => self.gains[voice] = value.clamp(-60.0, 12.0),
+                        "pan" => self.pans[voice] = value.clamp(-1.0, 1.0),
+                        _ => {}
+                    },
+                    _ => {}
+                },
+                None => {}
+            },
+        }
+        self.retune();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Applies a Hann window to a signal, for `power`
+    fn windowed(samples: &[f32]) -> Vec<f64> {
+        let length = samples.len() as f32;
+        samples
+            .iter()
+            .enumerate()
+            .map(|(index, sample)| {
+                let window = 0.5 - 0.5 * (std::f32::consts::TAU * index as f32 / length).cos();
+                (sample * window) as f64
+            })
+            .collect()
+    }
+
+    /// The power of a windowed signal at a frequency, with Goertzel's algorithm (one bin of a DFT)
+    fn power(windowed: &[f64], frequency: f32, sample_rate: f32) -> f64 {
+        let coefficient =
+            2.0 * (std::f64::consts::TAU * frequency as f64 / sample_rate as f64).cos();
+        let (mut previous, mut before) = (0.0f64, 0.0f64);
+        for sample in windowed.iter() {
+            let current = sample + coefficient * previous - before;
+            before = previous;
+            previous = current;
+        }
+        previous * previous + before * before - coefficient * previous * before
+    }
+
+    /// The frequency with the most energy in a signal from 50 Hz to 1 kHz, to within 0.1 Hz
+    fn peak(samples: &[f32], sample_rate: f32) -> f32 {
+        let windowed = windowed(samples);
+        let loudest = |frequencies: &mut dyn Iterator<Item = f32>| {
+            frequencies
+                .max_by(|a, b| {
+                    power(&windowed, *a, sample_rate).total_cmp(&power(&windowed, *b, sample_rate))
+                })
+                .unwrap()
+        };
+        // steps narrower than the window's main lobe find it, then it is searched finely
+        let coarse = loudest(&mut (10..=200).map(|step| step as f32 * 5.0));
+        loudest(&mut (-50..=50).map(|step| coarse + step as f32 * 0.1))
+    }
+
+    /// The power-weighted mean frequency of a signal from 50 Hz to 4 kHz, where its formants are
+    fn centroid(samples: &[f32], sample_rate: f32) -> f32 {
+        let windowed = windowed(samples);
+        let (weighted, total) = (5..=400)
+            .map(|step| step as f32 * 10.0)
+            .map(|frequency| (frequency, power(&windowed, frequency, sample_rate)))
+            .fold((0.0, 0.0), |(weighted, total), (frequency, power)| {
+                (weighted + frequency as f64 * power, total + power)
+            });
+        (weighted / total) as f32
+    }
+
+    /// Runs a second of a signal through an effect in blocks of 256 frames, waiting for its worker after each,
+    /// and returns the left and right output after the first half second, while the latency fills and the tracker locks on
+    fn run(
+        process: &mut dyn FnMut(&State, &mut Buffer),
+        signal: &dyn Fn(f64) -> f32,
+        sample_rate: u32,
+    ) -> [Vec<f32>; 2] {
+        let mut clock = 0;
+        let mut output = [Vec::new(), Vec::new()];
+        for _ in 0..sample_rate as usize / 256 {
+            let state = State::advance(sample_rate, &mut clock, 256);
+            let mut buffer = Buffer::new(2, 256);
+            for channel in 0..2 {
+                for (index, sample) in buffer.channel_mut(channel).iter_mut().enumerate() {
+                    *sample =
+                        signal((state.sample_clock + index as u64) as f64 / sample_rate as f64);
+                }
+            }
+            process(&state, &mut buffer);
+            for (channel, output) in output.iter_mut().enumerate() {
+                output.extend_from_slice(buffer.channel(channel));
+            }
+        }
+        output.map(|channel| channel[sample_rate as usize / 2..].to_vec())
+    }
+
+    /// A sine
+    fn sine(frequency: f32) -> impl Fn(f64) -> f32 {
+        move |time| 0.5 * (std::f64::consts::TAU * frequency as f64 * time).sin() as f32
+    }
+
+    /// A sawtooth-like tone with ten harmonics, more like a voice than a sine
+    fn tone(frequency: f32) -> impl Fn(f64) -> f32 {
+        move |time| {
+            (1..=10)
+                .map(|harmonic| {
+                    let phase = std::f64::consts::TAU * (frequency * harmonic as f32) as f64 * time;
+                    0.3 * phase.sin() / harmonic as f64
+                })
+                .sum::<f64>() as f32
+        }
+    }
+
+    /// Shifts a sine without preserving formants, and returns where the output peaks
+    fn shift(semitones: f32, frequency: f32, sample_rate: u32) -> f32 {
+        let mut shifter = PitchShift::new(semitones, 0.0);
+        shifter.set_parameter("formant", 0.0);
+        let [left, _] = run(
+            &mut |state, buffer| {
+                shifter.process(state, buffer);
+                shifter.shifting.settle();
+            },
+            &sine(frequency),
+            sample_rate,
+        );
+        peak(&left, sample_rate as f32)
+    }
+
+    #[test]
+    fn shifts_to_the_target_frequency() {
+        for sample_rate in [48000, 96000] {
+            for semitones in [-12.0, -7.0, 7.0, 12.0] {
+                let target = 220.0 * 2.0f32.powf(semitones / 12.0);
+                let found = shift(semitones, 220.0, sample_rate);
+                assert!(
+                    (found / target).log2().abs() * 1200.0 < 20.0,
+                    "shifting 220 Hz by {} semitones at {} Hz peaked at {} Hz instead of {} Hz",
+                    semitones,
+                    sample_rate,
+                    found,
+                    target
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn formant_mode_keeps_the_spectral_envelope() {
+        // a 150 Hz voice with a formant at 1.2 kHz, shifted up a fifth
+        let voice = |time: f64| {
+            (1..=20)
+                .map(|harmonic| {
+                    let frequency = 150.0 * harmonic as f64;
+                    let level = (-((frequency - 1200.0) / 300.0).powi(2)).exp();
+                    0.1 * level * (std::f64::consts::TAU * frequency * time).sin()
+                })
+                .sum::<f64>() as f32
+        };
+        let [input, _] = run(&mut |_, _| {}, &voice, 48000);
+        let shifted = |formant: bool| {
+            let mut shifter = PitchShift::new(7.0, 0.0);
+            shifter.set_parameter("formant", formant as u8 as f32);
+            let [left, _] = run(
+                &mut |state, buffer| {
+                    shifter.process(state, buffer);
+                    shifter.shifting.settle();
+                },
+                &voice,
+                48000,
+            );
+            left
+        };
+
+        let before = centroid(&input, 48000.0);
+        let kept = shifted(true);
+        let moved = shifted(false);
+        let after = centroid(&kept, 48000.0);
+        assert!(
+            (after / before).log2().abs() < 0.15,
+            "the formant moved from {} Hz to {} Hz",
+            before,
+            after
+        );
+        let after = centroid(&moved, 48000.0);
+        assert!(
+            (after / before).log2() > 0.4,
+            "without formant preservation the formant stayed at {} Hz instead of {} Hz",
+            after,
+            before * 1.5
+        );
+
+        // either way the harmonics are the new pitch's, not the old one's
+        let windowed = windowed(&kept);
+        let fundamental = 150.0 * 2.0f32.powf(7.0 / 12.0);
+        for harmonic in [7.0, 8.0] {
+            assert!(
+                power(&windowed, fundamental * (harmonic - 2.0), 48000.0)
+                    > 100.0 * power(&windowed, 150.0 * harmonic, 48000.0)
+            );
+        }
+    }
+
+    #[test]
+    fn harmonizes_along_the_scale() {
+        let major = SCALES[1];
+        // the third above E in C major is G, a minor third, not G#
+        assert_eq!(harmonize(64, 0, major, 2), 3);
+        // and above C it is E, a major third
+        assert_eq!(harmonize(60, 0, major, 2), 4);
+        // the fifth above B is F, a diminished fifth
+        assert_eq!(harmonize(71, 0, major, 4), 6);
+        // a fourth below C crosses down an octave to G
+        assert_eq!(harmonize(60, 0, major, -3), -5);
+        assert_eq!(harmonize(62, 0, major, 7), 12);
+        // in E major the third above E is G#
+        assert_eq!(harmonize(64, 4, major, 2), 4);
+        // in A minor the third above C is E
+        assert_eq!(harmonize(60, 9, SCALES[2], 2), 4);
+        // a note outside the scale moves from the scale note below it: C# in C major to E
+        assert_eq!(harmonize(61, 0, major, 2), 3);
+        // in the chromatic scale steps are semitones
+        assert_eq!(harmonize(60, 0, SCALES[0], 7), 7);
+    }
+
+    #[test]
+    fn harmonizer_adds_four_voices_in_key() {
+        // A3 in C major: a third up is C4, a fifth up E4, a fourth down E3 and an octave up A4
+        let mut harmonizer = Harmonizer::new(&[2, 4, -3, 7]);
+        harmonizer.set_parameter("dry", -60.0);
+        let [left, right] = run(
+            &mut |state, buffer| {
+                harmonizer.process(state, buffer);
+                harmonizer.shifting.settle();
+            },
+            &tone(220.0),
+            48000,
+        );
+        let (left, right) = (windowed(&left), windowed(&right));
+        let both: Vec<f64> = left.iter().zip(right.iter()).map(|(l, r)| l + r).collect();
+
+        let dry = power(&both, 220.0, 48000.0);
+        for (shift, voice) in [(3, "C4"), (7, "E4"), (-5, "E3"), (12, "A4")] {
+            let frequency = 220.0 * 2.0f32.powf(shift as f32 / 12.0);
+            assert!(
+                power(&both, frequency, 48000.0) > 100.0 * dry,
+                "{} is missing",
+                voice
+            );
+        }
+
+        // the voices are spread from left to right
+        let c4 = 220.0 * 2.0f32.powf(3.0 / 12.0);
+        assert!(power(&left, c4, 48000.0) > 2.0 * power(&right, c4, 48000.0));
+        assert!(power(&right, 440.0, 48000.0) > 2.0 * power(&left, 440.0, 48000.0));
+    }
+}
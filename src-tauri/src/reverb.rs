@@ -0,0 +1,331 @@
+//! reverb.rs
+//!
+//! Module for the algorithmic reverb effect, a feedback delay network
+
+use crate::audio::{plugin::Effect, Buffer, State, MAX_SAMPLE_RATE};
+
+/// The number of delay lines in the network. The Hadamard matrix mixing them needs a power of two.
+const LINES: usize = 8;
+
+/// The lengths of the delay lines at the largest room size, in seconds. They share no common factors, so their echoes don't pile up.
+const LENGTHS: [f32; LINES] = [
+    0.0297, 0.0371, 0.0411, 0.0437, 0.0533, 0.0593, 0.0671, 0.0739,
+];
+
+/// The lengths of the two diffusers on each input channel, in seconds
+const DIFFUSERS: [[f32; 2]; 2] = [[0.0047, 0.0036], [0.0051, 0.0031]];
+
+/// How much the diffusers feed back
+const DIFFUSION: f32 = 0.6;
+
+/// The smallest room, as a fraction of the delay line lengths
+const MIN_SIZE: f32 = 0.2;
+
+/// The longest pre-delay, in seconds
+pub const MAX_PREDELAY: f32 = 0.5;
+
+/// ## Ring
+///
+/// A delay line with a fixed capacity, read at any delay up to it.
+///
+/// ### Fields
+///
+/// * `samples: Vec<f32>` - The samples, used as a ring
+/// * `position: usize` - Where the next sample is written
+struct Ring {
+    samples: Vec<f32>,
+    position: usize,
+}
+
+impl Ring {
+    /// ## `new(seconds: f32) -> Self`
+    ///
+    /// Private function which creates a silent delay line long enough for the given time at MAX_SAMPLE_RATE.
+    fn new(seconds: f32) -> Self {
+        Self {
+            samples: vec![0.0; (seconds * MAX_SAMPLE_RATE) as usize + 2],
+            position: 0,
+        }
+    }
+
+    /// ## `read(&self, delay: usize) -> f32`
+    ///
+    /// Private function which returns the sample written `delay` frames ago (1 or more), up to the capacity.
+    fn read(&self, delay: usize) -> f32 {
+        let length = self.samples.len();
+        let delay = delay.clamp(1, length - 1);
+        self.samples[(self.position + length - delay) % length]
+    }
+
+    /// ## `write(&mut self, sample: f32)`
+    ///
+    /// Private function which writes the next sample.
+    fn write(&mut self, sample: f32) {
+        self.samples[self.position] = sample;
+        self.position = (self.position + 1) % self.samples.len();
+    }
+}
+
+/// ## `hadamard(values: &mut [f32; LINES])`
+///
+/// Mixes the delay lines with a normalized Hadamard matrix, in place. The matrix is orthogonal,
+/// so the network loses energy only through the feedback gains and damping.
+///
+/// ### Arguments
+///
+/// * `values: &mut [f32; LINES]` - The outputs of the delay lines
+fn hadamard(values: &mut [f32; LINES]) {
+    let mut width = 1;
+    while width < LINES {
+        for start in (0..LINES).step_by(width * 2) {
+            for index in start..start + width {
+                let (a, b) = (values[index], values[index + width]);
+                values[index] = a + b;
+                values[index + width] = a - b;
+            }
+        }
+        width *= 2;
+    }
+
+    let scale = 1.0 / (LINES as f32).sqrt();
+    for value in values.iter_mut() {
+        *value *= scale;
+    }
+}
+
+/// ## Reverb
+///
+/// A stereo reverb built as a feedback delay network: eight delay lines, mixed by a Hadamard matrix and fed back,
+/// each losing just enough per pass for the tail to fall 60 dB in the decay time. The left input feeds the even lines
+/// and the right input the odd ones, after a pre-delay and two allpass diffusers each, and the output is taken the same way.
+/// Works on a send bus (mix 1.0) or directly in a strip's chain.
+///
+/// ### Fields
+///
+/// * `size: f32` - The room size (0.0-1.0), which scales the delay lines
+/// * `decay: f32` - The time the tail takes to fall 60 dB, in seconds
+/// * `predelay: f32` - The delay before the reverb starts, in milliseconds
+/// * `damping: f32` - How much the high end is absorbed on every pass (0.0-1.0)
+/// * `width: f32` - The stereo width of the tail (0.0 mono, 1.0 full)
+/// * `mix: f32` - The reverb level (0.0 dry, 1.0 only reverb)
+/// * `lines: Vec<Ring>` - The delay lines
+/// * `filters: [f32; LINES]` - The damping filter of each line
+/// * `predelays: [Ring; 2]` - The pre-delay of each input channel
+/// * `diffusers: [[Ring; 2]; 2]` - The allpass diffusers of each input channel
+///
+/// ### Parameters
+///
+/// * "size", "decay", "predelay", "damping", "width", "mix" - As the fields
+pub struct Reverb {
+    size: f32,
+    decay: f32,
+    predelay: f32,
+    damping: f32,
+    width: f32,
+    mix: f32,
+    lines: Vec<Ring>,
+    filters: [f32; LINES],
+    predelays: [Ring; 2],
+    diffusers: [[Ring; 2]; 2],
+}
+
+impl Reverb {
+    /// ## `new(size: f32, decay: f32, mix: f32) -> Self`
+    ///
+    /// Creates a reverb with no pre-delay, some damping and full width. Every delay line is allocated at its longest,
+    /// so the size and pre-delay can change on the audio thread.
+    ///
+    /// ### Arguments
+    ///
+    /// * `size: f32` - The room size (0.0-1.0)
+    /// * `decay: f32` - The decay time, in seconds
+    /// * `mix: f32` - The reverb level (0.0-1.0)
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The new reverb
+    pub fn new(size: f32, decay: f32, mix: f32) -> Self {
+        Self {
+            size: size.clamp(0.0, 1.0),
+            decay: decay.clamp(0.1, 60.0),
+            predelay: 0.0,
+            damping: 0.3,
+            width: 1.0,
+            mix: mix.clamp(0.0, 1.0),
+            lines: LENGTHS.iter().map(|length| Ring::new(*length)).collect(),
+            filters: [0.0; LINES],
+            predelays: [Ring::new(MAX_PREDELAY), Ring::new(MAX_PREDELAY)],
+            diffusers: DIFFUSERS.map(|lengths| lengths.map(Ring::new)),
+        }
+    }
+}
+
+impl Effect for Reverb {
+    fn process(&mut self, state: &State, buffer: &mut Buffer) {
+        let sample_rate = state.sample_rate as f32;
+        let scale = MIN_SIZE + (1.0 - MIN_SIZE) * self.size;
+        let mut delays = [0usize; LINES];
+        let mut gains = [0.0f32; LINES];
+        for line in 0..LINES {
+            delays[line] = (LENGTHS[line] * scale * sample_rate) as usize;
+            // each pass through a line has to lose its share of 60 dB over the decay time
+            gains[line] = 10.0f32.powf(-3.0 * delays[line] as f32 / (self.decay * sample_rate));
+        }
+        let diffusers =
+            DIFFUSERS.map(|lengths| lengths.map(|length| (length * sample_rate) as usize));
+        let predelay = (self.predelay / 1000.0 * sample_rate) as usize;
+        let damping = self.damping.clamp(0.0, 0.95);
+        let mono = buffer.channels() < 2;
+
+        for index in 0..buffer.len() {
+            let dry = match mono {
+                true => {
+                    let sample = buffer.channel(0)[index];
+                    [sample, sample]
+                }
+                false => [buffer.channel(0)[index], buffer.channel(1)[index]],
+            };
+
+            // pre-delay, then diffuse each input channel through its allpasses
+            let mut inputs = [0.0f32; 2];
+            for channel in 0..2 {
+                let mut sample = match predelay {
+                    0 => dry[channel],
+                    _ => self.predelays[channel].read(predelay),
+                };
+                self.predelays[channel].write(dry[channel]);
+
+                for (diffuser, delay) in self.diffusers[channel].iter_mut().zip(diffusers[channel])
+                {
+                    let delayed = diffuser.read(delay);
+                    let written = sample + delayed * DIFFUSION;
+                    diffuser.write(written);
+                    sample = delayed - written * DIFFUSION;
+                }
+                inputs[channel] = sample;
+            }
+
+            let mut outputs = [0.0f32; LINES];
+            for line in 0..LINES {
+                let delayed = self.lines[line].read(delays[line]);
+                self.filters[line] = delayed * (1.0 - damping) + self.filters[line] * damping;
+                outputs[line] = self.filters[line] * gains[line];
+            }
+
+            let mut wet = [0.0f32; 2];
+            for line in 0..LINES {
+                wet[line % 2] += outputs[line];
+            }
+
+            hadamard(&mut outputs);
+            for line in 0..LINES {
+                self.lines[line].write(outputs[line] + inputs[line % 2]);
+            }
+
+            // narrow the tail by scaling its side signal
+            let mid = (wet[0] + wet[1]) * 0.5;
+            let side = (wet[0] - wet[1]) * 0.5 * self.width;
+            let wet = [mid + side, mid - side];
+            let level = 2.0 / LINES as f32;
+
+            match mono {
+                true => {
+                    buffer.channel_mut(0)[index] =
+                        dry[0] * (1.0 - self.mix) + mid * level * self.mix;
+                }
+                false => {
+                    for channel in 0..2 {
+                        buffer.channel_mut(channel)[index] =
+                            dry[channel] * (1.0 - self.mix) + wet[channel] * level * self.mix;
+                    }
+                }
+            }
+        }
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f32) {
+        match name {
+            "size" => self.size = value.clamp(0.0, 1.0),
+            "decay" => self.decay = value.clamp(0.1, 60.0),
+            "predelay" => self.predelay = value.clamp(0.0, MAX_PREDELAY * 1000.0),
+            "damping" => self.damping = value.clamp(0.0, 1.0),
+            "width" => self.width = value.clamp(0.0, 1.0),
+            "mix" => self.mix = value.clamp(0.0, 1.0),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oscillator::WhiteNoise;
+
+    /// ## `run(reverb: &mut Reverb, input: &[f32], frames: usize) -> Vec<f32>`
+    ///
+    /// Runs a mono input into both channels of a reverb at 48 kHz in blocks of 64 frames, followed by silence,
+    /// and returns the left channel.
+    fn run(reverb: &mut Reverb, input: &[f32], frames: usize) -> Vec<f32> {
+        let mut clock = 0;
+        let mut output = Vec::with_capacity(frames);
+        while output.len() < frames {
+            let len = (frames - output.len()).min(64);
+            let state = State::advance(48000, &mut clock, len);
+            let mut buffer = Buffer::new(2, len);
+            for channel in 0..2 {
+                for (index, sample) in buffer.channel_mut(channel).iter_mut().enumerate() {
+                    *sample = input.get(output.len() + index).copied().unwrap_or(0.0);
+                }
+            }
+            reverb.process(&state, &mut buffer);
+            output.extend_from_slice(buffer.channel(0));
+        }
+        output
+    }
+
+    /// ## `level(samples: &[f32]) -> f32`
+    ///
+    /// Returns the RMS level of some samples, in dB.
+    fn level(samples: &[f32]) -> f32 {
+        let power =
+            samples.iter().map(|sample| sample * sample).sum::<f32>() / samples.len() as f32;
+        10.0 * power.log10()
+    }
+
+    #[test]
+    fn tail_falls_60_db_in_the_decay_time() {
+        let mut reverb = Reverb::new(0.5, 1.0, 1.0);
+        reverb.set_parameter("damping", 0.0);
+        let output = run(&mut reverb, &[1.0], 72000);
+
+        // every 100 ms window is quieter than the one before it
+        let windows: Vec<f32> = output[4800..].chunks(4800).map(level).collect();
+        for pair in windows.windows(2) {
+            assert!(pair[1] < pair[0], "{:?}", windows);
+        }
+        // and a second later the tail is 60 dB down
+        let drop = level(&output[4800..9600]) - level(&output[52800..57600]);
+        assert!((drop - 60.0).abs() < 6.0, "fell {} dB", drop);
+    }
+
+    #[test]
+    fn stays_finite_at_max_size_and_damping() {
+        let mut reverb = Reverb::new(1.0, 60.0, 1.0);
+        reverb.set_parameter("damping", 1.0);
+        let mut noise = WhiteNoise::new();
+        let input: Vec<f32> = (0..96000).map(|_| noise.sample()).collect();
+        let output = run(&mut reverb, &input, 192000);
+        assert!(output
+            .iter()
+            .all(|sample| sample.is_finite() && sample.abs() < 4.0));
+        // a minute long tail is still ringing two seconds after the noise stops
+        assert!(level(&output[187200..]) > -40.0);
+
+        // the shortest decay dies out even with the lines at their longest
+        let mut reverb = Reverb::new(1.0, 0.1, 1.0);
+        reverb.set_parameter("damping", 1.0);
+        let output = run(&mut reverb, &input, 192000);
+        assert!(output.iter().all(|sample| sample.is_finite()));
+        assert!(level(&output[187200..]) < -120.0);
+    }
+}
@@ -0,0 +1,555 @@
+//! sampler.rs
+//!
+//! Module for playing audio files from memory
+
+use std::sync::Arc;
+
+use rodio::Source;
+
+use crate::audio::{self, plugin::Generator, Buffer, State};
+
+/// The most notes a sample player plays at once; the oldest note is cut when another one starts
+pub const SAMPLE_VOICES: usize = 16;
+
+/// How long a released note takes to fade out, in seconds, so gated and looping notes don't click
+pub const SAMPLE_RELEASE: f32 = 0.005;
+
+/// ## SampleData
+///
+/// An audio file decoded into memory. Shared between every player and pad using it.
+///
+/// ### Fields
+///
+/// * `channels: Vec<Vec<f32>>` - The samples of each channel (one or two)
+/// * `sample_rate: u32` - The sample rate of the file
+///
+/// ### Functions
+///
+/// * `load(path: &str) -> Result<Self, String>` - Decodes a WAV, MP3, FLAC or OGG file
+/// * `frames(&self) -> usize` - Returns the number of frames
+/// * `duration(&self) -> f32` - Returns the length in seconds
+/// * `sample_rate(&self) -> u32` - Returns the sample rate of the file
+/// * `frame(&self, position: f64) -> (f32, f32)` - Returns the interpolated frame at a fractional position
+pub struct SampleData {
+    channels: Vec<Vec<f32>>,
+    sample_rate: u32,
+}
+
+impl SampleData {
+    /// ## `load(path: &str) -> Result<Self, String>`
+    ///
+    /// Decodes a WAV, MP3, FLAC or OGG file into memory. Files with more than two channels keep their first two.
+    ///
+    /// ### Arguments
+    ///
+    /// * `path: &str` - The path of the file
+    ///
+    /// ### Returns
+    ///
+    /// * `Result<Self, String>` - The decoded file, or an error message
+    pub fn load(path: &str) -> Result<Self, String> {
+        let file = match std::fs::File::open(path) {
+            Ok(file) => file,
+            Err(e) => return Err(format!("Error opening {}: {}", path, e)),
+        };
+        let decoder = match rodio::Decoder::new(std::io::BufReader::new(file)) {
+            Ok(decoder) => decoder,
+            Err(e) => return Err(format!("Error decoding {}: {}", path, e)),
+        };
+
+        let file_channels = decoder.channels().max(1) as usize;
+        let sample_rate = decoder.sample_rate();
+        let kept = file_channels.min(2);
+
+        let mut channels: Vec<Vec<f32>> = vec![Vec::new(); kept];
+        for (index, sample) in decoder.convert_samples::<f32>().enumerate() {
+            let channel = index % file_channels;
+            if channel < kept {
+                channels[channel].push(sample);
+            }
+        }
+
+        // a cut off final frame would leave the channels with different lengths
+        let frames = channels
+            .iter()
+            .map(|channel| channel.len())
+            .min()
+            .unwrap_or(0);
+        for channel in channels.iter_mut() {
+            channel.truncate(frames);
+        }
+
+        if frames == 0 {
+            return Err(format!("{} contains no audio", path));
+        }
+
+        Ok(Self {
+            channels,
+            sample_rate,
+        })
+    }
+
+    /// ## `from_channels(channels: Vec<Vec<f32>>, sample_rate: u32) -> Self`
+    ///
+    /// Creates sample data from samples already in memory, for tests.
+    #[cfg(test)]
+    pub fn from_channels(channels: Vec<Vec<f32>>, sample_rate: u32) -> Self {
+        Self {
+            channels,
+            sample_rate,
+        }
+    }
+
+    /// ## `frames(&self) -> usize`
+    ///
+    /// Returns the number of frames.
+    ///
+    /// ### Returns
+    ///
+    /// * `usize` - The number of frames
+    pub fn frames(&self) -> usize {
+        self.channels[0].len()
+    }
+
+    /// ## `duration(&self) -> f32`
+    ///
+    /// Returns the length in seconds.
+    ///
+    /// ### Returns
+    ///
+    /// * `f32` - The length in seconds
+    pub fn duration(&self) -> f32 {
+        self.frames() as f32 / self.sample_rate.max(1) as f32
+    }
+
+    /// ## `sample_rate(&self) -> u32`
+    ///
+    /// Returns the sample rate of the file.
+    ///
+    /// ### Returns
+    ///
+    /// * `u32` - The sample rate in Hz
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// ## `frame(&self, position: f64) -> (f32, f32)`
+    ///
+    /// Returns the frame at a fractional position, interpolated between its neighbours (4-point Hermite).
+    /// Mono files play on both sides; positions outside the file are silent.
+    ///
+    /// ### Arguments
+    ///
+    /// * `position: f64` - The position in frames
+    ///
+    /// ### Returns
+    ///
+    /// * `(f32, f32)` - The left and right sample
+    pub fn frame(&self, position: f64) -> (f32, f32) {
+        let index = position.floor() as i64;
+        let fraction = (position - index as f64) as f32;
+
+        let mut sides = [0.0f32; 2];
+        for (side, value) in sides.iter_mut().enumerate() {
+            let channel = &self.channels[side.min(self.channels.len() - 1)];
+            let at = |offset: i64| match index + offset {
+                i if i < 0 || i >= channel.len() as i64 => 0.0,
+                i => channel[i as usize],
+            };
+            *value = audio::hermite(at(-1), at(0), at(1), at(2), fraction);
+        }
+
+        (sides[0], sides[1])
+    }
+}
+
+/// ## PlayMode
+///
+/// How a sample player responds to notes.
+///
+/// ### Variants
+///
+/// * `OneShot` - Plays from start to end, ignoring note off
+/// * `Loop` - Loops between start and end until note off
+/// * `Gated` - Plays from start to end, stopping early at note off
+///
+/// ### Functions
+///
+/// * `from_name(name: &str) -> Option<Self>` - Gets a play mode by name ("oneshot", "loop" or "gated")
+/// * `name(&self) -> &'static str` - Returns the name of the play mode
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlayMode {
+    OneShot,
+    Loop,
+    Gated,
+}
+
+impl PlayMode {
+    /// ## `from_name(name: &str) -> Option<Self>`
+    ///
+    /// Gets a play mode by name. Not case-sensitive.
+    ///
+    /// ### Arguments
+    ///
+    /// * `name: &str` - "oneshot", "loop" or "gated"
+    ///
+    /// ### Returns
+    ///
+    /// * `Option<Self>` - The play mode, or None if the name is unknown
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "oneshot" | "one-shot" | "shot" => Some(PlayMode::OneShot),
+            "loop" => Some(PlayMode::Loop),
+            "gated" | "gate" => Some(PlayMode::Gated),
+            _ => None,
+        }
+    }
+
+    /// ## `name(&self) -> &'static str`
+    ///
+    /// Returns the name of the play mode.
+    ///
+    /// ### Returns
+    ///
+    /// * `&'static str` - The name
+    pub fn name(&self) -> &'static str {
+        match self {
+            PlayMode::OneShot => "oneshot",
+            PlayMode::Loop => "loop",
+            PlayMode::Gated => "gated",
+        }
+    }
+}
+
+/// ## SampleVoice
+///
+/// One playing note of a sample.
+///
+/// ### Fields
+///
+/// * `note: u8` - The MIDI note that started it
+/// * `position: f64` - The position in the file, in frames
+/// * `pitch: f64` - The playback speed relative to the original pitch
+/// * `gain: (f32, f32)` - The left and right gain, from velocity (and pan, on drum pads)
+/// * `fade: f32` - The fade out gain, 1.0 until the note is released
+/// * `released: bool` - Whether the note is fading out
+#[derive(Clone, Copy, Debug)]
+pub struct SampleVoice {
+    pub note: u8,
+    pub position: f64,
+    pub pitch: f64,
+    pub gain: (f32, f32),
+    pub fade: f32,
+    pub released: bool,
+}
+
+impl SampleVoice {
+    /// ## `play(&mut self, sample: &SampleData, region: (f64, f64), looping: bool, state: &State, buffer: &mut Buffer) -> bool`
+    ///
+    /// Mixes the voice into a stereo block, resampling from the file's sample rate to the engine's.
+    ///
+    /// ### Arguments
+    ///
+    /// * `sample: &SampleData` - The sample being played
+    /// * `region: (f64, f64)` - The start and end of the region played, in frames
+    /// * `looping: bool` - Whether to loop the region until released
+    /// * `state: &State` - The current state of the audio engine
+    /// * `buffer: &mut Buffer` - The stereo block to mix into
+    ///
+    /// ### Returns
+    ///
+    /// * `bool` - Whether the voice is still playing
+    pub fn play(
+        &mut self,
+        sample: &SampleData,
+        region: (f64, f64),
+        looping: bool,
+        state: &State,
+        buffer: &mut Buffer,
+    ) -> bool {
+        let (start, end) = region;
+        let step = self.pitch * sample.sample_rate as f64 / state.sample_rate.max(1) as f64;
+        let fade_step = 1.0 / (SAMPLE_RELEASE * state.sample_rate as f32).max(1.0);
+
+        let (left, right) = buffer.stereo_mut();
+        for (left, right) in left.iter_mut().zip(right.iter_mut()) {
+            if self.position >= end {
+                match looping && end > start {
+                    true => self.position = start + (self.position - end) % (end - start),
+                    false => return false,
+                }
+            }
+
+            if self.released {
+                self.fade -= fade_step;
+                if self.fade <= 0.0 {
+                    return false;
+                }
+            }
+
+            let (sample_left, sample_right) = sample.frame(self.position);
+            *left += sample_left * self.gain.0 * self.fade;
+            *right += sample_right * self.gain.1 * self.fade;
+            self.position += step;
+        }
+
+        true
+    }
+}
+
+/// ## SamplePlayer
+///
+/// A generator that plays an audio file from memory, started by notes from MIDI, HID keys or the console.
+/// The file is resampled to the engine's sample rate, and repitched relative to a root note if one is set.
+///
+/// ### Fields
+///
+/// * `sample: Arc<SampleData>` - The decoded file
+/// * `mode: PlayMode` - How the player responds to notes
+/// * `start: f32` - Where playback starts, in seconds
+/// * `end: f32` - Where playback ends, in seconds; 0.0 plays to the end of the file
+/// * `root: Option<u8>` - The note that plays the file at its original pitch; None plays every note at the original pitch
+/// * `gain: f32` - The gain in dB
+/// * `voices: Vec<SampleVoice>` - The playing notes, up to SAMPLE_VOICES
+///
+/// ### Parameters
+///
+/// * "start", "end" - Start and end, in seconds
+/// * "mode" - 0 for one-shot, 1 for loop, 2 for gated
+/// * "root" - The root note, or a negative number to turn pitch tracking off
+/// * "gain" - The gain in dB
+pub struct SamplePlayer {
+    sample: Arc<SampleData>,
+    mode: PlayMode,
+    start: f32,
+    end: f32,
+    root: Option<u8>,
+    gain: f32,
+    voices: Vec<SampleVoice>,
+}
+
+impl SamplePlayer {
+    /// ## `new(sample: Arc<SampleData>, mode: PlayMode) -> Self`
+    ///
+    /// Creates a player for the whole file, at its original pitch.
+    ///
+    /// ### Arguments
+    ///
+    /// * `sample: Arc<SampleData>` - The decoded file
+    /// * `mode: PlayMode` - How the player responds to notes
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The new player
+    pub fn new(sample: Arc<SampleData>, mode: PlayMode) -> Self {
+        Self {
+            sample,
+            mode,
+            start: 0.0,
+            end: 0.0,
+            root: None,
+            gain: 0.0,
+            voices: Vec::with_capacity(SAMPLE_VOICES),
+        }
+    }
+
+    /// ## `set_root(&mut self, root: Option<u8>)`
+    ///
+    /// Sets the note that plays the file at its original pitch.
+    ///
+    /// ### Arguments
+    ///
+    /// * `root: Option<u8>` - The root note, or None to play every note at the original pitch
+    pub fn set_root(&mut self, root: Option<u8>) {
+        self.root = root;
+    }
+
+    /// ## `region(&self) -> (f64, f64)`
+    ///
+    /// Private function which returns the region played, in frames of the file.
+    ///
+    /// ### Returns
+    ///
+    /// * `(f64, f64)` - The start and end
+    fn region(&self) -> (f64, f64) {
+        let rate = self.sample.sample_rate as f64;
+        let frames = self.sample.frames() as f64;
+        let start = (self.start as f64 * rate).min(frames);
+        let end = match self.end > 0.0 {
+            true => (self.end as f64 * rate).clamp(start, frames),
+            false => frames,
+        };
+        (start, end)
+    }
+}
+
+impl Generator for SamplePlayer {
+    fn generate(&mut self, state: &State, buffer: &mut Buffer) {
+        buffer.clear();
+
+        let region = self.region();
+        let looping = self.mode == PlayMode::Loop;
+        let sample = &self.sample;
+        self.voices
+            .retain_mut(|voice| voice.play(sample, region, looping, state, buffer));
+    }
+
+    fn note_on(&mut self, note: u8, velocity: f32) {
+        if self.voices.len() >= SAMPLE_VOICES {
+            self.voices.remove(0);
+        }
+
+        let pitch = match self.root {
+            Some(root) => 2.0f64.powf((note as f64 - root as f64) / 12.0),
+            None => 1.0,
+        };
+        let gain = velocity * audio::db_to_gain(self.gain);
+        self.voices.push(SampleVoice {
+            note,
+            position: self.region().0,
+            pitch,
+            gain: (gain, gain),
+            fade: 1.0,
+            released: false,
+        });
+    }
+
+    fn note_off(&mut self, note: u8) {
+        match self.mode {
+            PlayMode::OneShot => {}
+            PlayMode::Loop | PlayMode::Gated => {
+                for voice in self.voices.iter_mut() {
+                    if voice.note == note {
+                        voice.released = true;
+                    }
+                }
+            }
+        }
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f32) {
+        match name {
+            "start" => self.start = value.max(0.0),
+            "end" => self.end = value.max(0.0),
+            "mode" => {
+                self.mode = match value as i32 {
+                    1 => PlayMode::Loop,
+                    2 => PlayMode::Gated,
+                    _ => PlayMode::OneShot,
+                }
+            }
+            "root" => {
+                self.root = match value < 0.0 {
+                    true => None,
+                    false => Some(value.min(127.0) as u8),
+                }
+            }
+            "gain" => self.gain = value,
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// ## `ramp(frames: usize, sample_rate: u32) -> Arc<SampleData>`
+    ///
+    /// Returns a mono sample whose every frame holds its own index.
+    fn ramp(frames: usize, sample_rate: u32) -> Arc<SampleData> {
+        Arc::new(SampleData::from_channels(
+            vec![(0..frames).map(|frame| frame as f32).collect()],
+            sample_rate,
+        ))
+    }
+
+    /// ## `run(player: &mut SamplePlayer, frames: usize) -> Vec<f32>`
+    ///
+    /// Plays a player at 48 kHz in blocks of 64 frames and returns the left channel.
+    fn run(player: &mut SamplePlayer, frames: usize) -> Vec<f32> {
+        let mut clock = 0;
+        let mut output = Vec::with_capacity(frames);
+        while output.len() < frames {
+            let len = (frames - output.len()).min(64);
+            let state = State::advance(48000, &mut clock, len);
+            let mut buffer = Buffer::new(2, len);
+            player.generate(&state, &mut buffer);
+            output.extend_from_slice(buffer.channel(0));
+        }
+        output
+    }
+
+    #[test]
+    fn loops_wrap_around_the_region() {
+        let mut player = SamplePlayer::new(ramp(100, 48000), PlayMode::Loop);
+        player.note_on(60, 1.0);
+        let output = run(&mut player, 250);
+        for (frame, sample) in output.iter().enumerate() {
+            assert_eq!(*sample, (frame % 100) as f32, "frame {}", frame);
+        }
+
+        // a one-shot stops at the end
+        let mut player = SamplePlayer::new(ramp(100, 48000), PlayMode::OneShot);
+        player.note_on(60, 1.0);
+        let output = run(&mut player, 250);
+        assert!(output[100..].iter().all(|sample| *sample == 0.0));
+        assert!(player.voices.is_empty());
+    }
+
+    #[test]
+    fn gated_notes_fade_out_when_released() {
+        let constant = Arc::new(SampleData::from_channels(vec![vec![1.0; 48000]], 48000));
+        let mut player = SamplePlayer::new(constant.clone(), PlayMode::Gated);
+        player.note_on(60, 1.0);
+        assert!(run(&mut player, 128).iter().all(|sample| *sample == 1.0));
+
+        // a note off for another note does nothing
+        player.note_off(61);
+        assert!(run(&mut player, 64).iter().all(|sample| *sample == 1.0));
+
+        // the fade takes SAMPLE_RELEASE, falling all the way
+        player.note_off(60);
+        let output = run(&mut player, 512);
+        let fade = (SAMPLE_RELEASE * 48000.0) as usize;
+        assert!(output
+            .windows(2)
+            .all(|pair| pair[1] < pair[0] || pair[1] == 0.0));
+        assert!(output[fade - 1] > 0.0);
+        assert!(output[fade..].iter().all(|sample| *sample == 0.0));
+        assert!(player.voices.is_empty());
+
+        // a one-shot ignores the note off
+        let mut player = SamplePlayer::new(constant, PlayMode::OneShot);
+        player.note_on(60, 1.0);
+        player.note_off(60);
+        assert!(run(&mut player, 512).iter().all(|sample| *sample == 1.0));
+    }
+
+    #[test]
+    fn resamples_files_at_other_rates() {
+        // a 24 kHz file moves half a frame for every frame at 48 kHz
+        let mut player = SamplePlayer::new(ramp(1000, 24000), PlayMode::OneShot);
+        player.note_on(60, 1.0);
+        let output = run(&mut player, 500);
+        // (the first frames lean on the silence before the file)
+        for (frame, sample) in output.iter().enumerate().skip(2) {
+            assert!(
+                (sample - frame as f32 / 2.0).abs() < 1e-3,
+                "frame {}",
+                frame
+            );
+        }
+
+        let mut player = SamplePlayer::new(ramp(48000, 44100), PlayMode::OneShot);
+        player.note_on(60, 1.0);
+        run(&mut player, 4800);
+        assert!((player.voices[0].position - 4410.0).abs() < 1e-6);
+
+        // an octave over the root plays twice as fast
+        player.set_root(Some(48));
+        player.note_on(60, 1.0);
+        run(&mut player, 4800);
+        assert!((player.voices[1].position - 8820.0).abs() < 1e-6);
+    }
+}
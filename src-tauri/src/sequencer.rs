@@ -0,0 +1,627 @@
+//! sequencer.rs
+//!
+//! Module for the step sequencer, which plays patterns of notes on strips from inside the audio thread
+
+use crate::audio::State;
+use crate::transport::Transport;
+
+/// The most notes the sequencer starts or releases in one block; any more are dropped
+pub const SEQUENCER_EVENTS: usize = 1024;
+
+/// The most times a step can repeat within itself
+pub const MAX_RATCHET: u8 = 8;
+
+/// The most steps a track can have
+pub const MAX_STEPS: usize = 256;
+
+/// ## Step
+///
+/// One step of a track.
+///
+/// ### Fields
+///
+/// * `velocity: f32` - The velocity (0.0-1.0); 0.0 leaves the step empty
+/// * `probability: f32` - The chance of the step playing (0.0-1.0)
+/// * `ratchet: u8` - How many times the step repeats within its length (1-8)
+/// * `offset: f32` - How early or late the step plays, as a fraction of a step (-0.5-0.5)
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Step {
+    pub velocity: f32,
+    #[serde(default = "Step::certain")]
+    pub probability: f32,
+    #[serde(default = "Step::once")]
+    pub ratchet: u8,
+    #[serde(default)]
+    pub offset: f32,
+}
+
+impl Step {
+    /// ## `off() -> Self`
+    ///
+    /// Returns an empty step.
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The step
+    pub fn off() -> Self {
+        Self::hit(0.0)
+    }
+
+    /// ## `hit(velocity: f32) -> Self`
+    ///
+    /// Returns a step that always plays once, on time.
+    ///
+    /// ### Arguments
+    ///
+    /// * `velocity: f32` - The velocity (0.0-1.0)
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The step
+    pub fn hit(velocity: f32) -> Self {
+        Self {
+            velocity: velocity.clamp(0.0, 1.0),
+            probability: Step::certain(),
+            ratchet: Step::once(),
+            offset: 0.0,
+        }
+    }
+
+    fn certain() -> f32 {
+        1.0
+    }
+
+    fn once() -> u8 {
+        1
+    }
+
+    /// ## `symbol(&self) -> char`
+    ///
+    /// Returns the character showing the step in a track's grid ("." empty, "o" soft, "x" loud, "r" ratcheted, "?" maybe).
+    ///
+    /// ### Returns
+    ///
+    /// * `char` - The character
+    pub fn symbol(&self) -> char {
+        match self {
+            step if step.velocity <= 0.0 => '.',
+            step if step.ratchet > 1 => 'r',
+            step if step.probability < 1.0 => '?',
+            step if step.velocity < 0.75 => 'o',
+            _ => 'x',
+        }
+    }
+}
+
+/// ## Track
+///
+/// A row of steps playing one note on one strip. Tracks can be of different lengths, and loop on their own.
+///
+/// ### Fields
+///
+/// * `name: String` - The name of the track
+/// * `strip: usize` - The strip whose generator plays the track
+/// * `note: u8` - The MIDI note played (a drum pad, or a pitch for a synth)
+/// * `gate: f32` - How long notes are held, as a fraction of a step (or of a ratchet)
+/// * `mute: bool` - Whether the track is muted
+/// * `steps: Vec<Step>` - The steps
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Track {
+    pub name: String,
+    pub strip: usize,
+    pub note: u8,
+    #[serde(default = "Track::half")]
+    pub gate: f32,
+    #[serde(default)]
+    pub mute: bool,
+    pub steps: Vec<Step>,
+}
+
+impl Track {
+    /// ## `new(name: &str, strip: usize, note: u8, length: usize) -> Self`
+    ///
+    /// Creates a track of empty steps.
+    ///
+    /// ### Arguments
+    ///
+    /// * `name: &str` - The name of the track
+    /// * `strip: usize` - The strip whose generator plays the track
+    /// * `note: u8` - The MIDI note played
+    /// * `length: usize` - The number of steps
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The new track
+    pub fn new(name: &str, strip: usize, note: u8, length: usize) -> Self {
+        Self {
+            name: name.to_owned(),
+            strip,
+            note: note.min(127),
+            gate: Track::half(),
+            mute: false,
+            steps: vec![Step::off(); length],
+        }
+    }
+
+    fn half() -> f32 {
+        0.5
+    }
+
+    /// ## `set_grid(&mut self, grid: &str) -> Result<(), String>`
+    ///
+    /// Programs the steps from a grid, one character per step: "x" loud, "o" soft, "." or "-" empty.
+    /// The track takes the length of the grid, which can be at most MAX_STEPS steps.
+    ///
+    /// ### Arguments
+    ///
+    /// * `grid: &str` - The grid, e.g. "x...o...x...o..."
+    ///
+    /// ### Returns
+    ///
+    /// * `Result<(), String>` - An error message, or nothing if successful
+    pub fn set_grid(&mut self, grid: &str) -> Result<(), String> {
+        let mut steps = Vec::new();
+        for symbol in grid.chars() {
+            match symbol {
+                'x' | 'X' => steps.push(Step::hit(1.0)),
+                'o' | 'O' => steps.push(Step::hit(0.5)),
+                '.' | '-' | '_' => steps.push(Step::off()),
+                '|' | ' ' => {}
+                _ => return Err(format!("Invalid step {}: expected x, o or .", symbol)),
+            }
+        }
+
+        match steps.len() {
+            0 => Err("A track needs at least one step".to_owned()),
+            length if length > MAX_STEPS => Err(format!(
+                "A track can have at most {} steps, not {}",
+                MAX_STEPS, length
+            )),
+            _ => {
+                self.steps = steps;
+                Ok(())
+            }
+        }
+    }
+
+    /// ## `grid(&self, steps_per_bar: usize) -> String`
+    ///
+    /// Returns the steps as a grid, with a bar line between bars.
+    ///
+    /// ### Arguments
+    ///
+    /// * `steps_per_bar: usize` - The number of steps in a bar
+    ///
+    /// ### Returns
+    ///
+    /// * `String` - The grid
+    pub fn grid(&self, steps_per_bar: usize) -> String {
+        let mut grid = String::new();
+        for (index, step) in self.steps.iter().enumerate() {
+            if index > 0 && steps_per_bar > 0 && index % steps_per_bar == 0 {
+                grid.push('|');
+            }
+            grid.push(step.symbol());
+        }
+        grid
+    }
+}
+
+/// ## Pattern
+///
+/// The tracks played by the sequencer, saved to and loaded from JSON. The tempo and time signature come from the transport.
+///
+/// ### Fields
+///
+/// * `steps_per_bar: usize` - The number of steps in a bar (16 for sixteenth notes in 4/4)
+/// * `tracks: Vec<Track>` - The tracks
+///
+/// ### Functions
+///
+/// * `new(steps_per_bar: usize) -> Self` - Creates an empty pattern
+/// * `step_length(&self, transport: &Transport) -> f64` - Returns the length of a step in beats
+/// * `track(&self, name: &str) -> Option<usize>` - Finds a track by name or index
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Pattern {
+    pub steps_per_bar: usize,
+    pub tracks: Vec<Track>,
+}
+
+impl Default for Pattern {
+    fn default() -> Self {
+        Self::new(16)
+    }
+}
+
+impl Pattern {
+    /// ## `new(steps_per_bar: usize) -> Self`
+    ///
+    /// Creates a pattern without tracks.
+    ///
+    /// ### Arguments
+    ///
+    /// * `steps_per_bar: usize` - The number of steps in a bar
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The new pattern
+    pub fn new(steps_per_bar: usize) -> Self {
+        Self {
+            steps_per_bar: steps_per_bar.max(1),
+            tracks: Vec::new(),
+        }
+    }
+
+    /// ## `step_length(&self, transport: &Transport) -> f64`
+    ///
+    /// Returns the length of a step in beats, from the transport's time signature.
+    ///
+    /// ### Arguments
+    ///
+    /// * `transport: &Transport` - The transport
+    ///
+    /// ### Returns
+    ///
+    /// * `f64` - The length of a step
+    pub fn step_length(&self, transport: &Transport) -> f64 {
+        transport.beats_per_bar() / self.steps_per_bar.max(1) as f64
+    }
+
+    /// ## `track(&self, name: &str) -> Option<usize>`
+    ///
+    /// Finds a track by name or index.
+    ///
+    /// ### Arguments
+    ///
+    /// * `name: &str` - The name or index of the track
+    ///
+    /// ### Returns
+    ///
+    /// * `Option<usize>` - The index of the track
+    pub fn track(&self, name: &str) -> Option<usize> {
+        match name.parse::<usize>() {
+            Ok(index) if index < self.tracks.len() => Some(index),
+            _ => self
+                .tracks
+                .iter()
+                .position(|track| track.name.eq_ignore_ascii_case(name)),
+        }
+    }
+
+    /// ## `load(path: &str) -> Result<Self, String>`
+    ///
+    /// Loads a pattern from a JSON file.
+    ///
+    /// ### Arguments
+    ///
+    /// * `path: &str` - The path of the file
+    ///
+    /// ### Returns
+    ///
+    /// * `Result<Self, String>` - The pattern, or an error message
+    pub fn load(path: &str) -> Result<Self, String> {
+        let file =
+            std::fs::File::open(path).map_err(|e| format!("Error opening {}: {}", path, e))?;
+        serde_json::from_reader(std::io::BufReader::new(file))
+            .map_err(|e| format!("Error reading {}: {}", path, e))
+    }
+
+    /// ## `save(&self, path: &str) -> Result<(), String>`
+    ///
+    /// Saves the pattern to a JSON file.
+    ///
+    /// ### Arguments
+    ///
+    /// * `path: &str` - The path of the file
+    ///
+    /// ### Returns
+    ///
+    /// * `Result<(), String>` - An error message, or nothing if successful
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let file =
+            std::fs::File::create(path).map_err(|e| format!("Error creating {}: {}", path, e))?;
+        serde_json::to_writer_pretty(file, self)
+            .map_err(|e| format!("Error writing {}: {}", path, e))
+    }
+}
+
+/// ## Event
+///
+/// A note the sequencer starts or releases within a block.
+///
+/// ### Fields
+///
+/// * `offset: usize` - The frame within the block
+/// * `strip: usize` - The strip whose generator plays the note
+/// * `note: u8` - The MIDI note
+/// * `velocity: f32` - The velocity (0.0-1.0); 0.0 releases the note
+#[derive(Clone, Copy, Debug)]
+pub struct Event {
+    pub offset: usize,
+    pub strip: usize,
+    pub note: u8,
+    pub velocity: f32,
+}
+
+/// ## Sequencer
+///
+/// Plays a pattern inside the audio thread while the transport is rolling. Every step's time is worked out
+/// from the transport's position, so notes land on the exact frame they are due whatever the block size,
+/// and follow tempo changes and locates.
+///
+/// ### Fields
+///
+/// * `pattern: Box<Pattern>` - The pattern being played
+/// * `playing: bool` - Whether the transport was rolling in the previous block
+/// * `next: f64` - Where the transport should be in this block if it did not jump, in beats
+/// * `seed: u64` - Varies which probable steps play from one play to the next
+/// * `events: Vec<Event>` - The events of the current block, up to SEQUENCER_EVENTS
+/// * `releases: Vec<Event>` - The notes of a swapped out pattern, released at the start of the next block; up to SEQUENCER_EVENTS
+///
+/// ### Functions
+///
+/// * `new() -> Self` - Creates a sequencer with an empty pattern
+/// * `set_pattern(&mut self, pattern: Box<Pattern>) -> Box<Pattern>` - Swaps the pattern, keeping the position and releasing the old pattern's notes
+/// * `events(&mut self, state: &State) -> &[Event]` - Works out the events of a block, sorted by frame
+pub struct Sequencer {
+    pattern: Box<Pattern>,
+    playing: bool,
+    next: f64,
+    seed: u64,
+    events: Vec<Event>,
+    releases: Vec<Event>,
+}
+
+impl Sequencer {
+    /// ## `new() -> Self`
+    ///
+    /// Creates a sequencer with an empty pattern.
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The new sequencer
+    pub fn new() -> Self {
+        Self {
+            pattern: Box::new(Pattern::default()),
+            playing: false,
+            next: 0.0,
+            seed: 0,
+            events: Vec::with_capacity(SEQUENCER_EVENTS),
+            releases: Vec::with_capacity(SEQUENCER_EVENTS),
+        }
+    }
+
+    /// ## `set_pattern(&mut self, pattern: Box<Pattern>) -> Box<Pattern>`
+    ///
+    /// Swaps the pattern. The new pattern carries on from the transport's position.
+    /// If the transport is rolling, every track of the old pattern has its note released at the start of the next block,
+    /// so notes it was holding don't hang.
+    ///
+    /// ### Arguments
+    ///
+    /// * `pattern: Box<Pattern>` - The new pattern
+    ///
+    /// ### Returns
+    ///
+    /// * `Box<Pattern>` - The previous pattern
+    pub fn set_pattern(&mut self, pattern: Box<Pattern>) -> Box<Pattern> {
+        if self.playing {
+            for track in self.pattern.tracks.iter() {
+                Self::push(&mut self.releases, 0, track, 0.0);
+            }
+        }
+        std::mem::replace(&mut self.pattern, pattern)
+    }
+
+    /// ## `events(&mut self, state: &State) -> &[Event]`
+    ///
+    /// Works out the notes started and released within a block, sorted by frame.
+    /// Releases come before starts on the same frame, so a note can be played again right as it ends.
+    /// Every track's note is released when the transport stops or jumps, and the notes of a pattern swapped out since the last block are released first.
+    ///
+    /// ### Arguments
+    ///
+    /// * `state: &State` - The state of the block
+    ///
+    /// ### Returns
+    ///
+    /// * `&[Event]` - The events
+    pub fn events(&mut self, state: &State) -> &[Event] {
+        self.events.clear();
+        self.events.extend(self.releases.drain(..));
+
+        let transport = &state.transport;
+        let rate = transport.beats_per_sample(state.sample_rate);
+        let jumped = (transport.beats - self.next).abs() > rate;
+        if self.playing && (!transport.playing || jumped) {
+            for track in self.pattern.tracks.iter() {
+                Self::push(&mut self.events, 0, track, 0.0);
+            }
+        }
+        if transport.playing && !self.playing {
+            self.seed = self.seed.wrapping_add(1);
+        }
+        self.playing = transport.playing;
+        self.next = transport
+            .advanced(state.buffer_size, state.sample_rate)
+            .beats;
+
+        if !transport.playing {
+            return &self.events;
+        }
+
+        let from = transport.beats;
+        let to = from + state.buffer_size as f64 * rate;
+        let length = self.pattern.step_length(transport);
+        // the frame a time in beats falls on, counted from the start of the block; the position is summed block by block,
+        // so a time within a thousandth of a frame of one is taken to be on it, or the frame would depend on the block size
+        let frame = |time: f64| ((time - from) / rate - 0.001).ceil() as i64;
+        let frames = state.buffer_size as i64;
+
+        for (index, track) in self.pattern.tracks.iter().enumerate() {
+            if track.mute || track.steps.is_empty() {
+                continue;
+            }
+
+            // steps can be pushed up to half a step either way and hold up to a step, so look a little around the block
+            let first = (from / length).floor() as i64 - 2;
+            let last = (to / length).ceil() as i64 + 1;
+            for global in first.max(0)..=last {
+                let step = &track.steps[global as usize % track.steps.len()];
+                if step.velocity <= 0.0 {
+                    continue;
+                }
+                if step.probability < 1.0 && chance(self.seed, index, global) >= step.probability {
+                    continue;
+                }
+
+                let ratchet = step.ratchet.clamp(1, MAX_RATCHET);
+                let part = length / ratchet as f64;
+                let time = (global as f64 + step.offset.clamp(-0.5, 0.5) as f64) * length;
+                for repeat in 0..ratchet {
+                    let on = frame(time + repeat as f64 * part);
+                    let off =
+                        frame(time + (repeat as f64 + track.gate.clamp(0.01, 1.0) as f64) * part);
+                    if on >= 0 && on < frames {
+                        Self::push(&mut self.events, on as usize, track, step.velocity);
+                    }
+                    if off >= 0 && off < frames {
+                        Self::push(&mut self.events, off as usize, track, 0.0);
+                    }
+                }
+            }
+        }
+
+        self.events.sort_unstable_by(|a, b| {
+            (a.offset, a.velocity > 0.0).cmp(&(b.offset, b.velocity > 0.0))
+        });
+        &self.events
+    }
+
+    /// ## `push(events: &mut Vec<Event>, offset: usize, track: &Track, velocity: f32)`
+    ///
+    /// Private function which adds an event, unless the block already has as many as it can hold.
+    ///
+    /// ### Arguments
+    ///
+    /// * `events: &mut Vec<Event>` - The events of the block
+    /// * `offset: usize` - The frame within the block
+    /// * `track: &Track` - The track playing the note
+    /// * `velocity: f32` - The velocity; 0.0 releases the note
+    fn push(events: &mut Vec<Event>, offset: usize, track: &Track, velocity: f32) {
+        if events.len() < SEQUENCER_EVENTS {
+            events.push(Event {
+                offset,
+                strip: track.strip,
+                note: track.note,
+                velocity,
+            });
+        }
+    }
+}
+
+/// ## `chance(seed: u64, track: usize, step: i64) -> f32`
+///
+/// Returns a random number for one step of one track (0.0-1.0). The same step always gets the same number
+/// until the transport is played again, so a ratcheted step split across blocks is decided only once.
+///
+/// ### Arguments
+///
+/// * `seed: u64` - The seed of the current play
+/// * `track: usize` - The index of the track
+/// * `step: i64` - The step, counted from the start of the song
+///
+/// ### Returns
+///
+/// * `f32` - The random number
+fn chance(seed: u64, track: usize, step: i64) -> f32 {
+    // splitmix64
+    let mut value = seed
+        .wrapping_mul(0x9E3779B97F4A7C15)
+        .wrapping_add((track as u64) << 32)
+        .wrapping_add(step as u64);
+    value = (value ^ (value >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    value = (value ^ (value >> 27)).wrapping_mul(0x94D049BB133111EB);
+    value ^= value >> 31;
+    (value >> 40) as f32 / (1u64 << 24) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// ## `play(pattern: &Pattern, block: usize, frames: usize) -> Vec<(u64, u8, f32)>`
+    ///
+    /// Plays a pattern from the start at 120 BPM and 48 kHz in blocks of a size,
+    /// returning the absolute frame, note and velocity of every event.
+    fn play(pattern: &Pattern, block: usize, frames: usize) -> Vec<(u64, u8, f32)> {
+        let sample_rate = 48000;
+        let mut sequencer = Sequencer::new();
+        sequencer.set_pattern(Box::new(pattern.clone()));
+        let mut transport = Transport {
+            playing: true,
+            ..Transport::default()
+        };
+        let mut clock = 0;
+        let mut played = Vec::new();
+        while (clock as usize) < frames {
+            let mut state = State::advance(sample_rate, &mut clock, block);
+            state.transport = transport;
+            for event in sequencer.events(&state) {
+                played.push((
+                    state.sample_clock + event.offset as u64,
+                    event.note,
+                    event.velocity,
+                ));
+            }
+            transport = transport.advanced(block, sample_rate);
+        }
+        played
+    }
+
+    #[test]
+    fn steps_land_on_the_same_frame_at_any_block_size() {
+        let mut pattern = Pattern::new(16);
+        let mut track = Track::new("hat", 0, 42, 16);
+        track.steps[0] = Step::hit(1.0);
+        track.steps[5] = Step {
+            offset: 0.25,
+            ..Step::hit(0.8)
+        };
+        track.steps[10] = Step {
+            ratchet: 3,
+            ..Step::hit(0.5)
+        };
+        pattern.tracks.push(track);
+
+        // a step is a sixteenth, or 6000 frames at 120 BPM and 48 kHz, and notes are held for half of one (or of a ratchet)
+        let expected = vec![
+            (0, 42, 1.0),
+            (3000, 42, 0.0),
+            (31500, 42, 0.8),
+            (34500, 42, 0.0),
+            (60000, 42, 0.5),
+            (61000, 42, 0.0),
+            (62000, 42, 0.5),
+            (63000, 42, 0.0),
+            (64000, 42, 0.5),
+            (65000, 42, 0.0),
+            (96000, 42, 1.0),
+        ];
+        for block in [64, 512, 1000] {
+            let played: Vec<(u64, u8, f32)> = play(&pattern, block, 96001)
+                .into_iter()
+                .filter(|(frame, _, _)| *frame <= 96000)
+                .collect();
+            assert_eq!(played, expected, "with blocks of {} frames", block);
+        }
+    }
+
+    #[test]
+    fn grids_longer_than_max_steps_are_refused() {
+        let mut track = Track::new("kick", 0, 36, 16);
+        assert!(track.set_grid(&"x".repeat(MAX_STEPS)).is_ok());
+        assert!(track.set_grid(&"x".repeat(MAX_STEPS + 1)).is_err());
+        assert_eq!(track.steps.len(), MAX_STEPS);
+    }
+}
@@ -0,0 +1,955 @@
+//! synth.rs
+//!
+//! Module for the polyphonic synth: voices with envelopes playing any oscillator from MIDI, HID keys and the sequencer
+
+use crate::audio::{self, plugin::Generator, Buffer, State};
+use crate::midi::ChannelMessage;
+use crate::oscillator::Oscillator;
+
+/// The most voices a synth can play at once; the voice count can be set anywhere up to it
+pub const MAX_VOICES: usize = 32;
+
+/// The controller number of the mod wheel, which adds vibrato
+pub const MOD_WHEEL: u8 = 1;
+
+/// The controller number of the sustain pedal; values of 64 and up hold released notes
+pub const SUSTAIN: u8 = 64;
+
+/// The controller number of All Sound Off, which silences every voice at once
+pub const ALL_SOUND_OFF: u8 = 120;
+
+/// The controller number of All Notes Off, which releases every voice
+pub const ALL_NOTES_OFF: u8 = 123;
+
+/// The vibrato depth with the mod wheel or aftertouch all the way up, in semitones
+const VIBRATO_DEPTH: f32 = 0.5;
+
+/// The vibrato rate, in Hz
+const VIBRATO_RATE: f64 = 5.5;
+
+/// ## Adsr
+///
+/// The shape of an envelope.
+///
+/// ### Fields
+///
+/// * `attack: f32` - The time from silence to full level, in seconds
+/// * `decay: f32` - The time from full level down to the sustain level, in seconds
+/// * `sustain: f32` - The level held while the note is held (0.0-1.0)
+/// * `release: f32` - The time from wherever the level is at note off down to silence, in seconds
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Adsr {
+    pub attack: f32,
+    pub decay: f32,
+    pub sustain: f32,
+    pub release: f32,
+}
+
+impl Default for Adsr {
+    fn default() -> Self {
+        Self {
+            attack: 0.005,
+            decay: 0.1,
+            sustain: 0.8,
+            release: 0.2,
+        }
+    }
+}
+
+/// ## Stage
+///
+/// Where an envelope is.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stage {
+    Idle,
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+/// ## Envelope
+///
+/// A linear ADSR envelope. Its shape is passed in every frame, so changing it takes effect on playing notes.
+///
+/// ### Fields
+///
+/// * `stage: Stage` - Where the envelope is
+/// * `level: f32` - The current level (0.0-1.0)
+/// * `released_from: f32` - The level when the note was released, so the release takes its full time from any level
+///
+/// ### Functions
+///
+/// * `new() -> Self` - Creates an idle envelope
+/// * `start(&mut self)` - Starts the attack from the current level, so a retriggered voice does not click
+/// * `release(&mut self)` - Starts the release
+/// * `kill(&mut self)` - Silences the envelope at once
+/// * `next(&mut self, adsr: &Adsr, sample_rate: f32) -> f32` - Moves the envelope one frame on, returning its level
+/// * `stage(&self) -> Stage` - Returns where the envelope is
+/// * `level(&self) -> f32` - Returns the current level
+pub struct Envelope {
+    stage: Stage,
+    level: f32,
+    released_from: f32,
+}
+
+impl Envelope {
+    /// ## `new() -> Self`
+    ///
+    /// Creates an idle envelope.
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The new envelope
+    pub fn new() -> Self {
+        Self {
+            stage: Stage::Idle,
+            level: 0.0,
+            released_from: 0.0,
+        }
+    }
+
+    /// ## `start(&mut self)`
+    ///
+    /// Starts the attack from the current level, so a retriggered voice does not click.
+    pub fn start(&mut self) {
+        self.stage = Stage::Attack;
+    }
+
+    /// ## `release(&mut self)`
+    ///
+    /// Starts the release, unless the envelope is idle or already releasing.
+    pub fn release(&mut self) {
+        match self.stage {
+            Stage::Idle | Stage::Release => {}
+            _ => {
+                self.stage = Stage::Release;
+                self.released_from = self.level;
+            }
+        }
+    }
+
+    /// ## `kill(&mut self)`
+    ///
+    /// Silences the envelope at once.
+    pub fn kill(&mut self) {
+        self.stage = Stage::Idle;
+        self.level = 0.0;
+    }
+
+    /// ## `next(&mut self, adsr: &Adsr, sample_rate: f32) -> f32`
+    ///
+    /// Moves the envelope one frame on.
+    ///
+    /// ### Arguments
+    ///
+    /// * `adsr: &Adsr` - The shape of the envelope
+    /// * `sample_rate: f32` - The sample rate of the audio engine
+    ///
+    /// ### Returns
+    ///
+    /// * `f32` - The level (0.0-1.0)
+    pub fn next(&mut self, adsr: &Adsr, sample_rate: f32) -> f32 {
+        let frames = |seconds: f32| (seconds * sample_rate).max(1.0);
+        match self.stage {
+            Stage::Idle => {}
+            Stage::Attack => {
+                self.level += 1.0 / frames(adsr.attack);
+                if self.level >= 1.0 {
+                    self.level = 1.0;
+                    self.stage = Stage::Decay;
+                }
+            }
+            Stage::Decay => {
+                let sustain = adsr.sustain.clamp(0.0, 1.0);
+                self.level -= (1.0 - sustain) / frames(adsr.decay);
+                if self.level <= sustain {
+                    self.level = sustain;
+                    self.stage = Stage::Sustain;
+                }
+            }
+            Stage::Sustain => self.level = adsr.sustain.clamp(0.0, 1.0),
+            Stage::Release => {
+                self.level -= self.released_from / frames(adsr.release);
+                if self.level <= 0.0 {
+                    self.kill();
+                }
+            }
+        }
+        self.level
+    }
+
+    /// ## `stage(&self) -> Stage`
+    ///
+    /// Returns where the envelope is.
+    ///
+    /// ### Returns
+    ///
+    /// * `Stage` - The stage
+    pub fn stage(&self) -> Stage {
+        self.stage
+    }
+
+    /// ## `level(&self) -> f32`
+    ///
+    /// Returns the current level.
+    ///
+    /// ### Returns
+    ///
+    /// * `f32` - The level (0.0-1.0)
+    pub fn level(&self) -> f32 {
+        self.level
+    }
+}
+
+/// ## VoiceMode
+///
+/// How a synth plays overlapping notes.
+///
+/// ### Variants
+///
+/// * `Poly` - Every note gets its own voice
+/// * `Mono` - One voice, playing the last note held; every note restarts the envelope
+/// * `Legato` - One voice, playing the last note held; notes played while another is held glide without restarting the envelope
+///
+/// ### Functions
+///
+/// * `from_name(name: &str) -> Option<Self>` - Gets a mode by name ("poly", "mono" or "legato")
+/// * `name(&self) -> &'static str` - Returns the name of the mode
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VoiceMode {
+    Poly,
+    Mono,
+    Legato,
+}
+
+impl VoiceMode {
+    /// ## `from_name(name: &str) -> Option<Self>`
+    ///
+    /// Gets a mode by name. Not case-sensitive.
+    ///
+    /// ### Arguments
+    ///
+    /// * `name: &str` - "poly", "mono" or "legato"
+    ///
+    /// ### Returns
+    ///
+    /// * `Option<Self>` - The mode, or None if the name is unknown
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "poly" => Some(VoiceMode::Poly),
+            "mono" => Some(VoiceMode::Mono),
+            "legato" => Some(VoiceMode::Legato),
+            _ => None,
+        }
+    }
+
+    /// ## `name(&self) -> &'static str`
+    ///
+    /// Returns the name of the mode.
+    ///
+    /// ### Returns
+    ///
+    /// * `&'static str` - The name
+    pub fn name(&self) -> &'static str {
+        match self {
+            VoiceMode::Poly => "poly",
+            VoiceMode::Mono => "mono",
+            VoiceMode::Legato => "legato",
+        }
+    }
+}
+
+/// ## Steal
+///
+/// Which voice a synth takes over when a note starts and every voice is busy. Voices already released are taken first.
+///
+/// ### Variants
+///
+/// * `Oldest` - The voice started longest ago
+/// * `Quietest` - The voice with the lowest envelope level
+/// * `Lowest` - The voice playing the lowest note
+/// * `Highest` - The voice playing the highest note
+/// * `Never` - No voice; the new note is dropped
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Steal {
+    Oldest,
+    Quietest,
+    Lowest,
+    Highest,
+    Never,
+}
+
+/// ## Voice
+///
+/// One voice of a synth.
+///
+/// ### Fields
+///
+/// * `oscillator: Box<dyn Oscillator>` - The sound source
+/// * `envelope: Envelope` - The amplitude envelope
+/// * `note: u8` - The MIDI note playing
+/// * `channel: u8` - The MIDI channel of the note
+/// * `velocity: f32` - The velocity (0.0-1.0)
+/// * `pitch: f32` - The current pitch as a fractional MIDI note, gliding towards `note` with portamento
+/// * `glide: f32` - How fast the pitch moves towards the note, in semitones per second
+/// * `pressure: f32` - The aftertouch on this note (0.0-1.0)
+/// * `held: bool` - Whether the key is down
+/// * `sustained: bool` - Whether the key was released while the sustain pedal was down
+/// * `age: u64` - When the note started, counted in notes
+struct Voice {
+    oscillator: Box<dyn Oscillator>,
+    envelope: Envelope,
+    note: u8,
+    channel: u8,
+    velocity: f32,
+    pitch: f32,
+    glide: f32,
+    pressure: f32,
+    held: bool,
+    sustained: bool,
+    age: u64,
+}
+
+impl Voice {
+    /// ## `active(&self) -> bool`
+    ///
+    /// Private function which returns whether the voice is sounding.
+    ///
+    /// ### Returns
+    ///
+    /// * `bool` - Whether the envelope is not idle
+    fn active(&self) -> bool {
+        self.envelope.stage() != Stage::Idle
+    }
+
+    /// ## `release(&mut self, sustain: bool)`
+    ///
+    /// Private function which lets the key go, holding the note if the sustain pedal is down.
+    ///
+    /// ### Arguments
+    ///
+    /// * `sustain: bool` - Whether the sustain pedal of the note's channel is down
+    fn release(&mut self, sustain: bool) {
+        self.held = false;
+        match sustain {
+            true => self.sustained = true,
+            false => self.envelope.release(),
+        }
+    }
+}
+
+/// ## Synth
+///
+/// A polyphonic generator playing an oscillator through per-voice ADSR envelopes. Notes from MIDI keep their channel,
+/// so the same note on two channels plays two voices; notes from HID keys, the console and the sequencer play on channel 1.
+/// Each channel has its own pitch bend, mod wheel (vibrato), aftertouch (vibrato) and sustain pedal.
+///
+/// ### Fields
+///
+/// * `voices: Vec<Voice>` - MAX_VOICES voices, of which the first `polyphony` are played
+/// * `polyphony: usize` - The number of voices played
+/// * `mode: VoiceMode` - How overlapping notes are played
+/// * `steal: Steal` - Which voice a new note takes over when every voice is busy
+/// * `adsr: Adsr` - The shape of the voices' envelopes
+/// * `portamento: f32` - The time a glide between notes takes, in seconds; 0.0 turns it off
+/// * `bend_range: f32` - How far a full pitch bend goes, in semitones
+/// * `gain: f32` - The gain in dB
+/// * `bend: [f32; 16]` - The pitch bend of each channel, in semitones
+/// * `modulation: [f32; 16]` - The mod wheel of each channel (0.0-1.0)
+/// * `pressure: [f32; 16]` - The channel aftertouch of each channel (0.0-1.0)
+/// * `sustain: [bool; 16]` - Whether the sustain pedal of each channel is down
+/// * `held: Vec<(u8, u8, f32)>` - The keys down in mono and legato modes (channel, note, velocity), the last one playing
+/// * `last: Option<f32>` - The pitch of the last note started, where portamento glides from
+/// * `age: u64` - The number of notes started
+/// * `vibrato: f64` - The phase of the vibrato (0.0-1.0)
+///
+/// ### Parameters
+///
+/// * "voices" - The number of voices (1-32)
+/// * "mode" - 0 for poly, 1 for mono, 2 for legato
+/// * "steal" - 0 for oldest, 1 for quietest, 2 for lowest, 3 for highest, 4 to never steal
+/// * "attack", "decay", "release" - Envelope times, in seconds
+/// * "sustain" - The sustain level (0.0-1.0)
+/// * "portamento" - The glide time, in seconds
+/// * "bend" - The pitch bend range, in semitones
+/// * "gain" - The gain in dB
+/// * "osc.<name>" - A parameter of the oscillator, set on every voice's copy (e.g. "osc.width")
+pub struct Synth {
+    voices: Vec<Voice>,
+    polyphony: usize,
+    mode: VoiceMode,
+    steal: Steal,
+    adsr: Adsr,
+    portamento: f32,
+    bend_range: f32,
+    gain: f32,
+    bend: [f32; 16],
+    modulation: [f32; 16],
+    pressure: [f32; 16],
+    sustain: [bool; 16],
+    held: Vec<(u8, u8, f32)>,
+    last: Option<f32>,
+    age: u64,
+    vibrato: f64,
+}
+
+impl Synth {
+    /// ## `new(oscillator: Box<dyn Oscillator>, polyphony: usize, mode: VoiceMode) -> Self`
+    ///
+    /// Creates a synth. Every voice gets its own copy of the oscillator.
+    ///
+    /// ### Arguments
+    ///
+    /// * `oscillator: Box<dyn Oscillator>` - The sound source
+    /// * `polyphony: usize` - The number of voices (1-32)
+    /// * `mode: VoiceMode` - How overlapping notes are played
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The new synth
+    pub fn new(oscillator: Box<dyn Oscillator>, polyphony: usize, mode: VoiceMode) -> Self {
+        let voices = (0..MAX_VOICES)
+            .map(|_| Voice {
+                oscillator: oscillator.duplicate(),
+                envelope: Envelope::new(),
+                note: 0,
+                channel: 0,
+                velocity: 0.0,
+                pitch: 0.0,
+                glide: 0.0,
+                pressure: 0.0,
+                held: false,
+                sustained: false,
+                age: 0,
+            })
+            .collect();
+
+        Self {
+            voices,
+            polyphony: polyphony.clamp(1, MAX_VOICES),
+            mode,
+            steal: Steal::Oldest,
+            adsr: Adsr::default(),
+            portamento: 0.0,
+            bend_range: 2.0,
+            gain: 0.0,
+            bend: [0.0; 16],
+            modulation: [0.0; 16],
+            pressure: [0.0; 16],
+            sustain: [false; 16],
+            held: Vec::with_capacity(128),
+            last: None,
+            age: 0,
+            vibrato: 0.0,
+        }
+    }
+
+    /// ## `free_voice(&self) -> Option<usize>`
+    ///
+    /// Private function which picks the voice for a new note in poly mode: an idle voice if there is one,
+    /// then the quietest released voice, then one chosen by the stealing policy.
+    ///
+    /// ### Returns
+    ///
+    /// * `Option<usize>` - The index of the voice, or None if the note should be dropped
+    fn free_voice(&self) -> Option<usize> {
+        let voices = &self.voices[..self.polyphony];
+        match voices.iter().position(|voice| !voice.active()) {
+            Some(index) => return Some(index),
+            None => {}
+        }
+
+        let released = (0..voices.len())
+            .filter(|index| voices[*index].envelope.stage() == Stage::Release)
+            .min_by(|a, b| {
+                voices[*a]
+                    .envelope
+                    .level()
+                    .total_cmp(&voices[*b].envelope.level())
+            });
+        match released {
+            Some(index) => return Some(index),
+            None => {}
+        }
+
+        let indices = 0..voices.len();
+        match self.steal {
+            Steal::Oldest => indices.min_by_key(|index| voices[*index].age),
+            Steal::Quietest => indices.min_by(|a, b| {
+                voices[*a]
+                    .envelope
+                    .level()
+                    .total_cmp(&voices[*b].envelope.level())
+            }),
+            Steal::Lowest => indices.min_by_key(|index| voices[*index].note),
+            Steal::Highest => indices.max_by_key(|index| voices[*index].note),
+            Steal::Never => None,
+        }
+    }
+
+    /// ## `play(&mut self, index: usize, channel: u8, note: u8, velocity: f32, retrigger: bool)`
+    ///
+    /// Private function which sets a voice playing a note, gliding to it if portamento is on.
+    ///
+    /// ### Arguments
+    ///
+    /// * `index: usize` - The index of the voice
+    /// * `channel: u8` - The MIDI channel
+    /// * `note: u8` - The MIDI note
+    /// * `velocity: f32` - The velocity (0.0-1.0)
+    /// * `retrigger: bool` - Whether to restart the envelope
+    fn play(&mut self, index: usize, channel: u8, note: u8, velocity: f32, retrigger: bool) {
+        self.age += 1;
+        let from = match (self.portamento > 0.0, self.last) {
+            (true, Some(last)) => last,
+            _ => note as f32,
+        };
+        self.last = Some(note as f32);
+
+        let glide = (note as f32 - from).abs() / self.portamento.max(f32::EPSILON);
+        let voice = &mut self.voices[index];
+        if !voice.active() {
+            voice.oscillator.reset(0.0);
+        }
+        voice.note = note;
+        voice.channel = channel;
+        voice.velocity = velocity;
+        voice.pitch = from;
+        voice.glide = glide;
+        voice.pressure = 0.0;
+        voice.held = true;
+        voice.sustained = false;
+        voice.age = self.age;
+        if retrigger || !voice.active() {
+            voice.envelope.start();
+        }
+    }
+
+    /// ## `start(&mut self, channel: u8, note: u8, velocity: f32)`
+    ///
+    /// Private function which starts a note.
+    ///
+    /// ### Arguments
+    ///
+    /// * `channel: u8` - The MIDI channel
+    /// * `note: u8` - The MIDI note
+    /// * `velocity: f32` - The velocity (0.0-1.0)
+    fn start(&mut self, channel: u8, note: u8, velocity: f32) {
+        match self.mode {
+            VoiceMode::Poly => {
+                // the same key again takes over its own voice
+                let index = match self.voices[..self.polyphony].iter().position(|voice| {
+                    voice.active() && voice.note == note && voice.channel == channel
+                }) {
+                    Some(index) => Some(index),
+                    None => self.free_voice(),
+                };
+                match index {
+                    Some(index) => self.play(index, channel, note, velocity, true),
+                    None => {}
+                }
+            }
+            VoiceMode::Mono | VoiceMode::Legato => {
+                let overlapping = !self.held.is_empty();
+                self.held.retain(|(held_channel, held_note, _)| {
+                    (*held_channel, *held_note) != (channel, note)
+                });
+                if self.held.len() < self.held.capacity() {
+                    self.held.push((channel, note, velocity));
+                }
+                let retrigger = self.mode == VoiceMode::Mono || !overlapping;
+                self.play(0, channel, note, velocity, retrigger);
+            }
+        }
+    }
+
+    /// ## `stop(&mut self, channel: u8, note: u8)`
+    ///
+    /// Private function which releases a note, by note number and channel.
+    ///
+    /// ### Arguments
+    ///
+    /// * `channel: u8` - The MIDI channel
+    /// * `note: u8` - The MIDI note
+    fn stop(&mut self, channel: u8, note: u8) {
+        let sustain = self.sustain[channel as usize & 0x0F];
+        match self.mode {
+            VoiceMode::Poly => {
+                for voice in self.voices.iter_mut() {
+                    if voice.held && voice.note == note && voice.channel == channel {
+                        voice.release(sustain);
+                    }
+                }
+            }
+            VoiceMode::Mono | VoiceMode::Legato => {
+                self.held.retain(|(held_channel, held_note, _)| {
+                    (*held_channel, *held_note) != (channel, note)
+                });
+                let voice = &self.voices[0];
+                if !voice.held || voice.note != note || voice.channel != channel {
+                    return;
+                }
+
+                // fall back on the last key still down
+                match self.held.last() {
+                    Some(&(channel, note, velocity)) => {
+                        let retrigger = self.mode == VoiceMode::Mono;
+                        self.play(0, channel, note, velocity, retrigger);
+                    }
+                    None => self.voices[0].release(sustain),
+                }
+            }
+        }
+    }
+
+    /// ## `pedal(&mut self, channel: u8, down: bool)`
+    ///
+    /// Private function which presses or lets go of a channel's sustain pedal. Letting go releases the notes it held.
+    ///
+    /// ### Arguments
+    ///
+    /// * `channel: u8` - The MIDI channel
+    /// * `down: bool` - Whether the pedal is down
+    fn pedal(&mut self, channel: u8, down: bool) {
+        self.sustain[channel as usize & 0x0F] = down;
+        if down {
+            return;
+        }
+        for voice in self.voices.iter_mut() {
+            if voice.channel == channel && voice.sustained {
+                voice.sustained = false;
+                voice.envelope.release();
+            }
+        }
+    }
+}
+
+impl Generator for Synth {
+    fn generate(&mut self, state: &State, buffer: &mut Buffer) {
+        buffer.clear();
+
+        let sample_rate = state.sample_rate.max(1) as f32;
+        let gain = audio::db_to_gain(self.gain);
+        let vibrato_step = VIBRATO_RATE / sample_rate as f64;
+        let vibrato = self.vibrato;
+
+        let (left, right) = buffer.stereo_mut();
+        for voice in self.voices.iter_mut() {
+            if !voice.active() {
+                continue;
+            }
+
+            let channel = voice.channel as usize & 0x0F;
+            let target = voice.note as f32;
+            let glide = voice.glide / sample_rate;
+            let bend = self.bend[channel];
+            let depth = self.modulation[channel]
+                .max(self.pressure[channel])
+                .max(voice.pressure)
+                * VIBRATO_DEPTH;
+
+            for (frame, (left, right)) in left.iter_mut().zip(right.iter_mut()).enumerate() {
+                if voice.pitch != target {
+                    voice.pitch = match voice.pitch < target {
+                        true => (voice.pitch + glide).min(target),
+                        false => (voice.pitch - glide).max(target),
+                    };
+                }
+
+                let wobble = match depth > 0.0 {
+                    true => {
+                        ((vibrato + frame as f64 * vibrato_step) * std::f64::consts::TAU).sin()
+                            as f32
+                            * depth
+                    }
+                    false => 0.0,
+                };
+                let frequency = 440.0 * 2.0f32.powf((voice.pitch + bend + wobble - 69.0) / 12.0);
+                let (sample_left, sample_right) = voice.oscillator.next(frequency, sample_rate);
+                let level = voice.envelope.next(&self.adsr, sample_rate) * voice.velocity * gain;
+                *left += sample_left * level;
+                *right += sample_right * level;
+
+                if !voice.active() {
+                    break;
+                }
+            }
+        }
+
+        self.vibrato = (vibrato + left.len() as f64 * vibrato_step).fract();
+    }
+
+    fn note_on(&mut self, note: u8, velocity: f32) {
+        self.start(0, note, velocity.clamp(0.0, 1.0));
+    }
+
+    fn note_off(&mut self, note: u8) {
+        self.stop(0, note);
+    }
+
+    fn midi(&mut self, channel: u8, message: ChannelMessage) {
+        let index = channel as usize & 0x0F;
+        match message {
+            ChannelMessage::NoteOn { note, velocity } => {
+                self.start(channel, note, velocity as f32 / 127.0)
+            }
+            ChannelMessage::NoteOff { note, .. } => self.stop(channel, note),
+            ChannelMessage::PitchBend { value } => {
+                self.bend[index] = value as f32 / 8192.0 * self.bend_range
+            }
+            ChannelMessage::ChannelPressure { pressure } => {
+                self.pressure[index] = pressure as f32 / 127.0
+            }
+            ChannelMessage::PolyPressure { note, pressure } => {
+                for voice in self.voices.iter_mut() {
+                    if voice.held && voice.note == note && voice.channel == channel {
+                        voice.pressure = pressure as f32 / 127.0;
+                    }
+                }
+            }
+            ChannelMessage::ControlChange { controller, value } => match controller {
+                MOD_WHEEL => self.modulation[index] = value as f32 / 127.0,
+                SUSTAIN => self.pedal(channel, value >= 64),
+                ALL_SOUND_OFF => {
+                    for voice in self.voices.iter_mut() {
+                        voice.envelope.kill();
+                    }
+                    self.held.clear();
+                }
+                ALL_NOTES_OFF => {
+                    for voice in self.voices.iter_mut() {
+                        voice.held = false;
+                        voice.sustained = false;
+                        voice.envelope.release();
+                    }
+                    self.held.clear();
+                }
+                _ => {}
+            },
+            ChannelMessage::ProgramChange { .. } => {}
+        }
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f32) {
+        match name.strip_prefix("osc.") {
+            Some(name) => {
+                for voice in self.voices.iter_mut() {
+                    voice.oscillator.set_parameter(name, value);
+                }
+                return;
+            }
+            None => {}
+        }
+
+        match name {
+            "voices" => {
+                self.polyphony = (value.max(1.0) as usize).min(MAX_VOICES);
+                for voice in self.voices[self.polyphony..].iter_mut() {
+                    voice.envelope.release();
+                }
+            }
+            "mode" => {
+                self.mode = match value as i32 {
+                    1 => VoiceMode::Mono,
+                    2 => VoiceMode::Legato,
+                    _ => VoiceMode::Poly,
+                };
+                self.held.clear();
+                for voice in self.voices.iter_mut() {
+                    voice.held = false;
+                    voice.envelope.release();
+                }
+            }
+            "steal" => {
+                self.steal = match value as i32 {
+                    1 => Steal::Quietest,
+                    2 => Steal::Lowest,
+                    3 => Steal::Highest,
+                    4 => Steal::Never,
+                    _ => Steal::Oldest,
+                }
+            }
+            "attack" => self.adsr.attack = value.max(0.0),
+            "decay" => self.adsr.decay = value.max(0.0),
+            "sustain" => self.adsr.sustain = value.clamp(0.0, 1.0),
+            "release" => self.adsr.release = value.max(0.0),
+            "portamento" => self.portamento = value.max(0.0),
+            "bend" => self.bend_range = value.clamp(0.0, 48.0),
+            "gain" => self.gain = value,
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oscillator::Sine;
+
+    fn synth(polyphony: usize, mode: VoiceMode) -> Synth {
+        Synth::new(Box::new(Sine::new()), polyphony, mode)
+    }
+
+    /// ## `run(synth: &mut Synth, frames: usize)`
+    ///
+    /// Moves a synth's envelopes on by generating a block at 48 kHz.
+    fn run(synth: &mut Synth, frames: usize) {
+        let mut clock = 0;
+        let state = State::advance(48000, &mut clock, frames);
+        let mut buffer = Buffer::new(2, frames);
+        synth.generate(&state, &mut buffer);
+    }
+
+    fn on(synth: &mut Synth, channel: u8, note: u8) {
+        synth.midi(
+            channel,
+            ChannelMessage::NoteOn {
+                note,
+                velocity: 100,
+            },
+        );
+    }
+
+    fn off(synth: &mut Synth, channel: u8, note: u8) {
+        synth.midi(channel, ChannelMessage::NoteOff { note, velocity: 0 });
+    }
+
+    fn pedal(synth: &mut Synth, channel: u8, down: bool) {
+        let value = match down {
+            true => 127,
+            false => 0,
+        };
+        synth.midi(
+            channel,
+            ChannelMessage::ControlChange {
+                controller: SUSTAIN,
+                value,
+            },
+        );
+    }
+
+    /// ## `stage(synth: &Synth, channel: u8, note: u8) -> Option<Stage>`
+    ///
+    /// Returns the envelope stage of the voice playing a note, if one is sounding.
+    fn stage(synth: &Synth, channel: u8, note: u8) -> Option<Stage> {
+        synth
+            .voices
+            .iter()
+            .find(|voice| voice.active() && voice.note == note && voice.channel == channel)
+            .map(|voice| voice.envelope.stage())
+    }
+
+    #[test]
+    fn steals_a_voice_when_every_voice_is_busy() {
+        let mut oldest = synth(MAX_VOICES, VoiceMode::Poly);
+        for note in 0..MAX_VOICES as u8 {
+            on(&mut oldest, 0, 36 + note);
+        }
+        on(&mut oldest, 0, 100);
+        assert_eq!(stage(&oldest, 0, 36), None);
+        assert_eq!(stage(&oldest, 0, 100), Some(Stage::Attack));
+        assert_eq!(stage(&oldest, 0, 37), Some(Stage::Attack));
+        assert_eq!(
+            oldest.voices.iter().filter(|voice| voice.active()).count(),
+            MAX_VOICES
+        );
+
+        let mut highest = synth(MAX_VOICES, VoiceMode::Poly);
+        highest.set_parameter("steal", 3.0);
+        for note in 0..MAX_VOICES as u8 {
+            on(&mut highest, 0, 36 + note);
+        }
+        on(&mut highest, 0, 20);
+        assert_eq!(stage(&highest, 0, 36 + MAX_VOICES as u8 - 1), None);
+        assert_eq!(stage(&highest, 0, 20), Some(Stage::Attack));
+
+        // a released voice is taken before a held one
+        let mut released = synth(4, VoiceMode::Poly);
+        for note in 60..64 {
+            on(&mut released, 0, note);
+        }
+        off(&mut released, 0, 62);
+        on(&mut released, 0, 70);
+        assert_eq!(stage(&released, 0, 62), None);
+        assert_eq!(stage(&released, 0, 60), Some(Stage::Attack));
+
+        let mut never = synth(4, VoiceMode::Poly);
+        never.set_parameter("steal", 4.0);
+        for note in 60..64 {
+            on(&mut never, 0, note);
+        }
+        on(&mut never, 0, 70);
+        assert_eq!(stage(&never, 0, 70), None);
+        assert_eq!(stage(&never, 0, 60), Some(Stage::Attack));
+    }
+
+    #[test]
+    fn mono_retriggers_and_legato_does_not() {
+        for mode in [VoiceMode::Mono, VoiceMode::Legato] {
+            let mut synth = synth(1, mode);
+            on(&mut synth, 0, 60);
+            // past the attack and decay, so a retrigger shows
+            run(&mut synth, 9600);
+            assert_eq!(stage(&synth, 0, 60), Some(Stage::Sustain));
+
+            on(&mut synth, 0, 64);
+            let expected = match mode {
+                VoiceMode::Mono => Stage::Attack,
+                _ => Stage::Sustain,
+            };
+            assert_eq!(stage(&synth, 0, 64), Some(expected), "{:?}", mode);
+            assert_eq!(stage(&synth, 0, 60), None);
+
+            // letting the new note go falls back on the one still held, and letting that go releases the voice
+            run(&mut synth, 9600);
+            off(&mut synth, 0, 64);
+            assert_eq!(stage(&synth, 0, 60), Some(expected), "{:?}", mode);
+            off(&mut synth, 0, 60);
+            assert_eq!(stage(&synth, 0, 60), Some(Stage::Release));
+        }
+    }
+
+    #[test]
+    fn sustain_pedal_holds_notes_until_it_is_let_go() {
+        let mut synth = synth(8, VoiceMode::Poly);
+        pedal(&mut synth, 0, true);
+        on(&mut synth, 0, 60);
+        on(&mut synth, 1, 64);
+        run(&mut synth, 9600);
+        off(&mut synth, 0, 60);
+        off(&mut synth, 1, 64);
+
+        // the pedal only holds notes on its own channel
+        assert_eq!(stage(&synth, 0, 60), Some(Stage::Sustain));
+        assert_eq!(stage(&synth, 1, 64), Some(Stage::Release));
+
+        // playing a held note again and letting it go keeps it held
+        on(&mut synth, 0, 60);
+        off(&mut synth, 0, 60);
+        assert_eq!(stage(&synth, 0, 60), Some(Stage::Attack));
+
+        pedal(&mut synth, 0, false);
+        assert_eq!(stage(&synth, 0, 60), Some(Stage::Release));
+        run(&mut synth, 48000);
+        assert_eq!(stage(&synth, 0, 60), None);
+    }
+
+    #[test]
+    fn note_off_matches_note_and_channel() {
+        let mut synth = synth(8, VoiceMode::Poly);
+        on(&mut synth, 0, 60);
+        on(&mut synth, 1, 60);
+        on(&mut synth, 1, 62);
+        off(&mut synth, 1, 60);
+        assert_eq!(stage(&synth, 0, 60), Some(Stage::Attack));
+        assert_eq!(stage(&synth, 1, 60), Some(Stage::Release));
+        assert_eq!(stage(&synth, 1, 62), Some(Stage::Attack));
+
+        // a note off on a channel that never played the note changes nothing
+        off(&mut synth, 2, 62);
+        assert_eq!(stage(&synth, 1, 62), Some(Stage::Attack));
+        off(&mut synth, 0, 60);
+        assert_eq!(stage(&synth, 0, 60), Some(Stage::Release));
+    }
+}
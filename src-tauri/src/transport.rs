@@ -0,0 +1,327 @@
+//! transport.rs
+//!
+//! Module for the transport: the musical clock shared by everything on the audio thread
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// The number of ticks in a beat
+pub const TICKS_PER_BEAT: u32 = 960;
+
+/// ## Transport
+///
+/// Where the song is, in musical time, at the first frame of a block. Every plugin gets it through `State`.
+/// Bars, beats and ticks count from zero; the console shows bars and beats counting from one.
+///
+/// ### Fields
+///
+/// * `playing: bool` - Whether the transport is rolling
+/// * `bpm: f64` - The tempo, in beats per minute
+/// * `beats_per_bar: u32` - The top of the time signature
+/// * `beat_unit: u32` - The bottom of the time signature; the tempo counts beats of this length
+/// * `beats: f64` - The position in beats from the start of the song
+/// * `bar: u32` - The current bar
+/// * `beat: u32` - The current beat within the bar
+/// * `tick: u32` - The current tick within the beat (0-959)
+/// * `phase: f64` - How far through the current beat the position is (0.0-1.0)
+///
+/// ### Functions
+///
+/// * `located(&self, beats: f64) -> Self` - Returns the transport moved to a position
+/// * `advanced(&self, frames: usize, sample_rate: u32) -> Self` - Returns the transport some frames later
+/// * `beats_per_sample(&self, sample_rate: u32) -> f64` - Returns how far the position moves per frame while playing
+/// * `beats_per_bar(&self) -> f64` - Returns the length of a bar in beats
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transport {
+    pub playing: bool,
+    pub bpm: f64,
+    pub beats_per_bar: u32,
+    pub beat_unit: u32,
+    pub beats: f64,
+    pub bar: u32,
+    pub beat: u32,
+    pub tick: u32,
+    pub phase: f64,
+}
+
+impl Default for Transport {
+    fn default() -> Self {
+        Self {
+            playing: false,
+            bpm: 120.0,
+            beats_per_bar: 4,
+            beat_unit: 4,
+            beats: 0.0,
+            bar: 0,
+            beat: 0,
+            tick: 0,
+            phase: 0.0,
+        }
+    }
+}
+
+impl Transport {
+    /// ## `located(&self, beats: f64) -> Self`
+    ///
+    /// Returns the transport moved to a position, with the bar, beat, tick and phase worked out from it.
+    ///
+    /// ### Arguments
+    ///
+    /// * `beats: f64` - The position in beats from the start of the song
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The moved transport
+    pub fn located(&self, beats: f64) -> Self {
+        let beats = beats.max(0.0);
+        let whole = beats.floor();
+        let phase = beats - whole;
+        let beats_per_bar = self.beats_per_bar.max(1) as f64;
+        Self {
+            beats,
+            bar: (whole / beats_per_bar).floor() as u32,
+            beat: (whole % beats_per_bar) as u32,
+            tick: ((phase * TICKS_PER_BEAT as f64) as u32).min(TICKS_PER_BEAT - 1),
+            phase,
+            ..*self
+        }
+    }
+
+    /// ## `advanced(&self, frames: usize, sample_rate: u32) -> Self`
+    ///
+    /// Returns the transport some frames later. A stopped transport stays where it is.
+    ///
+    /// ### Arguments
+    ///
+    /// * `frames: usize` - The number of frames
+    /// * `sample_rate: u32` - The sample rate of the audio engine
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The advanced transport
+    pub fn advanced(&self, frames: usize, sample_rate: u32) -> Self {
+        match self.playing && frames > 0 {
+            true => self.located(self.beats + frames as f64 * self.beats_per_sample(sample_rate)),
+            false => *self,
+        }
+    }
+
+    /// ## `beats_per_sample(&self, sample_rate: u32) -> f64`
+    ///
+    /// Returns how far the position moves per frame while playing.
+    ///
+    /// ### Arguments
+    ///
+    /// * `sample_rate: u32` - The sample rate of the audio engine
+    ///
+    /// ### Returns
+    ///
+    /// * `f64` - The number of beats in a frame
+    pub fn beats_per_sample(&self, sample_rate: u32) -> f64 {
+        self.bpm / 60.0 / sample_rate.max(1) as f64
+    }
+
+    /// ## `beats_per_bar(&self) -> f64`
+    ///
+    /// Returns the length of a bar in beats.
+    ///
+    /// ### Returns
+    ///
+    /// * `f64` - The number of beats in a bar
+    pub fn beats_per_bar(&self) -> f64 {
+        self.beats_per_bar.max(1) as f64
+    }
+}
+
+impl std::fmt::Display for Transport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}.{}.{:03} ({} bpm, {}/{}, {})",
+            self.bar + 1,
+            self.beat + 1,
+            self.tick,
+            self.bpm,
+            self.beats_per_bar,
+            self.beat_unit,
+            match self.playing {
+                true => "playing",
+                false => "stopped",
+            }
+        )
+    }
+}
+
+/// ## Position
+///
+/// Publishes the transport's position, tempo and time signature from the audio thread, so the control side
+/// (the console, the MIDI clock sender) can follow it without locking anything.
+///
+/// ### Fields
+///
+/// * `beats: AtomicU64` - The position in beats (the bits of an f64)
+/// * `playing: AtomicBool` - Whether the transport is rolling
+/// * `bpm: AtomicU64` - The tempo (the bits of an f64)
+/// * `signature: AtomicU64` - The beats per bar in the high half and the beat unit in the low half
+///
+/// ### Functions
+///
+/// * `new() -> Arc<Self>` - Creates a position at the start of the song
+/// * `publish(&self, transport: &Transport)` - Publishes the transport's position
+/// * `read(&self, transport: &Transport) -> Transport` - Returns the settings of a transport at the published position
+/// * `transport(&self) -> Transport` - Returns the transport as last published
+pub struct Position {
+    beats: AtomicU64,
+    playing: AtomicBool,
+    bpm: AtomicU64,
+    signature: AtomicU64,
+}
+
+impl Position {
+    /// ## `new() -> Arc<Self>`
+    ///
+    /// Creates a position at the start of the song.
+    ///
+    /// ### Returns
+    ///
+    /// * `Arc<Self>` - The new position
+    pub fn new() -> Arc<Self> {
+        let transport = Transport::default();
+        Arc::new(Self {
+            beats: AtomicU64::new(0.0f64.to_bits()),
+            playing: AtomicBool::new(false),
+            bpm: AtomicU64::new(transport.bpm.to_bits()),
+            signature: AtomicU64::new(
+                (transport.beats_per_bar as u64) << 32 | transport.beat_unit as u64,
+            ),
+        })
+    }
+
+    /// ## `publish(&self, transport: &Transport)`
+    ///
+    /// Publishes the transport's position.
+    ///
+    /// ### Arguments
+    ///
+    /// * `transport: &Transport` - The transport
+    pub fn publish(&self, transport: &Transport) {
+        self.beats
+            .store(transport.beats.to_bits(), Ordering::Relaxed);
+        self.playing.store(transport.playing, Ordering::Relaxed);
+        self.bpm.store(transport.bpm.to_bits(), Ordering::Relaxed);
+        self.signature.store(
+            (transport.beats_per_bar as u64) << 32 | transport.beat_unit as u64,
+            Ordering::Relaxed,
+        );
+    }
+
+    /// ## `read(&self, transport: &Transport) -> Transport`
+    ///
+    /// Returns the settings of a transport at the published position.
+    ///
+    /// ### Arguments
+    ///
+    /// * `transport: &Transport` - The tempo and time signature to use
+    ///
+    /// ### Returns
+    ///
+    /// * `Transport` - The transport
+    pub fn read(&self, transport: &Transport) -> Transport {
+        Transport {
+            playing: self.playing.load(Ordering::Relaxed),
+            ..*transport
+        }
+        .located(f64::from_bits(self.beats.load(Ordering::Relaxed)))
+    }
+
+    /// ## `transport(&self) -> Transport`
+    ///
+    /// Returns the transport as last published, with its tempo and time signature.
+    ///
+    /// ### Returns
+    ///
+    /// * `Transport` - The transport
+    pub fn transport(&self) -> Transport {
+        let signature = self.signature.load(Ordering::Relaxed);
+        let transport = Transport {
+            bpm: f64::from_bits(self.bpm.load(Ordering::Relaxed)),
+            beats_per_bar: (signature >> 32) as u32,
+            beat_unit: signature as u32,
+            ..Transport::default()
+        };
+        self.read(&transport)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn advancing_crosses_bar_boundaries() {
+        // 120 BPM at 48 kHz is 24000 frames a beat
+        let transport = Transport {
+            playing: true,
+            ..Transport::default()
+        }
+        .located(3.75);
+        assert_eq!((transport.bar, transport.beat, transport.tick), (0, 3, 720));
+
+        let advanced = transport.advanced(12000, 48000);
+        assert_eq!(advanced.beats, 4.25);
+        assert_eq!((advanced.bar, advanced.beat, advanced.tick), (1, 0, 240));
+        assert_eq!(advanced.phase, 0.25);
+
+        // a stopped transport, or no frames, goes nowhere
+        let stopped = Transport {
+            playing: false,
+            ..transport
+        };
+        assert_eq!(stopped.advanced(12000, 48000), stopped);
+        assert_eq!(transport.advanced(0, 48000), transport);
+    }
+
+    #[test]
+    fn locating_works_out_bars_and_beats_in_any_signature() {
+        let three_four = Transport {
+            beats_per_bar: 3,
+            ..Transport::default()
+        };
+        let located = three_four.located(7.5);
+        assert_eq!((located.bar, located.beat, located.tick), (2, 1, 480));
+        assert_eq!(located.to_string(), "3.2.480 (120 bpm, 3/4, stopped)");
+        let located = three_four.located(9.0);
+        assert_eq!((located.bar, located.beat, located.tick), (3, 0, 0));
+
+        let seven_eight = Transport {
+            beats_per_bar: 7,
+            beat_unit: 8,
+            ..Transport::default()
+        };
+        let located = seven_eight.located(15.0);
+        assert_eq!((located.bar, located.beat, located.tick), (2, 1, 0));
+        let located = seven_eight.located(13.999);
+        assert_eq!((located.bar, located.beat), (1, 6));
+        assert_eq!(located.tick, TICKS_PER_BEAT - 1);
+
+        // positions before the start are the start
+        assert_eq!(seven_eight.located(-2.0), seven_eight.located(0.0));
+    }
+
+    #[test]
+    fn beats_per_sample_follows_the_tempo() {
+        let transport = Transport {
+            playing: true,
+            ..Transport::default()
+        };
+        assert_eq!(transport.beats_per_sample(48000), 1.0 / 24000.0);
+
+        let slower = Transport {
+            bpm: 90.0,
+            ..transport
+        };
+        assert_eq!(slower.beats_per_sample(48000), 1.0 / 32000.0);
+        assert_eq!(slower.advanced(32000, 48000).beats, 1.0);
+        assert_eq!(slower.beats_per_sample(44100), 1.5 / 44100.0);
+    }
+}
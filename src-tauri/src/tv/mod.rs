@@ -1,6 +1,85 @@
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+use lazy_static::lazy_static;
 use log::debug;
 use tauri::Window;
 
+/// How many of the latest output samples the scope keeps for the TV window
+pub const SCOPE_FRAMES: usize = 1024;
+
+lazy_static! {
+    // The latest output of the first channel, written by the audio thread and rendered from the control side
+    pub static ref SCOPE: Scope = Scope::new();
+}
+
+/// ## Scope
+///
+/// The latest samples of the output's first channel, kept in a ring of atomics so the audio thread can publish
+/// every block without allocating, locking or waiting on the window it ends up in.
+///
+/// ### Fields
+///
+/// * `samples: Vec<AtomicU32>` - The samples as f32 bits, used as a ring
+/// * `position: AtomicUsize` - Where the next sample is written
+///
+/// ### Functions
+///
+/// * `new() -> Self` - Creates a silent scope
+/// * `write(&self, samples: &[f32])` - Publishes samples from the audio thread
+/// * `read(&self) -> Vec<f32>` - Reads the latest samples, oldest first
+pub struct Scope {
+    samples: Vec<AtomicU32>,
+    position: AtomicUsize,
+}
+
+impl Scope {
+    /// ## `new() -> Self`
+    ///
+    /// Creates a silent scope.
+    ///
+    /// ### Returns
+    ///
+    /// * `Self` - The new scope
+    pub fn new() -> Self {
+        Self {
+            samples: (0..SCOPE_FRAMES).map(|_| AtomicU32::new(0)).collect(),
+            position: AtomicUsize::new(0),
+        }
+    }
+
+    /// ## `write(&self, samples: &[f32])`
+    ///
+    /// Publishes samples from the audio thread. Only the last SCOPE_FRAMES are kept.
+    ///
+    /// ### Arguments
+    ///
+    /// * `samples: &[f32]` - The samples
+    pub fn write(&self, samples: &[f32]) {
+        let mut position = self.position.load(Ordering::Relaxed);
+        for sample in samples.iter().skip(samples.len().saturating_sub(SCOPE_FRAMES)) {
+            self.samples[position].store(sample.to_bits(), Ordering::Relaxed);
+            position = (position + 1) % SCOPE_FRAMES;
+        }
+        self.position.store(position, Ordering::Release);
+    }
+
+    /// ## `read(&self) -> Vec<f32>`
+    ///
+    /// Reads the latest samples, oldest first. A block written while reading can tear the oldest samples, which a scope doesn't mind.
+    ///
+    /// ### Returns
+    ///
+    /// * `Vec<f32>` - The latest SCOPE_FRAMES samples
+    pub fn read(&self) -> Vec<f32> {
+        let position = self.position.load(Ordering::Acquire);
+        (0..SCOPE_FRAMES)
+            .map(|offset| {
+                f32::from_bits(self.samples[(position + offset) % SCOPE_FRAMES].load(Ordering::Relaxed))
+            })
+            .collect()
+    }
+}
+
 pub trait VisualizerTrait {
     fn new() -> Self;
     fn render(&self, window: &Window, data: &Vec<f32>) -> Result<(), String>;
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface Levels { name: string, peak: Array<number>, hold: Array<number>, rms: Array<number>, momentary: number, short_term: number, integrated: number, clips: number, }
\ No newline at end of file
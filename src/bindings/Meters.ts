@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Levels } from "./Levels";
+
+export interface Meters { strips: Array<Levels>, master: Levels, }
\ No newline at end of file
@@ -8,6 +8,7 @@ import { appWindow } from "@tauri-apps/api/window";
 import { emit } from "@tauri-apps/api/event";
 import { FreqMessage } from "../bindings/FreqMessage";
 import Midi from "./Midi";
+import Meters from "./Meters";
 
 /**
  * ## App()
@@ -112,7 +113,7 @@ function Console() {
 				 * Displays list of available commands
 				 * Displays help for command -- requires command
 				 */
-				outputMessage({ kind: "Console", message: ["Available commands: help, clear, about, host, output, input, config, exit, reave, midi, hid"] });
+				outputMessage({ kind: "Console", message: ["Available commands: help, clear, about, host, output, input, config, exit, reave, midi, hid, strip, bus, render, meter, sample, synth, kit, seq, transport, clock, eq"] });
 				break;
 			case "clear":
 				/*
@@ -270,6 +271,10 @@ function Console() {
 				 * input select <input>
 				 * 		select input
 				 * 
+				 * input strip <input channel(s)> <output channel(s)>
+				 * 		play live input on the given output channels, e.g. "input strip 0 0,1"
+				 * 		either side can be a bus instead, e.g. "input strip 0 bus:vocals" or "input strip bus:vocals 0,1"
+				 * 
 				 * list: list all inputs
 				 * 
 				 * select: select input
@@ -302,6 +307,17 @@ function Console() {
 							outputMessage(response as ConsoleMessage);
 						});
 						break;
+					case "strip":
+						if (args.length < 3) {
+							outputMessage({ kind: "Error", message: ["Not enough arguments for input strip command."] });
+							outputMessage({ kind: "Error", message: ["Usage: input strip [input channel(s)] [output channel(s)]"] });
+							break;
+						}
+						invoke("input_strip", { input: args[1], output: args[2] }).then((response) => {
+							debug("Result from input strip: " + strValue(response as ConsoleMessage));
+							outputMessage(response as ConsoleMessage);
+						});
+						break;
 					case "stream":
 						if (args.length < 2) {
 							outputMessage({ kind: "Error", message: ["Not enough arguments for stream command."] });
@@ -338,7 +354,7 @@ function Console() {
 						break;
 					default:
 						outputMessage({ kind: "Error", message: ["Invalid input command: " + inputCommand] });
-						outputMessage({ kind: "Error", message: ["Usage: input [list|select]"] });
+						outputMessage({ kind: "Error", message: ["Usage: input [list|select|stream|strip]"] });
 						break;
 				}
 				break;
@@ -424,15 +440,17 @@ function Console() {
 			case "midi":
 				/*
 				 * Midi command
-				 * Usage: midi [list|start|stop] [device]
+				 * Usage: midi [list|connected|start|stop|channel] [device]
 				 * 
 				 * list: list available midi devices
-				 * start: start midi input -- requires device
-				 * stop: stop midi input
+				 * connected: list started midi inputs
+				 * start: start midi input -- requires device, by index or (part of) its name; several can be started
+				 * stop: stop midi input -- requires device, or "all"
+				 * channel: make a strip play one midi channel (1-16), or all of them -- e.g. "midi channel 3 10"
 				 */
 				if (args.length < 1) {
 					outputMessage({ kind: "Error", message: ["Not enough arguments for midi command."] });
-					outputMessage({ kind: "Error", message: ["Usage: midi [list|start|stop] [device]"] });
+					outputMessage({ kind: "Error", message: ["Usage: midi [list|connected|start|stop|channel] [device]"] });
 					break;
 				}
 
@@ -444,27 +462,49 @@ function Console() {
 							outputMessage(response as ConsoleMessage);
 						});
 						break;
+					case "connected":
+						invoke("midi_connected").then((response) => {
+							debug("Result from midi connected: " + strValue(response as ConsoleMessage));
+							outputMessage(response as ConsoleMessage);
+						});
+						break;
 					case "start":
 						if (args.length < 2) {
 							outputMessage({ kind: "Error", message: ["Not enough arguments for start midi command."] });
 							outputMessage({ kind: "Error", message: ["Usage: midi start [device]"] });
 							break;
 						}
-						let device_name = args[1] as string;
+						let device_name = args.slice(1).join(" ");
 						invoke("midi_start", { deviceName: device_name }).then((response) => {
 							debug("Result from midi start: " + strValue(response as ConsoleMessage));
 							outputMessage(response as ConsoleMessage);
 						});
 						break;
 					case "stop":
-						invoke("midi_stop").then((response) => {
+						if (args.length < 2) {
+							outputMessage({ kind: "Error", message: ["Not enough arguments for stop midi command."] });
+							outputMessage({ kind: "Error", message: ["Usage: midi stop [device|all]"] });
+							break;
+						}
+						invoke("midi_stop", { deviceName: args.slice(1).join(" ") }).then((response) => {
 							debug("Result from midi stop: " + strValue(response as ConsoleMessage));
 							outputMessage(response as ConsoleMessage);
 						});
 						break;
+					case "channel":
+						if (args.length < 2) {
+							outputMessage({ kind: "Error", message: ["Not enough arguments for channel midi command."] });
+							outputMessage({ kind: "Error", message: ["Usage: midi channel [strip] [1-16|all]"] });
+							break;
+						}
+						invoke("midi_channel", { strip: parseInt(args[1]), channel: args[2] }).then((response) => {
+							debug("Result from midi channel: " + strValue(response as ConsoleMessage));
+							outputMessage(response as ConsoleMessage);
+						});
+						break;
 					default:
 						outputMessage({ kind: "Error", message: ["Invalid midi command: " + midiCommand] });
-						outputMessage({ kind: "Error", message: ["Usage: midi [list|start|stop] [device]"] });
+						outputMessage({ kind: "Error", message: ["Usage: midi [list|connected|start|stop|channel] [device]"] });
 						break;
 				};
 				break;
@@ -495,6 +535,702 @@ function Console() {
 						break;
 				};
 				break;
+			case "strip":
+				/*
+				 * Strip command
+				 * Usage:
+				 * strip list
+				 * 		list all strips with their input and output
+				 * 
+				 * strip output <strip> <output channel(s)|bus:name>
+				 * 		change where a strip outputs to, e.g. "strip output 0 bus:drums"
+				 * 
+				 * strip param <strip|master> <effect|gen> <name> <value>
+				 * 		set a parameter of an effect in a strip's or the master bus' chain, e.g. "strip param 0 0 feedback 0.3",
+				 * 		or of a strip's generator, e.g. "strip param 3 gen start 0.5"
+				 * 
+				 * strip effect <strip|master> <name> [args...]
+				 * 		add an effect to the end of a chain, e.g. "strip effect master clip 0.9"
+				 * 		effects: clip, bitcrusher, delay, lofidelay, filter [cutoff] [resonance] [mode],
+				 * 		lowpass, highpass, bandpass and notch [cutoff] [resonance],
+				 * 		compressor [threshold] [ratio] [attack] [release], limiter [ceiling] [lookahead] [release], gate [threshold] [ratio] [range],
+				 * 		reverb [size] [decay] [mix] (parameters size, decay, predelay, damping, width, mix)
+				 * 		chorus [voices] [rate] [depth] [mix], flanger [rate] [depth] [feedback], phaser [stages] [rate] [feedback]
+				 * 		(their LFOs follow the tempo with "sync <beats>", and "phase <degrees>" offsets the right channel)
+				 * 		granulizer [duration] [density] [pitch] draws grains from the strip's last few seconds, e.g. "strip param 1 0 freeze 1"
+				 * 		holds the current audio (its parameters are as for "sample grain", plus mix)
+				 * 		pitchshift [semitones] [cents] (parameters semitones, cents, formant 0/1, mix)
+				 * 		harmonizer [interval...] adds up to four voices a number of scale steps from the tracked pitch, e.g. "strip effect 0 harmonizer 2 4"
+				 * 		(parameters key 0-11, scale 0 chromatic, 1 major, 2 minor, 3 harmonic minor, 4 dorian, 5/6 major/minor pentatonic,
+				 * 		voices, which spreads the voices' pans again, dry, formant, and <voice>.interval, <voice>.gain, <voice>.pan)
+				 * 		(both follow the pitch of the left and right channels together and lag 60 ms, with the dry signal delayed to match)
+				 * 		eq adds a parametric EQ with every band off, the same as "eq add"
+				 * 		filters follow the strip's notes and can map a controller, e.g. "strip param 1 0 env 3", "strip param 1 0 cc 74"
+				 * 		dynamics can be keyed by another strip, e.g. "strip param 1 0 key 0" ducks strip 1 with strip 0 (-1 unkeys)
+				 * 
+				 * strip gain <strip|master> <dB>
+				 * 		set the gain of a strip or the master bus, e.g. "strip gain 2 -6"
+				 * 
+				 * strip pan <strip|master> <-1..1> [balance|constant|compromise|linear]
+				 * 		pan a strip or the master bus, optionally choosing the pan law
+				 * 
+				 * strip mute <strip|master> [on|off]
+				 * strip solo <strip> [on|off]
+				 * 		mute or solo a strip (or mute the master bus), toggling if on/off is left out
+				 */
+				if (args.length < 1) {
+					outputMessage({ kind: "Error", message: ["Not enough arguments for strip command."] });
+					outputMessage({ kind: "Error", message: ["Usage: strip [list|output|param|effect|gain|pan|mute|solo]"] });
+					break;
+				}
+
+				let stripCommand = args[0];
+				switch (stripCommand) {
+					case "list":
+						invoke("strip_list").then((response) => {
+							debug("Result from strip list: " + strValue(response as ConsoleMessage));
+							outputMessage(response as ConsoleMessage);
+						});
+						break;
+					case "output":
+						if (args.length < 3) {
+							outputMessage({ kind: "Error", message: ["Not enough arguments for strip output command."] });
+							outputMessage({ kind: "Error", message: ["Usage: strip output [strip] [output channel(s)|bus:name]"] });
+							break;
+						}
+						invoke("strip_output", { index: parseInt(args[1]), output: args[2] }).then((response) => {
+							debug("Result from strip output: " + strValue(response as ConsoleMessage));
+							outputMessage(response as ConsoleMessage);
+						});
+						break;
+					case "param":
+						if (args.length < 5) {
+							outputMessage({ kind: "Error", message: ["Not enough arguments for strip param command."] });
+							outputMessage({ kind: "Error", message: ["Usage: strip param [strip] [effect|gen] [name] [value]"] });
+							break;
+						}
+						invoke("strip_parameter", { strip: args[1], effect: args[2], name: args[3], value: parseFloat(args[4]) }).then((response) => {
+							debug("Result from strip param: " + strValue(response as ConsoleMessage));
+							outputMessage(response as ConsoleMessage);
+						});
+						break;
+					case "effect":
+						if (args.length < 3) {
+							outputMessage({ kind: "Error", message: ["Not enough arguments for strip effect command."] });
+							outputMessage({ kind: "Error", message: ["Usage: strip effect [strip|master] [name] [args...]"] });
+							break;
+						}
+						invoke("strip_effect", { strip: args[1], name: args[2], args: args.slice(3).map((arg) => parseFloat(arg)) }).then((response) => {
+							debug("Result from strip effect: " + strValue(response as ConsoleMessage));
+							outputMessage(response as ConsoleMessage);
+						});
+						break;
+					case "gain":
+						if (args.length < 3) {
+							outputMessage({ kind: "Error", message: ["Not enough arguments for strip gain command."] });
+							outputMessage({ kind: "Error", message: ["Usage: strip gain [strip|master] [dB]"] });
+							break;
+						}
+						invoke("strip_gain", { strip: args[1], gain: parseFloat(args[2]) }).then((response) => {
+							debug("Result from strip gain: " + strValue(response as ConsoleMessage));
+							outputMessage(response as ConsoleMessage);
+						});
+						break;
+					case "pan":
+						if (args.length < 3) {
+							outputMessage({ kind: "Error", message: ["Not enough arguments for strip pan command."] });
+							outputMessage({ kind: "Error", message: ["Usage: strip pan [strip|master] [-1..1] [balance|constant|compromise|linear]"] });
+							break;
+						}
+						invoke("strip_pan", { strip: args[1], pan: parseFloat(args[2]), law: args[3] }).then((response) => {
+							debug("Result from strip pan: " + strValue(response as ConsoleMessage));
+							outputMessage(response as ConsoleMessage);
+						});
+						break;
+					case "mute":
+					case "solo":
+						if (args.length < 2) {
+							outputMessage({ kind: "Error", message: ["Not enough arguments for strip " + stripCommand + " command."] });
+							outputMessage({ kind: "Error", message: ["Usage: strip " + stripCommand + " [strip|master] [on|off]"] });
+							break;
+						}
+						invoke("strip_" + stripCommand, { strip: args[1], state: args[2] }).then((response) => {
+							debug("Result from strip " + stripCommand + ": " + strValue(response as ConsoleMessage));
+							outputMessage(response as ConsoleMessage);
+						});
+						break;
+					default:
+						outputMessage({ kind: "Error", message: ["Invalid strip command: " + stripCommand] });
+						outputMessage({ kind: "Error", message: ["Usage: strip [list|output|param|effect|gain|pan|mute|solo]"] });
+						break;
+				};
+				break;
+			case "meter":
+				/*
+				 * Meter command
+				 * Usage: meter [show|reset]
+				 * 
+				 * show: show peak, RMS, loudness and clip counts of every strip and the master bus
+				 * reset: reset integrated loudness and clip counts
+				 */
+				if (args.length < 1) {
+					outputMessage({ kind: "Error", message: ["Not enough arguments for meter command."] });
+					outputMessage({ kind: "Error", message: ["Usage: meter [show|reset]"] });
+					break;
+				}
+
+				let meterCommand = args[0];
+				switch (meterCommand) {
+					case "show":
+						invoke("meter_show").then((response) => {
+							debug("Result from meter show: " + strValue(response as ConsoleMessage));
+							outputMessage(response as ConsoleMessage);
+						});
+						break;
+					case "reset":
+						invoke("meter_reset").then((response) => {
+							debug("Result from meter reset: " + strValue(response as ConsoleMessage));
+							outputMessage(response as ConsoleMessage);
+						});
+						break;
+					default:
+						outputMessage({ kind: "Error", message: ["Invalid meter command: " + meterCommand] });
+						outputMessage({ kind: "Error", message: ["Usage: meter [show|reset]"] });
+						break;
+				}
+				break;
+			case "bus":
+				/*
+				 * Bus command
+				 * Usage: bus [list]
+				 * 
+				 * list: list all buses with the strips writing to and reading from them
+				 */
+				if (args.length < 1) {
+					outputMessage({ kind: "Error", message: ["Not enough arguments for bus command."] });
+					outputMessage({ kind: "Error", message: ["Usage: bus [list]"] });
+					break;
+				}
+
+				let busCommand = args[0];
+				switch (busCommand) {
+					case "list":
+						invoke("bus_list").then((response) => {
+							debug("Result from bus list: " + strValue(response as ConsoleMessage));
+							outputMessage(response as ConsoleMessage);
+						});
+						break;
+					default:
+						outputMessage({ kind: "Error", message: ["Invalid bus command: " + busCommand] });
+						outputMessage({ kind: "Error", message: ["Usage: bus [list]"] });
+						break;
+				};
+				break;
+			case "render":
+				/*
+				 * Render command
+				 * Usage: render <seconds> <file.wav>
+				 * 
+				 * renders the strips to a WAV file without playing them, e.g. "render 10 bounce.wav"
+				 */
+				if (args.length < 2) {
+					outputMessage({ kind: "Error", message: ["Not enough arguments for render command."] });
+					outputMessage({ kind: "Error", message: ["Usage: render [seconds] [file.wav]"] });
+					break;
+				}
+
+				invoke("render", { seconds: parseFloat(args[0]), path: args[1] }).then((response) => {
+					debug("Result from render: " + strValue(response as ConsoleMessage));
+					outputMessage(response as ConsoleMessage);
+				});
+				break;
+			case "sample":
+				/*
+				 * Sample command
+				 * Usage:
+				 * sample load <file> [oneshot|loop|gated] [root note]
+				 * 		load a WAV, MP3, FLAC or OGG file into a new strip playing MIDI notes and HID keys, e.g. "sample load reaved.mp3 gated 60"
+				 * 		without a root note every note plays the file at its original pitch
+				 * 
+				 * sample grain <file>
+				 * 		load a file into a new strip granulizing it while a note is held, pitched relative to middle C, e.g. "sample grain rain.wav"
+				 * 
+				 * sample trigger <strip> [note] [velocity]
+				 * sample release <strip> [note]
+				 * 		start or release a note from the console, e.g. "sample trigger 3 64 0.8"
+				 * 
+				 * start, end (seconds), mode (0 oneshot, 1 loop, 2 gated), root and gain (dB) are set with "strip param <strip> gen <name> <value>"
+				 * for a granulizing strip: start, end (0-1 of the file), scan (playhead speed), duration (ms), pitch (semitones), density (grains per second),
+				 * spray (ms), reverse (chance 0-1), width (0-1), envelope (0 sine, 1 triangle, 2 gaussian, 3 sinc), freeze (0/1) and gain (dB)
+				 */
+				if (args.length < 2) {
+					outputMessage({ kind: "Error", message: ["Not enough arguments for sample command."] });
+					outputMessage({ kind: "Error", message: ["Usage: sample [load|grain|trigger|release]"] });
+					break;
+				}
+
+				let sampleCommand = args[0];
+				switch (sampleCommand) {
+					case "load":
+						invoke("sample_load", { path: args[1], mode: args[2], root: args.length > 3 ? parseInt(args[3]) : undefined }).then((response) => {
+							debug("Result from sample load: " + strValue(response as ConsoleMessage));
+							outputMessage(response as ConsoleMessage);
+						});
+						break;
+					case "grain":
+						invoke("sample_grain", { path: args[1] }).then((response) => {
+							debug("Result from sample grain: " + strValue(response as ConsoleMessage));
+							outputMessage(response as ConsoleMessage);
+						});
+						break;
+					case "trigger":
+						invoke("sample_trigger", { strip: parseInt(args[1]), note: args.length > 2 ? parseInt(args[2]) : undefined, velocity: args.length > 3 ? parseFloat(args[3]) : undefined }).then((response) => {
+							debug("Result from sample trigger: " + strValue(response as ConsoleMessage));
+							outputMessage(response as ConsoleMessage);
+						});
+						break;
+					case "release":
+						invoke("sample_release", { strip: parseInt(args[1]), note: args.length > 2 ? parseInt(args[2]) : undefined }).then((response) => {
+							debug("Result from sample release: " + strValue(response as ConsoleMessage));
+							outputMessage(response as ConsoleMessage);
+						});
+						break;
+					default:
+						outputMessage({ kind: "Error", message: ["Invalid sample command: " + sampleCommand] });
+						outputMessage({ kind: "Error", message: ["Usage: sample [load|grain|trigger|release]"] });
+						break;
+				}
+				break;
+			case "synth":
+				/*
+				 * Synth command
+				 * Usage:
+				 * synth new [oscillator] [voices] [poly|mono|legato]
+				 * 		add a strip with a polyphonic synth playing MIDI notes and HID keys, e.g. "synth new sine 16 poly"
+				 * 		play it from the console with "sample trigger" and "sample release"
+				 * 
+				 * voices, mode (0 poly, 1 mono, 2 legato), steal (0 oldest, 1 quietest, 2 lowest, 3 highest, 4 never),
+				 * attack, decay, release (seconds), sustain (0-1), portamento (seconds), bend (semitones), gain (dB)
+				 * and oscillator parameters (osc.<name>) are set with "strip param <strip> gen <name> <value>"
+				 * 
+				 * oscillators: sine, saw, square, pulse, triangle, noise, pink
+				 * oscillator parameters: osc.unison (1-8), osc.detune (cents), osc.spread (0-1),
+				 * and for square and pulse osc.width (0.01-0.99), osc.pwm (0-0.49), osc.pwm_rate (Hz)
+				 */
+				if (args.length < 1) {
+					outputMessage({ kind: "Error", message: ["Not enough arguments for synth command."] });
+					outputMessage({ kind: "Error", message: ["Usage: synth [new]"] });
+					break;
+				}
+
+				let synthCommand = args[0];
+				switch (synthCommand) {
+					case "new":
+						invoke("synth_new", { oscillator: args[1], voices: args.length > 2 ? parseInt(args[2]) : undefined, mode: args[3] }).then((response) => {
+							debug("Result from synth new: " + strValue(response as ConsoleMessage));
+							outputMessage(response as ConsoleMessage);
+						});
+						break;
+					default:
+						outputMessage({ kind: "Error", message: ["Invalid synth command: " + synthCommand] });
+						outputMessage({ kind: "Error", message: ["Usage: synth [new]"] });
+						break;
+				}
+				break;
+			case "kit":
+				/*
+				 * Kit command
+				 * Usage:
+				 * kit list
+				 * 		list the drum kits in public/kits
+				 * 
+				 * kit load <name>
+				 * 		load a drum kit, adding a strip for every output its pads use, e.g. "kit load reaved"
+				 * 		pads play from MIDI notes and their HID keys; per-pad tune, gain and pan are set with
+				 * 		"strip param <strip> gen <pad>.<tune|gain|pan> <value>"
+				 */
+				if (args.length < 1) {
+					outputMessage({ kind: "Error", message: ["Not enough arguments for kit command."] });
+					outputMessage({ kind: "Error", message: ["Usage: kit [list|load]"] });
+					break;
+				}
+
+				let kitCommand = args[0];
+				switch (kitCommand) {
+					case "list":
+						invoke("kit_list").then((response) => {
+							debug("Result from kit list: " + strValue(response as ConsoleMessage));
+							outputMessage(response as ConsoleMessage);
+						});
+						break;
+					case "load":
+						if (args.length < 2) {
+							outputMessage({ kind: "Error", message: ["Not enough arguments for kit load command."] });
+							outputMessage({ kind: "Error", message: ["Usage: kit load [name]"] });
+							break;
+						}
+						invoke("kit_load", { name: args[1] }).then((response) => {
+							debug("Result from kit load: " + strValue(response as ConsoleMessage));
+							outputMessage(response as ConsoleMessage);
+						});
+						break;
+					default:
+						outputMessage({ kind: "Error", message: ["Invalid kit command: " + kitCommand] });
+						outputMessage({ kind: "Error", message: ["Usage: kit [list|load]"] });
+						break;
+				}
+				break;
+			case "seq":
+				/*
+				 * Sequencer command
+				 * Usage:
+				 * seq show
+				 * 		show the pattern, one grid per track ("x" loud, "o" soft, "r" ratcheted, "?" maybe, "." empty)
+				 * 
+				 * seq new [steps per bar]
+				 * 		start an empty pattern, e.g. "seq new 12" for triplets in 4/4
+				 * 
+				 * seq track <name> <strip> <note> [grid]
+				 * seq grid <track> <grid>
+				 * 		add a track playing a note on a strip, and program it, e.g. "seq track kick 3 36 x...x...x...x..."
+				 * 
+				 * seq step <track> <step> <velocity> [probability] [ratchet] [offset]
+				 * 		set one step, e.g. "seq step hat 6 0.8 0.5 3 -0.1"
+				 * 
+				 * seq mute <track> [on|off]
+				 * seq remove <track>
+				 * seq play
+				 * seq stop
+				 * 		same as transport play and transport stop
+				 * 
+				 * seq save <name>
+				 * seq load <name>
+				 * 		save or load a pattern in public/config/patterns
+				 */
+				if (args.length < 1) {
+					outputMessage({ kind: "Error", message: ["Not enough arguments for seq command."] });
+					outputMessage({ kind: "Error", message: ["Usage: seq [show|new|track|grid|step|mute|remove|play|stop|save|load]"] });
+					break;
+				}
+
+				let seqCommand = args[0];
+				switch (seqCommand) {
+					case "show":
+						invoke("seq_show").then((response) => {
+							debug("Result from seq show: " + strValue(response as ConsoleMessage));
+							outputMessage(response as ConsoleMessage);
+						});
+						break;
+					case "new":
+						invoke("seq_new", { stepsPerBar: args.length > 1 ? parseInt(args[1]) : undefined }).then((response) => {
+							debug("Result from seq new: " + strValue(response as ConsoleMessage));
+							outputMessage(response as ConsoleMessage);
+						});
+						break;
+					case "track":
+						if (args.length < 4) {
+							outputMessage({ kind: "Error", message: ["Not enough arguments for seq track command."] });
+							outputMessage({ kind: "Error", message: ["Usage: seq track [name] [strip] [note] [grid]"] });
+							break;
+						}
+						invoke("seq_track", { name: args[1], strip: parseInt(args[2]), note: parseInt(args[3]), grid: args.length > 4 ? args.slice(4).join("") : undefined }).then((response) => {
+							debug("Result from seq track: " + strValue(response as ConsoleMessage));
+							outputMessage(response as ConsoleMessage);
+						});
+						break;
+					case "grid":
+						if (args.length < 3) {
+							outputMessage({ kind: "Error", message: ["Not enough arguments for seq grid command."] });
+							outputMessage({ kind: "Error", message: ["Usage: seq grid [track] [grid]"] });
+							break;
+						}
+						invoke("seq_grid", { track: args[1], grid: args.slice(2).join("") }).then((response) => {
+							debug("Result from seq grid: " + strValue(response as ConsoleMessage));
+							outputMessage(response as ConsoleMessage);
+						});
+						break;
+					case "step":
+						if (args.length < 4) {
+							outputMessage({ kind: "Error", message: ["Not enough arguments for seq step command."] });
+							outputMessage({ kind: "Error", message: ["Usage: seq step [track] [step] [velocity] [probability] [ratchet] [offset]"] });
+							break;
+						}
+						invoke("seq_step", { track: args[1], step: parseInt(args[2]), velocity: parseFloat(args[3]), probability: args.length > 4 ? parseFloat(args[4]) : undefined, ratchet: args.length > 5 ? parseInt(args[5]) : undefined, offset: args.length > 6 ? parseFloat(args[6]) : undefined }).then((response) => {
+							debug("Result from seq step: " + strValue(response as ConsoleMessage));
+							outputMessage(response as ConsoleMessage);
+						});
+						break;
+					case "mute":
+						if (args.length < 2) {
+							outputMessage({ kind: "Error", message: ["Not enough arguments for seq mute command."] });
+							outputMessage({ kind: "Error", message: ["Usage: seq mute [track] [on|off]"] });
+							break;
+						}
+						invoke("seq_mute", { track: args[1], state: args[2] }).then((response) => {
+							debug("Result from seq mute: " + strValue(response as ConsoleMessage));
+							outputMessage(response as ConsoleMessage);
+						});
+						break;
+					case "remove":
+						if (args.length < 2) {
+							outputMessage({ kind: "Error", message: ["Not enough arguments for seq remove command."] });
+							outputMessage({ kind: "Error", message: ["Usage: seq remove [track]"] });
+							break;
+						}
+						invoke("seq_remove", { track: args[1] }).then((response) => {
+							debug("Result from seq remove: " + strValue(response as ConsoleMessage));
+							outputMessage(response as ConsoleMessage);
+						});
+						break;
+					case "play":
+						invoke("transport_play", { playing: true }).then((response) => {
+							debug("Result from seq play: " + strValue(response as ConsoleMessage));
+							outputMessage(response as ConsoleMessage);
+						});
+						break;
+					case "stop":
+						invoke("transport_play", { playing: false }).then((response) => {
+							debug("Result from seq stop: " + strValue(response as ConsoleMessage));
+							outputMessage(response as ConsoleMessage);
+						});
+						break;
+					case "save":
+						if (args.length < 2) {
+							outputMessage({ kind: "Error", message: ["Not enough arguments for seq save command."] });
+							outputMessage({ kind: "Error", message: ["Usage: seq save [name]"] });
+							break;
+						}
+						invoke("seq_save", { name: args[1] }).then((response) => {
+							debug("Result from seq save: " + strValue(response as ConsoleMessage));
+							outputMessage(response as ConsoleMessage);
+						});
+						break;
+					case "load":
+						if (args.length < 2) {
+							outputMessage({ kind: "Error", message: ["Not enough arguments for seq load command."] });
+							outputMessage({ kind: "Error", message: ["Usage: seq load [name]"] });
+							break;
+						}
+						invoke("seq_load", { name: args[1] }).then((response) => {
+							debug("Result from seq load: " + strValue(response as ConsoleMessage));
+							outputMessage(response as ConsoleMessage);
+						});
+						break;
+					default:
+						outputMessage({ kind: "Error", message: ["Invalid seq command: " + seqCommand] });
+						outputMessage({ kind: "Error", message: ["Usage: seq [show|new|track|grid|step|mute|remove|play|stop|save|load]"] });
+						break;
+				}
+				break;
+			case "transport":
+				/*
+				 * Transport command
+				 * Usage:
+				 * transport show
+				 * 		show the position as bar.beat.tick, the tempo and the time signature
+				 * 
+				 * transport play
+				 * transport stop
+				 * 		start or stop the transport where it is
+				 * 
+				 * transport locate <bar> [beat]
+				 * 		move to a bar and beat, counting from 1, e.g. "transport locate 9" or "transport locate 2 3.5"
+				 * 
+				 * transport tempo <bpm>
+				 * transport signature <beats/unit>
+				 * 		e.g. "transport tempo 128" or "transport signature 7/8"
+				 */
+				if (args.length < 1) {
+					outputMessage({ kind: "Error", message: ["Not enough arguments for transport command."] });
+					outputMessage({ kind: "Error", message: ["Usage: transport [show|play|stop|locate|tempo|signature]"] });
+					break;
+				}
+
+				let transportCommand = args[0];
+				switch (transportCommand) {
+					case "show":
+						invoke("transport_show").then((response) => {
+							debug("Result from transport show: " + strValue(response as ConsoleMessage));
+							outputMessage(response as ConsoleMessage);
+						});
+						break;
+					case "play":
+						invoke("transport_play", { playing: true }).then((response) => {
+							debug("Result from transport play: " + strValue(response as ConsoleMessage));
+							outputMessage(response as ConsoleMessage);
+						});
+						break;
+					case "stop":
+						invoke("transport_play", { playing: false }).then((response) => {
+							debug("Result from transport stop: " + strValue(response as ConsoleMessage));
+							outputMessage(response as ConsoleMessage);
+						});
+						break;
+					case "locate":
+						if (args.length < 2) {
+							outputMessage({ kind: "Error", message: ["Not enough arguments for transport locate command."] });
+							outputMessage({ kind: "Error", message: ["Usage: transport locate [bar] [beat]"] });
+							break;
+						}
+						invoke("transport_locate", { bar: parseInt(args[1]), beat: args.length > 2 ? parseFloat(args[2]) : undefined }).then((response) => {
+							debug("Result from transport locate: " + strValue(response as ConsoleMessage));
+							outputMessage(response as ConsoleMessage);
+						});
+						break;
+					case "tempo":
+						if (args.length < 2) {
+							outputMessage({ kind: "Error", message: ["Not enough arguments for transport tempo command."] });
+							outputMessage({ kind: "Error", message: ["Usage: transport tempo [bpm]"] });
+							break;
+						}
+						invoke("transport_tempo", { bpm: parseFloat(args[1]) }).then((response) => {
+							debug("Result from transport tempo: " + strValue(response as ConsoleMessage));
+							outputMessage(response as ConsoleMessage);
+						});
+						break;
+					case "signature":
+						if (args.length < 2) {
+							outputMessage({ kind: "Error", message: ["Not enough arguments for transport signature command."] });
+							outputMessage({ kind: "Error", message: ["Usage: transport signature [beats/unit]"] });
+							break;
+						}
+						invoke("transport_signature", { signature: args[1] }).then((response) => {
+							debug("Result from transport signature: " + strValue(response as ConsoleMessage));
+							outputMessage(response as ConsoleMessage);
+						});
+						break;
+					default:
+						outputMessage({ kind: "Error", message: ["Invalid transport command: " + transportCommand] });
+						outputMessage({ kind: "Error", message: ["Usage: transport [show|play|stop|locate|tempo|signature]"] });
+						break;
+				}
+				break;
+			case "clock":
+				/*
+				 * MIDI clock command
+				 * Usage:
+				 * clock show
+				 * 		show whether the transport follows MIDI clock, and where MIDI clock is sent
+				 * 
+				 * clock follow [on|off]
+				 * 		follow the clock, start, stop, continue and song position of connected MIDI inputs (toggles without on/off)
+				 * 
+				 * clock send <port>
+				 * clock stop
+				 * 		send MIDI clock to an output port, by index or name, or stop sending it
+				 */
+				if (args.length < 1) {
+					outputMessage({ kind: "Error", message: ["Not enough arguments for clock command."] });
+					outputMessage({ kind: "Error", message: ["Usage: clock [show|follow|send|stop]"] });
+					break;
+				}
+
+				let clockCommand = args[0];
+				switch (clockCommand) {
+					case "show":
+						invoke("clock_show").then((response) => {
+							debug("Result from clock show: " + strValue(response as ConsoleMessage));
+							outputMessage(response as ConsoleMessage);
+						});
+						break;
+					case "follow":
+						invoke("clock_follow", { state: args[1] }).then((response) => {
+							debug("Result from clock follow: " + strValue(response as ConsoleMessage));
+							outputMessage(response as ConsoleMessage);
+						});
+						break;
+					case "send":
+						if (args.length < 2) {
+							outputMessage({ kind: "Error", message: ["Not enough arguments for clock send command."] });
+							outputMessage({ kind: "Error", message: ["Usage: clock send [port]"] });
+							break;
+						}
+						invoke("clock_send", { port: args.slice(1).join(" ") }).then((response) => {
+							debug("Result from clock send: " + strValue(response as ConsoleMessage));
+							outputMessage(response as ConsoleMessage);
+						});
+						break;
+					case "stop":
+						invoke("clock_stop").then((response) => {
+							debug("Result from clock stop: " + strValue(response as ConsoleMessage));
+							outputMessage(response as ConsoleMessage);
+						});
+						break;
+					default:
+						outputMessage({ kind: "Error", message: ["Invalid clock command: " + clockCommand] });
+						outputMessage({ kind: "Error", message: ["Usage: clock [show|follow|send|stop]"] });
+						break;
+				}
+				break;
+			case "eq":
+				/*
+				 * EQ command
+				 * Usage:
+				 * eq add <strip|master>
+				 * 		add an 8 band parametric EQ to the end of a chain, with every band off
+				 * 
+				 * eq band <strip|master>[:effect] <band> <off|lowshelf|highshelf|peak|lowpass|highpass> [frequency] [gain|slope] [q]
+				 * 		set a band, e.g. "eq band 0 0 highpass 80 24" or "eq band master 3 peak 2500 -4 1.5"
+				 * 		the third value is the gain in dB for shelves and peaks, or the slope in dB/oct (12-48) for passes
+				 * 		a chain with more than one EQ needs the index of the one to set, e.g. "eq band 0:2 1 peak 400 -3"
+				 * 		the bands can also be set with "strip param", e.g. "strip param 0 2 1.gain -3"
+				 * 
+				 * eq show <strip|master>[:effect]
+				 * 		list the bands of an EQ
+				 * 
+				 * eq response <strip|master>[:effect] [points]
+				 * 		print the frequency response of an EQ, and send it to the console and TV windows as the "eq" event
+				 */
+				if (args.length < 2) {
+					outputMessage({ kind: "Error", message: ["Not enough arguments for eq command."] });
+					outputMessage({ kind: "Error", message: ["Usage: eq [add|band|show|response] [strip]"] });
+					break;
+				}
+
+				let eqCommand = args[0];
+				switch (eqCommand) {
+					case "add":
+						invoke("eq_add", { strip: args[1] }).then((response) => {
+							debug("Result from eq add: " + strValue(response as ConsoleMessage));
+							outputMessage(response as ConsoleMessage);
+						});
+						break;
+					case "band":
+						if (args.length < 4) {
+							outputMessage({ kind: "Error", message: ["Not enough arguments for eq band command."] });
+							outputMessage({ kind: "Error", message: ["Usage: eq band [strip] [band] [type] [frequency] [gain|slope] [q]"] });
+							break;
+						}
+						invoke("eq_band", {
+							strip: args[1],
+							band: parseInt(args[2]),
+							kind: args[3],
+							frequency: args.length > 4 ? parseFloat(args[4]) : undefined,
+							amount: args.length > 5 ? parseFloat(args[5]) : undefined,
+							q: args.length > 6 ? parseFloat(args[6]) : undefined
+						}).then((response) => {
+							debug("Result from eq band: " + strValue(response as ConsoleMessage));
+							outputMessage(response as ConsoleMessage);
+						});
+						break;
+					case "show":
+						invoke("eq_show", { strip: args[1] }).then((response) => {
+							debug("Result from eq show: " + strValue(response as ConsoleMessage));
+							outputMessage(response as ConsoleMessage);
+						});
+						break;
+					case "response":
+						invoke("eq_response", { strip: args[1], points: args.length > 2 ? parseInt(args[2]) : undefined }).then((response) => {
+							debug("Result from eq response: " + strValue(response as ConsoleMessage));
+							outputMessage(response as ConsoleMessage);
+						});
+						break;
+					default:
+						outputMessage({ kind: "Error", message: ["Invalid eq command: " + eqCommand] });
+						outputMessage({ kind: "Error", message: ["Usage: eq [add|band|show|response] [strip]"] });
+						break;
+				}
+				break;
 			case "":
 				break;
 			default:
@@ -591,6 +1327,7 @@ function Console() {
 			<div className="app">
 				<div className="container" data-tauri-drag-region>
 					{midi}
+					<Meters />
 					<div className="console">
 						<div className="console-output">
 							{output}
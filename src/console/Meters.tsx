@@ -0,0 +1,89 @@
+import { useEffect, useState } from "react";
+import { listen } from "@tauri-apps/api/event";
+import "./Meters.css";
+
+import { Levels } from "../bindings/Levels";
+import { Meters as MetersMessage } from "../bindings/Meters";
+
+// the range shown by the bars, in dB
+const FLOOR = -60;
+
+/**
+ * ## width(level: number)
+ * 
+ * Converts a level in dB to the width of a bar
+ * 
+ * ### Parameters
+ * @param level - The level in dB
+ * 
+ * ### Returns
+ * @returns The width of the bar, as a percentage
+ */
+function width(level: number): string {
+	let fraction = Math.min(Math.max((level - FLOOR) / -FLOOR, 0), 1);
+	return fraction * 100 + "%";
+}
+
+/**
+ * ## Meter(levels: Levels)
+ * 
+ * One row of meters: a bar per channel with its peak hold, followed by loudness and clip count
+ * 
+ * @returns meter row element
+ */
+function Meter({ levels }: { levels: Levels }) {
+	return (
+		<div className="meter">
+			<span className="meter-name">{levels.name}</span>
+			<div className="meter-channels">
+				{levels.peak.map((peak, channel) => (
+					<div key={channel} className="meter-bar">
+						<div className="meter-rms" style={{ width: width(levels.rms[channel]) }} />
+						<div className="meter-peak" style={{ width: width(peak) }} />
+						<div className="meter-hold" style={{ left: width(levels.hold[channel]) }} />
+					</div>
+				))}
+			</div>
+			<span className="meter-loudness">
+				{levels.short_term.toFixed(1)} S / {levels.integrated.toFixed(1)} I LUFS
+			</span>
+			<span className={levels.clips > 0 ? "meter-clips meter-clipped" : "meter-clips"}>
+				{levels.clips}
+			</span>
+		</div>
+	);
+}
+
+/**
+ * ## Meters()
+ * 
+ * Shows the levels of every strip and the master bus, as emitted by the audio engine
+ * 
+ * @returns meters element
+ */
+function Meters() {
+	const [meters, setMeters] = useState<MetersMessage | null>(null);
+
+	useEffect(() => {
+		const unlisten = listen("meters", (event) => {
+			setMeters(event.payload as MetersMessage);
+		});
+
+		return () => {
+			unlisten.then((unlisten) => unlisten());
+		};
+	}, []);
+
+	if (meters === null) {
+		return <></>;
+	}
+
+	return (
+		<div className="meters">
+			{meters.strips.map((levels) => <Meter key={levels.name} levels={levels} />)}
+			<Meter levels={meters.master} />
+		</div>
+	);
+}
+
+export default Meters;